@@ -0,0 +1,341 @@
+// This is Burlap's bytecode to C compiler, each op becomes a few lines of C
+// Jumps are gotos, and returns (or calls to functi values) go through a switch
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::Arguments;
+use crate::backend::value::{Overflow, Value};
+use crate::backend::vm::compiler::Program;
+use crate::backend::vm::optimizer::{decode_op, jump_target};
+use crate::backend::vm::vm::{shift2, shift3, Opcode::*};
+
+const RUNTIME: &str = include_str!("runtime.c");
+
+// Builtins in the runtime, and their C names
+const BUILTINS: [(&str, &str); 8] = [
+    ("print", "sk_bi_print"), ("string", "sk_bi_string"), ("int", "sk_bi_int"),
+    ("float", "sk_bi_float"), ("type", "sk_bi_type"), ("abs", "sk_bi_abs"),
+    ("exit", "sk_bi_exit"), ("__burlap_range", "sk_bi_range"),
+];
+
+fn builtin(name: &str) -> Option<&'static str> {
+    BUILTINS.iter().find(|b| b.0 == name).map(|b| b.1)
+}
+
+// A C string literal, escapes anything that isn't plain ASCII
+fn c_str(s: &str) -> String {
+    let mut ret = "\"".to_string();
+    for byte in s.bytes() {
+        match byte {
+            b'"' | b'\\' => { ret.push('\\'); ret.push(byte as char); },
+            b' '..=b'~' if byte != b'?' => ret.push(byte as char),
+            // Octal is always three digits, so it can't eat the next character
+            _ => ret += &format!("\\{:03o}", byte),
+        }
+    }
+    ret + "\""
+}
+
+struct CCompiler<'a> {
+    program: &'a mut Program,
+    // Every functi name, functi values are indexes into this
+    names: Vec<String>,
+    // Where calls return to, and functis (for calls to functi values)
+    dispatch: BTreeSet<usize>,
+    out: String,
+}
+
+impl CCompiler<'_> {
+    fn name_id(&mut self, name: &str) -> usize {
+        if let Some(id) = self.names.iter().position(|n| n == name) {
+            return id;
+        }
+        self.names.push(name.to_string());
+        self.names.len() - 1
+    }
+
+    fn err(&mut self, at: usize, msg: &str) -> String {
+        let (line, file, _) = self.program.get_info(at as u32);
+        format!("{}:{}: {} can't be compiled to C", file, line, msg)
+    }
+
+    // Reads a register (the stack is popped)
+    fn get(&self, reg: u8) -> String {
+        match reg {
+            0..=15 => format!("regs[{}]", reg),
+            16 => "sk_pop(&sk_stack)".to_string(),
+            17..=115 => format!("consts[{}]", reg - 17),
+            116..=186 => format!("sk_locals.items[{}]", reg - 116),
+            _ => format!("sk_get_global({})", reg - 187),
+        }
+    }
+
+    // Reads a register without popping
+    fn peek(&self, reg: u8) -> String {
+        if reg == 16 { "*sk_top()".to_string() } else { self.get(reg) }
+    }
+
+    fn set(&self, reg: u8, val: &str) -> String {
+        if reg == 16 {
+            format!("sk_push(&sk_stack, {});", val)
+        } else {
+            format!("regs[{}] = {};", reg, val)
+        }
+    }
+
+    // The right side is popped first
+    fn bin(&self, a: u8, b: u8, c: u8, expr: &str) -> String {
+        format!("{{ Value rhs = {}; Value lhs = {}; {} }}", self.get(b), self.get(a), self.set(c, expr))
+    }
+
+    fn const_functi(&self, reg: u8) -> Option<String> {
+        match self.program.consts.get((reg as usize).wrapping_sub(17)) {
+            Some(Value::Functi(name)) if (17..=115).contains(&reg) => Some(name.to_string()),
+            _ => None,
+        }
+    }
+
+    fn constant(&mut self, at: usize, val: &Value) -> Result<String, String> {
+        Ok(match val {
+            Value::None => "sk_none()".to_string(),
+            Value::Int(i) => format!("sk_int({})", i),
+            Value::Float(f) if f.is_nan() => "sk_float(NAN)".to_string(),
+            Value::Float(f) if f.is_infinite() =>
+                format!("sk_float({}INFINITY)", if *f < 0.0 { "-" } else { "" }),
+            Value::Float(f) => format!("sk_float({:e}f)", f),
+            Value::Bool(b) => format!("sk_bool({})", *b as u8),
+            Value::Byte(b) => format!("sk_byte({})", b),
+            Value::Str(s) => format!("sk_str_n({}, {})", c_str(s), s.len()),
+            Value::Functi(name) => format!("sk_functi({})", self.name_id(name)),
+            val => return Err(self.err(at, &format!("a {} constant", val.get_type()))),
+        })
+    }
+
+    // Calls to a known functi
+    fn static_call(&mut self, at: usize, name: &str, argc: u8) -> String {
+        if let Some(builtin) = builtin(name) {
+            return format!("sk_call_builtin({}, {});", builtin, argc);
+        }
+        let functi = self.program.functis.iter().find(|f| f.0 == name && f.2 == argc as i32);
+        if let Some(functi) = functi {
+            self.dispatch.insert(at + 1);
+            return format!("sk_call({}, regs); goto L{};", at, functi.1);
+        }
+        format!("sk_fail({});", c_str(&format!("incorrect number of arguments for \"{}\"", name)))
+    }
+
+    fn op(&mut self, at: usize) -> Result<String, String> {
+        let op = self.program.ops[at];
+        let (code, a, b, c) = decode_op(op);
+        let target = jump_target(op, at).unwrap_or(0);
+        let imm = b as i8;
+        Ok(match code {
+            NOP | SARG | PGB => String::new(),
+            LD => self.set(c, &format!("consts[{}]", shift2(a, b))),
+            LDL => self.set(16, &format!("consts[{}]", shift3(a, b, c))),
+            CP => self.set(b, &self.peek(a)),
+            POP => "sk_pop(&sk_stack);".to_string(),
+            CALL => {
+                self.dispatch.insert(at + 1);
+                format!("sk_call({}, regs); goto L{};", at, shift3(a, b, c))
+            },
+            VCALL if self.const_functi(a).is_some() => {
+                // Functi constants are known now
+                let name = self.const_functi(a).unwrap();
+                if builtin(&name).is_none() && !self.program.functis.iter().any(|f| f.0 == name) {
+                    return Err(self.err(at, &format!("\"{}\"", name)));
+                }
+                self.static_call(at, &name, b)
+            },
+            VCALL => {
+                self.dispatch.insert(at + 1);
+                format!(
+                    "{{ int to = sk_call_value({}, {}); if (to >= 0) {{ sk_call({}, regs); pc = to; goto dispatch; }} }}",
+                    self.get(a), b, at
+                )
+            },
+            RCALL => format!("goto L{};", shift3(a, b, c)),
+            RET => "pc = sk_ret(regs); goto dispatch;".to_string(),
+            LV_L => self.set(c, &format!("*sk_local({})", shift2(a, b))),
+            LV_G => self.set(c, &format!("sk_get_global({})", shift2(a, b))),
+            SV_L => format!("*sk_local({}) = {};", shift2(a, b), self.get(c)),
+            SV_G => format!("sk_set_global({}, {});", shift2(a, b), self.get(c)),
+            AV_L => format!(
+                "{{ Value val = {}; *sk_local({1}) = sk_add(*sk_local({1}), val); }}",
+                self.get(c), shift2(a, b)
+            ),
+            AV_G => format!(
+                "{{ Value val = {}; sk_set_global({1}, sk_add(sk_get_global({1}), val)); }}",
+                self.get(c), shift2(a, b)
+            ),
+            INC_L | INC_G | DEC_L | DEC_G => {
+                let f = if matches!(code, INC_L | INC_G) { "sk_add" } else { "sk_sub" };
+                let (var, store) = if matches!(code, INC_L | DEC_L) {
+                    (format!("*sk_local({})", shift2(a, b)), format!("*sk_local({}) = var;", shift2(a, b)))
+                } else {
+                    (format!("sk_get_global({})", shift2(a, b)), format!("sk_set_global({}, var);", shift2(a, b)))
+                };
+                format!("{{ Value var = {}({}, sk_int(1)); {} {} }}", f, var, store, self.set(c, "var"))
+            },
+            PLC => format!("sk_push_locals({}, {});", c, shift2(a, b)),
+            ITER => self.set(b, &format!("sk_iter({})", self.get(a))),
+            NXT => format!(
+                "{{ Value val; if (sk_next(&{}, &val)) {{ {} goto L{}; }} }}",
+                self.peek(a), self.set(b, "val"), target
+            ),
+            ADD => self.bin(a, b, c, "sk_add(lhs, rhs)"),
+            SUB => self.bin(a, b, c, "sk_sub(lhs, rhs)"),
+            MUL => self.bin(a, b, c, "sk_mul(lhs, rhs)"),
+            DIV => self.bin(a, b, c, "sk_div(lhs, rhs)"),
+            MOD => self.bin(a, b, c, "sk_mod(lhs, rhs)"),
+            BOR => self.bin(a, b, c, "sk_bor(lhs, rhs)"),
+            BAND => self.bin(a, b, c, "sk_band(lhs, rhs)"),
+            ADDI => self.set(c, &format!("sk_add({}, sk_int({}))", self.get(a), imm)),
+            SUBI => self.set(c, &format!("sk_sub({}, sk_int({}))", self.get(a), imm)),
+            EQ => self.bin(a, b, c, "sk_bool(sk_eq(lhs, rhs))"),
+            LT => self.bin(a, b, c, "sk_bool(sk_cmp(lhs, rhs) < 0)"),
+            GT => self.bin(a, b, c, "sk_bool(sk_cmp(lhs, rhs) > 0)"),
+            GE => self.bin(a, b, c, "sk_bool(sk_ge(lhs, rhs))"),
+            LE => self.bin(a, b, c, "sk_bool(sk_ge(rhs, lhs))"),
+            EQI => self.set(c, &format!("sk_bool(sk_eq({}, sk_int({})))", self.get(a), imm)),
+            LTI => self.set(c, &format!("sk_bool(sk_cmp({}, sk_int({})) < 0)", self.get(a), imm)),
+            GEI => self.set(c, &format!("sk_bool(sk_ge({}, sk_int({})))", self.get(a), imm)),
+            IST => self.set(c, &format!("sk_bool(sk_is_type({}, {}))", self.get(a), b)),
+            AND => self.bin(a, b, c, "sk_bool(sk_truthy(lhs) && sk_truthy(rhs))"),
+            OR => self.bin(a, b, c, "sk_bool(sk_truthy(lhs) || sk_truthy(rhs))"),
+            XOR => self.bin(a, b, c, "sk_bool(sk_truthy(lhs) != sk_truthy(rhs))"),
+            NOT if a == b && a == 16 => "*sk_top() = sk_bool(!sk_truthy(*sk_top()));".to_string(),
+            NOT => self.set(b, &format!("sk_bool(!sk_truthy({}))", self.get(a))),
+            JMP | JMPB => format!("goto L{};", target),
+            JMPNT => format!("if (!sk_truthy({})) goto L{};", self.get(a), target),
+            CARG => return Err(self.err(at, "args()")),
+            DEFER | RDEF => return Err(self.err(at, "defer")),
+            TRY => return Err(self.err(at, "'?'")),
+            ALO => return Err(self.err(at, "a reference")),
+            LFL | LL | INX | SKY | IN => return Err(self.err(at, "a list")),
+        })
+    }
+}
+
+// Compiles a program to C source, errors on anything the runtime doesn't have
+pub fn compile_c(program: &mut Program, args: &Arguments) -> Result<String, String> {
+    if args.strict {
+        return Err("--strict can't be compiled to C".to_string());
+    }
+    let len = program.ops.len();
+    let mut compiler = CCompiler {
+        program, names: vec![], dispatch: BTreeSet::new(), out: String::new(),
+    };
+    // Functis can be called by value
+    let functis: Vec<(String, usize, i32)> = compiler.program.functis.iter()
+        .map(|f| (f.0.clone(), f.1, f.2)).collect();
+    for (name, pos, _) in &functis {
+        compiler.name_id(name);
+        compiler.dispatch.insert(*pos);
+    }
+    // Only jump targets need labels
+    let mut labels: BTreeSet<usize> = (0..len)
+        .filter_map(|at| jump_target(compiler.program.ops[at], at)).collect();
+    labels.extend(functis.iter().map(|f| f.1));
+    labels.insert(0);
+    for at in 0..len {
+        let (code, a, b, c) = decode_op(compiler.program.ops[at]);
+        if matches!(code, CALL | RCALL) {
+            labels.insert(shift3(a, b, c));
+        }
+    }
+    // Consts
+    let mut consts = String::new();
+    for i in 0..compiler.program.consts.len() {
+        let val = compiler.program.consts[i].clone();
+        writeln!(consts, "    consts[{}] = {};", i, compiler.constant(0, &val)?).unwrap();
+    }
+    // Code
+    let mut body = String::new();
+    let mut where_at = None;
+    for at in 0..len {
+        let code = compiler.op(at)?;
+        if labels.contains(&at) || compiler.dispatch.contains(&at) {
+            writeln!(body, "L{}: ;", at).unwrap();
+            where_at = None;
+        }
+        if code.is_empty() {
+            continue;
+        }
+        // Errors need to know where they happened
+        let (line, file, span) = compiler.program.get_info(at as u32);
+        let place = match span {
+            Some((col, _)) => format!("{}:{}:{}", file, line, col),
+            None => format!("{}:{}", file, line),
+        };
+        if where_at.as_ref() != Some(&place) {
+            writeln!(body, "    sk_where = {};", c_str(&place)).unwrap();
+            where_at = Some(place);
+        }
+        writeln!(body, "    {}", code).unwrap();
+    }
+    if labels.contains(&len) {
+        writeln!(body, "L{}: ;", len).unwrap();
+    }
+
+    // Put it together
+    let out = &mut compiler.out;
+    let main = compiler.program.main_file().unwrap_or("<unknown>").to_string();
+    writeln!(out, "/* From: {} */", main).unwrap();
+    writeln!(out, "/* Compiled with Burlap {} */", env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(out, "#define SK_OVERFLOW {}", match args.overflow {
+        Overflow::Wrap => 0,
+        Overflow::Saturate => 1,
+        Overflow::Error => 2,
+    }).unwrap();
+    writeln!(out, "#define SK_MAX_CALL_DEPTH {}", args.max_call_depth).unwrap();
+    writeln!(out, "#define SK_VA_PRINT {}", args.extension_va_print as u8).unwrap();
+    writeln!(out, "#define SK_TOTAL_ORDER {}", args.extension_total_order as u8).unwrap();
+    // C doesn't allow empty arrays
+    let mut names: Vec<String> = compiler.names.iter().map(|n| c_str(n)).collect();
+    names.push("0".to_string());
+    writeln!(out, "static const char *sk_names[] = {{{}}};", names.join(", ")).unwrap();
+    out.push_str(RUNTIME);
+    // Calls to functi values, returns where to jump (or -1 for builtins)
+    writeln!(out, "\nstatic int sk_call_value(Value f, int argc) {{").unwrap();
+    writeln!(out, "    if (f.tag != T_FUNCTI) {{").unwrap();
+    writeln!(out, "        char msg[64];").unwrap();
+    writeln!(out, "        snprintf(msg, sizeof msg, \"cannot call %s\", sk_type(f));").unwrap();
+    writeln!(out, "        sk_fail(msg);").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "    switch (f.fn) {{").unwrap();
+    for (id, name) in compiler.names.iter().enumerate() {
+        if let Some(builtin) = builtin(name) {
+            writeln!(out, "        case {}: sk_call_builtin({}, argc); return -1;", id, builtin).unwrap();
+            continue;
+        }
+        write!(out, "        case {}:", id).unwrap();
+        for (_, pos, argc) in functis.iter().filter(|f| f.0 == *name) {
+            write!(out, " if (argc == {}) return {};", argc, pos).unwrap();
+        }
+        writeln!(
+            out, " sk_fail({});",
+            c_str(&format!("incorrect number of arguments for \"{}\"", name))
+        ).unwrap();
+    }
+    writeln!(out, "        default: sk_fail(\"functi can't be called from C\");").unwrap();
+    writeln!(out, "    }}\n    return -1;\n}}\n").unwrap();
+    writeln!(out, "int main(void) {{").unwrap();
+    writeln!(out, "    static Value consts[{}];", compiler.program.consts.len().max(1)).unwrap();
+    out.push_str(&consts);
+    writeln!(out, "    Value regs[16] = {{0}};").unwrap();
+    writeln!(out, "    int pc = 0;").unwrap();
+    writeln!(out, "    goto L0;").unwrap();
+    writeln!(out, "dispatch:").unwrap();
+    writeln!(out, "    switch (pc) {{").unwrap();
+    for at in &compiler.dispatch {
+        writeln!(out, "        case {0}: goto L{0};", at).unwrap();
+    }
+    writeln!(out, "        default: sk_fail(\"jumped somewhere unknown\");").unwrap();
+    writeln!(out, "    }}").unwrap();
+    out.push_str(&body);
+    writeln!(out, "    fflush(stdout);").unwrap();
+    writeln!(out, "    return 0;").unwrap();
+    writeln!(out, "}}").unwrap();
+    Ok(compiler.out)
+}
@@ -0,0 +1,74 @@
+pub mod c_compiler;
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::common::{print_err, ErrType};
+use crate::{to_ast, Arguments};
+use crate::backend::vm::compiler::{compile, Compiler};
+use c_compiler::compile_c;
+
+// `burlap compile --emit=c file.sk [-o out.c]`
+pub fn compile_cmd(cli_args: impl Iterator<Item = String>, args: &Arguments) -> bool {
+    let mut args = args.clone();
+    let mut file = None;
+    let mut out = None;
+    let mut cli_args = cli_args;
+    while let Some(arg) = cli_args.next() {
+        if arg == "-o" || arg == "--output" {
+            let Some(path) = cli_args.next() else {
+                print_err("'-o' is missing output file", ErrType::Err, args.extension_color);
+                return false;
+            };
+            out = Some(PathBuf::from(path));
+        } else if let Some(emit) = arg.strip_prefix("--emit=") {
+            if emit != "c" {
+                print_err(
+                    &format!("unknown emit: {} (only c is supported)", emit), ErrType::Err,
+                    args.extension_color
+                );
+                return false;
+            }
+        } else if file.is_none() {
+            file = Some(arg);
+        } else {
+            print_err(&format!("unknown argument: {}", arg), ErrType::Warn, args.extension_color);
+        }
+    }
+    let Some(file) = file else {
+        print_err("'compile' is missing a file", ErrType::Err, args.extension_color);
+        return false;
+    };
+    let result = (|| -> Result<PathBuf, String> {
+        args.source = fs::read_to_string(&file)
+            .map_err(|e| format!("failed to open file: {}", e))?;
+        args.path = PathBuf::from(&file);
+        args.name = file.clone();
+        args.is_repl = false;
+        // Compile errors are already printed
+        let mut ast = to_ast(&mut args).ok_or("")?;
+        let mut compiler = Compiler::new();
+        compiler.opt_level = args.opt_level;
+        compiler.program.path = args.path.clone();
+        compiler.program.path.pop();
+        if !compile(&mut ast, &Some(args.name.clone()), &mut compiler, false) {
+            return Err("".to_string());
+        }
+        let source = compile_c(&mut compiler.program, &args)?;
+        let out = out.unwrap_or_else(|| args.path.with_extension("c"));
+        fs::write(&out, source).map_err(|e| format!("failed to write {}: {}", out.display(), e))?;
+        Ok(out)
+    })();
+    match result {
+        Ok(out) => {
+            println!("Compiled {} to {}", file, out.display());
+            true
+        },
+        Err(err) => {
+            if !err.is_empty() {
+                print_err(&err, ErrType::Err, args.extension_color);
+            }
+            false
+        }
+    }
+}
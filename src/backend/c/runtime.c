@@ -0,0 +1,708 @@
+/* Burlap's C runtime, pasted into everything `burlap compile --emit=c` makes
+ * Only the simple types are here (no lists), and strings are never freed */
+// Not every program uses everything
+#pragma GCC diagnostic ignored "-Wunused-function"
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef enum { T_NONE, T_INT, T_FLOAT, T_BOOL, T_BYTE, T_STR, T_FUNCTI, T_RANGE } Tag;
+
+typedef struct {
+    size_t len;
+    char data[];
+} Str;
+
+typedef struct {
+    Tag tag;
+    union {
+        int32_t i;
+        float f;
+        int b;
+        uint8_t byte;
+        Str *s;
+        // Index into sk_names
+        int fn;
+        struct { int32_t at, max, step; } range;
+    };
+} Value;
+
+typedef struct {
+    Value *items;
+    size_t len, cap;
+} Vec;
+
+typedef struct {
+    int ret;
+    Value regs[16];
+    size_t local_size;
+} Frame;
+
+static Vec sk_stack, sk_locals, sk_globals;
+static Frame *sk_frames;
+static size_t sk_frames_len, sk_frames_cap;
+// Where the running code came from, for errors
+static const char *sk_where = "";
+
+static _Noreturn void sk_fail(const char *msg) {
+    fflush(stdout);
+    printf("Runtime Error at %s: %s\n", sk_where, msg);
+    exit(2);
+}
+
+static void *sk_grow(void *items, size_t *cap, size_t size) {
+    *cap = *cap ? *cap * 2 : 16;
+    items = realloc(items, *cap * size);
+    if (!items) {
+        sk_fail("out of memory");
+    }
+    return items;
+}
+
+// Values
+static Value sk_none(void) { Value v = {T_NONE, {0}}; return v; }
+static Value sk_int(int32_t i) { Value v = {T_INT, {0}}; v.i = i; return v; }
+static Value sk_float(float f) { Value v = {T_FLOAT, {0}}; v.f = f; return v; }
+static Value sk_bool(int b) { Value v = {T_BOOL, {0}}; v.b = !!b; return v; }
+static Value sk_byte(uint8_t b) { Value v = {T_BYTE, {0}}; v.byte = b; return v; }
+static Value sk_functi(int fn) { Value v = {T_FUNCTI, {0}}; v.fn = fn; return v; }
+
+static Value sk_str_n(const char *s, size_t len) {
+    Str *str = malloc(sizeof(Str) + len + 1);
+    if (!str) {
+        sk_fail("out of memory");
+    }
+    str->len = len;
+    memcpy(str->data, s, len);
+    str->data[len] = 0;
+    Value v = {T_STR, {0}};
+    v.s = str;
+    return v;
+}
+
+static Value sk_str(const char *s) { return sk_str_n(s, strlen(s)); }
+
+// The stack, locals, and globals
+static void sk_push(Vec *vec, Value v) {
+    if (vec->len == vec->cap) {
+        vec->items = sk_grow(vec->items, &vec->cap, sizeof(Value));
+    }
+    vec->items[vec->len++] = v;
+}
+
+static Value sk_pop(Vec *vec) {
+    if (!vec->len) {
+        sk_fail("overpopped stack");
+    }
+    return vec->items[--vec->len];
+}
+
+static Value *sk_top(void) {
+    if (!sk_stack.len) {
+        sk_fail("overpopped stack");
+    }
+    return &sk_stack.items[sk_stack.len - 1];
+}
+
+// Locals are counted from the top
+static Value *sk_local(int offset) {
+    return &sk_locals.items[sk_locals.len - 1 - offset];
+}
+
+// Args are moved from the stack, then the rest are none
+static void sk_push_locals(int args, int amount) {
+    for (int i = 0; i < args; i++) {
+        sk_push(&sk_locals, sk_pop(&sk_stack));
+    }
+    for (int i = 0; i < amount; i++) {
+        sk_push(&sk_locals, sk_none());
+    }
+}
+
+static Value sk_get_global(size_t offset) {
+    return offset < sk_globals.len ? sk_globals.items[offset] : sk_none();
+}
+
+static void sk_set_global(size_t offset, Value v) {
+    while (sk_globals.len <= offset) {
+        sk_push(&sk_globals, sk_none());
+    }
+    sk_globals.items[offset] = v;
+}
+
+// Calls
+static void sk_call(int ret, Value *regs) {
+    if (SK_MAX_CALL_DEPTH && sk_frames_len >= SK_MAX_CALL_DEPTH) {
+        char msg[64];
+        snprintf(msg, sizeof msg, "maximum recursion depth exceeded (%zu calls)", sk_frames_len);
+        sk_fail(msg);
+    }
+    if (sk_frames_len == sk_frames_cap) {
+        sk_frames = sk_grow(sk_frames, &sk_frames_cap, sizeof(Frame));
+    }
+    Frame *frame = &sk_frames[sk_frames_len++];
+    frame->ret = ret;
+    frame->local_size = sk_locals.len;
+    memcpy(frame->regs, regs, sizeof(frame->regs));
+    for (int i = 0; i < 16; i++) {
+        regs[i] = sk_none();
+    }
+}
+
+// Returns where to go next
+static int sk_ret(Value *regs) {
+    if (!sk_frames_len) {
+        sk_fail("return outside of a functi");
+    }
+    Frame *frame = &sk_frames[--sk_frames_len];
+    memcpy(regs, frame->regs, sizeof(frame->regs));
+    sk_locals.len = frame->local_size;
+    return frame->ret + 1;
+}
+
+// Strings
+static const char *sk_type(Value v) {
+    switch (v.tag) {
+        case T_INT: return "Number";
+        case T_FLOAT: return "Decimal";
+        case T_BOOL: return "Bool";
+        case T_BYTE: return "Byte";
+        case T_STR: return "String";
+        case T_FUNCTI: return "Functi";
+        case T_RANGE: return "__burlap_rangetype";
+        default: return "None";
+    }
+}
+
+// Like format_float, the shortest digits that read back the same
+static void sk_fmt_float(float f, char *out) {
+    if (isnan(f)) {
+        strcpy(out, "nan");
+        return;
+    } else if (isinf(f)) {
+        strcpy(out, f < 0 ? "-inf" : "inf");
+        return;
+    } else if (f == 0) {
+        strcpy(out, signbit(f) ? "-0.0" : "0.0");
+        return;
+    }
+    char buf[32];
+    for (int p = 0; p < 9; p++) {
+        snprintf(buf, sizeof buf, "%.*e", p, f);
+        if (strtof(buf, NULL) == f) {
+            break;
+        }
+    }
+    // buf is [-]d[.ddd]e[+-]dd
+    char digits[16] = {0};
+    int n = 0;
+    const char *c = buf;
+    if (*c == '-') {
+        *out++ = '-';
+        c++;
+    }
+    for (; *c != 'e'; c++) {
+        if (*c != '.') {
+            digits[n++] = *c;
+        }
+    }
+    // Never with an exponent, out needs room for 48 chars
+    int exp = atoi(c + 1);
+    if (exp >= 0) {
+        for (int i = 0; i <= exp; i++) {
+            *out++ = i < n ? digits[i] : '0';
+        }
+        *out++ = '.';
+        if (n > exp + 1) {
+            memcpy(out, digits + exp + 1, n - exp - 1);
+            out += n - exp - 1;
+        } else {
+            *out++ = '0';
+        }
+    } else {
+        *out++ = '0';
+        *out++ = '.';
+        for (int i = 0; i < -exp - 1; i++) {
+            *out++ = '0';
+        }
+        memcpy(out, digits, n);
+        out += n;
+    }
+    *out = 0;
+}
+
+static Str *sk_to_str(Value v) {
+    char buf[64];
+    switch (v.tag) {
+        case T_STR: return v.s;
+        case T_INT: snprintf(buf, sizeof buf, "%d", v.i); break;
+        case T_FLOAT: sk_fmt_float(v.f, buf); break;
+        case T_BOOL: strcpy(buf, v.b ? "true" : "false"); break;
+        case T_BYTE:
+            strcpy(buf, "0b");
+            for (int bit = 7; bit >= 0; bit--) {
+                buf[9 - bit] = (v.byte >> bit) & 1 ? '1' : '0';
+            }
+            buf[10] = 0;
+            break;
+        case T_FUNCTI: {
+            const char *name = sk_names[v.fn];
+            Str *str = malloc(sizeof(Str) + strlen(name) + 9);
+            if (!str) {
+                sk_fail("out of memory");
+            }
+            str->len = sprintf(str->data, "Functi(%s)", name);
+            return str;
+        }
+        case T_RANGE: strcpy(buf, "<__burlap_rangetype>"); break;
+        default: strcpy(buf, "none");
+    }
+    return sk_str(buf).s;
+}
+
+// Short and quoted, for errors
+static const char *sk_repr(Value v) {
+    static char bufs[2][160];
+    static int which = 0;
+    char *buf = bufs[which ^= 1];
+    Str *str = sk_to_str(v);
+    // Strings are quoted and escaped
+    char *repr = malloc(str->len * 4 + 3);
+    if (!repr) {
+        sk_fail("out of memory");
+    }
+    size_t len = 0;
+    if (v.tag == T_STR) {
+        repr[len++] = '"';
+    }
+    for (size_t i = 0; i < str->len; i++) {
+        unsigned char c = str->data[i];
+        const char *esc = c == '\n' ? "\\n" : c == '\t' ? "\\t" : c == '\r' ? "\\r"
+            : c == '\\' ? "\\\\" : c == '"' ? "\\\"" : NULL;
+        if (v.tag != T_STR) {
+            repr[len++] = c;
+        } else if (esc) {
+            memcpy(repr + len, esc, 2);
+            len += 2;
+        } else if (c < 0x20 || c == 0x7f) {
+            len += sprintf(repr + len, "\\x%02x", c);
+        } else {
+            repr[len++] = c;
+        }
+    }
+    if (v.tag == T_STR) {
+        repr[len++] = '"';
+    }
+    // Over 40 characters is cut to 37 and ...
+    size_t chars = 0, cut = len;
+    for (size_t i = 0; i < len; i++) {
+        // Continuation bytes aren't new characters
+        if ((repr[i] & 0xC0) != 0x80 && chars++ == 37) {
+            cut = i;
+        }
+    }
+    if (chars <= 40) {
+        cut = len;
+    }
+    memcpy(buf, repr, cut);
+    strcpy(buf + cut, chars > 40 ? "..." : "");
+    free(repr);
+    return buf;
+}
+
+static void sk_op_err(const char *verb, const char *op, Value l, Value r) {
+    char msg[256];
+    snprintf(
+        msg, sizeof msg, "Cannot %s %s and %s: %s %s %s", verb, sk_type(l), sk_type(r),
+        sk_repr(l), op, sk_repr(r)
+    );
+    sk_fail(msg);
+}
+
+// Conversions
+static int sk_truthy(Value v) {
+    switch (v.tag) {
+        case T_INT: return v.i != 0;
+        case T_FLOAT: return v.f != 0;
+        case T_BOOL: return v.b;
+        case T_STR: return v.s->len != 0;
+        default: return 0;
+    }
+}
+
+static int32_t sk_to_int(Value v) {
+    switch (v.tag) {
+        case T_INT: return v.i;
+        case T_FLOAT: return isnan(v.f) ? 0 : v.f >= 2147483647.0f ? INT32_MAX
+            : v.f <= -2147483648.0f ? INT32_MIN : (int32_t)v.f;
+        case T_BOOL: return v.b;
+        case T_BYTE: return v.byte;
+        case T_STR: {
+            // Like Rust's parse, a sign and digits, 0 if it doesn't fit
+            const char *c = v.s->data;
+            int neg = *c == '-';
+            if (*c == '-' || *c == '+') {
+                c++;
+            }
+            if (!*c) {
+                return 0;
+            }
+            int64_t num = 0;
+            for (; *c; c++) {
+                if (*c < '0' || *c > '9' || (num = num * 10 + (*c - '0')) > 2147483648LL) {
+                    return 0;
+                }
+            }
+            num = neg ? -num : num;
+            return num > INT32_MAX ? 0 : (int32_t)num;
+        }
+        default: return 0;
+    }
+}
+
+static float sk_to_float(Value v) {
+    switch (v.tag) {
+        case T_INT: return (float)v.i;
+        case T_FLOAT: return v.f;
+        case T_BOOL: return v.b;
+        case T_BYTE: return v.byte;
+        case T_STR: {
+            char *end;
+            float f = strtof(v.s->data, &end);
+            return v.s->len && end == v.s->data + v.s->len ? f : 0;
+        }
+        default: return 0;
+    }
+}
+
+// Math
+static Value sk_int_math(char op, int32_t l, int32_t r) {
+    int32_t out;
+    int over = op == '+' ? __builtin_add_overflow(l, r, &out)
+        : op == '-' ? __builtin_sub_overflow(l, r, &out)
+        : __builtin_mul_overflow(l, r, &out);
+    if (!over || SK_OVERFLOW == 0) {
+        // The builtins wrap
+        return sk_int(out);
+    } else if (SK_OVERFLOW == 1) {
+        int big = op == '+' ? r > 0 : op == '-' ? r < 0 : (l < 0) == (r < 0);
+        return sk_int(big ? INT32_MAX : INT32_MIN);
+    }
+    char msg[96];
+    snprintf(
+        msg, sizeof msg, "Integer overflow when trying to %s %d and %d",
+        op == '+' ? "add" : op == '-' ? "subtract" : "multiply", l, r
+    );
+    sk_fail(msg);
+    return sk_none();
+}
+
+static int sk_is_num(Value v) { return v.tag == T_INT || v.tag == T_FLOAT; }
+
+// The same order as TYPE_CHECKS, there aren't lists here
+static int sk_is_type(Value v, int type) {
+    switch (type) {
+        case 0: return sk_is_num(v);
+        case 1: return v.tag == T_STR;
+        case 3: return v.tag == T_BOOL;
+        case 4: return v.tag == T_NONE;
+        case 5: return v.tag == T_FUNCTI;
+        default: return 0;
+    }
+}
+
+static Value sk_add(Value l, Value r) {
+    if (l.tag == T_STR || r.tag == T_STR) {
+        Str *ls = sk_to_str(l), *rs = sk_to_str(r);
+        Value v = sk_str_n(ls->data, ls->len + rs->len);
+        memcpy(v.s->data + ls->len, rs->data, rs->len);
+        return v;
+    } else if (l.tag == T_INT && r.tag == T_INT) {
+        return sk_int_math('+', l.i, r.i);
+    } else if (sk_is_num(l) && sk_is_num(r)) {
+        return sk_float(sk_to_float(l) + sk_to_float(r));
+    }
+    sk_op_err("add", "+", l, r);
+    return sk_none();
+}
+
+static Value sk_sub(Value l, Value r) {
+    if (l.tag == T_INT && r.tag == T_INT) {
+        return sk_int_math('-', l.i, r.i);
+    } else if (sk_is_num(l) && sk_is_num(r)) {
+        return sk_float(sk_to_float(l) - sk_to_float(r));
+    }
+    sk_op_err("subtract", "-", l, r);
+    return sk_none();
+}
+
+static Value sk_mul(Value l, Value r) {
+    if (l.tag == T_STR && r.tag == T_INT) {
+        size_t times = r.i > 0 ? (size_t)r.i : 0;
+        Value v = sk_str_n("", 0);
+        v.s = realloc(v.s, sizeof(Str) + l.s->len * times + 1);
+        if (!v.s) {
+            sk_fail("out of memory");
+        }
+        for (size_t i = 0; i < times; i++) {
+            memcpy(v.s->data + i * l.s->len, l.s->data, l.s->len);
+        }
+        v.s->len = l.s->len * times;
+        v.s->data[v.s->len] = 0;
+        return v;
+    } else if (l.tag == T_INT && r.tag == T_INT) {
+        return sk_int_math('*', l.i, r.i);
+    } else if (sk_is_num(l) && sk_is_num(r)) {
+        return sk_float(sk_to_float(l) * sk_to_float(r));
+    }
+    sk_op_err("multiply", "*", l, r);
+    return sk_none();
+}
+
+static Value sk_div(Value l, Value r) {
+    if (sk_is_num(l) && sk_is_num(r)) {
+        return sk_float(sk_to_float(l) / sk_to_float(r));
+    }
+    sk_op_err("divide", "/", l, r);
+    return sk_none();
+}
+
+static Value sk_mod(Value l, Value r) {
+    if (l.tag == T_INT && r.tag == T_INT) {
+        if (r.i == 0) {
+            char msg[64];
+            snprintf(msg, sizeof msg, "Cannot modulo %d by zero", l.i);
+            sk_fail(msg);
+        }
+        return sk_int(r.i == -1 ? 0 : l.i % r.i);
+    } else if (sk_is_num(l) && sk_is_num(r)) {
+        return sk_float(fmodf(sk_to_float(l), sk_to_float(r)));
+    }
+    sk_op_err("modulo", "%", l, r);
+    return sk_none();
+}
+
+static Value sk_bor(Value l, Value r) {
+    if (l.tag == T_INT && r.tag == T_INT) {
+        return sk_int(l.i | r.i);
+    } else if (l.tag == T_BYTE && r.tag == T_BYTE) {
+        return sk_byte(l.byte | r.byte);
+    }
+    sk_op_err("or", "|", l, r);
+    return sk_none();
+}
+
+static Value sk_band(Value l, Value r) {
+    if (l.tag == T_INT && r.tag == T_INT) {
+        return sk_int(l.i & r.i);
+    } else if (l.tag == T_BYTE && r.tag == T_BYTE) {
+        return sk_byte(l.byte & r.byte);
+    }
+    sk_op_err("and", "&", l, r);
+    return sk_none();
+}
+
+// Comparison
+static int sk_eq(Value l, Value r) {
+    if (sk_is_num(l) && sk_is_num(r)) {
+        if (l.tag == T_INT && r.tag == T_INT) {
+            return l.i == r.i;
+        }
+        return sk_to_float(l) == sk_to_float(r);
+    } else if (l.tag != r.tag) {
+        return 0;
+    }
+    switch (l.tag) {
+        case T_NONE: return 1;
+        case T_BOOL: return l.b == r.b;
+        case T_BYTE: return l.byte == r.byte;
+        case T_STR: return l.s->len == r.s->len && !memcmp(l.s->data, r.s->data, l.s->len);
+        case T_FUNCTI: return l.fn == r.fn;
+        default: return 0;
+    }
+}
+
+// Rank of the type in the total order
+static int sk_rank(Value v) {
+    switch (v.tag) {
+        case T_NONE: return 0;
+        case T_BOOL: return 1;
+        case T_INT: case T_FLOAT: case T_BYTE: return 2;
+        case T_STR: return 3;
+        default: return 7;
+    }
+}
+
+// -1, 0, or 1
+static int sk_cmp(Value l, Value r) {
+    if (!SK_TOTAL_ORDER) {
+        float lf = sk_to_float(l), rf = sk_to_float(r);
+        // Only < and > are asked for, so NaN is neither
+        return lf < rf ? -1 : lf > rf ? 1 : 0;
+    }
+    int rank = sk_rank(l) - sk_rank(r);
+    if (rank) {
+        return rank < 0 ? -1 : 1;
+    } else if (l.tag == T_BOOL) {
+        return l.b - r.b;
+    } else if (l.tag == T_INT && r.tag == T_INT) {
+        return (l.i > r.i) - (l.i < r.i);
+    } else if (l.tag == T_STR) {
+        size_t len = l.s->len < r.s->len ? l.s->len : r.s->len;
+        int ord = memcmp(l.s->data, r.s->data, len);
+        return ord ? (ord < 0 ? -1 : 1) : (l.s->len > r.s->len) - (l.s->len < r.s->len);
+    } else if (sk_rank(l) == 2) {
+        // NaN is bigger than everything
+        float lf = sk_to_float(l), rf = sk_to_float(r);
+        if (isnan(lf) || isnan(rf)) {
+            return isnan(lf) - isnan(rf);
+        }
+        return (lf > rf) - (lf < rf);
+    }
+    return 0;
+}
+
+static int sk_ge(Value l, Value r) {
+    if (!SK_TOTAL_ORDER) {
+        // Not sk_cmp(l, r) >= 0, NaN is neither
+        return sk_to_float(l) >= sk_to_float(r);
+    }
+    return sk_cmp(l, r) >= 0;
+}
+
+// Iterators
+static Value sk_iter(Value v) {
+    if (v.tag != T_RANGE) {
+        char msg[96];
+        snprintf(msg, sizeof msg, "Cannot iterate over %s (only ranges are compiled to C)", sk_type(v));
+        sk_fail(msg);
+    }
+    return v;
+}
+
+static int sk_next(Value *iter, Value *out) {
+    if (iter->tag != T_RANGE) {
+        sk_fail("Require __burlap_rangetype");
+    } else if (iter->range.step == 0) {
+        return 0;
+    }
+    *out = sk_int(iter->range.at);
+    if (iter->range.at == iter->range.max) {
+        // Final part
+        iter->range.step = 0;
+    } else {
+        iter->range.at += iter->range.step;
+    }
+    return 1;
+}
+
+// Builtins, they take their args and return the result
+static void sk_bad_args(const char *name, int got, int need) {
+    char msg[96];
+    snprintf(
+        msg, sizeof msg, "too %s args for %s (got %d need %d)",
+        got > need ? "many" : "few", name, got, need
+    );
+    sk_fail(msg);
+}
+
+static Value sk_bi_print(Value *args, int n) {
+    if (SK_VA_PRINT) {
+        for (int i = 0; i < n; i++) {
+            Str *str = sk_to_str(args[i]);
+            fwrite(str->data, 1, str->len, stdout);
+            putchar(' ');
+        }
+    } else if (n != 1) {
+        sk_bad_args("print", n, 1);
+    } else {
+        Str *str = sk_to_str(args[0]);
+        fwrite(str->data, 1, str->len, stdout);
+    }
+    putchar('\n');
+    return sk_none();
+}
+
+static Value sk_bi_string(Value *args, int n) {
+    if (n != 1) {
+        sk_bad_args("string", n, 1);
+    }
+    if (args[0].tag == T_BYTE) {
+        // Bytes are a special case
+        char c = (char)args[0].byte;
+        return sk_str_n(&c, 1);
+    }
+    Value v = {T_STR, {0}};
+    v.s = sk_to_str(args[0]);
+    return v;
+}
+
+static Value sk_bi_int(Value *args, int n) {
+    if (n != 1) {
+        sk_bad_args("int", n, 1);
+    }
+    return sk_int(sk_to_int(args[0]));
+}
+
+static Value sk_bi_float(Value *args, int n) {
+    if (n != 1) {
+        sk_bad_args("float", n, 1);
+    }
+    return sk_float(sk_to_float(args[0]));
+}
+
+static Value sk_bi_type(Value *args, int n) {
+    if (n != 1) {
+        sk_bad_args("type", n, 1);
+    }
+    return sk_str(sk_type(args[0]));
+}
+
+static Value sk_bi_abs(Value *args, int n) {
+    if (n != 1) {
+        sk_bad_args("abs", n, 1);
+    }
+    switch (args[0].tag) {
+        case T_INT:
+            if (args[0].i == INT32_MIN) {
+                sk_fail("abs() overflowed");
+            }
+            return sk_int(args[0].i < 0 ? -args[0].i : args[0].i);
+        case T_FLOAT: return sk_float(fabsf(args[0].f));
+        case T_BYTE: return args[0];
+        default: {
+            char msg[64];
+            snprintf(msg, sizeof msg, "abs() only works on numbers, not %s", sk_type(args[0]));
+            sk_fail(msg);
+            return sk_none();
+        }
+    }
+}
+
+static Value sk_bi_exit(Value *args, int n) {
+    if (n > 1) {
+        sk_bad_args("exit", n, 1);
+    }
+    fflush(stdout);
+    exit(n ? sk_to_int(args[0]) : 0);
+}
+
+static Value sk_bi_range(Value *args, int n) {
+    if (n != 2) {
+        sk_bad_args("__burlap_range", n, 2);
+    }
+    Value v = {T_RANGE, {0}};
+    v.range.at = sk_to_int(args[0]);
+    v.range.max = sk_to_int(args[1]);
+    v.range.step = v.range.max > v.range.at ? 1 : -1;
+    return v;
+}
+
+// Calls a builtin with the args on the stack, and pushes the result
+static void sk_call_builtin(Value (*builtin)(Value *, int), int n) {
+    Value ret = builtin(sk_stack.items + sk_stack.len - n, n);
+    sk_stack.len -= n;
+    sk_push(&sk_stack, ret);
+}
@@ -1,2 +1,5 @@
 pub mod vm;
 pub mod value;
+pub mod tree;
+#[cfg(not(target_family = "wasm"))]
+pub mod c;
@@ -0,0 +1,360 @@
+// A tree walking evaluator, the reference that `burlap difftest` checks the VM against
+// It's slow and only knows the basics, but it's simple enough to trust
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::Arguments;
+use crate::lexer::TokenType;
+use crate::parser::{ASTNode, FunctiNode, StmtNode, AST};
+use crate::backend::value::{int_math, Value};
+use crate::backend::vm::compiler::Program;
+use crate::backend::vm::vm::{set_key, Vm};
+
+// How a statement ended
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+pub struct Evaluator<'a> {
+    args: Arguments,
+    // Functis by name and number of args, with the arg names
+    functis: FxHashMap<(String, usize), (&'a FunctiNode, Vec<String>)>,
+    globals: FxHashMap<String, Value>,
+    // The locals of each running functi, innermost last
+    frames: Vec<FxHashMap<String, Value>>,
+    // Builtins are run by a VM with an empty program
+    builtins: Vm,
+    // Statements left before it gives up
+    pub fuel: u64,
+}
+
+// Vars are named "file::name"
+fn unmangle(name: &str) -> &str {
+    name.split("::").nth(1).unwrap_or(name)
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(args: Arguments, ast: &'a AST) -> Evaluator<'a> {
+        let mut eval = Evaluator {
+            args: args.clone(), functis: FxHashMap::default(),
+            globals: FxHashMap::default(), frames: vec![],
+            builtins: Vm::new(args, Program::new()), fuel: u64::MAX,
+        };
+        for node in &ast.nodes {
+            eval.find_functis(ast, &node.node);
+        }
+        return eval;
+    }
+
+    // Functis can be called before they're declared, so find them all first
+    fn find_functis(&mut self, ast: &AST, node: &'a ASTNode) {
+        match node {
+            ASTNode::FunctiStmt(functi) => {
+                // Args are the last locals, like in the compiler
+                if let Some(data) = ast.get_functi(functi.name.clone()) {
+                    let names = data.locals[data.locals.len() - data.arg_num as usize..]
+                        .iter().map(|v| v.real_name.clone()).collect();
+                    self.functis.insert(
+                        (functi.name.clone(), data.arg_num as usize), (functi, names)
+                    );
+                }
+                self.find_functis(ast, &functi.body.node);
+            },
+            ASTNode::BodyStmt(nodes) => {
+                for node in nodes {
+                    self.find_functis(ast, &node.node);
+                }
+            },
+            ASTNode::IfStmt(_, body, else_part) => {
+                self.find_functis(ast, &body.node);
+                self.find_functis(ast, &else_part.node);
+            },
+            ASTNode::LoopStmt(body) | ASTNode::IterLoopStmt(_, _, body, _)
+                | ASTNode::WhileStmt(_, body) => self.find_functis(ast, &body.node),
+            _ => {},
+        }
+    }
+
+    pub fn run(&mut self, nodes: &[StmtNode]) -> Result<(), String> {
+        for node in nodes {
+            if let Flow::Return(_) = self.stmt(&node.node)? {
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    fn get_var(&self, name: &str) -> Value {
+        let name = unmangle(name);
+        if let Some(val) = self.frames.last().and_then(|frame| frame.get(name)) {
+            return val.clone();
+        }
+        if let Some(val) = self.globals.get(name) {
+            return val.clone();
+        }
+        // It might be a functi
+        if self.functis.keys().any(|(n, _)| n == name) || self.builtins.has_functie(name) {
+            return Value::Functi(Rc::new(name.to_string()));
+        }
+        return Value::None;
+    }
+
+    fn set_var(&mut self, name: &str, val: Value) {
+        let name = unmangle(name).to_string();
+        match self.frames.last_mut() {
+            // Functis can set globals, but only ones that aren't shadowed
+            Some(frame) if frame.contains_key(&name) || !self.globals.contains_key(&name) => {
+                frame.insert(name, val);
+            },
+            _ => {
+                self.globals.insert(name, val);
+            },
+        }
+    }
+
+    // Sets a var or an index of one (`x[1][2] = v`)
+    fn set(&mut self, lvalue: &ASTNode, val: Value) -> Result<(), String> {
+        match lvalue {
+            ASTNode::VarExpr(name) => {
+                self.set_var(name, val);
+                Ok(())
+            },
+            ASTNode::IndexExpr(list, index, _) => {
+                let index = self.expr(index)?;
+                let mut inner = self.expr(list)?;
+                set_key(&mut inner, index, val)?;
+                self.set(list, inner)
+            },
+            _ => Err("can't assign to that".to_string()),
+        }
+    }
+
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        if let Some((functi, names)) = self.functis.get(&(name.to_string(), args.len())).cloned() {
+            if self.frames.len() >= self.args.max_call_depth.max(1) {
+                return Err("maximum recursion depth exceeded".to_string());
+            }
+            self.frames.push(names.into_iter().zip(args).collect());
+            let ret = self.stmt(&functi.body.node);
+            self.frames.pop();
+            return Ok(match ret? {
+                Flow::Return(val) => val,
+                _ => Value::None,
+            });
+        }
+        match self.builtins.call_functie(name, args) {
+            Some(ret) => ret,
+            None => Err(format!("no function called \"{}\"", name)),
+        }
+    }
+
+    fn stmt(&mut self, node: &ASTNode) -> Result<Flow, String> {
+        self.fuel = self.fuel.checked_sub(1).ok_or("out of fuel")?;
+        match node {
+            ASTNode::LetStmt(names, vals) => {
+                for (name, val) in names.iter().zip(vals) {
+                    let val = self.expr(val)?;
+                    // Lets in functis are always locals
+                    match self.frames.last_mut() {
+                        Some(frame) => frame.insert(unmangle(name).to_string(), val),
+                        None => self.globals.insert(unmangle(name).to_string(), val),
+                    };
+                }
+            },
+            ASTNode::IfStmt(cond, body, else_part) => {
+                return if self.expr(cond)?.is_truthy() {
+                    self.stmt(&body.node)
+                } else {
+                    self.stmt(&else_part.node)
+                };
+            },
+            ASTNode::LoopStmt(body) => loop {
+                match self.stmt(&body.node)? {
+                    Flow::Break => break,
+                    ret @ Flow::Return(_) => return Ok(ret),
+                    _ => {},
+                }
+            },
+            ASTNode::IterLoopStmt(var, iter, body, _) => {
+                let mut iter = self.expr(iter)?.to_iter()?;
+                while let Some(val) = iter.iter_next()? {
+                    self.set_var(var, val);
+                    match self.stmt(&body.node)? {
+                        Flow::Break => break,
+                        ret @ Flow::Return(_) => return Ok(ret),
+                        _ => {},
+                    }
+                }
+            },
+            ASTNode::WhileStmt(cond, body) => {
+                while self.expr(cond)?.is_truthy() {
+                    match self.stmt(&body.node)? {
+                        Flow::Break => break,
+                        ret @ Flow::Return(_) => return Ok(ret),
+                        _ => {},
+                    }
+                }
+            },
+            ASTNode::BreakStmt => return Ok(Flow::Break),
+            ASTNode::ContinueStmt => return Ok(Flow::Continue),
+            ASTNode::ReturnStmt(val) => return Ok(Flow::Return(self.expr(val)?)),
+            ASTNode::BodyStmt(nodes) => {
+                for node in nodes {
+                    let flow = self.stmt(&node.node)?;
+                    if !matches!(flow, Flow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+            },
+            // Found before running
+            ASTNode::FunctiStmt(_) => {},
+            ASTNode::ImportStmt(_) | ASTNode::EndImportStmt(_) | ASTNode::Nop => {},
+            ASTNode::DeferStmt(_) => return Err("defer isn't supported".to_string()),
+            expr => {
+                self.expr(expr)?;
+            },
+        }
+        return Ok(Flow::Normal);
+    }
+
+    fn expr(&mut self, node: &ASTNode) -> Result<Value, String> {
+        Ok(match node {
+            ASTNode::StringExpr(s) => Value::Str(Rc::new(s.clone())),
+            ASTNode::NumberExpr(n) => Value::Int(*n),
+            ASTNode::DecimalExpr(f) => Value::Float(*f),
+            ASTNode::BoolExpr(b) => Value::Bool(*b),
+            ASTNode::NoneExpr => Value::None,
+            ASTNode::ByteExpr(b) => Value::Byte(*b),
+            ASTNode::VarExpr(name) => self.get_var(name),
+            ASTNode::CallExpr(callee, args, _) => {
+                let args = args.iter().map(|arg| self.expr(arg)).collect::<Result<Vec<_>, _>>()?;
+                let name = match &**callee {
+                    ASTNode::VarExpr(name) => unmangle(name).to_string(),
+                    callee => match self.expr(callee)? {
+                        Value::Functi(name) => (*name).clone(),
+                        val => return Err(format!("can't call {}", val.get_type())),
+                    },
+                };
+                self.call(&name, args)?
+            },
+            ASTNode::IndexExpr(list, index, _) => {
+                let index = self.expr(index)?;
+                self.expr(list)?.index(&index).unwrap_or(Value::None)
+            },
+            ASTNode::UnaryExpr(op, val) => match op {
+                TokenType::Minus => {
+                    let val = self.expr(val)?;
+                    self.binop(&Value::Int(0), &TokenType::Minus, val)?
+                },
+                TokenType::Not => Value::Bool(!self.expr(val)?.is_truthy()),
+                TokenType::PlusPlus | TokenType::MinusMinus => {
+                    let op = if op == &TokenType::PlusPlus { TokenType::Plus } else { TokenType::Minus };
+                    let old = self.expr(val)?;
+                    let ret = self.binop(&old, &op, Value::Int(1))?;
+                    self.set(val, ret.clone())?;
+                    ret
+                },
+                _ => return Err(format!("unknown unary op {:?}", op)),
+            },
+            ASTNode::BinopExpr(lhs, op, rhs, _) => match op {
+                // Short circuiting, the result is the last side that ran
+                TokenType::And | TokenType::Or => {
+                    let lhs = self.expr(lhs)?;
+                    if lhs.is_truthy() == (op == &TokenType::And) {
+                        self.expr(rhs)?
+                    } else {
+                        lhs
+                    }
+                },
+                TokenType::Equals => {
+                    let val = self.expr(rhs)?;
+                    self.set(lhs, val.clone())?;
+                    val
+                },
+                TokenType::PlusEquals | TokenType::MinusEquals | TokenType::TimesEquals
+                    | TokenType::DivEquals | TokenType::ModEquals => {
+                    let op = match op {
+                        TokenType::PlusEquals => TokenType::Plus,
+                        TokenType::MinusEquals => TokenType::Minus,
+                        TokenType::TimesEquals => TokenType::Times,
+                        TokenType::DivEquals => TokenType::Div,
+                        _ => TokenType::Modulo,
+                    };
+                    let (left, right) = (self.expr(lhs)?, self.expr(rhs)?);
+                    let val = self.binop(&left, &op, right)?;
+                    self.set(lhs, val.clone())?;
+                    val
+                },
+                // Like the VM, the list is first
+                TokenType::In => {
+                    let right = self.expr(rhs)?;
+                    let left = self.expr(lhs)?;
+                    self.binop(&left, op, right)?
+                },
+                _ => {
+                    let (left, right) = (self.expr(lhs)?, self.expr(rhs)?);
+                    self.binop(&left, op, right)?
+                },
+            },
+            ASTNode::ListExpr(keys, vals, fast) => {
+                // Values that are none are left out, like the VM
+                let mut items = vec![];
+                for at in 0..vals.len() {
+                    let val = self.expr(&vals[at])?;
+                    let key = if *fast { Value::None } else { self.expr(&keys[at])? };
+                    if !matches!(val, Value::None) {
+                        items.push((key, val));
+                    }
+                }
+                if *fast {
+                    Value::FastList(Rc::new(items.into_iter().map(|(_, val)| val).collect()))
+                } else {
+                    Value::List(Rc::new(items))
+                }
+            },
+            ASTNode::BlockExpr(stmts, val) => {
+                for stmt in stmts {
+                    self.stmt(&stmt.node)?;
+                }
+                self.expr(val)?
+            },
+            _ => return Err("that isn't supported".to_string()),
+        })
+    }
+
+    // The same as the ops in the VM
+    #[allow(clippy::neg_cmp_op_on_partial_ord)]
+    fn binop(&self, lhs: &Value, op: &TokenType, rhs: Value) -> Result<Value, String> {
+        let overflow = self.args.overflow;
+        Ok(match (op, lhs, &rhs) {
+            (TokenType::Plus, Value::Int(l), Value::Int(r)) => int_math('+', *l, *r, overflow)?,
+            (TokenType::Minus, Value::Int(l), Value::Int(r)) => int_math('-', *l, *r, overflow)?,
+            (TokenType::Times, Value::Int(l), Value::Int(r)) => int_math('*', *l, *r, overflow)?,
+            (TokenType::Modulo, Value::Int(l), Value::Int(r)) => int_math('%', *l, *r, overflow)?,
+            (TokenType::Plus, ..) => (lhs + rhs)?,
+            (TokenType::Minus, ..) => (lhs - rhs)?,
+            (TokenType::Times, ..) => (lhs * rhs)?,
+            (TokenType::Div, ..) => (lhs / rhs)?,
+            (TokenType::Modulo, ..) => (lhs % rhs)?,
+            (TokenType::BitOr, ..) => (lhs | rhs)?,
+            (TokenType::BitAnd, ..) => (lhs & rhs)?,
+            (TokenType::Xor, ..) => Value::Bool(lhs.is_truthy() != rhs.is_truthy()),
+            (TokenType::EqualsEquals, ..) => Value::Bool(lhs.eq(&rhs)),
+            (TokenType::NotEquals, ..) => Value::Bool(!lhs.eq(&rhs)),
+            (TokenType::Lt, ..) => Value::Bool(lhs.to_float() < rhs.to_float()),
+            (TokenType::Gt, ..) => Value::Bool(lhs.to_float() > rhs.to_float()),
+            // The VM does `!(a > b)` for `a <= b`, which is different for NaN
+            (TokenType::LtEquals, ..) => Value::Bool(!(lhs.to_float() > rhs.to_float())),
+            (TokenType::GtEquals, ..) => Value::Bool(!(lhs.to_float() < rhs.to_float())),
+            (TokenType::In, ..) => match rhs.contains(lhs) {
+                Some(b) => Value::Bool(b),
+                None => return Err(format!("Cannot use in on {} and {}", lhs.get_type(), rhs.get_type())),
+            },
+            _ => return Err(format!("unknown op {:?}", op)),
+        })
+    }
+}
@@ -1,8 +1,20 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+use std::cmp::Ordering;
 use std::fs::File;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread::JoinHandle;
+use std::time::Instant;
+use std::hash::{Hash, Hasher};
 use std::ops;
 
+use rustc_hash::FxHashSet;
+
+use crate::common::IMPOSSIBLE_STATE;
+use crate::backend::vm::vm::Coroutine;
+
 #[derive(Debug)]
 pub struct FileInfo {
     pub name: String,
@@ -17,6 +29,307 @@ impl PartialEq for FileInfo {
     }
 }
 
+#[derive(Debug)]
+pub struct ThreadInfo {
+    // None once it has been joined
+    pub handle: Option<JoinHandle<Result<SendValue, String>>>,
+}
+
+impl PartialEq for ThreadInfo {
+    fn eq(&self, _: &ThreadInfo) -> bool {
+        return false;
+    }
+}
+
+// Both ends of a channel, so it can be passed around as one value
+#[derive(Debug, Clone)]
+pub struct ChannelInfo {
+    pub sender: Sender<SendValue>,
+    pub receiver: Arc<Mutex<Receiver<SendValue>>>,
+}
+
+impl PartialEq for ChannelInfo {
+    fn eq(&self, other: &ChannelInfo) -> bool {
+        return Arc::ptr_eq(&self.receiver, &other.receiver);
+    }
+}
+
+// An async functi call, timer, or IO, the event loop in the VM runs it
+#[derive(Debug)]
+pub struct TaskInfo {
+    pub state: TaskState,
+    // The task it's waiting for (from await)
+    pub waiting: Option<Rc<RefCell<TaskInfo>>>,
+}
+
+#[derive(Debug)]
+pub enum TaskState {
+    // An async functi and its args, before it starts
+    Start(Value, Vec<Value>),
+    // Paused at an await
+    Paused(Box<Coroutine>),
+    Running,
+    // A timer (from sleep_async), done at the instant
+    Sleep(Instant),
+    Io(IoOp),
+    Done(Result<Value, String>),
+}
+
+impl TaskInfo {
+    // Everything it holds on to, for gc
+    pub fn values(&self) -> Vec<Value> {
+        let mut ret: Vec<Value> = match &self.state {
+            TaskState::Start(functi, args) =>
+                std::iter::once(functi).chain(args).cloned().collect(),
+            TaskState::Paused(coroutine) => coroutine.values().cloned().collect(),
+            TaskState::Done(Ok(val)) => vec![val.clone()],
+            _ => vec![],
+        };
+        ret.extend(self.waiting.iter().map(|task| Value::Task(task.clone())));
+        return ret;
+    }
+}
+
+impl PartialEq for TaskInfo {
+    fn eq(&self, _: &TaskInfo) -> bool {
+        return false;
+    }
+}
+
+// Non-blocking IO for a task, the event loop does a bit of it at a time
+#[derive(Debug)]
+pub enum IoOp {
+    // The file, and what has been read so far
+    Read(File, Vec<u8>),
+    // The file, and what is left to write
+    Write(File, Vec<u8>),
+    Accept(Rc<SocketInfo>),
+    Recv(Rc<SocketInfo>),
+    Send(Rc<SocketInfo>, Vec<u8>),
+}
+
+// Sockets are always non-blocking, the async builtins wait for them
+#[derive(Debug)]
+pub enum SocketInfo {
+    Listener(TcpListener),
+    Stream(TcpStream),
+}
+
+impl PartialEq for SocketInfo {
+    fn eq(&self, _: &SocketInfo) -> bool {
+        return false;
+    }
+}
+
+// What to do when integer math overflows
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Overflow {
+    #[default]
+    Wrap,
+    Saturate,
+    Error,
+}
+
+thread_local! {
+    // The mode the operators on values use, set by the VM
+    static OVERFLOW: Cell<Overflow> = const { Cell::new(Overflow::Wrap) };
+}
+
+pub fn set_overflow(mode: Overflow) {
+    OVERFLOW.with(|o| o.set(mode));
+}
+
+// Integer math that follows the overflow mode
+pub fn int_math(op: char, l: i32, r: i32, mode: Overflow) -> Result<Value, String> {
+    let (checked, name) = match op {
+        '+' => (l.checked_add(r), "add"),
+        '-' => (l.checked_sub(r), "subtract"),
+        '*' => (l.checked_mul(r), "multiply"),
+        '%' if r == 0 => return Err(format!("Cannot modulo {} by zero", l)),
+        // Only MIN % -1 fails, and the remainder (0) fits
+        '%' => return Ok(Value::Int(l.wrapping_rem(r))),
+        _ => return Err(IMPOSSIBLE_STATE.to_string()),
+    };
+    if let Some(i) = checked {
+        return Ok(Value::Int(i));
+    }
+    Ok(Value::Int(match mode {
+        Overflow::Wrap => match op {
+            '+' => l.wrapping_add(r),
+            '-' => l.wrapping_sub(r),
+            _ => l.wrapping_mul(r),
+        },
+        Overflow::Saturate => match op {
+            '+' => l.saturating_add(r),
+            '-' => l.saturating_sub(r),
+            _ => l.saturating_mul(r),
+        },
+        Overflow::Error => return Err(
+            format!("Integer overflow when trying to {} {} and {}", name, l, r)
+        ),
+    }))
+}
+
+// Set items, hashed by type and value
+#[derive(Debug, Clone)]
+pub struct SetKey(pub Value);
+
+impl Hash for SetKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.get_type().hash(state);
+        match &self.0 {
+            Value::Str(s) => s.hash(state),
+            Value::Int(i) => i.hash(state),
+            // -0.0 == 0.0, so they must hash the same, and so must every NaN
+            Value::Float(f) => (
+                if *f == 0.0 { 0 } else if f.is_nan() { f32::NAN.to_bits() } else { f.to_bits() }
+            ).hash(state),
+            Value::Bool(b) => b.hash(state),
+            Value::Byte(b) => b.hash(state),
+            Value::Functi(n) | Value::BoundFuncti(n, _) => n.hash(state),
+            Value::List(_) | Value::FastList(_) | Value::Tuple(_) => {
+                for val in self.0.values().unwrap() {
+                    SetKey(val).hash(state);
+                }
+            },
+            // Everything else only hashes the type
+            _ => {},
+        }
+    }
+}
+
+impl PartialEq for SetKey {
+    fn eq(&self, other: &SetKey) -> bool {
+        // Same as keys, 1 and 1.0 are different items
+        if let (Value::Float(l), Value::Float(r)) = (&self.0, &other.0) {
+            // NaN is never equal, but sets need it to be the same item
+            return l == r || (l.is_nan() && r.is_nan());
+        }
+        return self.0.get_type() == other.0.get_type() && self.0.eq(&other.0);
+    }
+}
+
+impl Eq for SetKey {}
+
+// Weak handle to a value (from weak)
+#[derive(Debug, Clone)]
+pub enum WeakRef {
+    Str(Weak<String>),
+    List(Weak<Vec<(Value, Value)>>),
+    FastList(Weak<Vec<Value>>),
+    Tuple(Weak<Vec<Value>>),
+    Set(Weak<FxHashSet<SetKey>>),
+    // Values that aren't reference counted never go away
+    Strong(Box<Value>),
+}
+
+impl WeakRef {
+    pub fn new(val: &Value) -> WeakRef {
+        match val {
+            Value::Str(s) => WeakRef::Str(Rc::downgrade(s)),
+            Value::List(l) => WeakRef::List(Rc::downgrade(l)),
+            Value::FastList(l) => WeakRef::FastList(Rc::downgrade(l)),
+            Value::Tuple(t) => WeakRef::Tuple(Rc::downgrade(t)),
+            Value::Set(s) => WeakRef::Set(Rc::downgrade(s)),
+            _ => WeakRef::Strong(Box::new(val.clone())),
+        }
+    }
+    // The value, if it's still alive
+    pub fn upgrade(&self) -> Option<Value> {
+        Some(match self {
+            WeakRef::Str(s) => Value::Str(s.upgrade()?),
+            WeakRef::List(l) => Value::List(l.upgrade()?),
+            WeakRef::FastList(l) => Value::FastList(l.upgrade()?),
+            WeakRef::Tuple(t) => Value::Tuple(t.upgrade()?),
+            WeakRef::Set(s) => Value::Set(s.upgrade()?),
+            WeakRef::Strong(v) => (**v).clone(),
+        })
+    }
+    pub fn is_alive(&self) -> bool {
+        match self {
+            WeakRef::Str(s) => s.strong_count() != 0,
+            WeakRef::List(l) => l.strong_count() != 0,
+            WeakRef::FastList(l) | WeakRef::Tuple(l) => l.strong_count() != 0,
+            WeakRef::Set(s) => s.strong_count() != 0,
+            WeakRef::Strong(_) => true,
+        }
+    }
+    // The address of what it points to, none for values that never go away
+    pub fn ptr(&self) -> Option<*const ()> {
+        match self {
+            WeakRef::Str(s) => Some(Weak::as_ptr(s) as *const ()),
+            WeakRef::List(l) => Some(Weak::as_ptr(l) as *const ()),
+            WeakRef::FastList(l) | WeakRef::Tuple(l) => Some(Weak::as_ptr(l) as *const ()),
+            WeakRef::Set(s) => Some(Weak::as_ptr(s) as *const ()),
+            WeakRef::Strong(_) => None,
+        }
+    }
+}
+
+impl PartialEq for WeakRef {
+    fn eq(&self, other: &WeakRef) -> bool {
+        // Same handle if it points to the same thing
+        match (self, other) {
+            (WeakRef::Str(l), WeakRef::Str(r)) => l.ptr_eq(r),
+            (WeakRef::List(l), WeakRef::List(r)) => l.ptr_eq(r),
+            (WeakRef::FastList(l), WeakRef::FastList(r))
+                | (WeakRef::Tuple(l), WeakRef::Tuple(r)) => l.ptr_eq(r),
+            (WeakRef::Set(l), WeakRef::Set(r)) => l.ptr_eq(r),
+            (WeakRef::Strong(l), WeakRef::Strong(r)) => l.eq(r),
+            _ => false,
+        }
+    }
+}
+
+// Deep copy of a value that can be sent to another thread
+#[derive(Debug)]
+pub enum SendValue {
+    Str(String),
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+    Byte(u8),
+    List(Vec<(SendValue, SendValue)>),
+    FastList(Vec<SendValue>),
+    Tuple(Vec<SendValue>),
+    Set(Vec<SendValue>),
+    None,
+    Functi(String),
+    BoundFuncti(String, Vec<SendValue>),
+    RangeType(i32, i32, i32),
+    RefType(i32, bool),
+    Channel(ChannelInfo),
+    Result(bool, Box<SendValue>),
+}
+
+impl SendValue {
+    pub fn to_value(self) -> Value {
+        let all = |vals: Vec<SendValue>| vals.into_iter().map(|v| v.to_value());
+        match self {
+            SendValue::Str(s) => Value::Str(Rc::new(s)),
+            SendValue::Int(i) => Value::Int(i),
+            SendValue::Float(f) => Value::Float(f),
+            SendValue::Bool(b) => Value::Bool(b),
+            SendValue::Byte(b) => Value::Byte(b),
+            SendValue::List(l) => Value::List(Rc::new(
+                l.into_iter().map(|(k, v)| (k.to_value(), v.to_value())).collect()
+            )),
+            SendValue::FastList(l) => Value::FastList(Rc::new(all(l).collect())),
+            SendValue::Tuple(t) => Value::Tuple(Rc::new(all(t).collect())),
+            SendValue::Set(s) => Value::Set(Rc::new(all(s).map(SetKey).collect())),
+            SendValue::None => Value::None,
+            SendValue::Functi(n) => Value::Functi(Rc::new(n)),
+            SendValue::BoundFuncti(n, args) => Value::BoundFuncti(
+                Rc::new(n), Rc::new(all(args).collect())
+            ),
+            SendValue::RangeType(at, max, step) => Value::RangeType(at, max, step),
+            SendValue::RefType(offset, global) => Value::RefType(offset, global),
+            SendValue::Channel(ch) => Value::Channel(Rc::new(ch)),
+            SendValue::Result(is_ok, val) => Value::Result(is_ok, Rc::new(val.to_value())),
+        }
+    }
+}
+
 // Value enum for variables
 // TODO: Make smaller (pointer tagging?)
 #[derive(Debug, Clone, PartialEq)]
@@ -27,10 +340,22 @@ pub enum Value {
     Float(f32),
     Bool(bool),
     Byte(u8),
-    List(Rc<Vec<(String, Value)>>),
+    // Keys can be any value, none means it has no key
+    List(Rc<Vec<(Value, Value)>>),
     None,
     File(Rc<RefCell<FileInfo>>),
     Functi(Rc<String>),
+    // Functi with args bound to it (from bind)
+    BoundFuncti(Rc<String>, Rc<Vec<Value>>),
+    Set(Rc<FxHashSet<SetKey>>),
+    Tuple(Rc<Vec<Value>>),
+    Weak(Rc<WeakRef>),
+    // Ok or err value (from ok and err), true means ok
+    Result(bool, Rc<Value>),
+    Thread(Rc<RefCell<ThreadInfo>>),
+    Channel(Rc<ChannelInfo>),
+    Task(Rc<RefCell<TaskInfo>>),
+    Socket(Rc<SocketInfo>),
 
     // FastList (used for lists with only ordered number keys)
     FastList(Rc<Vec<Value>>),
@@ -48,6 +373,67 @@ pub enum Value {
 }
 
 // Methods
+// Error for binops that don't work on the operand types
+pub fn op_err(verb: &str, op: &str, left: &Value, right: &Value) -> String {
+    format!(
+        "Cannot {} {} and {}: {} {} {}", verb, left.get_type(), right.get_type(),
+        left.short_repr(), op, right.short_repr()
+    )
+}
+
+// Max items shown by the pretty printer
+const PRETTY_MAX: usize = 100;
+
+// Collections nested deeper than this are shown as `[...]`, so printing can't overflow the stack
+const MAX_DEPTH: usize = 64;
+
+// Quote a string and escape control characters
+fn escape_str(s: &str) -> String {
+    let mut ret = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '\n' => ret += "\\n",
+            '\t' => ret += "\\t",
+            '\r' => ret += "\\r",
+            '\\' => ret += "\\\\",
+            '"' => ret += "\\\"",
+            c if c.is_control() => ret += &format!("\\x{:02x}", c as u32),
+            c => ret.push(c),
+        }
+    }
+    ret + "\""
+}
+
+// The shortest digits that read back as the same float, so 0.1 + 0.2 is 0.3
+// Without exponents and in lowercase, so they can be read back as literals
+pub fn format_float(f: f32) -> String {
+    if f.is_nan() {
+        return "nan".to_string();
+    }
+    let ret = format!("{}", f);
+    if f.is_finite() && !ret.contains('.') {
+        // Keep it a float
+        return ret + ".0";
+    }
+    ret
+}
+
+// Length of a string without color codes
+fn visible_len(s: &str) -> usize {
+    let mut len = 0;
+    let mut in_code = false;
+    for c in s.chars() {
+        if c == '\x1b' {
+            in_code = true;
+        } else if in_code {
+            in_code = c != 'm';
+        } else {
+            len += 1;
+        }
+    }
+    len
+}
+
 impl Value {
     // To int conversion
     pub fn to_int(&self) -> i32 {
@@ -74,12 +460,79 @@ impl Value {
             _ => 0.0,
         };
     }
+    // The collection's address, to find cycles
+    fn collection_ptr(&self) -> Option<*const ()> {
+        match self {
+            Value::List(l) => Some(Rc::as_ptr(l) as *const ()),
+            Value::FastList(l) | Value::Tuple(l) => Some(Rc::as_ptr(l) as *const ()),
+            Value::Set(set) => Some(Rc::as_ptr(set) as *const ()),
+            _ => None,
+        }
+    }
+    // Adds the address of everything this can reach to marked (for gc)
+    pub fn mark(&self, marked: &mut FxHashSet<*const ()>) {
+        let mut todo = vec![self.clone()];
+        while let Some(val) = todo.pop() {
+            let ptr = match &val {
+                Value::Str(s) => Rc::as_ptr(s) as *const (),
+                Value::BoundFuncti(_, bound) => Rc::as_ptr(bound) as *const (),
+                Value::Result(_, inner) => Rc::as_ptr(inner) as *const (),
+                Value::Iter(iter) => Rc::as_ptr(iter) as *const (),
+                Value::Task(task) => Rc::as_ptr(task) as *const (),
+                _ => match val.collection_ptr() {
+                    Some(ptr) => ptr,
+                    None => continue,
+                },
+            };
+            if !marked.insert(ptr) {
+                continue;
+            }
+            match &val {
+                Value::List(l) => todo.extend(l.iter().flat_map(|(k, v)| [k.clone(), v.clone()])),
+                Value::FastList(l) | Value::Tuple(l) | Value::BoundFuncti(_, l) =>
+                    todo.extend(l.iter().cloned()),
+                Value::Set(set) => todo.extend(set.iter().map(|key| key.0.clone())),
+                Value::Result(_, inner) => todo.push((**inner).clone()),
+                Value::Iter(iter) => todo.extend(iter.0.iter().cloned()),
+                Value::Task(task) => todo.extend(task.borrow().values()),
+                _ => {},
+            }
+        }
+    }
+    // Collections that are too deep, or inside of themselves, aren't shown
+    fn elided(&self, parents: &[*const ()]) -> Option<&'static str> {
+        let ptr = self.collection_ptr()?;
+        if parents.len() < MAX_DEPTH && !parents.contains(&ptr) {
+            return None;
+        }
+        Some(match self {
+            Value::Tuple(_) => "(...)",
+            Value::Set(_) => "{...}",
+            _ => "[...]",
+        })
+    }
     // String conversion
     pub fn to_string(&self) -> Result<String, String> {
+        self.to_string_at(&mut vec![])
+    }
+    // Parents are the collections this is inside of
+    fn to_string_at(&self, parents: &mut Vec<*const ()>) -> Result<String, String> {
+        if let Some(elided) = self.elided(parents) {
+            return Ok(elided.to_string());
+        }
+        if let Some(ptr) = self.collection_ptr() {
+            parents.push(ptr);
+            let ret = self.to_string_inner(parents);
+            parents.pop();
+            return ret;
+        }
+        self.to_string_inner(parents)
+    }
+    fn to_string_inner(&self, parents: &mut Vec<*const ()>) -> Result<String, String> {
         Ok(match self {
             Value::Str(s) => (**s).clone(),
             Value::Int(i) => format!("{}", i),
-            Value::Float(f) => format!("{:?}", f),
+            Value::Float(f) => format_float(*f),
             Value::Bool(b) => format!("{}", b),
             Value::Byte(b) => {
                 let mut ret = "0b".to_string();
@@ -93,11 +546,11 @@ impl Value {
                 // Add each element
                 for val in l.iter() {
                     // The the index isn't a number, print the index
-                    if val.0 != "" {
-                        ret += &val.0;
+                    if val.0 != Value::None {
+                        ret += &val.0.to_string_at(parents)?;
                         ret += ": ";
                     }
-                    ret += &val.1.to_string()?;
+                    ret += &val.1.to_string_at(parents)?;
                     ret += ", ";
                 }
                 // Remove trailing ", "
@@ -111,7 +564,7 @@ impl Value {
                 let mut ret = "[".to_string();
                 // Add each element
                 for val in l.iter() {
-                    ret += &(val.to_string()? + ", ");
+                    ret += &(val.to_string_at(parents)? + ", ");
                 }
                 // Remove trailing ", "
                 if ret.len() != 1 {
@@ -120,8 +573,56 @@ impl Value {
                 ret += "]";
                 ret
             }
+            Value::Tuple(t) => {
+                let mut ret = "(".to_string();
+                for val in t.iter() {
+                    ret += &(val.to_string_at(parents)? + ", ");
+                }
+                // Remove trailing ", ", but keep the comma for one item
+                if t.len() > 1 {
+                    ret.truncate(ret.len() - 2);
+                } else if t.len() == 1 {
+                    ret.truncate(ret.len() - 1);
+                }
+                ret += ")";
+                ret
+            },
+            Value::Set(set) => {
+                // Sorted so printing is the same every time
+                let mut items: Vec<&Value> = set.iter().map(|i| &i.0).collect();
+                items.sort_by(|a, b| a.total_cmp(b));
+                let mut ret = "{".to_string();
+                for val in items {
+                    ret += &(val.to_string_at(parents)? + ", ");
+                }
+                // Remove trailing ", "
+                if ret.len() != 1 {
+                    ret.truncate(ret.len() - 2);
+                }
+                ret += "}";
+                ret
+            },
             Value::None => "none".to_string(),
             Value::Functi(n) => format!("Functi({})", n),
+            Value::BoundFuncti(n, args) => {
+                let mut ret = format!("Functi({}", n);
+                for arg in args.iter() {
+                    ret += ", ";
+                    ret += &arg.to_string_at(parents)?;
+                }
+                ret + ")"
+            },
+            Value::Weak(w) => if w.is_alive() {
+                "Weak(alive)".to_string()
+            } else {
+                "Weak(dead)".to_string()
+            },
+            Value::Result(is_ok, val) =>
+                format!("{}({})", if *is_ok { "ok" } else { "err" }, val.to_string_at(parents)?),
+            Value::Thread(_) => "Thread".to_string(),
+            Value::Channel(_) => "Channel".to_string(),
+            Value::Task(_) => "Task".to_string(),
+            Value::Socket(_) => "Socket".to_string(),
             Value::RefType(offset, global) => format!("RefType({}: {})", ["local", "global"][*global as usize], offset),
             // Anything else
             _ => return Err(
@@ -129,6 +630,283 @@ impl Value {
             ),
         })
     }
+    // A short form of the value for errors
+    pub fn short_repr(&self) -> String {
+        let repr = self.pretty_at(false, None, &mut vec![]);
+        if repr.chars().count() > 40 {
+            repr.chars().take(37).collect::<String>() + "..."
+        } else {
+            repr
+        }
+    }
+
+    // Pretty printing for the REPL
+    pub fn pretty(&self, color: bool) -> String {
+        self.pretty_at(color, Some(0), &mut vec![])
+    }
+
+    // No indent means everything is on one line
+    fn pretty_at(&self, color: bool, indent: Option<usize>, parents: &mut Vec<*const ()>) -> String {
+        if let Some(elided) = self.elided(parents) {
+            return elided.to_string();
+        }
+        let Some(ptr) = self.collection_ptr() else {
+            return self.pretty_inner(color, indent, parents);
+        };
+        parents.push(ptr);
+        let ret = self.pretty_inner(color, indent, parents);
+        parents.pop();
+        ret
+    }
+
+    fn pretty_inner(&self, color: bool, indent: Option<usize>, parents: &mut Vec<*const ()>) -> String {
+        let inner = indent.map(|i| i + 1);
+        // Collections are split up, everything else is just colored
+        let paint = |code: &str, text: String| if color {
+            format!("{}{}\x1b[0m", code, text)
+        } else {
+            text
+        };
+        let items: Vec<String> = match self {
+            Value::Str(s) => return paint("\x1b[1;33m", escape_str(s)),
+            Value::Int(_) | Value::Float(_) =>
+                return paint("\x1b[1;35m", self.to_string().unwrap()),
+            Value::Byte(_) => return paint("\x1b[1;34m", self.to_string().unwrap()),
+            Value::Bool(_) | Value::None =>
+                return paint("\x1b[32m", self.to_string().unwrap()),
+            Value::List(l) => l.iter().take(PRETTY_MAX).map(|(k, v)| {
+                if *k != Value::None {
+                    k.pretty_at(color, inner, parents) + ": " + &v.pretty_at(color, inner, parents)
+                } else {
+                    v.pretty_at(color, inner, parents)
+                }
+            }).collect(),
+            Value::FastList(l) | Value::Tuple(l) => l.iter().take(PRETTY_MAX)
+                .map(|v| v.pretty_at(color, inner, parents)).collect(),
+            Value::Set(_) => self.set_values().unwrap().iter().take(PRETTY_MAX)
+                .map(|v| v.pretty_at(color, inner, parents)).collect(),
+            _ => return paint("\x1b[36m", self.to_string().unwrap_or(
+                format!("<{}>", self.get_type())
+            )),
+        };
+        let (open, close) = match self {
+            Value::Tuple(_) => ("(", ")"),
+            Value::Set(_) => ("{", "}"),
+            _ => ("[", "]"),
+        };
+        let mut items = items;
+        // Truncate huge collections
+        let len = match self {
+            Value::List(l) => l.len(),
+            Value::FastList(l) | Value::Tuple(l) => l.len(),
+            Value::Set(set) => set.len(),
+            _ => 0,
+        };
+        if len > PRETTY_MAX {
+            items.push(paint("\x1b[2m", format!("... ({} more)", len - PRETTY_MAX)));
+        }
+        // Keep the tuple comma for one item
+        if let (Value::Tuple(_), 1) = (self, items.len()) {
+            return format!("({},)", items[0]);
+        }
+        // Use one line if it fits
+        let flat = items.join(", ");
+        let Some(indent) = indent else {
+            return format!("{}{}{}", open, flat, close);
+        };
+        if !flat.contains('\n') && visible_len(&flat) + indent * 4 <= 72 {
+            return format!("{}{}{}", open, flat, close);
+        }
+        let pad = "    ".repeat(indent);
+        let mut ret = open.to_string() + "\n";
+        let is_collection = |v: &Value| matches!(v,
+            Value::List(_) | Value::FastList(_) | Value::Tuple(_) | Value::Set(_)
+        );
+        let plain = match self {
+            Value::FastList(l) | Value::Tuple(l) => !l.iter().any(is_collection),
+            Value::Set(set) => !set.iter().any(|i| is_collection(&i.0)),
+            _ => false,
+        };
+        if plain {
+            // Pack simple items onto as few lines as possible
+            let mut line = String::new();
+            for item in items {
+                if !line.is_empty() && visible_len(&line) + visible_len(&item) + indent * 4 > 72 {
+                    ret += &format!("{}    {}\n", pad, line.trim_end());
+                    line = String::new();
+                }
+                line += &(item + ", ");
+            }
+            ret += &format!("{}    {}\n", pad, line.trim_end());
+        } else {
+            // Otherwise put each item on it's own line
+            for item in items {
+                ret += &format!("{}    {},\n", pad, item);
+            }
+        }
+        ret + &pad + close
+    }
+
+    // Deep copy for sending to another thread
+    pub fn to_send(&self) -> Result<SendValue, String> {
+        let all = |vals: &[Value]| vals.iter()
+            .map(|v| v.to_send()).collect::<Result<Vec<SendValue>, String>>();
+        Ok(match self {
+            Value::Str(s) => SendValue::Str((**s).clone()),
+            Value::Int(i) => SendValue::Int(*i),
+            Value::Float(f) => SendValue::Float(*f),
+            Value::Bool(b) => SendValue::Bool(*b),
+            Value::Byte(b) => SendValue::Byte(*b),
+            Value::List(l) => SendValue::List(l.iter()
+                .map(|(k, v)| Ok((k.to_send()?, v.to_send()?)))
+                .collect::<Result<_, String>>()?
+            ),
+            Value::FastList(l) => SendValue::FastList(all(l)?),
+            Value::Tuple(t) => SendValue::Tuple(all(t)?),
+            Value::Set(_) => SendValue::Set(all(&self.set_values().unwrap())?),
+            Value::None => SendValue::None,
+            Value::Functi(n) => SendValue::Functi((**n).clone()),
+            Value::BoundFuncti(n, args) => SendValue::BoundFuncti((**n).clone(), all(args)?),
+            Value::RangeType(at, max, step) => SendValue::RangeType(*at, *max, *step),
+            Value::RefType(offset, global) => SendValue::RefType(*offset, *global),
+            Value::Channel(ch) => SendValue::Channel((**ch).clone()),
+            Value::Result(is_ok, val) => SendValue::Result(*is_ok, Box::new(val.to_send()?)),
+            _ => return Err(
+                format!("Cannot send {} to another thread", self.get_type())
+            ),
+        })
+    }
+    // Converts to bytes (for serialize), the tag byte comes first
+    pub fn serialize(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        let len = |out: &mut Vec<u8>, len: usize| -> Result<(), String> {
+            let len = u32::try_from(len).map_err(|_| "value too big to serialize")?;
+            out.extend(len.to_le_bytes());
+            Ok(())
+        };
+        let str = |out: &mut Vec<u8>, s: &str| -> Result<(), String> {
+            len(out, s.len())?;
+            out.extend(s.as_bytes());
+            Ok(())
+        };
+        let all = |out: &mut Vec<u8>, vals: &[Value]| -> Result<(), String> {
+            len(out, vals.len())?;
+            vals.iter().try_for_each(|v| v.serialize(out))
+        };
+        match self {
+            Value::None => out.push(0),
+            Value::Bool(b) => out.extend([1, *b as u8]),
+            Value::Int(i) => {
+                out.push(2);
+                out.extend(i.to_le_bytes());
+            },
+            Value::Float(f) => {
+                out.push(3);
+                out.extend(f.to_le_bytes());
+            },
+            Value::Byte(b) => out.extend([4, *b]),
+            Value::Str(s) => {
+                out.push(5);
+                str(out, s)?;
+            },
+            Value::FastList(l) => {
+                out.push(6);
+                all(out, l)?;
+            },
+            Value::List(l) => {
+                out.push(7);
+                len(out, l.len())?;
+                for (k, v) in l.iter() {
+                    k.serialize(out)?;
+                    v.serialize(out)?;
+                }
+            },
+            Value::Tuple(t) => {
+                out.push(8);
+                all(out, t)?;
+            },
+            Value::Set(_) => {
+                out.push(9);
+                all(out, &self.set_values().unwrap())?;
+            },
+            // Functis are saved by name
+            Value::Functi(n) => {
+                out.push(10);
+                str(out, n)?;
+            },
+            Value::BoundFuncti(n, args) => {
+                out.push(11);
+                str(out, n)?;
+                all(out, args)?;
+            },
+            Value::Result(is_ok, val) => {
+                out.extend([12, *is_ok as u8]);
+                val.serialize(out)?;
+            },
+            _ => return Err(format!("Cannot serialize {}", self.get_type())),
+        }
+        Ok(())
+    }
+
+    // Reads a value written by serialize
+    pub fn deserialize(bytes: &[u8], at: &mut usize, depth: usize) -> Result<Value, String> {
+        const BAD: &str = "invalid serialized data";
+        if depth > 1000 {
+            return Err("serialized data is nested too deep".to_string());
+        }
+        let take = |at: &mut usize, n: usize| -> Result<&[u8], String> {
+            let ret = bytes.get(*at..*at + n).ok_or(BAD)?;
+            *at += n;
+            Ok(ret)
+        };
+        let word = |at: &mut usize| -> Result<[u8; 4], String> {
+            Ok(take(at, 4)?.try_into().unwrap())
+        };
+        let str = |at: &mut usize| -> Result<String, String> {
+            let len = u32::from_le_bytes(word(at)?) as usize;
+            String::from_utf8(take(at, len)?.to_vec()).map_err(|_| BAD.to_string())
+        };
+        let tag = take(at, 1)?[0];
+        let all = |at: &mut usize| -> Result<Vec<Value>, String> {
+            let len = u32::from_le_bytes(word(at)?) as usize;
+            // Every value is at least a byte, so this stops huge allocations
+            let mut ret = Vec::with_capacity(len.min(bytes.len()));
+            for _ in 0..len {
+                ret.push(Value::deserialize(bytes, at, depth + 1)?);
+            }
+            Ok(ret)
+        };
+        Ok(match tag {
+            0 => Value::None,
+            1 => Value::Bool(take(at, 1)?[0] != 0),
+            2 => Value::Int(i32::from_le_bytes(word(at)?)),
+            3 => Value::Float(f32::from_le_bytes(word(at)?)),
+            4 => Value::Byte(take(at, 1)?[0]),
+            5 => Value::Str(Rc::new(str(at)?)),
+            6 => Value::FastList(Rc::new(all(at)?)),
+            7 => {
+                let len = u32::from_le_bytes(word(at)?) as usize;
+                let mut ret = Vec::with_capacity(len.min(bytes.len()));
+                for _ in 0..len {
+                    let key = Value::deserialize(bytes, at, depth + 1)?;
+                    ret.push((key, Value::deserialize(bytes, at, depth + 1)?));
+                }
+                Value::List(Rc::new(ret))
+            },
+            8 => Value::Tuple(Rc::new(all(at)?)),
+            9 => Value::Set(Rc::new(all(at)?.into_iter().map(SetKey).collect())),
+            10 => Value::Functi(Rc::new(str(at)?)),
+            11 => {
+                let name = str(at)?;
+                Value::BoundFuncti(Rc::new(name), Rc::new(all(at)?))
+            },
+            12 => {
+                let is_ok = take(at, 1)?[0] != 0;
+                Value::Result(is_ok, Rc::new(Value::deserialize(bytes, at, depth + 1)?))
+            },
+            _ => return Err(BAD.to_string()),
+        })
+    }
+
     // Truthy conversion
     pub fn is_truthy(&self) -> bool {
         return match self {
@@ -138,6 +916,10 @@ impl Value {
             Value::Bool(b) => *b,
             Value::List(l) => !l.is_empty(),
             Value::FastList(l) => !l.is_empty(),
+            Value::Set(set) => !set.is_empty(),
+            Value::Tuple(t) => !t.is_empty(),
+            Value::Weak(w) => w.is_alive(),
+            Value::Result(is_ok, _) => *is_ok,
             #[cfg(feature = "cffi")]
             Value::Ptr(ptr) => *ptr != 0,
             _ => false,
@@ -153,9 +935,17 @@ impl Value {
             Value::Bool(_) => "Bool",
             Value::Byte(_) => "Byte",
             Value::List(_) | Value::FastList(_) => "List",
+            Value::Set(_) => "Set",
+            Value::Tuple(_) => "Tuple",
+            Value::Weak(_) => "Weak",
+            Value::Result(..) => "Result",
+            Value::Thread(_) => "Thread",
+            Value::Channel(_) => "Channel",
+            Value::Task(_) => "Task",
+            Value::Socket(_) => "Socket",
             Value::None => "None",
             Value::File(..) => "File",
-            Value::Functi(..) => "Functi",
+            Value::Functi(..) | Value::BoundFuncti(..) => "Functi",
             // Internal types
             #[cfg(feature = "cffi")]
             Value::Ptr(_) => "__burlap_ptr",
@@ -167,7 +957,7 @@ impl Value {
     // Lists
     // TODO: When rust finally stabilizes generators, rewrite this
     pub fn values(&self) -> Option<Vec<Value>> {
-        if let Value::FastList(l) = self {
+        if let Value::FastList(l) | Value::Tuple(l) = self {
             return Some((**l).clone());
         }
         if let Value::List(l) = self {
@@ -175,14 +965,26 @@ impl Value {
         }
         None
     }
+    // Set items, sorted so iterating is the same every time
+    pub fn set_values(&self) -> Option<Vec<Value>> {
+        let Value::Set(set) = self else {
+            return None;
+        };
+        let mut items: Vec<Value> = set.iter().map(|i| i.0.clone()).collect();
+        items.sort_by(|a, b| a.total_cmp(b));
+        return Some(items);
+    }
     // Iterators
     pub fn to_iter(&self) -> Result<Value, String> {
         if let Value::RangeType(..) | Value::Iter(..) = self {
             return Ok(self.clone());
         }
-        if let Value::FastList(list) = self {
+        if let Value::FastList(list) | Value::Tuple(list) = self {
             return Ok(Value::Iter(Rc::new(((**list).clone(), 0))));
         }
+        if let Some(items) = self.set_values() {
+            return Ok(Value::Iter(Rc::new((items, 0))));
+        }
         if let Value::Str(str) = self {
             return Ok(Value::Iter(
                 Rc::new((
@@ -209,9 +1011,16 @@ impl Value {
                 *step = 0;
                 return Ok(Some(Value::Int(*at)));
             }
+            if (*step > 0) == (*at > *max) {
+                // Stepped past the end
+                return Ok(None);
+            }
             let ret = Value::Int(*at);
-            // Step
-            *at += *step;
+            // Step, going past the largest int is the end
+            match at.checked_add(*step) {
+                Some(next) => *at = next,
+                None => *step = 0,
+            }
             return Ok(Some(ret));
         }
         // It's not rangetype, must be an iter
@@ -234,7 +1043,9 @@ impl Value {
     }
     // Contains
     pub fn contains(&self, val: &Value) -> Option<bool> {
-        if let Some(vals) = self.values() {
+        if let Value::Set(set) = self {
+            return Some(set.contains(&SetKey(val.clone())));
+        } else if let Some(vals) = self.values() {
             return Some(vals.iter().any(|i| i.eq(val)));
         } else if let Value::Str(str) = self {
             if let Value::Str(vstr) = val {
@@ -259,7 +1070,7 @@ impl Value {
             } else {
                 None
             }
-        } else if let Value::FastList(list) = self {
+        } else if let Value::FastList(list) | Value::Tuple(list) = self {
             // String indexing doesn't work
             return if let Value::Str(_) = index {
                 None
@@ -271,18 +1082,23 @@ impl Value {
             // Not a list
             return None;
         };
-        // String indexing (keys)
-        if let Value::Str(s) = index {
-            for i in &**l {
-                if i.0 == **s {
-                    return Some(i.1.clone());
-                }
-            }
-            return None;
+        // Keys
+        if let Some(i) = l.iter().find(|i| i.0.is_key(index)) {
+            return Some(i.1.clone());
         }
         // Number indexing
+        if let Value::Str(_) = index {
+            return None;
+        }
         return l.get(index.to_int() as usize).map(|(_, v)| v.clone());
     }
+    // If self is the key of a list item (same type and equal)
+    pub fn is_key(&self, key: &Value) -> bool {
+        if let Value::None = self {
+            return false;
+        }
+        return self.get_type() == key.get_type() && self.eq(key);
+    }
     // ==
     pub fn eq(&self, right: &Value) -> bool {
         return match self {
@@ -349,6 +1165,13 @@ impl Value {
                     false
                 }
             },
+            Value::BoundFuncti(n, args) => {
+                if let Value::BoundFuncti(n_right, args_right) = right {
+                    **n == **n_right && **args == **args_right
+                } else {
+                    false
+                }
+            },
             // Pointers
             #[cfg(feature = "cffi")]
             Value::Ptr(p) => {
@@ -359,8 +1182,16 @@ impl Value {
                 }
             },
             // Lists
-            Value::List(_) | Value::FastList(_) => {
+            Value::List(_) | Value::FastList(_) | Value::Tuple(_) => {
                 // This isn't spec defined, so may change in the future
+                if let Value::Tuple(_) = self {
+                    // Tuples only equal tuples
+                    if right.get_type() != "Tuple" {
+                        return false;
+                    }
+                } else if right.get_type() != "List" {
+                    return false;
+                }
                 let Some(rhs) = right.values() else {
                     return false;
                 };
@@ -379,6 +1210,53 @@ impl Value {
                 }
                 true
             },
+            // Channels
+            Value::Channel(ch) => {
+                if let Value::Channel(ch_right) = right {
+                    ch == ch_right
+                } else {
+                    false
+                }
+            },
+            // Tasks and sockets are only equal to themselves
+            Value::Task(task) => {
+                if let Value::Task(task_right) = right {
+                    Rc::ptr_eq(task, task_right)
+                } else {
+                    false
+                }
+            },
+            Value::Socket(socket) => {
+                if let Value::Socket(socket_right) = right {
+                    Rc::ptr_eq(socket, socket_right)
+                } else {
+                    false
+                }
+            },
+            // Weak handles
+            Value::Weak(w) => {
+                if let Value::Weak(w_right) = right {
+                    w == w_right
+                } else {
+                    false
+                }
+            },
+            // Results
+            Value::Result(is_ok, val) => {
+                if let Value::Result(r_is_ok, r_val) = right {
+                    is_ok == r_is_ok && val.eq(r_val)
+                } else {
+                    false
+                }
+            },
+            // Sets
+            Value::Set(set) => {
+                if let Value::Set(set_right) = right {
+                    set == set_right
+                } else {
+                    false
+                }
+            },
             // RefType
             Value::RefType(offset, global) => {
                 if let Value::RefType(roffset, rglobal) = right {
@@ -391,6 +1269,59 @@ impl Value {
             _ => false,
         };
     }
+
+    // Rank of the type in the total order
+    fn type_rank(&self) -> u8 {
+        match self {
+            Value::None => 0,
+            Value::Bool(_) => 1,
+            Value::Int(_) | Value::Float(_) | Value::Byte(_) => 2,
+            Value::Str(_) => 3,
+            Value::List(_) | Value::FastList(_) => 4,
+            Value::Tuple(_) => 5,
+            Value::Set(_) => 6,
+            _ => 7,
+        }
+    }
+
+    // Total order: None < Bool < Number < String < List < Tuple < Set < anything else
+    pub fn total_cmp(&self, right: &Value) -> Ordering {
+        let rank = self.type_rank().cmp(&right.type_rank());
+        if rank != Ordering::Equal {
+            return rank;
+        }
+        return match (self, right) {
+            (Value::Bool(l), Value::Bool(r)) => l.cmp(r),
+            (Value::Int(l), Value::Int(r)) => l.cmp(r),
+            (Value::Str(l), Value::Str(r)) => l.cmp(r),
+            (Value::List(_) | Value::FastList(_) | Value::Tuple(_), _) => {
+                // Compare item by item, then by length
+                let (l, r) = (self.values().unwrap(), right.values().unwrap());
+                for (lv, rv) in l.iter().zip(r.iter()) {
+                    let ord = lv.total_cmp(rv);
+                    if ord != Ordering::Equal {
+                        return ord;
+                    }
+                }
+                l.len().cmp(&r.len())
+            },
+            (Value::Set(_), _) => {
+                let (l, r) = (self.set_values().unwrap(), right.set_values().unwrap());
+                Value::FastList(Rc::new(l)).total_cmp(&Value::FastList(Rc::new(r)))
+            },
+            // Mixed numbers (NaN is bigger than everything), f64 holds every int and float exactly
+            _ if self.type_rank() == 2 => {
+                let exact = |v: &Value| match v {
+                    Value::Int(i) => *i as f64,
+                    _ => v.to_float() as f64,
+                };
+                let (l, r) = (exact(self), exact(right));
+                l.partial_cmp(&r).unwrap_or_else(|| l.is_nan().cmp(&r.is_nan()))
+            },
+            // Files and such have no order
+            _ => Ordering::Equal,
+        };
+    }
 }
 
 // Add
@@ -405,11 +1336,11 @@ impl_op_ex!(+ |left: &Value, right: &Value| -> Result<Value, String> {
             if let Some(vals) = right.values() {
                 // Concat
                 for val in vals.clone() {
-                    list.push(("".to_string(), val));
+                    list.push((Value::None, val));
                 }
             } else {
                 // Append
-                list.push(("".to_string(), right.clone()));
+                list.push((Value::None, right.clone()));
             }
             Value::List(rc_list)
         },
@@ -444,10 +1375,10 @@ impl_op_ex!(+ |left: &Value, right: &Value| -> Result<Value, String> {
             Value::Float((*l as f32) + *r),
         // Int and int
         (Value::Int(l), Value::Int(r)) =>
-            Value::Int(*l + *r),
+            int_math('+', *l, *r, OVERFLOW.with(Cell::get))?,
         // Anything else
         _ => return Err(
-            format!("Cannot add {} and {}", left.get_type(), right.get_type())
+            op_err("add", "+", left, right)
         ),
     })
 });
@@ -464,10 +1395,13 @@ impl_op_ex!(- |left: &Value, right: &Value| -> Result<Value, String> {
             Value::Float((*l as f32) - *r),
         // Int and int
         (Value::Int(l), Value::Int(r)) =>
-            Value::Int(*l - *r),
+            int_math('-', *l, *r, OVERFLOW.with(Cell::get))?,
+        // Set difference
+        (Value::Set(l), Value::Set(r)) =>
+            Value::Set(Rc::new(l.difference(r).cloned().collect())),
         // Anything else
         _ => return Err(
-            format!("Cannot subtract {} and {}", left.get_type(), right.get_type())
+            op_err("subtract", "-", left, right)
         ),
     })
 });
@@ -515,8 +1449,11 @@ impl_op_ex!(* |left: &Value, right: &Value| -> Result<Value, String> {
         (Value::List(l), Value::Int(r)) => {
             // Check if it is valid
             for (key, _) in l.iter() {
-                if key != "" {
-                    return Err(format!("Cannot multiply list with named keys (found a key named \"{key}\")"));
+                if *key != Value::None {
+                    return Err(format!(
+                        "Cannot multiply list with named keys (found a key named \"{}\")",
+                        key.to_string()?
+                    ));
                 }
             }
             // Copy
@@ -534,10 +1471,10 @@ impl_op_ex!(* |left: &Value, right: &Value| -> Result<Value, String> {
             Value::Float((*l as f32) * *r),
         // Int and int
         (Value::Int(l), Value::Int(r)) =>
-            Value::Int(*l * *r),
+            int_math('*', *l, *r, OVERFLOW.with(Cell::get))?,
         // Anything else
         _ => return Err(
-            format!("Cannot multiply {} and {}", left.get_type(), right.get_type())
+            op_err("multiply", "*", left, right)
         ),
     })
 });
@@ -557,7 +1494,7 @@ impl_op_ex!(/ |left: &Value, right: &Value| -> Result<Value, String> {
             Value::Float((*l as f32) / (*r as f32)),
         // Anything else
         _ => return Err(
-            format!("Cannot divide {} and {}", left.get_type(), right.get_type())
+            op_err("divide", "/", left, right)
         )
     })
 });
@@ -574,10 +1511,42 @@ impl_op_ex!(% |left: &Value, right: &Value| -> Result<Value, String> {
             Value::Float((*l as f32) % *r),
         // Int/int
         (Value::Int(l), Value::Int(r)) =>
-            Value::Int(*l % *r),
+            int_math('%', *l, *r, OVERFLOW.with(Cell::get))?,
+        // Anything else
+        _ => return Err(
+            op_err("modulo", "%", left, right)
+        )
+    })
+});
+
+// Bitwise or and union
+impl_op_ex!(| |left: &Value, right: &Value| -> Result<Value, String> {
+    Ok(match (left, right) {
+        (Value::Int(l), Value::Int(r)) =>
+            Value::Int(*l | *r),
+        (Value::Byte(l), Value::Byte(r)) =>
+            Value::Byte(*l | *r),
+        (Value::Set(l), Value::Set(r)) =>
+            Value::Set(Rc::new(l.union(r).cloned().collect())),
+        // Anything else
+        _ => return Err(
+            op_err("or", "|", left, right)
+        )
+    })
+});
+
+// Bitwise and and intersection
+impl_op_ex!(& |left: &Value, right: &Value| -> Result<Value, String> {
+    Ok(match (left, right) {
+        (Value::Int(l), Value::Int(r)) =>
+            Value::Int(*l & *r),
+        (Value::Byte(l), Value::Byte(r)) =>
+            Value::Byte(*l & *r),
+        (Value::Set(l), Value::Set(r)) =>
+            Value::Set(Rc::new(l.intersection(r).cloned().collect())),
         // Anything else
         _ => return Err(
-            format!("Cannot modulo {} and {}", left.get_type(), right.get_type())
+            op_err("and", "&", left, right)
         )
     })
 });
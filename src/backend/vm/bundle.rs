@@ -0,0 +1,237 @@
+// Standalone executables, a copy of burlap with a compiled program appended to it
+// The end of the file is the payload, its length, then TRAILER
+use std::env;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::common::{print_err, ErrType};
+use crate::{to_ast, Arguments};
+use crate::backend::value::Overflow;
+use crate::backend::vm::compiler::{compile, Compiler, Program};
+
+// Bumped when the payload layout changes
+const TRAILER: &[u8] = b"SKBUNDL\x01";
+
+// Embedded files, by name
+type Resources = Vec<(String, Vec<u8>)>;
+
+fn read_u64(bytes: &[u8], at: &mut usize) -> Option<u64> {
+    let word = bytes.get(*at..*at + 8)?;
+    *at += 8;
+    Some(u64::from_le_bytes(word.try_into().unwrap()))
+}
+
+fn read_bytes<'a>(bytes: &'a [u8], at: &mut usize) -> Option<&'a [u8]> {
+    let len = read_u64(bytes, at)? as usize;
+    let data = bytes.get(*at..*at + len)?;
+    *at += len;
+    Some(data)
+}
+
+fn write_bytes(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend((data.len() as u64).to_le_bytes());
+    out.extend(data);
+}
+
+// The flags that change how the program runs, in order
+fn flags(args: &Arguments) -> [bool; 9] {
+    [
+        args.is_debug, args.backtrace, args.strict, args.extension_color,
+        args.extension_auto_none, args.extension_functies, args.extension_va_print,
+        args.extension_debugging_functies, args.extension_total_order,
+    ]
+}
+
+fn set_flags(args: &mut Arguments, flags: &[u8]) {
+    let fields = [
+        &mut args.is_debug, &mut args.backtrace, &mut args.strict, &mut args.extension_color,
+        &mut args.extension_auto_none, &mut args.extension_functies,
+        &mut args.extension_va_print, &mut args.extension_debugging_functies,
+        &mut args.extension_total_order,
+    ];
+    for (field, flag) in fields.into_iter().zip(flags) {
+        *field = *flag != 0;
+    }
+}
+
+fn to_payload(
+    args: &Arguments, program: &Program, resources: &Resources
+) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    write_bytes(&mut out, args.name.as_bytes());
+    out.extend(flags(args).map(|flag| flag as u8));
+    out.push(match args.overflow {
+        Overflow::Wrap => 0,
+        Overflow::Saturate => 1,
+        Overflow::Error => 2,
+    });
+    out.extend((args.log_level as u64).to_le_bytes());
+    out.extend((args.max_call_depth as u64).to_le_bytes());
+    out.extend((resources.len() as u64).to_le_bytes());
+    for (name, data) in resources {
+        write_bytes(&mut out, name.as_bytes());
+        write_bytes(&mut out, data);
+    }
+    out.extend(program.to_bytes()?);
+    Ok(out)
+}
+
+fn from_payload(bytes: &[u8]) -> Option<(Arguments, Program, Resources)> {
+    let mut args = Arguments::new();
+    let mut at = 0;
+    args.name = String::from_utf8(read_bytes(bytes, &mut at)?.to_vec()).ok()?;
+    let len = flags(&args).len();
+    set_flags(&mut args, bytes.get(at..at + len)?);
+    at += len;
+    args.overflow = match bytes.get(at)? {
+        0 => Overflow::Wrap,
+        1 => Overflow::Saturate,
+        _ => Overflow::Error,
+    };
+    at += 1;
+    args.log_level = read_u64(bytes, &mut at)? as usize;
+    args.max_call_depth = read_u64(bytes, &mut at)? as usize;
+    let mut resources = vec![];
+    for _ in 0..read_u64(bytes, &mut at)? {
+        let name = String::from_utf8(read_bytes(bytes, &mut at)?.to_vec()).ok()?;
+        resources.push((name, read_bytes(bytes, &mut at)?.to_vec()));
+    }
+    let program = Program::from_bytes(&bytes[at..])?;
+    Some((args, program, resources))
+}
+
+// Gets the bundled program, if this executable has one
+pub fn load() -> Option<(Arguments, Program, Resources)> {
+    let exe = env::current_exe().ok()?;
+    let mut file = fs::File::open(&exe).ok()?;
+    // Only the end is read, so normal runs don't load the whole binary
+    let mut end = [0u8; 16];
+    file.seek(SeekFrom::End(-(end.len() as i64))).ok()?;
+    file.read_exact(&mut end).ok()?;
+    if &end[8..] != TRAILER {
+        return None;
+    }
+    let len = u64::from_le_bytes(end[..8].try_into().unwrap());
+    file.seek(SeekFrom::End(-(end.len() as i64) - len as i64)).ok()?;
+    let mut payload = vec![0u8; len as usize];
+    file.read_exact(&mut payload).ok()?;
+    let (mut args, mut program, resources) = from_payload(&payload)?;
+    // Imports and files are relative to the executable
+    args.path = exe.with_file_name(&args.name);
+    args.is_repl = false;
+    args.cache = false;
+    program.path = args.path.clone();
+    program.path.pop();
+    // Everything is for the program
+    args.program_args.push(args.name.clone());
+    args.program_args.extend(env::args().skip(1));
+    Some((args, program, resources))
+}
+
+// The running executable, without a bundle if it has one
+fn base_exe() -> Result<Vec<u8>, String> {
+    let exe = env::current_exe().map_err(|e| format!("failed to find burlap: {}", e))?;
+    let mut bytes = fs::read(&exe).map_err(|e| format!("failed to read burlap: {}", e))?;
+    if bytes.ends_with(TRAILER) {
+        let mut at = bytes.len() - 16;
+        let len = read_u64(&bytes, &mut at).unwrap() as usize;
+        bytes.truncate(bytes.len().saturating_sub(16 + len));
+    }
+    Ok(bytes)
+}
+
+fn write_exe(out: &Path, bytes: &[u8]) -> Result<(), String> {
+    fs::write(out, bytes).map_err(|e| format!("failed to write {}: {}", out.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(out, fs::Permissions::from_mode(0o755))
+            .map_err(|e| format!("failed to make {} executable: {}", out.display(), e))?;
+    }
+    Ok(())
+}
+
+// `burlap bundle file.sk [-o out] [-r resource]...`
+pub fn bundle(cli_args: impl Iterator<Item = String>, args: &Arguments) -> bool {
+    let mut args = args.clone();
+    let mut file = None;
+    let mut out = None;
+    let mut resource_paths = vec![];
+    let mut cli_args = cli_args;
+    while let Some(arg) = cli_args.next() {
+        if arg == "-o" || arg == "--output" || arg == "-r" || arg == "--resource" {
+            let Some(value) = cli_args.next() else {
+                print_err(&format!("'{}' is missing a file", arg), ErrType::Err, args.extension_color);
+                return false;
+            };
+            if arg == "-o" || arg == "--output" {
+                out = Some(PathBuf::from(value));
+            } else {
+                resource_paths.push(value);
+            }
+        } else if file.is_none() {
+            file = Some(arg);
+        } else {
+            print_err(&format!("unknown argument: {}", arg), ErrType::Warn, args.extension_color);
+        }
+    }
+    let Some(file) = file else {
+        print_err("'bundle' is missing a file", ErrType::Err, args.extension_color);
+        return false;
+    };
+    let result = (|| -> Result<PathBuf, String> {
+        args.source = fs::read_to_string(&file)
+            .map_err(|e| format!("failed to open file: {}", e))?;
+        args.path = PathBuf::from(&file);
+        args.name = file.clone();
+        args.is_repl = false;
+        // Resources are relative to the file, and looked up by the name they were given
+        let mut resources = vec![];
+        for name in resource_paths {
+            let data = fs::read(args.path.with_file_name(&name))
+                .map_err(|e| format!("failed to read resource {}: {}", name, e))?;
+            resources.push((name, data));
+        }
+        // Compile
+        // Compile errors are already printed
+        let mut ast = to_ast(&mut args).ok_or("")?;
+        let mut compiler = Compiler::new();
+        compiler.opt_level = args.opt_level;
+        compiler.program.path = args.path.clone();
+        compiler.program.path.pop();
+        if !compile(&mut ast, &Some(args.name.clone()), &mut compiler, false) {
+            return Err("".to_string());
+        }
+        // Only the file name is kept, so the bundle can be moved
+        if let Some(name) = args.path.file_name() {
+            args.name = name.to_string_lossy().to_string();
+        }
+        let payload = to_payload(&args, &compiler.program, &resources)?;
+        // Write
+        let out = out.unwrap_or_else(|| {
+            let mut out = PathBuf::from(&file);
+            out.set_extension(env::consts::EXE_EXTENSION);
+            out
+        });
+        let mut bytes = base_exe()?;
+        let len = payload.len() as u64;
+        bytes.extend(payload);
+        bytes.extend(len.to_le_bytes());
+        bytes.extend(TRAILER);
+        write_exe(&out, &bytes)?;
+        Ok(out)
+    })();
+    match result {
+        Ok(out) => {
+            println!("Bundled {} into {}", file, out.display());
+            true
+        },
+        Err(err) => {
+            if !err.is_empty() {
+                print_err(&err, ErrType::Err, args.extension_color);
+            }
+            false
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// The bytecode cache, compiled programs are kept in `.burlap-cache/` next to the file
+use std::fs;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+
+use rustc_hash::FxHasher;
+
+use crate::Arguments;
+use crate::backend::vm::compiler::Program;
+
+// Bumped when the bytecode changes
+const MAGIC: &[u8] = b"SKC\x05";
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = FxHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+// Anything that changes how the source compiles
+fn key(args: &Arguments, source: &str) -> u64 {
+    let flags = format!(
+        "{} {} {} {} {} {} {} {}", env!("CARGO_PKG_VERSION"), args.print_result,
+        args.opt_level, args.extension_auto_none, args.extension_functies,
+        args.extension_va_print, args.extension_debugging_functies,
+        args.extension_total_order
+    );
+    hash(&[flags.as_bytes(), source.as_bytes()].concat())
+}
+
+fn cache_path(args: &Arguments) -> Option<PathBuf> {
+    let file = fs::canonicalize(&args.path).ok()?;
+    let dir = file.parent()?.join(".burlap-cache");
+    let name = file.file_name()?.to_string_lossy().to_string();
+    Some(dir.join(format!("{}-{:016x}.skc", name, hash(file.to_string_lossy().as_bytes()))))
+}
+
+fn read_u64(bytes: &[u8], at: &mut usize) -> Option<u64> {
+    let word = bytes.get(*at..*at + 8)?;
+    *at += 8;
+    Some(u64::from_le_bytes(word.try_into().unwrap()))
+}
+
+// Gets the cached program, if the source (and every import) hasn't changed
+pub fn load(args: &Arguments) -> Option<Program> {
+    let bytes = fs::read(cache_path(args)?).ok()?;
+    let mut at = MAGIC.len();
+    if !bytes.starts_with(MAGIC) || read_u64(&bytes, &mut at)? != key(args, &args.source) {
+        return None;
+    }
+    // Imported files
+    for _ in 0..read_u64(&bytes, &mut at)? {
+        let len = read_u64(&bytes, &mut at)? as usize;
+        let file = std::str::from_utf8(bytes.get(at..at + len)?).ok()?;
+        at += len;
+        let file_hash = read_u64(&bytes, &mut at)?;
+        if hash(&fs::read(Path::new(file)).ok()?) != file_hash {
+            return None;
+        }
+    }
+    let mut program = Program::from_bytes(&bytes[at..])?;
+    // The file might have moved
+    program.path = args.path.clone();
+    program.path.pop();
+    Some(program)
+}
+
+// Saves a program, failing is fine because it's only a cache
+pub fn store(args: &Arguments, source: &str, program: &Program) {
+    let Some(path) = cache_path(args) else {
+        return;
+    };
+    let Ok(program_bytes) = program.to_bytes() else {
+        return;
+    };
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(key(args, source).to_le_bytes());
+    // The main file is checked by the key, everything else needs a hash
+    let main = fs::canonicalize(&args.path).ok();
+    let mut files = vec![];
+    for file in program.files() {
+        if fs::canonicalize(&file).ok() == main {
+            continue;
+        }
+        let Ok(contents) = fs::read(&file) else {
+            // Not a real file, so it can't be checked
+            return;
+        };
+        files.push((file, hash(&contents)));
+    }
+    bytes.extend((files.len() as u64).to_le_bytes());
+    for (file, file_hash) in files {
+        bytes.extend((file.len() as u64).to_le_bytes());
+        bytes.extend(file.as_bytes());
+        bytes.extend(file_hash.to_le_bytes());
+    }
+    bytes.extend(program_bytes);
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let _ = fs::write(path, bytes);
+}
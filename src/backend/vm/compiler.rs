@@ -1,40 +1,210 @@
 // This is Burlap's bytecode compiler, it does *not* compile to C or a native instruction set
 use std::rc::Rc;
+use std::collections::HashMap;
 use std::cmp::Ordering;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::ptr::null_mut;
 
 use crate::common::IMPOSSIBLE_STATE;
 use crate::lexer::TokenType;
-use crate::parser::{ASTNode, ASTNode::*, StmtNode, AST, FunctiData, FunctiNode};
-use crate::backend::value::Value;
-use crate::backend::vm::vm::Opcode;
+use crate::parser::{ASTNode, ASTNode::*, StmtNode, AST, FunctiData, FunctiNode, Span};
+use crate::backend::value::{SendValue, Value};
+use crate::backend::vm::vm::{changes_var, Opcode, TYPE_CHECKS};
+use crate::backend::vm::optimizer;
 
 #[derive(Debug)]
 pub struct Program {
     // Opcodes and constants
     pub ops: Vec<u32>,
     pub consts: Vec<Value>,
-    // Function locations (name, byte pos, arg num))
+    // Function locations (name, byte pos, arg num, doc))
     // TODO: Don't use tuple, i32 -> u8
-    pub functis: Vec<(String, usize, i32)>,
+    pub functis: Vec<(String, usize, i32, String)>,
     // Import dir
     pub path: PathBuf,
+    // Global names, by offset (for globals())
+    pub global_names: Vec<String>,
 
     // Side tables
     line_table: Vec<(u32, u32, usize)>,
     file_table: Vec<(u32, u32, String)>,
+    span_table: Vec<(u32, u32, Span)>,
+}
+
+// A copy of a program that can be sent to another thread
+pub struct SendProgram {
+    ops: Vec<u32>,
+    consts: Vec<SendValue>,
+    functis: Vec<(String, usize, i32, String)>,
+    path: PathBuf,
+    global_names: Vec<String>,
+    line_table: Vec<(u32, u32, usize)>,
+    file_table: Vec<(u32, u32, String)>,
+    span_table: Vec<(u32, u32, Span)>,
+}
+
+impl SendProgram {
+    pub fn to_program(self) -> Program {
+        Program {
+            ops: self.ops,
+            consts: self.consts.into_iter().map(|c| c.to_value()).collect(),
+            functis: self.functis, path: self.path,
+            global_names: self.global_names,
+            line_table: self.line_table, file_table: self.file_table,
+            span_table: self.span_table,
+        }
+    }
 }
 
 impl Program {
+    // Copy for another thread
+    pub fn to_send(&self) -> Result<SendProgram, String> {
+        Ok(SendProgram {
+            ops: self.ops.clone(),
+            consts: self.consts.iter().map(|c| c.to_send())
+                .collect::<Result<_, String>>()?,
+            functis: self.functis.clone(), path: self.path.clone(),
+            global_names: self.global_names.clone(),
+            line_table: self.line_table.clone(), file_table: self.file_table.clone(),
+            span_table: self.span_table.clone(),
+        })
+    }
+
     // Init
     pub fn new() -> Program {
         Program {
             ops: vec![], consts: vec![],
             functis: Vec::new(),
             path: PathBuf::from("."),
+            global_names: vec![],
             line_table: vec![],
             file_table: vec![],
+            span_table: vec![],
+        }
+    }
+
+    // Converts to bytes (for the bytecode cache)
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        fn put_str(out: &mut Vec<u8>, str: &str) {
+            out.extend((str.len() as u32).to_le_bytes());
+            out.extend(str.as_bytes());
+        }
+        let mut out = vec![];
+        out.extend((self.ops.len() as u32).to_le_bytes());
+        for op in &self.ops {
+            out.extend(op.to_le_bytes());
+        }
+        out.extend((self.consts.len() as u32).to_le_bytes());
+        for val in &self.consts {
+            val.serialize(&mut out)?;
+        }
+        out.extend((self.functis.len() as u32).to_le_bytes());
+        for (name, pos, arg_num, doc) in &self.functis {
+            put_str(&mut out, name);
+            out.extend((*pos as u32).to_le_bytes());
+            out.extend(arg_num.to_le_bytes());
+            put_str(&mut out, doc);
+        }
+        put_str(&mut out, &self.path.to_string_lossy());
+        out.extend((self.global_names.len() as u32).to_le_bytes());
+        for name in &self.global_names {
+            put_str(&mut out, name);
+        }
+        // Side tables
+        out.extend((self.line_table.len() as u32).to_le_bytes());
+        for (start, end, line) in &self.line_table {
+            out.extend([*start, *end, *line as u32].iter().flat_map(|n| n.to_le_bytes()));
+        }
+        out.extend((self.file_table.len() as u32).to_le_bytes());
+        for (start, end, file) in &self.file_table {
+            out.extend([*start, *end].iter().flat_map(|n| n.to_le_bytes()));
+            put_str(&mut out, file);
+        }
+        out.extend((self.span_table.len() as u32).to_le_bytes());
+        for (start, end, span) in &self.span_table {
+            out.extend(
+                [*start, *end, span.line as u32, span.col as u32, span.size as u32]
+                    .iter().flat_map(|n| n.to_le_bytes())
+            );
+        }
+        Ok(out)
+    }
+
+    // Reads a program written by to_bytes
+    pub fn from_bytes(bytes: &[u8]) -> Option<Program> {
+        let num = |at: &mut usize| {
+            let word = bytes.get(*at..*at + 4)?;
+            *at += 4;
+            Some(u32::from_le_bytes(word.try_into().unwrap()))
+        };
+        let str = |at: &mut usize| {
+            let len = num(at)? as usize;
+            let str = std::str::from_utf8(bytes.get(*at..*at + len)?).ok()?;
+            *at += len;
+            Some(str.to_string())
+        };
+        let mut at = 0;
+        let mut program = Program::new();
+        program.ops = (0..num(&mut at)?).map(|_| num(&mut at)).collect::<Option<_>>()?;
+        for _ in 0..num(&mut at)? {
+            program.consts.push(Value::deserialize(bytes, &mut at, 0).ok()?);
+        }
+        for _ in 0..num(&mut at)? {
+            let name = str(&mut at)?;
+            let pos = num(&mut at)? as usize;
+            let arg_num = num(&mut at)? as i32;
+            program.functis.push((name, pos, arg_num, str(&mut at)?));
+        }
+        program.path = PathBuf::from(str(&mut at)?);
+        for _ in 0..num(&mut at)? {
+            program.global_names.push(str(&mut at)?);
+        }
+        for _ in 0..num(&mut at)? {
+            let (start, end) = (num(&mut at)?, num(&mut at)?);
+            program.line_table.push((start, end, num(&mut at)? as usize));
+        }
+        for _ in 0..num(&mut at)? {
+            let (start, end) = (num(&mut at)?, num(&mut at)?);
+            program.file_table.push((start, end, str(&mut at)?));
+        }
+        for _ in 0..num(&mut at)? {
+            let (start, end) = (num(&mut at)?, num(&mut at)?);
+            let span = Span {
+                line: num(&mut at)? as usize, col: num(&mut at)? as usize,
+                size: num(&mut at)? as usize,
+            };
+            program.span_table.push((start, end, span));
+        }
+        if at != bytes.len() {
+            return None;
+        }
+        Some(program)
+    }
+
+    // The files the program was compiled from
+    pub fn files(&self) -> Vec<String> {
+        let mut files: Vec<String> = self.file_table.iter().map(|f| f.2.clone()).collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    // The file that was run (the rest are imports)
+    pub fn main_file(&self) -> Option<&str> {
+        self.file_table.first().map(|f| f.2.as_str())
+    }
+
+    // Moves the side tables along with the code they're for
+    pub fn remap(&mut self, map: impl Fn(usize) -> usize) {
+        let map = |pos: u32| map(pos as usize) as u32;
+        for entry in self.line_table.iter_mut() {
+            (entry.0, entry.1) = (map(entry.0), map(entry.1));
+        }
+        for entry in self.file_table.iter_mut() {
+            (entry.0, entry.1) = (map(entry.0), map(entry.1));
+        }
+        for entry in self.span_table.iter_mut() {
+            (entry.0, entry.1) = (map(entry.0), map(entry.1));
         }
     }
 
@@ -50,12 +220,18 @@ impl Program {
         ).map(|x| table[x].2.clone()).ok()
     }
 
-    pub fn get_info(&mut self, index: u32) -> (usize, String) {
+    // Gets the line, file, and (column, size) of an instruction
+    pub fn get_info(&mut self, index: u32) -> (usize, String, Option<(usize, usize)>) {
         let file = Self::bin_range(index, &self.file_table)
             .unwrap_or("Unknown File".to_string());
         let line = Self::bin_range(index, &self.line_table)
             .unwrap_or(0);
-        (line, file)
+        // Spans nest, so use the innermost one (on the same line)
+        let span = self.span_table.iter()
+            .filter(|x| x.0 <= index && index <= x.1 && x.2.line == line)
+            .min_by_key(|x| x.1 - x.0)
+            .map(|x| (x.2.col, x.2.size));
+        (line, file, span)
     }
 }
 
@@ -93,6 +269,12 @@ pub struct Compiler {
 
     // The current funci
     functi: Option<FunctiData>,
+
+    // Inlining
+    // 0 disables it, higher levels inline bigger functis
+    pub opt_level: u8,
+    // Functis that can be inlined (name, arg num, arg names, returned expr)
+    inlines: Vec<(String, i32, Vec<String>, ASTNode)>,
 }
 
 impl Compiler {
@@ -102,7 +284,24 @@ impl Compiler {
             regs: [true; 17], needs_args: false,
             break_addrs: vec![], loop_top: 0,
             on_stack_only: false, line_start: 0,
-            inc_start: 0, ast: null_mut(), functi: None
+            inc_start: 0, ast: null_mut(), functi: None,
+            opt_level: 1, inlines: vec![]
+        }
+    }
+
+    // Continues compiling an existing program
+    pub fn from_program(program: Program) -> Compiler {
+        let mut compiler = Compiler::new();
+        compiler.line_start = program.ops.len() as u32;
+        compiler.program = program;
+        compiler
+    }
+
+    // Marks the instructions from start to now as coming from span
+    fn add_span(&mut self, start: usize, span: &Span) {
+        let end = self.program.ops.len();
+        if span.line != 0 && end > start {
+            self.program.span_table.push((start as u32, end as u32 - 1, *span));
         }
     }
 
@@ -305,8 +504,15 @@ impl Compiler {
     fn _var(&mut self, var: &String, reg: Reg, mut op: Opcode) -> Option<()> {
         // Get the offset
         let (offset, global) = self.get_var_offset(var)?;
-        if global && (op == Opcode::SV_L || op == Opcode::LV_L) {
-            op = if op == Opcode::SV_L { Opcode::SV_G } else { Opcode::LV_G };
+        if global {
+            op = match op {
+                Opcode::SV_L => Opcode::SV_G,
+                Opcode::LV_L => Opcode::LV_G,
+                Opcode::AV_L => Opcode::AV_G,
+                Opcode::INC_L => Opcode::INC_G,
+                Opcode::DEC_L => Opcode::DEC_G,
+                op => op,
+            };
         }
         self.add_op_args(
             op,
@@ -330,11 +536,7 @@ impl Compiler {
             // It's a function
             self.free_reg(reg);
             let name = var.clone().split("::").nth(1).unwrap_or(var).to_string();
-            if name == "__burlap_debug_blackbox" {
-                self.push(Value::None)
-            } else {
-                self.push(Value::Functi(Rc::new(name.clone())))
-            }
+            self.push(Value::Functi(Rc::new(name)))
         } else {
             reg
         }
@@ -366,35 +568,19 @@ fn compile_unary(
             compiler.add_op_args(Opcode::NOT, ret as u8, res as u8, 0);
             res
         },
-        // ++/--
-        TokenType::PlusPlus => {
-            let ret = compile_expr(compiler, val)?;
-            let tmp = compiler.push(Value::Int(1));
-            let res = compiler.get_mut_reg(ret);
-            compiler.add_op_args(Opcode::ADD, ret as u8, tmp as u8, res as u8);
-            if ret == STACK {
-                compiler.dup();
-            }
-            compiler.free_reg(tmp);
+        // ++/--, changes the var in place
+        TokenType::PlusPlus | TokenType::MinusMinus => {
             let VarExpr(ref s) = *val else {
-                panic!("++ needs a var, how did you do this?");
+                panic!("++/-- needs a var, how did you do this?");
             };
-            compiler.set_var(s, res);
-            ret
-        },
-        TokenType::MinusMinus => {
-            let ret = compile_expr(compiler, val)?;
-            let tmp = compiler.push(Value::Int(1));
-            let res = compiler.get_mut_reg(ret);
-            compiler.add_op_args(Opcode::SUB, ret as u8, tmp as u8, res as u8);
-            if ret == STACK {
-                compiler.dup();
-            }
-            compiler.free_reg(tmp);
-            let VarExpr(ref s) = *val else {
-                panic!("-- needs a var, how did you do this?");
+            let op = match (op == &TokenType::PlusPlus, compiler.functi.is_none()) {
+                (true, true) => Opcode::INC_G,
+                (true, false) => Opcode::INC_L,
+                (false, true) => Opcode::DEC_G,
+                (false, false) => Opcode::DEC_L,
             };
-            compiler.set_var(s, res);
+            let res = compiler.alloc_reg();
+            compiler._var(s, res, op)?;
             res
         },
         _ => panic!("{}", IMPOSSIBLE_STATE),
@@ -408,7 +594,7 @@ fn compile_set(compiler: &mut Compiler, lvalue: &ASTNode, value: Reg) -> Option<
         compiler.set_var(&s, value);
         compiler.free_reg(value);
         return Some(());
-    } else if let IndexExpr(list, index) = lvalue.clone() {
+    } else if let IndexExpr(list, index, _) = lvalue.clone() {
         let ireg = compile_expr(compiler, &index)?;
         let lreg = compile_expr(compiler, &list)?;
         compiler.add_op_args(Opcode::SKY, lreg as u8, ireg as u8, value as u8);
@@ -429,7 +615,9 @@ fn compile_short_binop(
     // Uses jump instructions to:
     // Turn `a() && b()` into `r = a(); if r  { r = b() }; r`
     // Turn `a() || b()` into `r = a(); if !r { r = b() }; r`
+    // The result goes in lhs, so it can't be a constant
     let lhs = compile_expr(compiler, lhs)?;
+    let lhs = compiler.to_mut_reg(lhs);
     let dup_tmp = compiler.alloc_reg();
     if lhs == STACK {
         compiler.dup();
@@ -461,6 +649,100 @@ fn compile_short_binop(
     return Some(lhs);
 }
 
+// Gets the immediate version of an op (op, immediate, needs not) if the rhs fits in a byte
+fn get_imm_op(op: &TokenType, rhs: &ASTNode) -> Option<(Opcode, i8, bool)> {
+    let NumberExpr(val) = rhs else {
+        return None;
+    };
+    let imm = i8::try_from(*val).ok()?;
+    Some(match op {
+        TokenType::Plus | TokenType::PlusEquals => (Opcode::ADDI, imm, false),
+        TokenType::Minus | TokenType::MinusEquals => (Opcode::SUBI, imm, false),
+        TokenType::EqualsEquals => (Opcode::EQI, imm, false),
+        TokenType::NotEquals => (Opcode::EQI, imm, true),
+        TokenType::Lt => (Opcode::LTI, imm, false),
+        TokenType::GtEquals => (Opcode::GEI, imm, false),
+        _ => return None,
+    })
+}
+
+fn compile_imm_binop(
+    compiler: &mut Compiler,
+    lhs: &ASTNode, op: &TokenType, imm_op: (Opcode, i8, bool),
+    clean: bool
+) -> Option<Reg> {
+    // Compiles ops with a small number on the right (`i += 1`, `i < 100`)
+    // The number is stored in the instruction, so it doesn't need a const or register
+    let (imm_op, imm, negate) = imm_op;
+    let lreg = compile_expr(compiler, lhs)?;
+    let resreg = if lreg == STACK { STACK } else { compiler.alloc_reg() };
+    compiler.add_op_args(imm_op, lreg, imm as u8, resreg);
+    if negate {
+        compiler.add_op_args(Opcode::NOT, resreg, resreg, 0);
+    }
+    compiler.free_reg(lreg);
+    if let TokenType::PlusEquals | TokenType::MinusEquals = op {
+        // Set the variable
+        compile_set(compiler, lhs, resreg)?;
+    } else if clean {
+        // Clean up the stack
+        if resreg == STACK {
+            compiler.add_op(Opcode::POP);
+        }
+        compiler.free_reg(resreg);
+    }
+    return Some(resreg);
+}
+
+// If node can't run code or set a var, so it doesn't matter if vars are read before or after it
+fn can_read_after(node: &ASTNode) -> bool {
+    match node {
+        StringExpr(_) | NumberExpr(_) | DecimalExpr(_) | BoolExpr(_) | NoneExpr | ByteExpr(_)
+            | VarExpr(_) => true,
+        IndexExpr(list, index, _) => can_read_after(list) && can_read_after(index),
+        UnaryExpr(op, val) => {
+            !matches!(op, TokenType::PlusPlus | TokenType::MinusMinus) && can_read_after(val)
+        },
+        BinopExpr(lhs, op, rhs, _) => !matches!(op,
+            TokenType::Equals | TokenType::PlusEquals | TokenType::MinusEquals
+            | TokenType::TimesEquals | TokenType::DivEquals | TokenType::ModEquals
+        ) && can_read_after(lhs) && can_read_after(rhs),
+        ListExpr(keys, vals, _) => keys.iter().chain(vals).all(can_read_after),
+        _ => false,
+    }
+}
+
+fn compile_append(
+    compiler: &mut Compiler, lhs: &ASTNode, op: &TokenType, rhs: &ASTNode
+) -> Option<Reg> {
+    // Turns `s = s + x` and `s += x` into `AV s, x`, which doesn't copy `s`
+    let VarExpr(name) = lhs else {
+        return None;
+    };
+    let rhs = match (op, rhs) {
+        (TokenType::PlusEquals, _) => rhs,
+        (TokenType::Equals, BinopExpr(same, TokenType::Plus, rhs, _))
+            if **same == *lhs => &**rhs,
+        _ => return None,
+    };
+    // Small numbers are faster with ADDI
+    if let NumberExpr(n) = rhs {
+        if i8::try_from(*n).is_ok() {
+            return None;
+        }
+    }
+    // The var is read after the rhs, so the rhs can't be able to change it
+    if !can_read_after(rhs) {
+        return None;
+    }
+    compiler.get_var_offset(name)?;
+    let reg = compile_expr(compiler, rhs)?;
+    let op = if compiler.functi.is_none() { Opcode::AV_G } else { Opcode::AV_L };
+    compiler._var(name, reg, op)?;
+    compiler.free_reg(reg);
+    return Some(reg);
+}
+
 fn compile_binop<'a>(
     compiler: &mut Compiler,
     mut lhs: &'a ASTNode, op: &TokenType, mut rhs: &'a ASTNode,
@@ -470,6 +752,16 @@ fn compile_binop<'a>(
     if op == &TokenType::And || op == &TokenType::Or {
         return compile_short_binop(compiler, lhs, op, rhs, clean);
     }
+    // Appending to a var (`s += x` or `s = s + x`) can be done in place
+    if clean {
+        if let Some(reg) = compile_append(compiler, lhs, op, rhs) {
+            return Some(reg);
+        }
+    }
+    // So are ops with small numbers
+    if let Some(imm_op) = get_imm_op(op, rhs) {
+        return compile_imm_binop(compiler, lhs, op, imm_op, clean);
+    }
     // Makes stuff faster
     if op == &TokenType::In {
        (lhs, rhs) = (rhs, lhs);
@@ -502,6 +794,12 @@ fn compile_binop<'a>(
         TokenType::Modulo | TokenType::ModEquals => {
             compiler.add_op_args(Opcode::MOD, lreg, rreg, resreg);
         },
+        TokenType::BitOr => {
+            compiler.add_op_args(Opcode::BOR, lreg, rreg, resreg);
+        },
+        TokenType::BitAnd => {
+            compiler.add_op_args(Opcode::BAND, lreg, rreg, resreg);
+        },
         TokenType::And => {
             compiler.add_op_args(Opcode::AND, lreg, rreg, resreg);
         },
@@ -529,12 +827,10 @@ fn compile_binop<'a>(
             compiler.add_op_args(Opcode::NOT, resreg, resreg, 0);
         },
         TokenType::LtEquals => {
-            compiler.add_op_args(Opcode::GT, lreg, rreg, resreg);
-            compiler.add_op_args(Opcode::NOT, resreg, resreg, 0);
+            compiler.add_op_args(Opcode::LE, lreg, rreg, resreg);
         },
         TokenType::GtEquals => {
-            compiler.add_op_args(Opcode::LT, lreg, rreg, resreg);
-            compiler.add_op_args(Opcode::NOT, resreg, resreg, 0);
+            compiler.add_op_args(Opcode::GE, lreg, rreg, resreg);
         },
         TokenType::Colon => {
             compiler.add_op_args(Opcode::INX, lreg, rreg, resreg);
@@ -550,7 +846,9 @@ fn compile_binop<'a>(
         | TokenType::ModEquals | TokenType::Equals = op.clone()
     {
         let resreg = if *op == TokenType::Equals {
-            compiler.free_reg(resreg);
+            if resreg != rreg {
+                compiler.free_reg(resreg);
+            }
             rreg
         } else {
             compiler.to_mut_reg(resreg)
@@ -566,47 +864,220 @@ fn compile_binop<'a>(
         compiler.free_reg(lreg);
         return Some(resreg);
     }
+    // rreg is the result when it's a register, so only lreg is freed
     compiler.free_reg(lreg);
-    compiler.free_reg(rreg);
     return Some(resreg);
 }
 
+// Max number of nodes in an inlined functi, by opt level
+// Errors in inlined code point at the call and skip the functi in backtraces, so it's opt-in
+fn inline_limit(opt_level: u8) -> usize {
+    match opt_level {
+        0 | 1 => 0,
+        2 => 8,
+        _ => 32,
+    }
+}
+
+// The size of an expression that can be inlined, None if it can't be
+fn inline_size(expr: &ASTNode) -> Option<usize> {
+    Some(1 + match expr {
+        StringExpr(_) | NumberExpr(_) | DecimalExpr(_) | BoolExpr(_)
+        | NoneExpr | ByteExpr(_) | VarExpr(_) => 0,
+        IndexExpr(lhs, rhs, _) => inline_size(lhs)? + inline_size(rhs)?,
+        UnaryExpr(TokenType::Minus | TokenType::Not, val) => inline_size(val)?,
+        BinopExpr(lhs, op, rhs, _) => {
+            // Assignments and pipes (calls) have side effects
+            if matches!(op,
+                TokenType::Equals | TokenType::PlusEquals | TokenType::MinusEquals
+                | TokenType::TimesEquals | TokenType::DivEquals | TokenType::ModEquals
+                | TokenType::Pipe
+            ) {
+                return None;
+            }
+            inline_size(lhs)? + inline_size(rhs)?
+        },
+        ListExpr(keys, values, _) => {
+            let mut size = 0;
+            for node in keys.iter().chain(values.iter()) {
+                size += inline_size(node)?;
+            }
+            size
+        },
+        _ => return None,
+    })
+}
+
+// Calls f on every variable in an inlinable expression
+fn inline_vars(expr: &ASTNode, f: &mut impl FnMut(&String) -> bool) -> bool {
+    match expr {
+        VarExpr(name) => f(name),
+        IndexExpr(lhs, rhs, _) | BinopExpr(lhs, _, rhs, _) =>
+            inline_vars(lhs, f) && inline_vars(rhs, f),
+        UnaryExpr(_, val) => inline_vars(val, f),
+        ListExpr(keys, values, _) =>
+            keys.iter().chain(values.iter()).all(|node| inline_vars(node, f)),
+        _ => true,
+    }
+}
+
+// Replaces the arg names with the args
+fn inline_args(expr: &ASTNode, names: &[String], args: &[ASTNode]) -> ASTNode {
+    let sub = |node: &ASTNode| Box::new(inline_args(node, names, args));
+    match expr {
+        VarExpr(name) => {
+            let real_name = name.split("::").nth(1).unwrap_or(name);
+            match names.iter().position(|n| n == real_name) {
+                Some(at) => args[at].clone(),
+                None => expr.clone(),
+            }
+        },
+        IndexExpr(lhs, rhs, span) => IndexExpr(sub(lhs), sub(rhs), *span),
+        UnaryExpr(op, val) => UnaryExpr(op.clone(), sub(val)),
+        BinopExpr(lhs, op, rhs, span) => BinopExpr(sub(lhs), op.clone(), sub(rhs), *span),
+        ListExpr(keys, values, fast) => ListExpr(
+            keys.iter().map(|node| inline_args(node, names, args)).collect(),
+            values.iter().map(|node| inline_args(node, names, args)).collect(),
+            *fast
+        ),
+        _ => expr.clone(),
+    }
+}
+
+// Saves a functi for inlining if it's just `return <small expr>;`
+fn save_inline(compiler: &mut Compiler, functi: &FunctiNode, data: &FunctiData) {
+    let BodyStmt(ref body) = functi.body.node else {
+        return;
+    };
+    let [StmtNode { node: ReturnStmt(ref ret), .. }] = body[..] else {
+        return;
+    };
+    if inline_size(ret).is_none_or(|size| size > inline_limit(compiler.opt_level)) {
+        return;
+    }
+    let names: Vec<String> = data.locals[data.locals.len() - data.arg_num as usize..].iter()
+        .map(|var| var.real_name.clone()).collect();
+    // Anything else has to be a global
+    let ast = compiler.get_ast();
+    let ok = inline_vars(ret, &mut |name| {
+        let real_name = name.split("::").nth(1).unwrap_or(name);
+        names.iter().any(|n| n == real_name)
+            || ast.get_var_offset(name.clone(), None).is_some()
+    });
+    // Static calls go to the first definition
+    let first = compiler.program.functis.iter()
+        .filter(|i| i.0 == functi.name && i.2 == data.arg_num).count() == 1;
+    if ok && first {
+        compiler.inlines.push((functi.name.clone(), data.arg_num, names, *ret.clone()));
+    }
+}
+
+// Compiles a call to a small functi as the functi body
+fn compile_inline(compiler: &mut Compiler, expr: &ASTNode, args: &[ASTNode]) -> Option<Reg> {
+    let VarExpr(ref n) = *expr else {
+        return None;
+    };
+    let n = n.split("::").nth(1).unwrap();
+    let (_, _, names, body) = compiler.inlines.iter()
+        .find(|i| i.0 == n && i.1 == args.len() as i32)?.clone();
+    // Args are evaluated once, so only simple ones can be copied
+    if !args.iter().all(|arg| matches!(arg,
+        StringExpr(_) | NumberExpr(_) | DecimalExpr(_) | BoolExpr(_)
+        | NoneExpr | ByteExpr(_) | VarExpr(_)
+    )) {
+        return None;
+    }
+    // Globals can't be shadowed by the caller's locals
+    if let Some(ref functi) = compiler.functi {
+        let shadowed = !inline_vars(&body, &mut |name| {
+            let real_name = name.split("::").nth(1).unwrap_or(name);
+            names.iter().any(|n| n == real_name)
+                || !functi.locals.iter().any(|var| var.real_name == real_name)
+        });
+        if shadowed {
+            return None;
+        }
+    }
+    compile_expr(compiler, &inline_args(&body, &names, args))
+}
+
+fn compile_reftype(compiler: &mut Compiler, name: &String) -> Option<Reg> {
+    let (offset, global) = compiler.get_var_offset(name)?;
+    if global {
+        // Global offsets don't change
+        return Some(compiler.push(Value::RefType(offset, global)));
+    }
+    // Local offsets do, and need to be figured out at runtime
+    let reg = compiler.alloc_reg();
+    compiler.add_op_args(
+        Opcode::ALO,
+        ((offset >> 8) & 255) as u8,
+        (offset & 255) as u8,
+        reg as u8
+    );
+    return Some(reg);
+}
+
+// The var a builtin like push changes, it's given a reference to it instead of the value
+fn changed_var<'a>(compiler: &mut Compiler, expr: &ASTNode, args: &'a [ASTNode]) -> Option<&'a String> {
+    let (VarExpr(n), Some(VarExpr(var))) = (expr, args.first()) else {
+        return None;
+    };
+    // A var with the same name hides the builtin
+    if !changes_var(n.split("::").nth(1)?) || compiler.get_var_offset(n).is_some() {
+        return None;
+    }
+    compiler.get_var_offset(var)?;
+    return Some(var);
+}
+
+// Which type a call like `is_num(x)` checks, they're one op instead of a call
+fn type_checked(compiler: &mut Compiler, expr: &ASTNode, args: &[ASTNode]) -> Option<u8> {
+    let VarExpr(n) = expr else {
+        return None;
+    };
+    if args.len() != 1 || compiler.get_var_offset(n).is_some() {
+        return None;
+    }
+    let name = n.split("::").nth(1)?;
+    TYPE_CHECKS.iter().position(|check| *check == name).map(|ty| ty as u8)
+}
+
 fn compile_call(compiler: &mut Compiler, expr: &ASTNode, args: &Vec<ASTNode>) -> Option<Reg> {
     if let ASTNode::VarExpr(ref n) = *expr {
         let n = n.clone().split("::").nth(1).unwrap().to_string();
         if n == "__burlap_reftype" {
-            let Some(VarExpr(name)) = args.get(0) else {
-                println!("Compiler Error (internal): __burlap_reftype requires a variable");
-                return None;
+            let ret = match args.get(0) {
+                Some(VarExpr(name)) => compile_reftype(compiler, name),
+                _ => None,
             };
-            let Some((offset, global)) = compiler.get_var_offset(name) else {
+            if ret.is_none() {
                 println!("Compiler Error (internal): __burlap_reftype requires a variable");
-                return None;
-            };
-            if global {
-                // Global offsets don't change
-                return Some(compiler.push(Value::RefType(offset, global)));
-            } else {
-                // Local offsets do, and need to be figured out at runtime
-                let reg = compiler.alloc_reg();
-                compiler.add_op_args(
-                    Opcode::ALO,
-                    ((offset >> 8) & 255) as u8,
-                    (offset & 255) as u8,
-                    reg as u8
-                );
-                return Some(reg);
             }
+            return ret;
         } else if n == "__burlap_debug_blackbox" {
             return compile_expr(compiler, &args[0]);
         }
     }
+    if let Some(ty) = type_checked(compiler, expr, args) {
+        let val = compile_expr(compiler, &args[0])?;
+        let res = compiler.get_sole_reg(val);
+        compiler.add_op_args(Opcode::IST, val, ty, res);
+        return Some(res);
+    }
+    if let Some(reg) = compile_inline(compiler, expr, args) {
+        return Some(reg);
+    }
     // Push the args onto the stack
+    let changed = changed_var(compiler, expr, args);
     let old_on_stack = compiler.on_stack_only;
     // TODO: Instead of on_stack_only, use a target reg
     compiler.on_stack_only = true;
-    for arg in args.iter() {
-        let reg = compile_expr(compiler, arg)?;
+    for (at, arg) in args.iter().enumerate() {
+        let reg = match changed {
+            Some(var) if at == 0 => compile_reftype(compiler, var)?,
+            _ => compile_expr(compiler, arg)?,
+        };
         if reg != STACK {
             compiler.move_(reg, STACK);
         }
@@ -684,15 +1155,21 @@ fn compile_expr(compiler: &mut Compiler, node: &ASTNode) -> Option<Reg> {
             compiler.push(Value::Byte(*val))
         },
         // Binop/unary
-        BinopExpr(lhs, op, rhs) => {
-            return compile_binop(compiler, lhs, op, rhs, false);
+        BinopExpr(lhs, op, rhs, span) => {
+            let start = compiler.program.ops.len();
+            let reg = compile_binop(compiler, lhs, op, rhs, false)?;
+            compiler.add_span(start, span);
+            reg
         }
         UnaryExpr(op, val) => {
             return compile_unary(compiler, op, val);
         },
         // Calls
-        CallExpr(expr, args) => {
-            return compile_call(compiler, expr, args);
+        CallExpr(expr, args, span) => {
+            let start = compiler.program.ops.len();
+            let reg = compile_call(compiler, expr, args)?;
+            compiler.add_span(start, span);
+            reg
         },
         // List
         ListExpr(keys, values, fast) => {
@@ -706,7 +1183,11 @@ fn compile_expr(compiler: &mut Compiler, node: &ASTNode) -> Option<Reg> {
                     compiler.free_reg(reg);
                 }
                 if !*fast {
-                    compiler.push_to_stack(Value::Str(Rc::new(keys[at].clone())));
+                    let reg = compile_expr(compiler, &keys[at])?;
+                    if reg != STACK {
+                        compiler.move_(reg, STACK);
+                        compiler.free_reg(reg);
+                    }
                 }
             }
             compiler.on_stack_only = old_on_stack;
@@ -731,14 +1212,34 @@ fn compile_expr(compiler: &mut Compiler, node: &ASTNode) -> Option<Reg> {
             reg
         },
         // Indexes
-        IndexExpr(val, index) => {
+        IndexExpr(val, index, span) => {
             // Push
+            let start = compiler.program.ops.len();
             let index = compile_expr(compiler, index)?;
             let expr = compile_expr(compiler, val)?;
             compiler.add_op_args(Opcode::INX, expr as u8, index as u8, expr as u8);
+            compiler.add_span(start, span);
             compiler.free_reg(index);
             expr
         },
+        // Desugared statements
+        BlockExpr(stmts, val) => {
+            let old_on_stack = compiler.on_stack_only;
+            compiler.on_stack_only = false;
+            for stmt in stmts {
+                compile_stmt(compiler, &None, stmt, false)?;
+            }
+            compiler.on_stack_only = old_on_stack;
+            compile_expr(compiler, val)?
+        },
+        // `?`, returns errs and unwraps everything else
+        TryExpr(val) => {
+            let reg = compile_expr(compiler, val)?;
+            let dst = compiler.get_mut_reg(reg);
+            compiler.add_op_args(Opcode::TRY, reg, 2, dst);
+            compiler.add_op(Opcode::RET);
+            dst
+        },
         // Anonymous functions
         FunctiStmt(node) => {
             compile_functi(compiler, &None, node, false)?;
@@ -777,9 +1278,10 @@ fn compile_functi(
     compiler: &mut Compiler, filename: &Option<String>, functi: &FunctiNode, _anon: bool
 ) -> Option<()> {
     let data = compiler.get_ast().get_functi(functi.name.clone()).unwrap();
-    // TODO: Assert that old_functi is never Some(...) when anon is false
     let old_functi = compiler.functi.clone();
+    let old_needs_args = compiler.needs_args;
     compiler.functi = Some(data.clone());
+    compiler.needs_args = false;
     // Jump around function
     compiler.add_op(Opcode::JMP);
     let pos = compiler.program.ops.len();
@@ -787,14 +1289,15 @@ fn compile_functi(
     compiler.program.functis.push((
         functi.name.clone(),
         compiler.program.ops.len(),
-        data.arg_num
+        data.arg_num,
+        data.doc.clone()
     ));
     // Arg saving
     let start = compiler.program.ops.len();
     compiler.add_op(Opcode::NOP);
     // Load args from stack
     let arg_num = data.arg_num as usize;
-    let lclen = data.locals.len() - arg_num;
+    let lclen = data.frame_size();
     compiler.add_op_args(
         Opcode::PLC,
         ((lclen >> 8) & 255) as u8,
@@ -803,6 +1306,9 @@ fn compile_functi(
     );
     // Compile body
     compile_body(compiler, filename, &functi.body)?;
+    if compiler.opt_level > 1 {
+        save_inline(compiler, functi, data);
+    }
     // Return
     compiler.push_to_stack(Value::None);
     compiler.add_op(Opcode::RET);
@@ -810,14 +1316,26 @@ fn compile_functi(
     if compiler.needs_args {
         compiler.program.ops[start] = ((Opcode::SARG as u32) << 24)
             + ((arg_num as u32 & 255) << 16);
-        compiler.needs_args = false;
     }
     // Fill jump
     compiler.fill_jmp(pos, 0, None);
     compiler.functi = old_functi;
+    compiler.needs_args = old_needs_args;
     Some(())
 }
 
+// Moves loads that don't change out of the loop starting at top
+fn hoist_loop(compiler: &mut Compiler, top: usize) {
+    if compiler.opt_level == 0 {
+        return;
+    }
+    let free: Vec<u8> = (0..16).filter(|reg| compiler.regs[*reg as usize]).collect();
+    if let Some(moves) = optimizer::hoist_loop(&mut compiler.program, top, &free) {
+        compiler.line_start = moves.map(compiler.line_start as usize) as u32;
+        compiler.inc_start = moves.map(compiler.inc_start as usize) as u32;
+    }
+}
+
 fn compile_stmt(
     compiler: &mut Compiler, filename: &Option<String>, node: &StmtNode, dirty: bool
 ) -> Option<()> {
@@ -834,6 +1352,7 @@ fn compile_stmt(
             for (name, val) in names.iter().zip(vals.iter()) {
                 let vreg = compile_expr(compiler, val)?;
                 compiler.set_var(name, vreg);
+                compiler.free_reg(vreg);
             }
         },
         IfStmt(cond, body, else_part) => {
@@ -905,6 +1424,7 @@ fn compile_stmt(
                 compiler.fill_jmp(*addr, 0, None);
             }
             compiler.break_addrs.truncate(last_size);
+            hoist_loop(compiler, compiler.loop_top);
             compiler.loop_top = old_top;
         },
         IterLoopStmt(var, iter, body, _already_def) => {
@@ -939,9 +1459,11 @@ fn compile_stmt(
                 compiler.fill_jmp(*addr, 0, None);
             }
             compiler.break_addrs.truncate(last_size);
+            let top = compiler.loop_top;
             compiler.loop_top = old_top;
             // Clean up the iter
             compiler.fill_jmp(jmp_pos, 0, None);
+            hoist_loop(compiler, top);
             compiler.free_reg(iter);
             if iter == STACK {
                 compiler.add_op(Opcode::POP);
@@ -975,6 +1497,7 @@ fn compile_stmt(
             }
             // Exit jump
             compiler.fill_jmp(exit_jump_pos, 0, Some(cond));
+            hoist_loop(compiler, compiler.loop_top);
             compiler.loop_top = old_top;
             compiler.free_reg(cond);
         },
@@ -992,7 +1515,7 @@ fn compile_stmt(
             return compile_functi(compiler, filename, node, false);
         },
         ReturnStmt(ret) => {
-            if let CallExpr(expr, args) = *ret.clone() {
+            if let CallExpr(expr, args, _) = *ret.clone() {
                 let functi = compiler.program.functis.last().unwrap().clone();
                 let do_tco = if let ASTNode::VarExpr(name) = *expr {
                     name == functi.0 && args.len() == functi.1
@@ -1032,9 +1555,24 @@ fn compile_stmt(
             // Return return value
             compiler.add_op(Opcode::RET);
         },
-        ImportStmt => {
+        DeferStmt(stmt) => {
+            // Jump around the deferred code
+            compiler.add_op(Opcode::JMP);
+            let pos = compiler.program.ops.len();
+            compile_stmt(compiler, filename, stmt, false)?;
+            compiler.add_op(Opcode::RDEF);
+            compiler.fill_jmp(pos, 0, None);
+            // Schedule it
+            compiler.add_op_args(
+                Opcode::DEFER,
+                ((pos >> 16) & 255) as u8,
+                ((pos >> 8) & 255) as u8,
+                (pos & 255) as u8
+            );
+        },
+        ImportStmt(file) => {
             compiler.program.file_table.push((
-                compiler.inc_start, compiler.program.ops.len() as u32, filename.clone().unwrap()
+                compiler.inc_start, compiler.program.ops.len() as u32, file.clone()
             ));
             compiler.inc_start = compiler.program.ops.len() as u32;
         },
@@ -1050,8 +1588,10 @@ fn compile_stmt(
         },
         // Expressions
         // Binops don't always return, so let them manage cleaning the stack
-        BinopExpr(lhs, op, rhs) => {
+        BinopExpr(lhs, op, rhs, span) => {
+            let start = compiler.program.ops.len();
             let reg = compile_binop(compiler, lhs, op, rhs, !dirty)?;
+            compiler.add_span(start, span);
             if dirty && reg != STACK {
                 // Copy to stack
                 compiler.move_(reg, STACK);
@@ -1075,6 +1615,41 @@ fn compile_stmt(
     return Some(());
 }
 
+// Names of the global slots, functi locals share the table so they're left empty
+fn global_names(ast: &AST, main: &str) -> Vec<String> {
+    // (first slot, uses) and local uses for each name in each file
+    let mut uses: HashMap<(&str, &str), (usize, usize)> = HashMap::new();
+    for (at, v) in ast.all_vars.iter().enumerate() {
+        uses.entry((&v.real_name, &v.module)).or_insert((at, 0)).1 += 1;
+    }
+    let mut local_uses: HashMap<(&str, &str), usize> = HashMap::new();
+    for v in ast.functis.iter().flat_map(|f| f.locals.iter()) {
+        *local_uses.entry((&v.real_name, &v.module)).or_insert(0) += 1;
+    }
+    let mut names: Vec<String> = ast.all_vars.iter().enumerate().map(|(at, v)| {
+        let key = (v.real_name.as_str(), v.module.as_str());
+        // Only the first slot with a name is used (each file has its own)
+        let (first, count) = uses[&key];
+        let local_count = local_uses.get(&key).copied().unwrap_or(0);
+        if first == at && count > local_count { v.real_name.clone() } else { String::new() }
+    }).collect();
+    // Globals from different files can have the same name, the main file's (or the first) keeps it
+    let rank = |at: usize| (ast.all_vars[at].module != main, at);
+    let mut best: HashMap<&str, (bool, usize)> = HashMap::new();
+    for (at, name) in names.iter().enumerate().filter(|(_, n)| !n.is_empty()) {
+        let old = best.entry(name).or_insert(rank(at));
+        *old = (*old).min(rank(at));
+    }
+    let qualify: Vec<usize> = names.iter().enumerate()
+        .filter(|(at, name)| !name.is_empty() && best[name.as_str()] < rank(*at))
+        .map(|(at, _)| at).collect();
+    for at in qualify {
+        let file = Path::new(&ast.all_vars[at].module).file_stem().unwrap_or_default();
+        names[at] = format!("{}::{}", file.to_string_lossy(), names[at]);
+    }
+    return names;
+}
+
 pub fn compile(
     ast: &mut AST, filename: &Option<String>, compiler: &mut Compiler, repl: bool
 ) -> bool {
@@ -1089,7 +1664,9 @@ pub fn compile(
         0
     );
     compiler.ast = ast;
+    compiler.program.global_names = global_names(ast, filename.as_deref().unwrap_or(""));
     compiler.inc_start = compiler.program.ops.len() as u32;
+    let start = compiler.program.ops.len();
     // Compile
     for node in &ast.nodes[..ast.nodes.len()-1] {
         if compile_stmt(compiler, filename, node, false).is_none() {
@@ -1113,6 +1690,99 @@ pub fn compile(
     compiler.program.line_table.push((
         compiler.line_start, compiler.program.ops.len() as u32, last.line
     ));
+    if compiler.opt_level > 0 {
+        if let Some(moves) = optimizer::simplify_jumps(&mut compiler.program, start) {
+            compiler.line_start = moves.map(compiler.line_start as usize) as u32;
+            compiler.inc_start = moves.map(compiler.inc_start as usize) as u32;
+        }
+    }
     compiler.ast = null_mut();
     return true;
 }
+
+// Compiles a program a piece at a time (the REPL, eval, and runtime imports)
+// New code only ever goes on the end, so offsets into older code stay valid
+pub struct IncrementalCompiler {
+    compiler: Compiler,
+}
+
+// How big the program was before a piece, so a failed piece can be removed
+struct Checkpoint {
+    ops: usize,
+    consts: usize,
+    functis: usize,
+    global_names: Vec<String>,
+    tables: (usize, usize, usize),
+    inlines: usize,
+    starts: (u32, u32, usize),
+}
+
+impl IncrementalCompiler {
+    pub fn new(program: Program, opt_level: u8) -> IncrementalCompiler {
+        let mut compiler = Compiler::from_program(program);
+        compiler.opt_level = opt_level;
+        IncrementalCompiler { compiler }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.compiler.program
+    }
+
+    // Lends the program out (to the VM), it has to be given back before compiling more
+    pub fn take_program(&mut self) -> Program {
+        std::mem::replace(&mut self.compiler.program, Program::new())
+    }
+
+    pub fn give_program(&mut self, program: Program) {
+        self.compiler.program = program;
+    }
+
+    // Compiles the nodes of ast onto the end, returns where the new code starts
+    // If it fails, the program is left how it was
+    pub fn compile(
+        &mut self, ast: &mut AST, filename: &Option<String>, repl: bool
+    ) -> Option<usize> {
+        let checkpoint = self.checkpoint();
+        let start = checkpoint.ops;
+        if compile(ast, filename, &mut self.compiler, repl) {
+            return Some(start);
+        }
+        self.rollback(checkpoint);
+        None
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        let (compiler, program) = (&self.compiler, &self.compiler.program);
+        Checkpoint {
+            ops: program.ops.len(),
+            consts: program.consts.len(),
+            functis: program.functis.len(),
+            global_names: program.global_names.clone(),
+            tables: (
+                program.line_table.len(), program.file_table.len(), program.span_table.len()
+            ),
+            inlines: compiler.inlines.len(),
+            starts: (compiler.inc_start, compiler.line_start, compiler.old_line),
+        }
+    }
+
+    fn rollback(&mut self, checkpoint: Checkpoint) {
+        let compiler = &mut self.compiler;
+        let program = &mut compiler.program;
+        program.ops.truncate(checkpoint.ops);
+        program.consts.truncate(checkpoint.consts);
+        program.functis.truncate(checkpoint.functis);
+        program.global_names = checkpoint.global_names;
+        program.line_table.truncate(checkpoint.tables.0);
+        program.file_table.truncate(checkpoint.tables.1);
+        program.span_table.truncate(checkpoint.tables.2);
+        compiler.inlines.truncate(checkpoint.inlines);
+        (compiler.inc_start, compiler.line_start, compiler.old_line) = checkpoint.starts;
+        // Anything half done
+        compiler.regs = [true; 17];
+        compiler.needs_args = false;
+        compiler.on_stack_only = false;
+        compiler.break_addrs.clear();
+        compiler.functi = None;
+    }
+}
@@ -1,5 +1,5 @@
 // This disassembles the VM's bytecode into a human readable form
-use crate::backend::vm::vm::{Opcode, shift2, shift3};
+use crate::backend::vm::vm::{Opcode, shift2, shift3, TYPE_CHECKS};
 use crate::backend::vm::compiler::Program;
 use Opcode::*;
 
@@ -30,6 +30,7 @@ pub fn dis_single(program: &Program, at: usize) -> String {
         NOP => "NOP".to_string(),
         POP => "POP".to_string(),
         RET => "RET".to_string(),
+        RDEF => "RDEF".to_string(),
 
         CP => format!("CP r{a}, r{b}"),
         SARG => format!("SARG {a}"),
@@ -37,15 +38,23 @@ pub fn dis_single(program: &Program, at: usize) -> String {
         CALL => format!("CALL @{}", shift3(a, b, c)),
         VCALL => format!("VCALL r{a}, {b}"),
         RCALL => format!("RCALL @{}", shift3(a, b, c)),
+        DEFER => format!("DEFER @{}", shift3(a, b, c)),
+        TRY => format!("TRY r{a}, @{}, r{c}", at + b as usize),
         LV_L => format!("LV(L) {}, r{c}", shift2(a, b)),
         LV_G => format!("LV(G) {}, r{c}", shift2(a, b)),
         SV_L => format!("SV(L) {}, r{c}", shift2(a, b)),
         SV_G => format!("SV(G) {}, r{c}", shift2(a, b)),
+        AV_L => format!("AV(L) {}, r{c}", shift2(a, b)),
+        AV_G => format!("AV(G) {}, r{c}", shift2(a, b)),
+        INC_L => format!("INC(L) {}, r{c}", shift2(a, b)),
+        INC_G => format!("INC(G) {}, r{c}", shift2(a, b)),
+        DEC_L => format!("DEC(L) {}, r{c}", shift2(a, b)),
+        DEC_G => format!("DEC(G) {}, r{c}", shift2(a, b)),
         ALO => format!("ALO +{}, r{c}", shift2(a, b)),
         PLC => format!("PLC {}, {c}", shift2(a, b)),
         PGB => format!("PGB {}", shift2(a, b)),
-        LFL => format!("LFL r{a}, {}", shift2(a, b)),
-        LL => format!("LL r{a}, {}", shift2(a, b)),
+        LFL => format!("LFL r{a}, {}", shift2(b, c)),
+        LL => format!("LL r{a}, {}", shift2(b, c)),
         INX => format!("INX r{a}, r{b}, r{c}"),
         ITER => format!("ITER r{a}, r{b}"),
         NXT => format!("NXT r{a}, r{b}, @{}", at + c as usize),
@@ -60,13 +69,23 @@ pub fn dis_single(program: &Program, at: usize) -> String {
         MUL => dis_binop("MUL", a, b, c),
         DIV => dis_binop("DIV", a, b, c),
         MOD => dis_binop("MOD", a, b, c),
+        BOR => dis_binop("BOR", a, b, c),
+        BAND => dis_binop("BAND", a, b, c),
         AND => dis_binop("AND", a, b, c),
         XOR => dis_binop("XOR", a, b, c),
         OR => dis_binop("OR", a, b, c),
         EQ => dis_binop("EQ", a, b, c),
         GT => dis_binop("GT", a, b, c),
+        GE => dis_binop("GE", a, b, c),
+        LE => dis_binop("LE", a, b, c),
         LT => dis_binop("LT", a, b, c),
         IN => dis_binop("IN", a, b, c),
+        ADDI => format!("ADDI r{a}, {}, r{c}", b as i8),
+        SUBI => format!("SUBI r{a}, {}, r{c}", b as i8),
+        EQI => format!("EQI r{a}, {}, r{c}", b as i8),
+        LTI => format!("LTI r{a}, {}, r{c}", b as i8),
+        GEI => format!("GEI r{a}, {}, r{c}", b as i8),
+        IST => format!("IST r{a}, {}, r{c}", TYPE_CHECKS[b as usize]),
 
         LD => {
             let nsrc = shift2(a, b);
@@ -81,8 +100,27 @@ pub fn dis_single(program: &Program, at: usize) -> String {
     }
 }
 
-pub fn dis(program: &Program, start: usize) {
+pub fn dis_to_string(program: &Program, start: usize) -> String {
+    let mut ret = String::new();
     for i in start..program.ops.len() {
+        ret += &format!("{i}: {}\n", dis_single(program, i));
+    }
+    return ret;
+}
+
+pub fn dis(program: &Program, start: usize) {
+    print!("{}", dis_to_string(program, start));
+}
+
+pub fn dis_functi(program: &Program, name: &str) -> bool {
+    let Some(functi) = program.functis.iter().rev().find(|f| f.0 == name) else {
+        return false;
+    };
+    // The jump around the functi says where it ends
+    let (_, a, b, c) = decode_op(program.ops[functi.1 - 1]);
+    let end = functi.1 - 1 + shift3(a, b, c);
+    for i in functi.1..end.min(program.ops.len()) {
         println!("{i}: {}", dis_single(program, i));
     }
+    return true;
 }
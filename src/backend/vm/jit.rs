@@ -0,0 +1,599 @@
+// The JIT, functis that are called a lot are compiled to native code with Cranelift
+// Only functis that use numbers and bools in locals (and call themselves) are compiled,
+// they can't have side effects, so anything unexpected reruns the call in the interpreter
+use cranelift_codegen::ir::{
+    condcodes::{FloatCC, IntCC}, types, AbiParam, Block, InstBuilder, MemFlags, StackSlotData,
+    StackSlotKind, Value as Reg,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Module};
+use rustc_hash::FxHashMap;
+
+use crate::Arguments;
+use crate::backend::value::{Overflow, Value};
+use crate::backend::vm::compiler::Program;
+use crate::backend::vm::optimizer::{decode_op, jump_target};
+use crate::backend::vm::vm::{shift2, shift3, Opcode::*};
+
+// Calls before a functi is compiled
+const THRESHOLD: u32 = 1000;
+// Native calls nested deeper than this bail, so the interpreter's stack is used instead
+const NATIVE_DEPTH: usize = 10000;
+// Bail flags, the interpreter has to do it, or the calls went too deep
+const BAIL: u8 = 1;
+const TOO_DEEP: u8 = 2;
+
+// The type of a value while compiling
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Ty {
+    // Locals that haven't been set
+    Unset,
+    Int,
+    Bool,
+    // Different on different paths
+    Mixed,
+}
+
+impl Ty {
+    fn join(self, other: Ty) -> Ty {
+        if self == other { self } else { Ty::Mixed }
+    }
+}
+
+// The types before an op
+#[derive(Clone, PartialEq)]
+struct State {
+    regs: [Ty; 16],
+    locals: Vec<Ty>,
+    stack: Vec<Ty>,
+}
+
+impl State {
+    fn join(&mut self, other: &State) -> Option<bool> {
+        if self.stack.len() != other.stack.len() {
+            return None;
+        }
+        let old = self.clone();
+        for (ty, other) in self.regs.iter_mut().zip(other.regs) {
+            *ty = ty.join(other);
+        }
+        for (ty, other) in self.locals.iter_mut().zip(&other.locals) {
+            *ty = ty.join(*other);
+        }
+        for (ty, other) in self.stack.iter_mut().zip(&other.stack) {
+            *ty = ty.join(*other);
+        }
+        Some(*self != old)
+    }
+
+    fn read(&mut self, program: &Program, reg: u8) -> Option<Ty> {
+        let ty = match reg {
+            0..=15 => self.regs[reg as usize],
+            16 => self.stack.pop()?,
+            17..=115 => match program.consts[reg as usize - 17] {
+                Value::Int(_) => Ty::Int,
+                Value::Bool(_) => Ty::Bool,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        matches!(ty, Ty::Int | Ty::Bool).then_some(ty)
+    }
+
+    fn write(&mut self, reg: u8, ty: Ty) -> Option<()> {
+        match reg {
+            0..=15 => self.regs[reg as usize] = ty,
+            16 => self.stack.push(ty),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn local(&self, offset: usize) -> Option<usize> {
+        self.locals.len().checked_sub(offset + 1)
+    }
+}
+
+// The shape of a functi that can be compiled
+struct Plan {
+    entry: usize,
+    argc: usize,
+    returns: Ty,
+    // The types before each op, None if it can't be reached
+    states: Vec<Option<State>>,
+    locals: usize,
+    max_stack: usize,
+}
+
+// Finds the types of everything, None if something can't be compiled
+fn plan(program: &Program, entry: usize, argc: usize, returns: Ty) -> Option<Plan> {
+    let ops = &program.ops;
+    // The jump around the functi says where it ends
+    let end = jump_target(*ops.get(entry.checked_sub(1)?)?, entry - 1)?;
+    let (code, a, b, c) = decode_op(*ops.get(entry + 1)?);
+    if decode_op(ops[entry]).0 != NOP || code != PLC || c as usize != argc {
+        return None;
+    }
+    let mut locals = vec![Ty::Int; argc];
+    locals.resize(argc + shift2(a, b), Ty::Unset);
+    let start = entry + 2;
+    let mut states: Vec<Option<State>> = vec![None; end - entry];
+    states[start - entry] = Some(State { regs: [Ty::Unset; 16], locals, stack: vec![] });
+    let mut work = vec![start];
+    let mut max_stack = 0;
+    while let Some(at) = work.pop() {
+        let mut state = states[at - entry].clone().unwrap();
+        let op = ops[at];
+        let (code, a, b, c) = decode_op(op);
+        let mut next = vec![at + 1];
+        match code {
+            NOP => {},
+            LD => {
+                let ty = match program.consts[shift2(a, b)] {
+                    Value::Int(_) => Ty::Int,
+                    Value::Bool(_) => Ty::Bool,
+                    _ => return None,
+                };
+                state.write(c, ty)?;
+            },
+            LDL => {
+                let ty = match program.consts[shift3(a, b, c)] {
+                    Value::Int(_) => Ty::Int,
+                    Value::Bool(_) => Ty::Bool,
+                    _ => return None,
+                };
+                state.stack.push(ty);
+            },
+            CP => {
+                let ty = if a == 16 { *state.stack.last()? } else { state.read(program, a)? };
+                state.write(b, ty)?;
+            },
+            POP => { state.stack.pop()?; },
+            CALL if shift3(a, b, c) == entry => {
+                for _ in 0..argc {
+                    (state.stack.pop()? == Ty::Int).then_some(())?;
+                }
+                state.stack.push(returns);
+            },
+            RET => {
+                (state.stack.last()? == &returns).then_some(())?;
+                next.clear();
+            },
+            LV_L => {
+                let ty = state.locals[state.local(shift2(a, b))?];
+                matches!(ty, Ty::Int | Ty::Bool).then_some(())?;
+                state.write(c, ty)?;
+            },
+            SV_L => {
+                let ty = state.read(program, c)?;
+                let at = state.local(shift2(a, b))?;
+                state.locals[at] = ty;
+            },
+            AV_L | INC_L | DEC_L => {
+                if code == AV_L {
+                    (state.read(program, c)? == Ty::Int).then_some(())?;
+                }
+                let at = state.local(shift2(a, b))?;
+                (state.locals[at] == Ty::Int).then_some(())?;
+                if code != AV_L {
+                    state.write(c, Ty::Int)?;
+                }
+            },
+            ADD | SUB | MUL | MOD | BOR | BAND | EQ | LT | GT | GE | LE => {
+                let rhs = state.read(program, b)?;
+                let lhs = state.read(program, a)?;
+                // Only numbers can be compared as they're both numbers or both bools
+                let ok = lhs == rhs && (lhs == Ty::Int || code == EQ);
+                ok.then_some(())?;
+                state.write(c, if matches!(code, EQ | LT | GT | GE | LE) { Ty::Bool } else { Ty::Int })?;
+            },
+            ADDI | SUBI | EQI | LTI | GEI => {
+                (state.read(program, a)? == Ty::Int).then_some(())?;
+                state.write(c, if matches!(code, EQI | LTI | GEI) { Ty::Bool } else { Ty::Int })?;
+            },
+            AND | OR | XOR => {
+                state.read(program, b)?;
+                state.read(program, a)?;
+                state.write(c, Ty::Bool)?;
+            },
+            NOT => {
+                state.read(program, a)?;
+                state.write(b, Ty::Bool)?;
+            },
+            JMP | JMPB => next = vec![jump_target(op, at)?],
+            JMPNT => {
+                state.read(program, a)?;
+                next.push(jump_target(op, at)?);
+            },
+            _ => return None,
+        }
+        max_stack = max_stack.max(state.stack.len());
+        for to in next {
+            if to < start || to >= end {
+                return None;
+            }
+            match &mut states[to - entry] {
+                Some(old) => if old.join(&state)? {
+                    work.push(to);
+                },
+                slot => {
+                    *slot = Some(state.clone());
+                    work.push(to);
+                },
+            }
+        }
+    }
+    // Mixed values can be moved around, but not used
+    let locals = states[start - entry].as_ref()?.locals.len();
+    Some(Plan { entry, argc, returns, states, locals, max_stack })
+}
+
+// Compiled code: (bail flag, calls left, args) -> return value
+type Native = unsafe extern "C" fn(*mut u8, i32, *const i32) -> i32;
+
+pub struct Compiled {
+    native: Native,
+    pub argc: usize,
+    returns: Ty,
+}
+
+#[derive(Default)]
+pub struct Jit {
+    // Calls to each functi, by address
+    counts: FxHashMap<usize, u32>,
+    // None if it couldn't be compiled
+    compiled: FxHashMap<usize, Option<Compiled>>,
+    // Holds the code, so it's only made when something is compiled
+    module: Option<JITModule>,
+    // Where native calls last went too deep, calls just under it stay in the interpreter
+    too_deep: Option<usize>,
+}
+
+impl Jit {
+    // Counts a call, and gets the compiled code once it's hot
+    pub fn get(&mut self, program: &Program, addr: usize, args: &Arguments) -> Option<&Compiled> {
+        if !self.compiled.contains_key(&addr) {
+            let count = self.counts.entry(addr).or_insert(0);
+            *count += 1;
+            if *count < THRESHOLD {
+                return None;
+            }
+            let compiled = self.compile(program, addr, args);
+            self.compiled.insert(addr, compiled);
+        }
+        self.compiled.get(&addr)?.as_ref()
+    }
+
+    // Runs compiled code at a call depth, None if the interpreter has to do it instead
+    pub fn call(
+        &mut self, addr: usize, args: &[i32], depth: usize, max_depth: usize
+    ) -> Option<Value> {
+        // Otherwise rerunning a deep call would go native (and bail) at every level
+        if self.too_deep.is_some_and(|at| depth > at && depth < at + NATIVE_DEPTH) {
+            return None;
+        }
+        let compiled = self.compiled.get(&addr)?.as_ref()?;
+        let left = if max_depth == 0 {
+            NATIVE_DEPTH
+        } else {
+            max_depth.saturating_sub(depth).min(NATIVE_DEPTH)
+        };
+        let mut bail = 0u8;
+        let ret = unsafe { (compiled.native)(&mut bail, left as i32, args.as_ptr()) };
+        match bail {
+            0 => {},
+            TOO_DEEP => {
+                self.too_deep = Some(depth);
+                return None;
+            },
+            _ => return None,
+        }
+        Some(if compiled.returns == Ty::Bool { Value::Bool(ret != 0) } else { Value::Int(ret) })
+    }
+
+    // Forgets everything, for when code changes (the old code is kept until the JIT is gone)
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.compiled.clear();
+        self.too_deep = None;
+    }
+
+    fn compile(&mut self, program: &Program, addr: usize, args: &Arguments) -> Option<Compiled> {
+        // Native math wraps, so other overflow modes stay in the VM
+        if args.overflow != Overflow::Wrap {
+            return None;
+        }
+        let functi = program.functis.iter().find(|f| f.1 == addr)?;
+        let argc = usize::try_from(functi.2).ok()?;
+        let plan = plan(program, addr, argc, Ty::Int)
+            .or_else(|| plan(program, addr, argc, Ty::Bool))?;
+        if self.module.is_none() {
+            let mut flags = settings::builder();
+            flags.set("opt_level", "speed").ok()?;
+            let isa = cranelift_native::builder().ok()?
+                .finish(settings::Flags::new(flags)).ok()?;
+            self.module = Some(JITModule::new(JITBuilder::with_isa(isa, default_libcall_names())));
+        }
+        let module = self.module.as_mut()?;
+        let ptr = module.target_config().pointer_type();
+        let mut ctx = module.make_context();
+        ctx.func.signature.params.extend([
+            AbiParam::new(ptr), AbiParam::new(types::I32), AbiParam::new(ptr)
+        ]);
+        ctx.func.signature.returns.push(AbiParam::new(types::I32));
+        let id = module.declare_anonymous_function(&ctx.func.signature).ok()?;
+        build(module, &mut ctx, id, program, &plan, args);
+        module.define_function(id, &mut ctx).ok()?;
+        module.clear_context(&mut ctx);
+        module.finalize_definitions().ok()?;
+        let code = module.get_finalized_function(id);
+        Some(Compiled {
+            native: unsafe { std::mem::transmute::<*const u8, Native>(code) },
+            argc: plan.argc,
+            returns: plan.returns,
+        })
+    }
+}
+
+// < or >, numbers are compared as decimals without the total order extension
+fn compare(b: &mut FunctionBuilder, less: bool, lhs: Reg, rhs: Reg, args: &Arguments) -> Reg {
+    if args.extension_total_order {
+        let cc = if less { IntCC::SignedLessThan } else { IntCC::SignedGreaterThan };
+        return b.ins().icmp(cc, lhs, rhs);
+    }
+    let lhs = b.ins().fcvt_from_sint(types::F32, lhs);
+    let rhs = b.ins().fcvt_from_sint(types::F32, rhs);
+    let cc = if less { FloatCC::LessThan } else { FloatCC::GreaterThan };
+    b.ins().fcmp(cc, lhs, rhs)
+}
+
+// Turns the plan into Cranelift IR
+fn build(
+    module: &mut JITModule, ctx: &mut Context, id: FuncId, program: &Program, plan: &Plan,
+    args: &Arguments
+) {
+    let ptr = module.target_config().pointer_type();
+    let this = module.declare_func_in_func(id, &mut ctx.func);
+    let mut fn_ctx = FunctionBuilderContext::new();
+    let mut b = FunctionBuilder::new(&mut ctx.func, &mut fn_ctx);
+    // Registers, then locals, then the stack
+    let var_count = 16 + plan.locals + plan.max_stack;
+    let var = |i: usize| Variable::from_u32(i as u32);
+    let entry_block = b.create_block();
+    b.append_block_params_for_function_params(entry_block);
+    b.switch_to_block(entry_block);
+    b.seal_block(entry_block);
+    let (bail_ptr, left, arg_ptr) = {
+        let params = b.block_params(entry_block);
+        (params[0], params[1], params[2])
+    };
+    let zero = b.ins().iconst(types::I32, 0);
+    for i in 0..var_count {
+        b.declare_var(var(i), types::I32);
+        b.def_var(var(i), zero);
+    }
+    // Args were pushed in order, so the last one is the first local
+    for i in 0..plan.argc {
+        let arg = b.ins().load(types::I32, MemFlags::trusted(), arg_ptr, (i * 4) as i32);
+        b.def_var(var(16 + plan.argc - 1 - i), arg);
+    }
+    let bail = b.create_block();
+    let too_deep = b.create_block();
+    // A call under this one bailed, it already set the flag
+    let unwind = b.create_block();
+    let blocks: Vec<Option<Block>> = plan.states.iter()
+        .map(|s| s.as_ref().map(|_| b.create_block())).collect();
+    let block_at = |at: usize| blocks[at - plan.entry].unwrap();
+    let start = block_at(plan.entry + 2);
+    let deep = b.ins().icmp_imm(IntCC::SignedLessThanOrEqual, left, 0);
+    b.ins().brif(deep, too_deep, &[], start, &[]);
+
+    for (i, state) in plan.states.iter().enumerate() {
+        let Some(state) = state else {
+            continue;
+        };
+        let at = plan.entry + i;
+        b.switch_to_block(block_at(at));
+        let mut sp = state.stack.len();
+        let locals = state.locals.len();
+        let op = program.ops[at];
+        let (code, a, ob, c) = decode_op(op);
+        // Reads and writes registers
+        macro_rules! get {
+            ($reg:expr) => {{
+                let reg = $reg;
+                match reg {
+                    0..=15 => b.use_var(var(reg as usize)),
+                    16 => { sp -= 1; b.use_var(var(16 + plan.locals + sp)) },
+                    _ => {
+                        let val = match program.consts[reg as usize - 17] {
+                            Value::Int(i) => i as i64,
+                            Value::Bool(v) => v as i64,
+                            _ => unreachable!(),
+                        };
+                        b.ins().iconst(types::I32, val)
+                    },
+                }
+            }};
+        }
+        macro_rules! set {
+            ($reg:expr, $val:expr) => {{
+                let (reg, val) = ($reg, $val);
+                if reg == 16 {
+                    b.def_var(var(16 + plan.locals + sp), val);
+                    sp += 1;
+                } else {
+                    b.def_var(var(reg as usize), val);
+                }
+            }};
+        }
+        let local = |offset: usize| var(16 + locals - 1 - offset);
+        let bool_of = |b: &mut FunctionBuilder, cond: Reg| b.ins().uextend(types::I32, cond);
+        let mut falls = true;
+        match code {
+            NOP => {},
+            LD | LDL => {
+                let at = if code == LD { shift2(a, ob) } else { shift3(a, ob, c) };
+                let val = match program.consts[at] {
+                    Value::Int(i) => i as i64,
+                    Value::Bool(v) => v as i64,
+                    _ => unreachable!(),
+                };
+                let val = b.ins().iconst(types::I32, val);
+                set!(if code == LD { c } else { 16 }, val);
+            },
+            CP => {
+                let val = if a == 16 { b.use_var(var(16 + plan.locals + sp - 1)) } else { get!(a) };
+                set!(ob, val);
+            },
+            POP => sp -= 1,
+            CALL => {
+                let slot = b.create_sized_stack_slot(StackSlotData::new(
+                    StackSlotKind::ExplicitSlot, (plan.argc * 4).max(4) as u32, 2
+                ));
+                for i in (0..plan.argc).rev() {
+                    let arg = get!(16);
+                    b.ins().stack_store(arg, slot, (i * 4) as i32);
+                }
+                let args = b.ins().stack_addr(ptr, slot, 0);
+                let deeper = b.ins().iadd_imm(left, -1);
+                let call = b.ins().call(this, &[bail_ptr, deeper, args]);
+                let ret = b.inst_results(call)[0];
+                let failed = b.ins().load(types::I8, MemFlags::trusted(), bail_ptr, 0);
+                let after = b.create_block();
+                b.ins().brif(failed, unwind, &[], after, &[]);
+                b.seal_block(after);
+                b.switch_to_block(after);
+                set!(16, ret);
+            },
+            RET => {
+                let val = get!(16);
+                b.ins().return_(&[val]);
+                falls = false;
+            },
+            LV_L => {
+                let val = b.use_var(local(shift2(a, ob)));
+                set!(c, val);
+            },
+            SV_L => {
+                let val = get!(c);
+                b.def_var(local(shift2(a, ob)), val);
+            },
+            AV_L => {
+                let val = get!(c);
+                let old = b.use_var(local(shift2(a, ob)));
+                let new = b.ins().iadd(old, val);
+                b.def_var(local(shift2(a, ob)), new);
+            },
+            INC_L | DEC_L => {
+                let old = b.use_var(local(shift2(a, ob)));
+                let new = b.ins().iadd_imm(old, if code == INC_L { 1 } else { -1 });
+                b.def_var(local(shift2(a, ob)), new);
+                set!(c, new);
+            },
+            ADD | SUB | MUL | MOD | BOR | BAND | EQ | LT | GT | GE | LE => {
+                let rhs = get!(ob);
+                let lhs = get!(a);
+                let val = match code {
+                    ADD => b.ins().iadd(lhs, rhs),
+                    SUB => b.ins().isub(lhs, rhs),
+                    MUL => b.ins().imul(lhs, rhs),
+                    BOR => b.ins().bor(lhs, rhs),
+                    BAND => b.ins().band(lhs, rhs),
+                    MOD => {
+                        // The interpreter has the errors (and MIN % -1)
+                        let plus_one = b.ins().iadd_imm(rhs, 1);
+                        let bad = b.ins().icmp_imm(IntCC::UnsignedLessThanOrEqual, plus_one, 1);
+                        let after = b.create_block();
+                        b.ins().brif(bad, bail, &[], after, &[]);
+                        b.seal_block(after);
+                        b.switch_to_block(after);
+                        b.ins().srem(lhs, rhs)
+                    },
+                    EQ => {
+                        let cond = b.ins().icmp(IntCC::Equal, lhs, rhs);
+                        bool_of(&mut b, cond)
+                    },
+                    _ => {
+                        let mut cond = compare(&mut b, matches!(code, LT | GE), lhs, rhs, args);
+                        if matches!(code, GE | LE) {
+                            // Ints are never NaN, so these are the opposite of LT and GT
+                            cond = b.ins().bxor_imm(cond, 1);
+                        }
+                        bool_of(&mut b, cond)
+                    },
+                };
+                set!(c, val);
+            },
+            ADDI | SUBI | EQI | LTI | GEI => {
+                let lhs = get!(a);
+                let imm = ob as i8 as i64;
+                let val = match code {
+                    ADDI => b.ins().iadd_imm(lhs, imm),
+                    SUBI => b.ins().iadd_imm(lhs, -imm),
+                    EQI => {
+                        let cond = b.ins().icmp_imm(IntCC::Equal, lhs, imm);
+                        bool_of(&mut b, cond)
+                    },
+                    _ => {
+                        let rhs = b.ins().iconst(types::I32, imm);
+                        let mut cond = compare(&mut b, true, lhs, rhs, args);
+                        if code == GEI {
+                            // Ints are never NaN, so this is the opposite of LTI
+                            cond = b.ins().bxor_imm(cond, 1);
+                        }
+                        bool_of(&mut b, cond)
+                    },
+                };
+                set!(c, val);
+            },
+            AND | OR | XOR => {
+                let rhs = get!(ob);
+                let lhs = get!(a);
+                let rhs = b.ins().icmp_imm(IntCC::NotEqual, rhs, 0);
+                let lhs = b.ins().icmp_imm(IntCC::NotEqual, lhs, 0);
+                let cond = match code {
+                    AND => b.ins().band(lhs, rhs),
+                    OR => b.ins().bor(lhs, rhs),
+                    _ => b.ins().bxor(lhs, rhs),
+                };
+                let val = bool_of(&mut b, cond);
+                set!(c, val);
+            },
+            NOT => {
+                let val = get!(a);
+                let cond = b.ins().icmp_imm(IntCC::Equal, val, 0);
+                let val = bool_of(&mut b, cond);
+                set!(ob, val);
+            },
+            JMP | JMPB => {
+                b.ins().jump(block_at(jump_target(op, at).unwrap()), &[]);
+                falls = false;
+            },
+            JMPNT => {
+                let cond = get!(a);
+                b.ins().brif(cond, block_at(at + 1), &[], block_at(jump_target(op, at).unwrap()), &[]);
+                falls = false;
+            },
+            _ => unreachable!(),
+        }
+        if falls {
+            b.ins().jump(block_at(at + 1), &[]);
+        }
+    }
+    // Tell the interpreter to do it
+    for (block, flag) in [(bail, BAIL), (too_deep, TOO_DEEP)] {
+        b.switch_to_block(block);
+        let flag = b.ins().iconst(types::I8, flag as i64);
+        b.ins().store(MemFlags::trusted(), flag, bail_ptr, 0);
+        b.ins().jump(unwind, &[]);
+    }
+    b.switch_to_block(unwind);
+    let zero = b.ins().iconst(types::I32, 0);
+    b.ins().return_(&[zero]);
+    b.seal_all_blocks();
+    b.finalize();
+}
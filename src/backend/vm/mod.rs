@@ -1,7 +1,14 @@
 pub mod compiler;
+pub mod optimizer;
 #[cfg(not(target_family = "wasm"))]
 #[cfg(feature = "cffi")]
 pub mod cffi;
 #[cfg(not(target_family = "wasm"))]
 pub mod dis;
+#[cfg(not(target_family = "wasm"))]
+pub mod cache;
+#[cfg(not(target_family = "wasm"))]
+pub mod bundle;
+#[cfg(feature = "jit")]
+pub mod jit;
 pub mod vm;
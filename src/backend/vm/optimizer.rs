@@ -0,0 +1,314 @@
+// Bytecode optimizations that run after the compiler emits code
+use crate::backend::vm::compiler::Program;
+use crate::backend::vm::vm::{Opcode, shift2, shift3};
+use Opcode::*;
+
+// Field shifts, for reading and patching operands
+const A: u32 = 16;
+const B: u32 = 8;
+const C: u32 = 0;
+
+pub fn decode_op(op: u32) -> (Opcode, u8, u8, u8) {
+    return (
+        unsafe {
+            std::mem::transmute::<u8, Opcode>(((op & 0xFF000000) >> 24).try_into().unwrap())
+        },
+        ((op & 0xFF0000) >> 16).try_into().unwrap(),
+        ((op & 0xFF00) >> 8).try_into().unwrap(),
+        (op & 0xFF).try_into().unwrap(),
+    );
+}
+
+#[inline]
+fn field(op: u32, shift: u32) -> u8 {
+    ((op >> shift) & 255) as u8
+}
+
+#[inline]
+fn set_field(op: u32, shift: u32, val: u8) -> u32 {
+    (op & !(255 << shift)) | ((val as u32) << shift)
+}
+
+// The fields an op reads registers from, and the fields it writes registers to
+fn reg_fields(op: u32) -> (&'static [u32], &'static [u32]) {
+    match decode_op(op).0 {
+        LD | LV_L | LV_G | ALO | INC_L | INC_G | DEC_L | DEC_G => (&[], &[C]),
+        LDL | NOP | POP | SARG | CALL | RCALL | RET | DEFER | RDEF | PLC | PGB
+        | JMP | JMPB => (&[], &[]),
+        CP | ITER | NOT => (&[A], &[B]),
+        TRY => (&[A], &[C]),
+        CARG | LFL | LL => (&[], &[A]),
+        VCALL | JMPNT => (&[A], &[]),
+        SV_L | SV_G | AV_L | AV_G => (&[C], &[]),
+        INX | ADD | SUB | MUL | DIV | MOD | BOR | BAND | AND | OR | XOR
+        | EQ | GT | LT | GE | LE | IN => (&[A, B], &[C]),
+        ADDI | SUBI | EQI | LTI | GEI | IST => (&[A], &[C]),
+        // The list/iterator is changed in place
+        SKY => (&[A, B, C], &[A]),
+        NXT => (&[A], &[A, B]),
+    }
+}
+
+// Where a relative jump goes
+pub fn jump_target(op: u32, at: usize) -> Option<usize> {
+    let (op, a, b, c) = decode_op(op);
+    match op {
+        JMP => Some(at + shift3(a, b, c)),
+        JMPB => Some(at - shift3(a, b, c)),
+        JMPNT => Some(at + shift2(b, c)),
+        NXT => Some(at + c as usize),
+        TRY => Some(at + b as usize),
+        _ => None,
+    }
+}
+
+// Points a relative jump somewhere else, None if it doesn't fit
+fn retarget(op: u32, at: usize, target: usize) -> Option<u32> {
+    let (code, a, ..) = decode_op(op);
+    let head = op & 0xFF000000;
+    match code {
+        JMP if target >= at && target - at < 1 << 24 => Some(head + (target - at) as u32),
+        JMPB if target <= at && at - target < 1 << 24 => Some(head + (at - target) as u32),
+        JMPNT if target >= at && target - at < 1 << 16 =>
+            Some(head + ((a as u32) << 16) + (target - at) as u32),
+        NXT if target >= at && target - at < 1 << 8 =>
+            Some(set_field(op, C, (target - at) as u8)),
+        TRY if target >= at && target - at < 1 << 8 =>
+            Some(set_field(op, B, (target - at) as u8)),
+        _ => None,
+    }
+}
+
+// Where code in [top, end) moved to after ops were hoisted above top
+pub struct Moves {
+    top: usize,
+    end: usize,
+    hoisted: usize,
+    // Old positions that were taken out of the loop, sorted
+    removed: Vec<usize>,
+}
+
+impl Moves {
+    pub fn map(&self, pos: usize) -> usize {
+        if pos < self.top {
+            return pos;
+        }
+        // Loads that share a register are only hoisted once, so the loop can shrink
+        let before = if pos >= self.end {
+            self.removed.len()
+        } else {
+            self.removed.partition_point(|r| *r < pos)
+        };
+        pos + self.hoisted - before
+    }
+}
+
+// Moves loads that are the same every iteration out of the loop in [top, end)
+// `free` are registers nothing else is using
+pub fn hoist_loop(program: &mut Program, top: usize, free: &[u8]) -> Option<Moves> {
+    let end = program.ops.len();
+    let ops = &program.ops[top..end];
+    // Absolute addresses would need fixing
+    if ops.iter().any(|op| decode_op(*op).0 == DEFER)
+        || program.functis.iter().any(|f| f.1 >= top && f.1 < end)
+    {
+        return None;
+    }
+    // Calls might change any global
+    let calls = ops.iter().any(|op| matches!(decode_op(*op).0, CALL | VCALL | RCALL));
+    let jumps: Vec<(usize, usize)> = (top..end)
+        .filter_map(|at| Some((at, jump_target(program.ops[at], at)?)))
+        .collect();
+    // Registers the loop doesn't touch
+    let mut spare: Vec<u8> = free.iter().copied().filter(|reg| !ops.iter().any(|op| {
+        let (reads, writes) = reg_fields(*op);
+        reads.iter().chain(writes).any(|f| field(*op, *f) == *reg)
+    })).collect();
+
+    let mut ops = ops.to_vec();
+    // (load without the register, register it's in)
+    let mut hoisted: Vec<(u32, u8)> = vec![];
+    let mut removed = vec![];
+    for at in 0..ops.len() {
+        let op = ops[at];
+        let (code, a, b, reg) = decode_op(op);
+        let invariant = match code {
+            LD => true,
+            LV_G => !calls && !ops.iter().any(|op| {
+                let (code, oa, ob, _) = decode_op(*op);
+                matches!(code, SV_G | AV_G | INC_G | DEC_G) && shift2(oa, ob) == shift2(a, b)
+            }),
+            _ => false,
+        };
+        if !invariant || reg >= 16 {
+            continue;
+        }
+        // The uses are everything until the register is set again
+        let mut uses = vec![];
+        let mut last = ops.len() - 1;
+        for (i, use_op) in ops.iter().enumerate().skip(at + 1) {
+            let (reads, writes) = reg_fields(*use_op);
+            let read: Vec<u32> = reads.iter().copied()
+                .filter(|f| field(*use_op, *f) == reg).collect();
+            if !read.is_empty() {
+                uses.push((i, read));
+            }
+            if writes.iter().any(|f| field(*use_op, *f) == reg) {
+                last = i;
+                break;
+            }
+        }
+        // Things changed in place can't be shared between iterations
+        if uses.iter().any(|(i, _)| {
+            let (code, ua, ..) = decode_op(ops[*i]);
+            matches!(code, SKY | NXT) && ua == reg
+        }) {
+            continue;
+        }
+        // Nothing can jump into the middle of the uses
+        let (first, last) = (top + at, top + last);
+        if jumps.iter().any(|(from, to)|
+            *to > first && *to <= last && (*from < first || *from > last)
+        ) {
+            continue;
+        }
+        // Share the register if it's already hoisted
+        let load = op & !255;
+        let new_reg = if let Some((_, r)) = hoisted.iter().find(|h| h.0 == load) {
+            *r
+        } else if let Some(r) = spare.pop() {
+            hoisted.push((load, r));
+            r
+        } else {
+            break;
+        };
+        for (i, fields) in uses {
+            for f in fields {
+                ops[i] = set_field(ops[i], f, new_reg);
+            }
+        }
+        removed.push(top + at);
+    }
+    if removed.is_empty() {
+        return None;
+    }
+
+    let moves = Moves { top, end, hoisted: hoisted.len(), removed };
+    let mut new_ops: Vec<(Option<usize>, u32)> = hoisted.iter()
+        .map(|(load, reg)| (None, load + *reg as u32)).collect();
+    new_ops.extend(ops.into_iter().enumerate()
+        .map(|(i, op)| (Some(top + i), op))
+        .filter(|(at, _)| moves.removed.binary_search(&at.unwrap()).is_err()));
+    // Jumps to the top go after the hoisted ops, so they don't run again
+    relocate(program, &moves, new_ops)?;
+    Some(moves)
+}
+
+// An unconditional jump from at to target
+fn jump(at: usize, target: usize) -> Option<u32> {
+    let (op, offset) = if target > at {
+        (JMP, target - at)
+    } else {
+        (JMPB, at - target)
+    };
+    if offset >= 1 << 24 {
+        return None;
+    }
+    Some(((op as u32) << 24) + offset as u32)
+}
+
+// Puts ops (with their old position, if they had one) in place of the code that moved
+fn relocate(program: &mut Program, moves: &Moves, ops: Vec<(Option<usize>, u32)>) -> Option<()> {
+    let mut new_ops = Vec::with_capacity(ops.len());
+    for (at, op) in ops {
+        let Some(at) = at else {
+            new_ops.push(op);
+            continue;
+        };
+        let (code, a, b, c) = decode_op(op);
+        new_ops.push(if let Some(target) = jump_target(op, at) {
+            retarget(op, moves.map(at), moves.map(target))?
+        } else if matches!(code, CALL | RCALL | DEFER) {
+            // These are absolute
+            (op & 0xFF000000) + moves.map(shift3(a, b, c)) as u32
+        } else {
+            op
+        });
+    }
+    program.ops.splice(moves.top..moves.end, new_ops);
+    for functi in program.functis.iter_mut() {
+        functi.1 = moves.map(functi.1);
+    }
+    program.remap(|pos| moves.map(pos));
+    Some(())
+}
+
+// Follows jumps to jumps, so only one is taken
+fn final_target(ops: &[u32], mut target: usize) -> usize {
+    // Infinite loops (`loop {}`) jump to themselves
+    for _ in 0..16 {
+        let Some(&op) = ops.get(target) else {
+            break;
+        };
+        if !matches!(decode_op(op).0, JMP | JMPB) {
+            break;
+        }
+        target = jump_target(op, target).unwrap();
+    }
+    target
+}
+
+// Threads jumps, removes jumps that go nowhere, and folds branches on constants
+// Only code after start is changed
+pub fn simplify_jumps(program: &mut Program, start: usize) -> Option<Moves> {
+    let end = program.ops.len();
+    let mut ops = program.ops.clone();
+    // The jump around a functi is used to find where it ends
+    let around: Vec<usize> = program.functis.iter().map(|f| f.1 - 1).collect();
+    let mut removed = vec![];
+    for at in start..end {
+        let op = ops[at];
+        let (code, a, ..) = decode_op(op);
+        // Constant conditions
+        if code == JMPNT && (17..=115).contains(&a) {
+            if program.consts[a as usize - 17].is_truthy() {
+                // Never taken
+                removed.push(at);
+                continue;
+            }
+            ops[at] = jump(at, jump_target(op, at)?)?;
+        }
+        let op = ops[at];
+        let Some(target) = jump_target(op, at) else {
+            continue;
+        };
+        let target = final_target(&ops, target);
+        match decode_op(op).0 {
+            JMP | JMPB if !around.contains(&at) => ops[at] = jump(at, target)?,
+            JMPNT => ops[at] = retarget(op, at, target).unwrap_or(op),
+            _ => {},
+        }
+    }
+    // Jumps to the next instruction do nothing
+    for (at, op) in ops.iter_mut().enumerate().take(end).skip(start) {
+        let (code, a, ..) = decode_op(*op);
+        if jump_target(*op, at) != Some(at + 1) || around.contains(&at) || removed.contains(&at) {
+            continue;
+        }
+        match code {
+            JMP => removed.push(at),
+            // The condition still has to be popped
+            JMPNT if a == 16 => *op = (POP as u32) << 24,
+            JMPNT => removed.push(at),
+            _ => {},
+        }
+    }
+    removed.sort();
+    let moves = Moves { top: start, end, hoisted: 0, removed };
+    let new_ops = (start..end)
+        .filter(|at| moves.removed.binary_search(at).is_err())
+        .map(|at| (Some(at), ops[at]))
+        .collect();
+    relocate(program, &moves, new_ops)?;
+    Some(moves)
+}
@@ -4,21 +4,40 @@ use std::rc::Rc;
 use std::fs::OpenOptions;
 use std::io::{Write, Read, Seek, SeekFrom};
 use std::io;
-#[cfg(feature = "cffi")]
+use std::sync::{Arc, Mutex};
+#[cfg(not(target_family = "wasm"))]
+use std::sync::OnceLock;
+#[cfg(not(target_family = "wasm"))]
+use std::time::{Duration, Instant};
+use std::time::SystemTime;
 use std::path::PathBuf;
 
 use crate::Arguments;
-use crate::common::IMPOSSIBLE_STATE;
-use crate::backend::vm::compiler::Program;
+use crate::common::{
+    ErrType, IMPOSSIBLE_STATE, LOG_LEVELS, capture_err, json_errors,
+    print_json_err, print_line, runtime_snippet, set_eval_source, Stream
+};
+use crate::lexer::{lex, Token, TokenType};
+use crate::parser::{parse, ASTNode, AST};
+use crate::backend::vm::compiler::{IncrementalCompiler, Program};
 use crate::backend::vm::dis::dis_single;
-use crate::backend::value::{FileInfo, Value};
+use crate::backend::value::{
+    format_float, int_math, set_overflow, ChannelInfo, FileInfo, IoOp, SendValue, SetKey,
+    SocketInfo, TaskInfo, TaskState, ThreadInfo, Value, WeakRef
+};
 #[cfg(feature = "cffi")]
 use crate::backend::vm::cffi::{load_functi, load_library};
 #[cfg(feature = "cffi")]
 use crate::backend::vm::cffi::call as ffi_call;
+#[cfg(feature = "jit")]
+use crate::backend::vm::jit::Jit;
 
-use rustc_hash::FxHashMap;
-use rand::Rng;
+use rustc_hash::{FxHashMap, FxHashSet};
+use rand::{Rng, RngCore};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono::format::StrftimeItems;
+#[cfg(not(target_family = "wasm"))]
+use chrono::{Local, Utc};
 
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -50,6 +69,12 @@ pub enum Opcode {
     RCALL,
     // RETurn (ret)
     RET,
+    // DEFER code until the functi returns ([u24 "address"])
+    DEFER,
+    // Return from DEFerred code
+    RDEF,
+    // TRY a result, errs are pushed for the RET after it ([register "value", u8 "offset if not err", register "dst"])
+    TRY,
 
     // Variables
     // Load Variable Local/Global ([u16 "offset", register "dst"])
@@ -58,10 +83,18 @@ pub enum Opcode {
     // Set Variable Local/Global ([u16 "offset", register "value"])
     SV_L,
     SV_G,
+    // Add to Variable Local/Global in place ([u16 "offset", register "value"])
+    AV_L,
+    AV_G,
+    // INCrement/DECrement Variable Local/Global in place ([u16 "offset", register "dst"])
+    INC_L,
+    INC_G,
+    DEC_L,
+    DEC_G,
     // Push LoCals ([u16 "amount", u8 "arg number"])
     PLC,
     // Push GloBals ([u16 "amount"])
-    // Only used once in normal programs, needed more for interactive sessions (such as the REPL)
+    // Only a preallocation hint, globals also grow when set (needed for interactive sessions such as the REPL)
     PGB,
     // Add Locals Offset ([u16 "offset", reg "reftype"])
     ALO,
@@ -91,6 +124,14 @@ pub enum Opcode {
     DIV,
     // MODulo ([register "a", register "b", register "dst"])
     MOD,
+    // ADD Immediate ([register "a", i8 "b", register "dst"])
+    ADDI,
+    // SUBtract Immediate ([register "a", i8 "b", register "dst"])
+    SUBI,
+    // Bitwise OR ([register "a", register "b", register "dst"])
+    BOR,
+    // Bitwise AND ([register "a", register "b", register "dst"])
+    BAND,
 
     // Boolean
     // AND ([register "a", register "b", register "dst"])
@@ -109,8 +150,20 @@ pub enum Opcode {
     GT,
     // Less Than ([register "a", register "b", register "dst"])
     LT,
+    // Greater than or Equal ([register "a", register "b", register "dst"])
+    GE,
+    // Less than or Equal ([register "a", register "b", register "dst"])
+    LE,
     // IN ([register "a", register "b", register "dst"])
     IN,
+    // EQuals Immediate ([register "a", i8 "b", register "dst"])
+    EQI,
+    // Less Than Immediate ([register "a", i8 "b", register "dst"])
+    LTI,
+    // Greater than or Equal Immediate ([register "a", i8 "b", register "dst"])
+    GEI,
+    // IS Type ([register "value", u8 "type", register "dst"]), the type is an index of TYPE_CHECKS
+    IST,
 
     // Jumps
     // JuMP ([u24 "address"])
@@ -125,12 +178,454 @@ pub enum Opcode {
 // A functie is a sack functions implemented in rust
 type Functie = fn(&mut Vm, Vec<Value>) -> Result<Value, String>;
 
+// What a builtin needs to be there
+#[derive(Clone, Copy, PartialEq)]
+pub enum Needs {
+    Nothing,
+    // Touches the system or waits for input, sandboxed VMs don't have it
+    System,
+    // --use-burlap-extensions
+    Functies,
+    // --use-more-debug
+    Debugging,
+}
+
+// A builtin, the parser checks calls with it and the VM calls the handler
+pub struct Builtin {
+    pub name: &'static str,
+    // The fewest and most args, the most is -1 for any number
+    pub min_args: i32,
+    pub max_args: i32,
+    // None if the compiler does it
+    handler: Option<Functie>,
+    pub doc: &'static str,
+    pub needs: Needs,
+    // It changes the var given as the first arg, so it's given a __burlap_reftype
+    pub changes_var: bool,
+}
+
+impl Builtin {
+    const fn new(
+        name: &'static str, min_args: i32, max_args: i32,
+        handler: Option<Functie>, doc: &'static str
+    ) -> Builtin {
+        Builtin {
+            name, min_args, max_args, handler, doc, needs: Needs::Nothing, changes_var: false
+        }
+    }
+
+    const fn needs(mut self, needs: Needs) -> Builtin {
+        self.needs = needs;
+        self
+    }
+
+    const fn changes_var(mut self) -> Builtin {
+        self.changes_var = true;
+        self
+    }
+
+    pub fn takes(&self, arg_num: i32) -> bool {
+        arg_num >= self.min_args && (self.max_args == -1 || arg_num <= self.max_args)
+    }
+
+    // Every number of args it takes, like overloaded functis (-1 is any number)
+    pub fn arities(&self) -> Vec<i32> {
+        if self.max_args == -1 {
+            vec![-1]
+        } else {
+            (self.min_args..=self.max_args).collect()
+        }
+    }
+
+    fn is_available(&self, args: &Arguments) -> bool {
+        match self.needs {
+            Needs::Nothing => true,
+            Needs::System => !args.sandbox,
+            Needs::Functies => args.extension_functies,
+            Needs::Debugging => args.extension_debugging_functies,
+        }
+    }
+}
+
+static BUILTINS: &[&[Builtin]] = &[
+    CORE,
+    #[cfg(not(target_family = "wasm"))]
+    SYSTEM,
+    #[cfg(feature = "compression")]
+    COMPRESSION,
+    FUNCTIES,
+    #[cfg(feature = "cffi")]
+    CFFI,
+    DEBUGGING,
+];
+
+// The builtins that can be used with these args
+pub fn get_builtins(args: &Arguments) -> impl Iterator<Item = &'static Builtin> + '_ {
+    BUILTINS.iter().flat_map(|group| group.iter()).filter(|b| b.is_available(args))
+}
+
+pub fn get_builtin(args: &Arguments, name: &str) -> Option<&'static Builtin> {
+    get_builtins(args).find(|b| b.name == name)
+}
+
+// For the compiler, which doesn't have the args
+pub fn changes_var(name: &str) -> bool {
+    BUILTINS.iter().flat_map(|group| group.iter()).any(|b| b.name == name && b.changes_var)
+}
+
+// Everything, on every platform
+const CORE: &[Builtin] = &[
+    // Builtins
+    Builtin::new("print", 1, 1, Some(sk_print), "Prints a value and a newline"),
+    Builtin::new("input", 1, 1, Some(sk_input),
+        "Prints a prompt and returns a line from stdin").needs(Needs::System),
+    Builtin::new("type", 1, 1, Some(sk_type), "Returns the name of a value's type"),
+    Builtin::new("is_num", 1, 1, Some(sk_is_num), "If a value is a number or decimal"),
+    Builtin::new("is_str", 1, 1, Some(sk_is_str), "If a value is a string"),
+    Builtin::new("is_list", 1, 1, Some(sk_is_list), "If a value is a list"),
+    Builtin::new("is_bool", 1, 1, Some(sk_is_bool), "If a value is a bool"),
+    Builtin::new("is_none", 1, 1, Some(sk_is_none), "If a value is none"),
+    Builtin::new("is_functi", 1, 1, Some(sk_is_functi), "If a value is a functi"),
+    Builtin::new("len", 1, 1, Some(sk_len),
+        "Returns the last index of a list or string, none if it's empty"),
+    Builtin::new("count", 1, 2, Some(sk_count),
+        "Counts the items of a list, or how many times a value is in it"),
+    Builtin::new("range", 2, 3, Some(sk_range),
+        "Returns the numbers from the start to the end, by a step if given (lazy if there's a lot)"),
+    Builtin::new("linspace", 3, 3, Some(sk_linspace),
+        "Returns a list of a number of evenly spaced decimals from the start to the end"),
+    Builtin::new("args", 0, 0, None, "Returns the args of the current functi"),
+    Builtin::new("program_args", 0, 0, Some(sk_program_args),
+        "Returns the args given to the program"),
+    Builtin::new("rand", 2, 2, Some(sk_rand), "Returns a random number between the min and max"),
+    Builtin::new("min", 1, -1, Some(sk_min), "Returns the smallest of a list or the args"),
+    Builtin::new("max", 1, -1, Some(sk_max), "Returns the largest of a list or the args"),
+    Builtin::new("sum", 1, 1, Some(sk_sum), "Adds up a list"),
+    Builtin::new("abs", 1, 1, Some(sk_abs), "Returns the absolute value of a number"),
+    Builtin::new("round", 1, 2, Some(sk_round), "Rounds a number, to a number of digits if given"),
+    Builtin::new("floor", 1, 2, Some(sk_floor),
+        "Rounds a number down, to a number of digits if given"),
+    Builtin::new("ceil", 1, 2, Some(sk_ceil), "Rounds a number up, to a number of digits if given"),
+    Builtin::new("trunc", 1, 2, Some(sk_trunc),
+        "Rounds a number towards zero, to a number of digits if given"),
+    Builtin::new("to_fixed", 2, 2, Some(sk_to_fixed),
+        "Returns a number as a string with a number of digits after the point"),
+    Builtin::new("is_nan", 1, 1, Some(sk_is_nan), "If a number is NaN"),
+    Builtin::new("is_finite", 1, 1, Some(sk_is_finite), "If a number isn't NaN or infinite"),
+    Builtin::new("random_bytes", 1, 1, Some(sk_random_bytes),
+        "Returns secure random bytes from the OS"),
+    Builtin::new("encode", 2, 3, Some(sk_encode),
+        "Turns a string into bytes (UTF-8, UTF-16, or Latin-1)"),
+    Builtin::new("decode", 2, 3, Some(sk_decode),
+        "Turns bytes into a string (UTF-8, UTF-16, or Latin-1)"),
+    Builtin::new("ord", 1, 1, Some(sk_ord), "Returns the code point of a character"),
+    Builtin::new("chr", 1, 1, Some(sk_chr), "Returns the character of a code point"),
+    Builtin::new("is_digit", 1, 1, Some(sk_is_digit), "If a string is only digits"),
+    Builtin::new("is_alpha", 1, 1, Some(sk_is_alpha), "If a string is only letters"),
+    Builtin::new("is_space", 1, 1, Some(sk_is_space), "If a string is only whitespace"),
+    Builtin::new("is_upper", 1, 1, Some(sk_is_upper), "If a string's letters are all uppercase"),
+    Builtin::new("is_lower", 1, 1, Some(sk_is_lower), "If a string's letters are all lowercase"),
+    Builtin::new("uuid4", 0, 0, Some(sk_uuid4), "Returns a random UUID"),
+    Builtin::new("sort", 1, 1, Some(sk_sort), "Returns a sorted copy of a list"),
+    Builtin::new("reverse", 1, 1, Some(sk_reverse), "Returns a reversed copy of a list or string"),
+    Builtin::new("index_of", 2, 2, Some(sk_index_of),
+        "Returns the index of the first match in a list or string, none if there isn't one"),
+    Builtin::new("contains", 2, 2, Some(sk_contains), "If a value is in a list, set, or string"),
+    Builtin::new("flatten", 1, 2, Some(sk_flatten),
+        "Puts the items of lists in a list into it, a number of levels deep (1 if not given)"),
+    Builtin::new("unique", 1, 1, Some(sk_unique),
+        "Returns a list without repeated values, in the same order"),
+    Builtin::new("chunk", 2, 2, Some(sk_chunk), "Splits a list into lists of a size"),
+    Builtin::new("push", 2, 2, Some(sk_push), "Adds a value to the end of a list var").changes_var(),
+    Builtin::new("pop", 1, 1, Some(sk_pop),
+        "Removes the last value of a list var and returns it").changes_var(),
+    Builtin::new("insert", 3, 3, Some(sk_insert),
+        "Puts a value into a list var before an index").changes_var(),
+    Builtin::new("remove_at", 2, 2, Some(sk_remove_at),
+        "Removes the value at an index of a list var and returns it").changes_var(),
+    Builtin::new("extend", 2, 2, Some(sk_extend),
+        "Adds the values of a list to the end of a list var").changes_var(),
+    Builtin::new("bind", 1, -1, Some(sk_bind), "Returns a functi with its first args set"),
+    Builtin::new("items", 1, 1, Some(sk_items), "Returns the keys and values of a list as tuples"),
+    Builtin::new("set", 1, 1, Some(sk_set), "Turns a list into a set"),
+    Builtin::new("tuple", 1, 1, Some(sk_tuple), "Turns a list into a tuple"),
+    Builtin::new("serialize", 1, 1, Some(sk_serialize), "Turns a value into bytes"),
+    Builtin::new("deserialize", 1, 1, Some(sk_deserialize),
+        "Turns bytes from serialize back into a value"),
+    Builtin::new("weak", 1, 2, Some(sk_weak),
+        "Returns a weak handle to a value, with a functi to call when it's dropped"),
+    Builtin::new("upgrade", 1, 1, Some(sk_upgrade),
+        "Returns the value of a weak handle, or none if it's gone"),
+    Builtin::new("ok", 1, 1, Some(sk_ok), "Returns an ok result"),
+    Builtin::new("err", 1, 1, Some(sk_err), "Returns an error result"),
+    Builtin::new("is_err", 1, 1, Some(sk_is_err), "If a result is an error"),
+    Builtin::new("unwrap", 1, 1, Some(sk_unwrap),
+        "Returns the value of an ok result, errors are thrown"),
+    Builtin::new("try_call", 1, 2, Some(sk_try_call),
+        "Calls a functi with a list of args, returns ok with what it returned or err with its error"),
+    Builtin::new("gc", 0, 0, Some(sk_gc), "Runs drop callbacks that are waiting, and collects cycles"),
+    Builtin::new("term_color", 2, 2, Some(sk_term_color), "Returns a string in a terminal color"),
+    Builtin::new("set_error_handler", 1, 1, Some(sk_set_error_handler),
+        "Sets the functi called with uncaught errors"),
+    Builtin::new("at_exit", 1, 1, Some(sk_at_exit), "Adds a functi to call when the program ends"),
+    Builtin::new("help", 1, 1, Some(sk_help), "Prints the docs of a functi"),
+    Builtin::new("__doc", 1, 1, Some(sk_doc), "Returns the docs of a functi"),
+    Builtin::new("eval", 1, 1, Some(sk_eval), "Runs code and returns the last value"),
+    Builtin::new("compile", 1, 1, Some(sk_compile), "Returns a functi that runs code"),
+    // Reflection
+    Builtin::new("globals", 0, 0, Some(sk_globals), "Returns the globals and their values"),
+    Builtin::new("functions", 0, 0, Some(sk_functions),
+        "Returns the names and arities of the functis and builtins"),
+    Builtin::new("arity", 1, 1, Some(sk_arity), "Returns how many args a functi takes"),
+    Builtin::new("is_main", 0, 0, Some(sk_is_main), "If the caller is in the main file"),
+    // Time
+    Builtin::new("now_ms", 0, 0, Some(sk_now_ms), "Returns the milliseconds since the Unix epoch"),
+    Builtin::new("stopwatch", 0, 0, Some(sk_stopwatch), "Returns a stopwatch for elapsed"),
+    Builtin::new("elapsed", 1, 1, Some(sk_elapsed),
+        "Returns the seconds since a stopwatch started"),
+    Builtin::new("strftime", 2, 2, Some(sk_strftime), "Formats a date"),
+    Builtin::new("strptime", 2, 2, Some(sk_strptime), "Parses a date"),
+    // Logging
+    Builtin::new("log_debug", 1, 1, Some(sk_log_debug), "Logs a debug message"),
+    Builtin::new("log_info", 1, 1, Some(sk_log_info), "Logs an info message"),
+    Builtin::new("log_warn", 1, 1, Some(sk_log_warn), "Logs a warning"),
+    Builtin::new("log_error", 1, 1, Some(sk_log_error), "Logs an error"),
+    Builtin::new("set_log_level", 1, 1, Some(sk_set_log_level),
+        "Sets the lowest level that's logged"),
+    // Casts
+    Builtin::new("int", 1, 1, Some(sk_int), "Turns a value into an int"),
+    Builtin::new("float", 1, 1, Some(sk_float), "Turns a value into a decimal"),
+    Builtin::new("string", 1, 1, Some(sk_string), "Turns a value into a string"),
+    Builtin::new("byte", 1, 1, Some(sk_byte), "Turns a value into a byte"),
+    Builtin::new("to_num", 1, 1, Some(sk_to_num),
+        "Turns a value into a number or decimal, erroring if it can't"),
+    Builtin::new("to_str", 1, 1, Some(sk_to_str),
+        "Turns a value into a string, erroring if it can't"),
+    Builtin::new("to_bool", 1, 1, Some(sk_to_bool),
+        "Turns a value into a bool, erroring if it can't"),
+    Builtin::new("to_list", 1, 1, Some(sk_to_list),
+        "Turns a value into a list, erroring if it can't"),
+    Builtin::new("parse_int", 1, 2, Some(sk_parse_int), "Parses an int, in a radix if given"),
+    Builtin::new("parse_float", 1, 1, Some(sk_parse_float), "Parses a decimal"),
+    Builtin::new("to_hex", 1, 1, Some(sk_to_hex), "Returns an int in hex"),
+    Builtin::new("to_bin", 1, 1, Some(sk_to_bin), "Returns an int in binary"),
+    Builtin::new("to_oct", 1, 1, Some(sk_to_oct), "Returns an int in octal"),
+    // Non-togglable internals
+    Builtin::new("__burlap_range", 2, 3, Some(sk_fastrange), "A lazy range, for loops"),
+    Builtin::new("__burlap_add_key", 3, 3, Some(sk_add_key), "Adds a key to a list"),
+    Builtin::new("__burlap_set", 1, 1, Some(sk_set), "Makes a set, for set literals"),
+    Builtin::new("__burlap_tuple", 1, 1, Some(sk_tuple), "Makes a tuple, for tuple literals"),
+    Builtin::new("__burlap_version", 0, 0, Some(sk_version), "Returns the version of Burlap"),
+    Builtin::new("__burlap_has", 1, 1, Some(sk_has), "If Burlap has a feature"),
+];
+
+// Files, the terminal, and threads
+#[cfg(not(target_family = "wasm"))]
+const SYSTEM: &[Builtin] = &[
+    // File IO
+    Builtin::new("open", 2, 2, Some(sk_open), "Opens a file").needs(Needs::System),
+    Builtin::new("close", 1, 1, Some(sk_close), "Closes a file").needs(Needs::System),
+    Builtin::new("read", 1, 1, Some(sk_read), "Reads the rest of a file").needs(Needs::System),
+    Builtin::new("write", 2, 2, Some(sk_write), "Writes to a file").needs(Needs::System),
+    Builtin::new("seek", 2, 2, Some(sk_seek), "Moves to a position in a file").needs(Needs::System),
+    Builtin::new("flush", 1, 1, Some(sk_flush), "Flushes a file").needs(Needs::System),
+    Builtin::new("eprint", 1, 1, Some(sk_eprint), "Prints a value to stderr").needs(Needs::System),
+    Builtin::new("eprintln", 1, 1, Some(sk_eprintln),
+        "Prints a value and a newline to stderr").needs(Needs::System),
+    Builtin::new("exit", 0, 1, Some(sk_exit),
+        "Ends the program, after deferred code and exit hooks").needs(Needs::System),
+    Builtin::new("term_size", 0, 0, Some(sk_term_size),
+        "Returns the width and height of the terminal").needs(Needs::System),
+    Builtin::new("term_clear", 0, 0, Some(sk_term_clear),
+        "Clears the terminal").needs(Needs::System),
+    Builtin::new("cursor_move", 2, 2, Some(sk_cursor_move),
+        "Moves the cursor").needs(Needs::System),
+    Builtin::new("cursor_visible", 1, 1, Some(sk_cursor_visible),
+        "Shows or hides the cursor").needs(Needs::System),
+    Builtin::new("read_key", 0, 0, Some(sk_read_key),
+        "Waits for a key and returns it").needs(Needs::System),
+    Builtin::new("read_line", 0, 0, Some(sk_read_line),
+        "Returns a line from stdin").needs(Needs::System),
+    Builtin::new("read_all_stdin", 0, 0, Some(sk_read_all_stdin),
+        "Returns all of stdin").needs(Needs::System),
+    Builtin::new("sleep", 1, 1, Some(sk_sleep),
+        "Waits for a number of seconds").needs(Needs::System),
+    Builtin::new("import_module", 1, 1, Some(sk_import_module),
+        "Imports a file and returns its globals and functis").needs(Needs::System),
+    Builtin::new("reload", 1, 1, Some(sk_reload),
+        "Recompiles the functis in an imported file").needs(Needs::System),
+    Builtin::new("resource", 1, 1, Some(sk_resource),
+        "Returns a file bundled with the program").needs(Needs::System),
+    Builtin::new("breakpoint", 0, 0, Some(sk_breakpoint),
+        "Stops in the debugger").needs(Needs::System),
+    Builtin::new("set_debugger", 1, 1, Some(sk_set_debugger),
+        "Sets the functi called by breakpoint").needs(Needs::System),
+    Builtin::new("csv_read", 2, 2, Some(sk_csv_read),
+        "Reads CSV from a file or string").needs(Needs::System),
+    Builtin::new("csv_write", 2, 2, Some(sk_csv_write),
+        "Writes rows to a CSV file").needs(Needs::System),
+    // Files and dirs
+    Builtin::new("list_dir", 1, 1, Some(sk_list_dir),
+        "Returns the names in a dir").needs(Needs::System),
+    Builtin::new("mkdir", 1, 1, Some(sk_mkdir), "Makes a dir and its parents").needs(Needs::System),
+    Builtin::new("remove_file", 1, 1, Some(sk_remove_file), "Removes a file").needs(Needs::System),
+    Builtin::new("rename", 2, 2, Some(sk_rename), "Renames a file or dir").needs(Needs::System),
+    Builtin::new("file_size", 1, 1, Some(sk_file_size),
+        "Returns the size of a file in bytes").needs(Needs::System),
+    Builtin::new("is_dir", 1, 1, Some(sk_is_dir), "If a path is a dir").needs(Needs::System),
+    Builtin::new("is_file", 1, 1, Some(sk_is_file), "If a path is a file").needs(Needs::System),
+    Builtin::new("mtime", 1, 1, Some(sk_mtime),
+        "Returns when a file was last changed").needs(Needs::System),
+    Builtin::new("utc_now", 0, 0, Some(sk_utc_now),
+        "Returns the date and time in UTC").needs(Needs::System),
+    Builtin::new("local_now", 0, 0, Some(sk_local_now),
+        "Returns the local date and time").needs(Needs::System),
+    Builtin::new("set_log_file", 1, 1, Some(sk_set_log_file),
+        "Sends logs to a file").needs(Needs::System),
+    Builtin::new("watch", 2, 2, Some(sk_watch),
+        "Returns a watcher for changes to a path").needs(Needs::System),
+    Builtin::new("poll_events", 1, 1, Some(sk_poll_events),
+        "Returns the changes a watcher has seen").needs(Needs::System),
+    Builtin::new("glob", 1, 1, Some(sk_glob),
+        "Returns the paths matching a pattern").needs(Needs::System),
+    Builtin::new("temp_file", 0, 0, Some(sk_temp_file),
+        "Makes a file that's removed when the program ends").needs(Needs::System),
+    Builtin::new("temp_dir", 0, 0, Some(sk_temp_dir),
+        "Makes a dir that's removed when the program ends").needs(Needs::System),
+    // Threads
+    Builtin::new("spawn", 1, 2, Some(sk_spawn),
+        "Runs a functi on a new thread").needs(Needs::System),
+    Builtin::new("join", 1, 1, Some(sk_join),
+        "Waits for a thread and returns its result").needs(Needs::System),
+    Builtin::new("channel", 0, 0, Some(sk_channel),
+        "Returns a channel for sending values between threads").needs(Needs::System),
+    Builtin::new("send", 2, 2, Some(sk_send), "Sends a value on a channel").needs(Needs::System),
+    Builtin::new("recv", 1, 1, Some(sk_recv),
+        "Waits for a value from a channel").needs(Needs::System),
+    Builtin::new("try_recv", 1, 1, Some(sk_try_recv),
+        "Returns a value from a channel, or none").needs(Needs::System),
+    // Async
+    Builtin::new("sleep_async", 1, 1, Some(sk_sleep_async),
+        "Returns a task that's done after a number of milliseconds").needs(Needs::System),
+    Builtin::new("__burlap_task", 1, -1, Some(sk_task),
+        "Calls an async functi").needs(Needs::System),
+    Builtin::new("__burlap_await", 1, 1, Some(sk_await),
+        "Waits for a task").needs(Needs::System),
+    Builtin::new("read_async", 1, 1, Some(sk_read_async),
+        "Returns a task that reads a file").needs(Needs::System),
+    Builtin::new("write_async", 2, 2, Some(sk_write_async),
+        "Returns a task that writes a file").needs(Needs::System),
+    Builtin::new("listen", 1, 1, Some(sk_listen),
+        "Returns a socket listening on an address").needs(Needs::System),
+    Builtin::new("connect", 1, 1, Some(sk_connect),
+        "Returns a socket connected to an address").needs(Needs::System),
+    Builtin::new("socket_addr", 1, 1, Some(sk_socket_addr),
+        "Returns the address of a socket").needs(Needs::System),
+    Builtin::new("accept_async", 1, 1, Some(sk_accept_async),
+        "Returns a task that waits for a connection to a socket").needs(Needs::System),
+    Builtin::new("recv_async", 1, 1, Some(sk_recv_async),
+        "Returns a task that receives from a socket").needs(Needs::System),
+    Builtin::new("send_async", 2, 2, Some(sk_send_async),
+        "Returns a task that sends to a socket").needs(Needs::System),
+];
+
+#[cfg(feature = "compression")]
+const COMPRESSION: &[Builtin] = &[
+    Builtin::new("gzip_compress", 1, 1, Some(sk_gzip_compress), "Compresses bytes with gzip"),
+    Builtin::new("gzip_decompress", 1, 1, Some(sk_gzip_decompress), "Decompresses gzip bytes"),
+    Builtin::new("deflate_compress", 1, 1, Some(sk_deflate_compress),
+        "Compresses bytes with deflate"),
+    Builtin::new("deflate_decompress", 1, 1, Some(sk_deflate_decompress),
+        "Decompresses deflate bytes"),
+];
+
+// Burlap internal functies
+const FUNCTIES: &[Builtin] = &[
+    Builtin::new("__burlap_typed_eq", 2, 2, Some(sk_typed_eq),
+        "If two values are equal and the same type").needs(Needs::Functies),
+    Builtin::new("__burlap_print", 1, 1, Some(sk_real_print),
+        "Prints a value without a newline").needs(Needs::Functies),
+    Builtin::new("__burlap_throw", 1, 1, Some(sk_throw), "Throws an error").needs(Needs::Functies),
+    Builtin::new("__burlap_reftype", 1, 1, None,
+        "Returns a reference to a var").needs(Needs::Functies),
+    Builtin::new("__burlap_set_var", 2, 2, Some(sk_set_var),
+        "Sets the var of a reference").needs(Needs::Functies),
+    Builtin::new("__burlap_load_var", 1, 1, Some(sk_load_var),
+        "Returns the value of the var of a reference").needs(Needs::Functies),
+    Builtin::new("__burlap_stats", 0, 0, Some(sk_stats),
+        "Returns stats about the VM").needs(Needs::Functies),
+];
+
+#[cfg(feature = "cffi")]
+const CFFI: &[Builtin] = &[
+    Builtin::new("__burlap_load_lib", 1, 1, Some(sk_libload),
+        "Loads a C library").needs(Needs::Functies),
+    Builtin::new("__burlap_load_functi", 2, 2, Some(sk_functiload),
+        "Loads a functi from a C library").needs(Needs::Functies),
+    Builtin::new("__burlap_ffi_call", 3, 3, Some(sk_call_c),
+        "Calls a C functi").needs(Needs::Functies),
+    Builtin::new("__burlap_ptr", 1, 1, Some(sk_ptr),
+        "Returns a pointer to a value").needs(Needs::Functies),
+];
+
+const DEBUGGING: &[Builtin] = &[
+    Builtin::new("__burlap_debug_on", 0, 0, Some(sk_debug_on),
+        "Prints every op as it runs").needs(Needs::Debugging),
+    Builtin::new("__burlap_debug_off", 0, 0, Some(sk_debug_off),
+        "Stops printing ops").needs(Needs::Debugging),
+    Builtin::new("__burlap_debug_blackbox", 1, 1, Some(sk_debug_blackbox),
+        "Returns its arg, but can't be optimized away").needs(Needs::Debugging),
+];
+
+// What an instruction hook can see, before the op runs
+#[cfg(feature = "hooks")]
+pub struct VmState<'a> {
+    pub program: &'a Program,
+    pub stack: &'a [Value],
+    pub regs: &'a [Value; 16],
+    pub locals: &'a [Value],
+    pub globals: &'a [Value],
+    pub call_depth: usize,
+}
+
+// Called with the pc and op before every op (from set_instruction_hook)
+#[cfg(feature = "hooks")]
+pub type InstructionHook = Box<dyn FnMut(usize, u32, &VmState)>;
+
 // Call frames
+#[derive(Debug)]
 struct CallFrame {
     args: Option<Vec<Value>>,
     return_addr: usize,
     regs: [Value; 16],
     local_size: usize,
+    // Deferred code addresses and the RET that is running them
+    defers: Vec<usize>,
+    defer_from: usize,
+    // If the return value should be thrown away (drop callbacks)
+    discard: bool,
+    // How big the stack was after the args were taken
+    stack_size: usize,
+}
+
+// A paused async functi call, with the frames, locals, and stack it made
+// The sizes in the frames start at its first local and stack value, so it can be resumed anywhere
+#[derive(Debug)]
+pub struct Coroutine {
+    frames: Vec<CallFrame>,
+    locals: Vec<Value>,
+    stack: Vec<Value>,
+    regs: [Value; 16],
+    // Where it starts again (after the await)
+    at: usize,
+}
+
+impl Coroutine {
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        let frames = self.frames.iter()
+            .flat_map(|frame| frame.regs.iter().chain(frame.args.iter().flatten()));
+        self.locals.iter().chain(&self.stack).chain(&self.regs).chain(frames)
+    }
 }
 
 #[inline]
@@ -144,6 +639,9 @@ pub fn shift3(a: u8, b: u8, c: u8) -> usize {
 }
 
 // VM state
+// When each file was modified and its size, by path
+type FileTimes = FxHashMap<String, (SystemTime, u64)>;
+
 pub struct Vm {
     // Extensions
     pub args: Arguments,
@@ -159,6 +657,23 @@ pub struct Vm {
     locals: Vec<Value>,
     // Functies
     functies: FxHashMap<String, Functie>,
+    // Weak handles with drop callbacks, and if they need to be checked
+    drops: Vec<(WeakRef, Value)>,
+    check_drops: bool,
+    // Cycles are collected when there are this many drop callbacks
+    gc_threshold: usize,
+    // Callbacks (and their args) to run before the next op, uses check_drops
+    queued: Vec<(Value, Vec<Value>)>,
+    // Watched paths from watch(), with the callback and last seen files
+    watchers: Vec<(String, Value, FileTimes)>,
+    // Tasks that aren't done, the event loop runs them when something awaits
+    tasks: Vec<Rc<RefCell<TaskInfo>>>,
+    // Tasks that are running (innermost last), and the run_nested depth they're running at
+    running: Vec<(Rc<RefCell<TaskInfo>>, usize)>,
+    // What the running task is waiting for, it pauses once this is set
+    awaiting: Option<Rc<RefCell<TaskInfo>>>,
+    // How long the event loop sleeps when IO isn't ready, longer the longer nothing happens
+    io_wait: Duration,
 
     // Call frames
     call_frames: Vec<CallFrame>,
@@ -171,88 +686,73 @@ pub struct Vm {
     // Misc
     pub jump: bool,
     pub at: usize,
+    // Number of instructions run (for __burlap_stats)
+    ops_run: u64,
+    // Exit code from exit, and if deferred code is running before exiting
+    exit_code: Option<i32>,
+    exit_in_defer: bool,
+    // Called with uncaught errors (from set_error_handler)
+    error_handler: Option<Value>,
+    // How many run_nested calls are running, and the error that stopped the innermost one
+    nested: usize,
+    nested_err: Option<String>,
+    // The call frames from before the innermost run_nested, exit() doesn't unwind them
+    nested_frames: usize,
+    // Called by breakpoint() (from set_debugger), the built in debugger runs if there isn't one
+    debugger: Option<Value>,
+    // Called when the program ends, last first (from at_exit)
+    exit_hooks: Vec<Value>,
+    // The names of everything (without nodes), for eval
+    pub ast: Option<AST>,
+    // Modules from import_module, by path
+    modules: FxHashMap<String, Value>,
+    // Files and dirs from temp_file/temp_dir, removed when the VM is gone
+    temps: Vec<PathBuf>,
+    // The file logs go to, stderr if there isn't one
+    log_file: Option<std::fs::File>,
+    // Files embedded by `burlap bundle`, by name
+    pub resources: FxHashMap<String, Vec<u8>>,
+    // Native code for hot functis
+    #[cfg(feature = "jit")]
+    jit: Jit,
+    // For profilers, tracers, and coverage tools
+    #[cfg(feature = "hooks")]
+    instruction_hook: Option<InstructionHook>,
 }
 
 impl Vm {
     // Init
     pub fn new(args: Arguments, program: Program) -> Vm {
+        set_overflow(args.overflow);
         // Builtin functions
         let mut functies = FxHashMap::with_capacity_and_hasher(
             16, Default::default()
         );
-        // Builtins
-        functies.insert("print".to_string(), sk_print as Functie);
-        functies.insert("input".to_string(), sk_input as Functie);
-        functies.insert("type".to_string(), sk_type as Functie);
-        functies.insert("len".to_string(), sk_len as Functie);
-        functies.insert("count".to_string(), sk_count as Functie);
-        functies.insert("range".to_string(), sk_range as Functie);
-        functies.insert("rand".to_string(), sk_rand as Functie);
-        // File IO
-        #[cfg(not(target_family = "wasm"))]
-        {
-            functies.insert("open".to_string(), sk_open as Functie);
-            functies.insert("close".to_string(), sk_close as Functie);
-            functies.insert("read".to_string(), sk_read as Functie);
-            functies.insert("write".to_string(), sk_write as Functie);
-            functies.insert("seek".to_string(), sk_seek as Functie);
-            functies.insert("flush".to_string(), sk_flush as Functie);
-        }
-        // Casts
-        functies.insert("int".to_string(), sk_int as Functie);
-        functies.insert("float".to_string(), sk_float as Functie);
-        functies.insert("string".to_string(), sk_string as Functie);
-        functies.insert("byte".to_string(), sk_byte as Functie);
-        // Non-togglable internals
-        functies.insert("__burlap_range".to_string(), sk_fastrange as Functie);
-        // Burlap internal functies
-        if args.extension_functies {
-            functies.insert(
-                "__burlap_typed_eq".to_string(), sk_typed_eq as Functie
-            );
-            functies.insert(
-                "__burlap_print".to_string(), sk_real_print as Functie
-            );
-            functies.insert(
-                "__burlap_throw".to_string(), sk_throw as Functie
-            );
-            functies.insert(
-                "__burlap_set_var".to_string(), sk_set_var as Functie
-            );
-            functies.insert(
-                "__burlap_load_var".to_string(), sk_load_var as Functie
-            );
-            #[cfg(feature = "cffi")]
-            functies.insert(
-                "__burlap_load_lib".to_string(), sk_libload as Functie
-            );
-            #[cfg(feature = "cffi")]
-            functies.insert(
-                "__burlap_load_functi".to_string(), sk_functiload as Functie
-            );
-            #[cfg(feature = "cffi")]
-            functies.insert(
-                "__burlap_ffi_call".to_string(), sk_call_c as Functie
-            );
-            #[cfg(feature = "cffi")]
-            functies.insert(
-                "__burlap_ptr".to_string(), sk_ptr as Functie
-            );
-        }
-        if args.extension_debugging_functies {
-            functies.insert(
-                "__burlap_debug_on".to_string(), sk_debug_on as Functie
-            );
-            functies.insert(
-                "__burlap_debug_off".to_string(), sk_debug_off as Functie
-            );
+        for builtin in get_builtins(&args) {
+            if let Some(handler) = builtin.handler {
+                functies.insert(builtin.name.to_string(), handler);
+            }
         }
+        // Start the clock
+        clock_ms();
         const NONE: Value = Value::None;
         Vm {
             stack: vec![], call_frames: vec![], jump: false,
             at: 0, filename: "".to_string(), locals: vec![],
             args, has_err: false, in_func: false, functies,
-            globals: vec![], regs: [NONE; 16], program,
+            drops: vec![], check_drops: false, gc_threshold: GC_THRESHOLD, ops_run: 0,
+            queued: vec![], watchers: vec![], tasks: vec![], running: vec![], awaiting: None,
+            io_wait: IO_POLL,
+            exit_code: None, exit_in_defer: false, error_handler: None, debugger: None,
+            nested: 0, nested_err: None, nested_frames: 0,
+            exit_hooks: vec![],
+            globals: vec![], regs: [NONE; 16], program, ast: None,
+            modules: FxHashMap::default(), temps: vec![], log_file: None,
+            resources: FxHashMap::default(),
+            #[cfg(feature = "jit")]
+            jit: Jit::default(),
+            #[cfg(feature = "hooks")]
+            instruction_hook: None,
         }
     }
 
@@ -286,12 +786,33 @@ impl Vm {
         if add_keywords {
             ret.extend(vec![
                 "true", "false", "none", "functi", "let", "return", "in",
-                "if", "else", "loop", "while", "import",
+                "if", "else", "loop", "while", "import", "export",
             ].iter().map(|i| i.to_string()).collect::<Vec<String>>());
         }
         return ret;
     }
 
+    // Runs a hook before every op, or stops with none
+    #[cfg(feature = "hooks")]
+    pub fn set_instruction_hook(&mut self, hook: Option<InstructionHook>) {
+        self.instruction_hook = hook;
+    }
+
+    // Builtins, for running them outside of the VM
+    pub fn has_functie(&self, name: &str) -> bool {
+        self.functies.contains_key(name)
+    }
+
+    pub fn call_functie(&mut self, name: &str, args: Vec<Value>) -> Option<Result<Value, String>> {
+        let functie = *self.functies.get(name)?;
+        Some(functie(self, args))
+    }
+
+    // Get a global by offset (for the REPL)
+    pub fn get_global(&self, offset: usize) -> Option<&Value> {
+        self.globals.get(offset)
+    }
+
     // Getting/setting vars
     fn get_var_offset(&mut self, offset: u16, global: bool) -> (&mut Vec<Value>, usize) {
         let vec = if global { &mut self.globals } else { &mut self.locals };
@@ -301,12 +822,29 @@ impl Vm {
     
     pub fn get_var(&mut self, offset: u16, global: bool) -> Value {
         let (vec, off) = self.get_var_offset(offset, global);
-        return vec[off as usize].clone();
+        if global {
+            // Globals that haven't been set yet are none
+            return vec.get(off).cloned().unwrap_or(Value::None);
+        }
+        return vec[off].clone();
+    }
+
+    // Moves the value out of a var, so it can be changed without copying
+    fn take_var(&mut self, offset: u16, global: bool) -> Value {
+        let (vec, off) = self.get_var_offset(offset, global);
+        let Some(var) = vec.get_mut(off) else {
+            return Value::None;
+        };
+        return std::mem::replace(var, Value::None);
     }
 
     pub fn set_var(&mut self, offset: u16, val: Value, global: bool) {
         let (vec, off) = self.get_var_offset(offset, global);
-        vec[off as usize] = val;
+        if global && off >= vec.len() {
+            // Grow the globals, so older slots stay valid
+            vec.resize(off + 1, Value::None);
+        }
+        vec[off] = val;
     }
 
     // Functies
@@ -345,15 +883,14 @@ impl Vm {
         };
         // Check non-builtins
         let mut exists: bool = false;
-        for (fname, addr, argn) in &self.program.functis {
+        for (fname, addr, argn, _) in &self.program.functis {
             // Same name
             if &name == fname {
                 exists = true;
                 // Correct args
                 if argn == &(arg_num as i32) {
                     // Go to it!
-                    self.call(*addr);
-                    return Ok(());
+                    return self.call(*addr);
                 }
             }
         }
@@ -364,8 +901,726 @@ impl Vm {
         return Err(format!("no function called \"{name}\", this should never happen"));
     }
 
+    // Runs a functi on its own (used by threads)
+    pub fn run_functi(&mut self, name: String, args: Vec<Value>) -> Result<Value, String> {
+        let Ok(arg_num) = u8::try_from(args.len()) else {
+            return Err("too many arguments".to_string());
+        };
+        self.stack.extend(args);
+        // Return to the final NOP
+        self.at = self.program.ops.len().saturating_sub(2);
+        self.call_name(name, arg_num)?;
+        if !self.call_frames.is_empty() {
+            self.jump = false;
+            if !run_loop(self) {
+                return Err("the functi had an error".to_string());
+            }
+        }
+        return Ok(self.stack.pop().unwrap_or(Value::None));
+    }
+
+    // Compiles code onto the end of the program, returns where it starts
+    // With a functi name, the code becomes the body of a functi returning the last value
+    pub fn compile_code(
+        &mut self, code: &str, name: &str, functi: Option<&str>
+    ) -> Result<Option<usize>, String> {
+        let tokens = self.lex_code(code, name)?;
+        return self.compile_tokens(tokens, name, functi);
+    }
+
+    // Cached programs weren't parsed, so do it now for the names
+    fn load_ast(&mut self) {
+        if self.ast.is_none() && !self.args.source.is_empty() {
+            let mut args = self.args.clone();
+            args.is_repl = false;
+            self.ast = crate::to_ast(&mut args).map(|mut ast| {
+                ast.nodes = vec![];
+                ast
+            });
+            self.args.source.clear();
+        }
+    }
+
+    // Lexes code for compile_code
+    fn lex_code(&mut self, code: &str, name: &str) -> Result<Vec<Token>, String> {
+        self.load_ast();
+        if self.ast.is_none() {
+            return Err("code can't be compiled here".to_string());
+        }
+        // Like the REPL, the last semicolon is optional
+        let code = if code.trim_end().is_empty() || code.trim_end().ends_with([';', '}']) {
+            code.to_string()
+        } else {
+            code.to_string() + ";"
+        };
+        set_eval_source(&code);
+        let Some(mut tokens) = lex(&code, name.to_string(), true, self.args.extension_color) else {
+            return Err(format!("{} has errors", name));
+        };
+        if tokens.is_empty() {
+            // Empty code has no EOF
+            tokens.push(Token {
+                token: TokenType::Eof, str: "".to_string(), stream: Stream {
+                    name: name.to_string(), line: 1, at: 0, rat: 0, size: 0
+                }
+            });
+        }
+        return Ok(tokens);
+    }
+
+    // Compiles lexed code for compile_code
+    fn compile_tokens(
+        &mut self, mut tokens: Vec<Token>, name: &str, functi: Option<&str>
+    ) -> Result<Option<usize>, String> {
+        let Some(ast) = &self.ast else {
+            return Err("code can't be compiled here".to_string());
+        };
+        let mut args = self.args.clone();
+        args.name = name.to_string();
+        if let Some(functi) = functi {
+            // Wrap it in `functi name() { ... }`
+            let token = |token: TokenType| Token {
+                token, stream: tokens[0].stream.clone(), str: "".to_string()
+            };
+            let start = vec![
+                token(TokenType::Func(true)),
+                token(TokenType::Identifier(functi.to_string())),
+                token(TokenType::Lparan), token(TokenType::Rparan),
+                token(TokenType::Lbrace),
+            ];
+            let end = tokens.len() - 1;
+            tokens.insert(end, token(TokenType::Rbrace));
+            tokens.splice(0..0, start);
+        }
+        let mut ast = ast.clone();
+        ast.nodes = vec![];
+        let Some(mut ast) = parse(ast, tokens, &args) else {
+            return Err(format!("{} has errors", name));
+        };
+        if let Some(ASTNode::FunctiStmt(node)) = ast.nodes.first_mut().map(|n| &mut n.node) {
+            // Return the last value
+            if let ASTNode::BodyStmt(body) = &mut node.body.node {
+                if let Some(last) = body.last_mut().filter(|last| last.node.is_expr()) {
+                    let expr = std::mem::replace(&mut last.node, ASTNode::Nop);
+                    last.node = ASTNode::ReturnStmt(Box::new(expr));
+                }
+            }
+        }
+        if ast.nodes.is_empty() {
+            return Ok(None);
+        }
+        // The final NOP is replaced by a jump over the new code
+        let end = self.program.ops.len() - 1;
+        let mut compiler = IncrementalCompiler::new(
+            std::mem::replace(&mut self.program, Program::new()), args.opt_level
+        );
+        let start = compiler.compile(&mut ast, &Some(args.name.clone()), true);
+        self.program = compiler.take_program();
+        if start.is_none() {
+            return Err(format!("{} has errors", name));
+        }
+        let offset = self.program.ops.len() - 1 - end;
+        self.program.ops[end] = ((Opcode::JMP as u32) << 24) + offset as u32;
+        ast.nodes = vec![];
+        self.ast = Some(ast);
+        return Ok(Some(end + 1));
+    }
+
+    // Runs the loop for run_code and call_nested, errors are given back instead of reported
+    // Frames past `frames` are the ones it made
+    fn run_nested(&mut self, frames: usize) -> Result<(), String> {
+        let locals = self.locals.len();
+        // Nothing is printed at the end
+        let (is_repl, print_result) = (self.args.is_repl, self.args.print_result);
+        self.args.is_repl = false;
+        self.args.print_result = false;
+        let outer_frames = std::mem::replace(&mut self.nested_frames, frames);
+        self.nested += 1;
+        let ok = run_loop(self);
+        self.nested -= 1;
+        self.nested_frames = outer_frames;
+        self.args.is_repl = is_repl;
+        self.args.print_result = print_result;
+        if ok {
+            return Ok(());
+        }
+        // Unwind anything it called
+        self.call_frames.truncate(frames);
+        self.locals.truncate(locals);
+        return Err(self.nested_err.take().unwrap_or_else(|| "the code had an error".to_string()));
+    }
+
+    // Runs compiled code in the middle of execution, returning the last value
+    pub fn run_code(&mut self, start: usize) -> Result<Value, String> {
+        let (at, jump, regs) = (self.at, self.jump, self.regs.clone());
+        let base = self.stack.len();
+        self.at = start;
+        self.jump = false;
+        let ret = self.run_nested(self.call_frames.len());
+        let ret = ret.map(|_| self.stack.get(base).cloned().unwrap_or(Value::None));
+        self.stack.truncate(base);
+        (self.at, self.jump, self.regs) = (at, jump, regs);
+        return ret;
+    }
+
+    // Calls a functi in the middle of execution, and gives back what it returned or its error
+    fn call_nested(&mut self, name: String, args: Vec<Value>) -> Result<Value, String> {
+        let Ok(arg_num) = u8::try_from(args.len()) else {
+            return Err("too many arguments".to_string());
+        };
+        let (at, jump, regs) = (self.at, self.jump, self.regs.clone());
+        let (base, frames, locals) = (self.stack.len(), self.call_frames.len(), self.locals.len());
+        self.stack.extend(args);
+        // Return to the final NOP
+        self.at = self.program.ops.len().saturating_sub(2);
+        let mut ret = self.call_name(name, arg_num);
+        if ret.is_ok() && self.call_frames.len() > frames {
+            self.jump = false;
+            ret = self.run_nested(frames);
+        }
+        let ret = ret.map(|_| self.stack.pop().unwrap_or(Value::None));
+        if ret.is_err() {
+            // Including the frame it made
+            self.call_frames.truncate(frames);
+            self.locals.truncate(locals);
+        }
+        self.stack.truncate(base);
+        (self.at, self.jump, self.regs) = (at, jump, regs);
+        return ret;
+    }
+
+    // The event loop
+    // Makes a task for the event loop
+    #[cfg(not(target_family = "wasm"))]
+    fn new_task(&mut self, state: TaskState) -> Value {
+        let task = Rc::new(RefCell::new(TaskInfo { state, waiting: None }));
+        self.tasks.push(task.clone());
+        return Value::Task(task);
+    }
+
+    // Runs a task until it's done or awaits one that isn't
+    #[cfg(not(target_family = "wasm"))]
+    fn resume_task(&mut self, task: &Rc<RefCell<TaskInfo>>) {
+        const NONE: Value = Value::None;
+        let state = std::mem::replace(&mut task.borrow_mut().state, TaskState::Running);
+        // What the await it paused at gives back
+        let awaited = task.borrow_mut().waiting.take().map(|waiting| match &waiting.borrow().state {
+            TaskState::Done(ret) => ret.clone(),
+            _ => Err(IMPOSSIBLE_STATE.to_string()),
+        });
+        let (at, jump) = (self.at, self.jump);
+        let regs = std::mem::replace(&mut self.regs, [NONE; 16]);
+        let (base, frames, locals) = (self.stack.len(), self.call_frames.len(), self.locals.len());
+        let mut ret = match state {
+            TaskState::Start(functi, args) => self.start_task(functi, args),
+            TaskState::Paused(coroutine) => {
+                self.call_frames.extend(coroutine.frames.into_iter().map(|mut frame| {
+                    frame.local_size += locals;
+                    frame.stack_size += base;
+                    frame
+                }));
+                self.locals.extend(coroutine.locals);
+                self.stack.extend(coroutine.stack);
+                self.regs = coroutine.regs;
+                self.at = coroutine.at;
+                // The await left a none for its result
+                match awaited {
+                    Some(Ok(val)) => {
+                        self.stack.pop();
+                        self.stack.push(val);
+                        Ok(())
+                    },
+                    Some(Err(e)) => Err(e),
+                    None => Ok(()),
+                }
+            },
+            _ => Err(IMPOSSIBLE_STATE.to_string()),
+        };
+        if ret.is_ok() && self.call_frames.len() > frames {
+            self.jump = false;
+            self.running.push((task.clone(), self.nested + 1));
+            ret = self.run_nested(frames);
+            self.running.pop();
+        }
+        let state = match self.awaiting.take() {
+            Some(waiting) if ret.is_ok() => {
+                task.borrow_mut().waiting = Some(waiting);
+                TaskState::Paused(Box::new(Coroutine {
+                    frames: self.call_frames.drain(frames..).map(|mut frame| {
+                        frame.local_size -= locals;
+                        frame.stack_size -= base;
+                        frame
+                    }).collect(),
+                    locals: self.locals.split_off(locals),
+                    stack: self.stack.split_off(base),
+                    regs: std::mem::replace(&mut self.regs, [NONE; 16]),
+                    at: self.at + 1,
+                }))
+            },
+            _ => {
+                let ret = ret.map(|_| self.stack.pop().unwrap_or(Value::None));
+                self.call_frames.truncate(frames);
+                self.locals.truncate(locals);
+                TaskState::Done(ret)
+            },
+        };
+        self.stack.truncate(base);
+        (self.at, self.jump, self.regs) = (at, jump, regs);
+        task.borrow_mut().state = state;
+    }
+
+    // Calls the functi of a task that's starting
+    #[cfg(not(target_family = "wasm"))]
+    fn start_task(&mut self, functi: Value, args: Vec<Value>) -> Result<(), String> {
+        let (name, mut all) = match functi {
+            Value::Functi(name) => (name, vec![]),
+            Value::BoundFuncti(name, bound) => (name, (*bound).clone()),
+            _ => return Err(IMPOSSIBLE_STATE.to_string()),
+        };
+        all.extend(args);
+        let Ok(arg_num) = u8::try_from(all.len()) else {
+            return Err("too many arguments".to_string());
+        };
+        self.stack.extend(all);
+        // Return to the final NOP
+        self.at = self.program.ops.len().saturating_sub(2);
+        return self.call_name((*name).clone(), arg_num);
+    }
+
+    // Runs every task that can go once, false if none could
+    // Errs if a task nothing can await failed
+    #[cfg(not(target_family = "wasm"))]
+    fn poll_tasks(&mut self) -> Result<bool, String> {
+        let mut progressed = false;
+        for task in self.tasks.clone() {
+            let ready = match &task.borrow().state {
+                TaskState::Start(..) => true,
+                TaskState::Paused(_) => task.borrow().waiting.as_ref()
+                    .is_none_or(|waiting| matches!(waiting.borrow().state, TaskState::Done(_))),
+                _ => false,
+            };
+            if ready {
+                self.resume_task(&task);
+                progressed = true;
+                continue;
+            }
+            let mut info = task.borrow_mut();
+            let done = match &mut info.state {
+                TaskState::Sleep(until) => (Instant::now() >= *until).then_some(Ok(Value::None)),
+                TaskState::Io(op) => match poll_io(op) {
+                    Ok(ret) => {
+                        // Even a bit of IO means it should go again without waiting
+                        progressed = true;
+                        ret.map(Ok)
+                    },
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => None,
+                    Err(e) => Some(Err(e.to_string())),
+                },
+                _ => None,
+            };
+            if let Some(done) = done {
+                info.state = TaskState::Done(done);
+                progressed = true;
+            }
+        }
+        let mut failed = None;
+        self.tasks.retain(|task| {
+            let TaskState::Done(ref ret) = task.borrow().state else {
+                return true;
+            };
+            if let (Err(e), 1) = (ret, Rc::strong_count(task)) {
+                failed.get_or_insert_with(|| e.clone());
+            }
+            false
+        });
+        if let Some(e) = failed {
+            return Err(format!("async functi failed: {}", e));
+        }
+        if progressed {
+            self.io_wait = IO_POLL;
+        }
+        return Ok(progressed);
+    }
+
+    // Sleeps until a timer is done, or a bit if there's IO
+    // Errs if the tasks can never finish (because they're waiting for each other)
+    #[cfg(not(target_family = "wasm"))]
+    fn wait_for_tasks(&mut self) -> Result<(), String> {
+        let mut wait: Option<Duration> = None;
+        for task in &self.tasks {
+            let time = match &task.borrow().state {
+                TaskState::Sleep(until) => until.saturating_duration_since(Instant::now()),
+                TaskState::Io(_) => self.io_wait,
+                _ => continue,
+            };
+            wait = Some(wait.map_or(time, |wait| wait.min(time)));
+        }
+        let Some(wait) = wait else {
+            return Err("await would wait forever, the tasks are waiting for each other".to_string());
+        };
+        std::thread::sleep(wait);
+        // Idle IO (like waiting for a connection) is checked less and less often
+        if wait == self.io_wait {
+            self.io_wait = (self.io_wait * 2).min(IO_POLL_MAX);
+        }
+        return Ok(());
+    }
+
+    // Runs the event loop until a task is done, and gives back its result
+    #[cfg(not(target_family = "wasm"))]
+    fn run_until(&mut self, task: &Rc<RefCell<TaskInfo>>) -> Result<Value, String> {
+        loop {
+            if let TaskState::Done(ret) = &task.borrow().state {
+                return ret.clone().map_err(|e| format!("async functi failed: {}", e));
+            }
+            if self.exit_code.is_some() {
+                return Ok(Value::None);
+            }
+            if !self.poll_tasks()? {
+                self.wait_for_tasks()?;
+            }
+        }
+    }
+
+    // Runs the tasks that nothing awaited when the program ends, false if one failed
+    #[cfg(not(target_family = "wasm"))]
+    fn finish_tasks(&mut self) -> bool {
+        if self.exit_code.is_some() {
+            return true;
+        }
+        while !self.tasks.is_empty() && self.exit_code.is_none() {
+            let ret = match self.poll_tasks() {
+                Ok(false) => self.wait_for_tasks(),
+                ret => ret.map(|_| ()),
+            };
+            if let Err(e) = ret {
+                let msg = format!("Runtime Error: {}", e);
+                if !capture_err(msg.clone()) {
+                    println!("{}", msg);
+                }
+                self.tasks.clear();
+                return false;
+            }
+        }
+        // A task called exit()
+        self.finish_exit();
+        return true;
+    }
+
+    // Where each call was made from ("file:line"), the innermost first
+    fn backtrace(&mut self) -> Vec<Value> {
+        let addrs: Vec<usize> = self.call_frames.iter().rev()
+            .map(|frame| frame.return_addr).collect();
+        addrs.into_iter().map(|addr| {
+            let (line, file, _) = self.program.get_info(addr as u32 + 1);
+            Value::Str(Rc::new(format!("{}:{}", file, line)))
+        }).collect()
+    }
+
+    // Give an uncaught error to the handler from set_error_handler
+    fn handle_error(&mut self, handler: &Value, msg: String) -> Result<Value, String> {
+        let (line, file, _) = self.program.get_info(self.at as u32);
+        let backtrace = self.backtrace();
+        // Unwind everything, the handler runs at the top level
+        // The frames are put back if it fails, so the error can still be reported
+        let (at, frames) = (self.at, std::mem::take(&mut self.call_frames));
+        self.locals.clear();
+        self.stack.clear();
+        let mut args = vec![
+            Value::Str(Rc::new(msg)), Value::Int(line as i32),
+            Value::Str(Rc::new(file)), Value::FastList(Rc::new(backtrace)),
+        ];
+        let name = match handler {
+            Value::BoundFuncti(name, bound) => {
+                args.splice(0..0, bound.iter().cloned());
+                name
+            },
+            Value::Functi(name) => name,
+            _ => return Err(IMPOSSIBLE_STATE.to_string()),
+        };
+        let ret = self.call_nested((**name).clone(), args);
+        if ret.is_err() {
+            (self.at, self.call_frames) = (at, frames);
+        }
+        return ret;
+    }
+
+    // The names and values of the locals in the functi that is running
+    fn local_vars(&mut self) -> Vec<(Value, Value)> {
+        self.load_ast();
+        let (Some(ast), Some(frame)) = (&self.ast, self.call_frames.last()) else {
+            return vec![];
+        };
+        // The innermost functi around the current op, each is jumped around
+        let Some((name, _, arg_num, _)) = self.program.functis.iter()
+            .filter(|(_, pos, ..)| {
+                let end = self.program.ops.get(pos.wrapping_sub(1))
+                    .map_or(0, |op| pos - 1 + (op & 0xFFFFFF) as usize);
+                *pos <= self.at && self.at < end
+            })
+            .max_by_key(|(_, pos, ..)| *pos) else {
+            return vec![];
+        };
+        let Some(functi) = ast.functis.iter()
+            .find(|f| &f.name == name && f.arg_num == *arg_num) else {
+            return vec![];
+        };
+        let mut ret: Vec<(Value, Value)> = vec![];
+        for var in &functi.locals {
+            let name = &var.real_name;
+            // Hidden vars have a $ in their name, and shadowed ones only show once
+            if name.contains('$') || ret.iter().any(|(n, _)| n.to_string().ok().as_ref() == Some(name)) {
+                continue;
+            }
+            let Some(offset) = ast.get_var_offset(name.clone(), Some(functi)) else {
+                continue;
+            };
+            let Some(at) = self.locals.len().checked_sub(offset as usize + 1)
+                .filter(|at| *at >= frame.local_size) else {
+                continue;
+            };
+            ret.push((Value::Str(Rc::new(name.clone())), self.locals[at].clone()));
+        }
+        return ret;
+    }
+
+    // The names and values of the globals
+    fn global_vars(&self) -> Vec<(Value, Value)> {
+        // Hidden and shadowing vars have a $ in their name
+        self.program.global_names.iter().zip(self.globals.iter())
+            .filter(|(name, _)| !name.is_empty() && !name.contains('$') && !name.starts_with("__"))
+            .map(|(name, val)| (Value::Str(Rc::new(name.clone())), val.clone()))
+            .collect()
+    }
+
+    // Pauses at a breakpoint, for the functi from set_debugger or the built in debugger
+    #[cfg(not(target_family = "wasm"))]
+    fn breakpoint(&mut self) -> Result<(), String> {
+        let (line, file, _) = self.program.get_info(self.at as u32);
+        let locals = self.local_vars();
+        let globals = self.global_vars();
+        let backtrace = self.backtrace();
+        if let Some(debugger) = self.debugger.clone() {
+            let info = vec![
+                (Value::Str(Rc::new("file".to_string())), Value::Str(Rc::new(file))),
+                (Value::Str(Rc::new("line".to_string())), Value::Int(line as i32)),
+                (Value::Str(Rc::new("pc".to_string())), Value::Int(self.at as i32)),
+                (Value::Str(Rc::new("locals".to_string())), Value::List(Rc::new(locals))),
+                (Value::Str(Rc::new("globals".to_string())), Value::List(Rc::new(globals))),
+                (Value::Str(Rc::new("backtrace".to_string())), Value::FastList(Rc::new(backtrace))),
+            ];
+            // It runs before the next op, like drop callbacks
+            self.queued.push((debugger, vec![Value::List(Rc::new(info))]));
+            self.check_drops = true;
+            return Ok(());
+        }
+        println!("Breakpoint at {}:{}", file, line);
+        let color = self.args.extension_color;
+        let show = |vars: &Vec<(Value, Value)>| -> Result<(), String> {
+            for (name, val) in vars {
+                println!("{} = {}", name.to_string()?, val.pretty(color));
+            }
+            Ok(())
+        };
+        loop {
+            print!("(debug) ");
+            let _ = io::stdout().flush();
+            let mut input = String::new();
+            // Continue at the end of the input
+            if io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+                println!();
+                return Ok(());
+            }
+            let (cmd, arg) = input.trim().split_once(' ').unwrap_or((input.trim(), ""));
+            match cmd {
+                "c" | "continue" => return Ok(()),
+                "l" | "locals" => show(&locals)?,
+                "g" | "globals" => show(&globals)?,
+                "bt" | "backtrace" => {
+                    println!("{}:{} (here)", file, line);
+                    for at in &backtrace {
+                        println!("{}", at.to_string()?);
+                    }
+                },
+                "pc" => println!("{}: {}", self.at, dis_single(&self.program, self.at)),
+                "p" | "print" => {
+                    let arg = arg.trim();
+                    match locals.iter().chain(globals.iter())
+                        .find(|(name, _)| name.to_string().ok().as_deref() == Some(arg)) {
+                        Some((_, val)) => println!("{}", val.pretty(color)),
+                        None => println!("no var called \"{}\"", arg),
+                    }
+                },
+                "q" | "quit" => {
+                    self.exit_code.get_or_insert(1);
+                    return Ok(());
+                },
+                "" => {},
+                _ => {
+                    println!("Commands:");
+                    println!("  c, continue    keep running");
+                    println!("  l, locals      show the locals");
+                    println!("  g, globals     show the globals");
+                    println!("  p, print NAME  show a var");
+                    println!("  bt, backtrace  show where the calls were made");
+                    println!("  pc             show the current op");
+                    println!("  q, quit        exit the program");
+                },
+            }
+        }
+    }
+
+    // The code given to exit(), once the program has stopped
+    pub fn take_exit_code(&mut self) -> Option<i32> {
+        self.exit_code.take()
+    }
+
+    // Cleans up after exit() once everything is unwound
+    fn finish_exit(&mut self) {
+        // Hooks can call exit again, which wins
+        let Some(code) = self.exit_code.take() else {
+            return;
+        };
+        self.run_exit_hooks();
+        let _ = io::stdout().flush();
+        self.remove_temps();
+        // Whatever is running the VM exits with it
+        self.exit_code = Some(self.exit_code.unwrap_or(code));
+    }
+
+    // Run the functis from at_exit, the last one added runs first
+    pub fn run_exit_hooks(&mut self) {
+        let (is_repl, print_result) = (self.args.is_repl, self.args.print_result);
+        self.args.is_repl = false;
+        self.args.print_result = false;
+        while let Some(hook) = self.exit_hooks.pop() {
+            let (name, args) = match hook {
+                Value::Functi(name) => (name, vec![]),
+                Value::BoundFuncti(name, bound) => (name, (*bound).clone()),
+                _ => continue,
+            };
+            // The program is done, so anything left from an error can go
+            self.call_frames.clear();
+            self.locals.clear();
+            self.stack.clear();
+            // Errors are printed by the run loop, the other hooks still run
+            let _ = self.run_functi((*name).clone(), args);
+        }
+        self.args.is_repl = is_repl;
+        self.args.print_result = print_result;
+    }
+
+    // Remove the files and dirs from temp_file/temp_dir
+    pub fn remove_temps(&mut self) {
+        for path in self.temps.drain(..) {
+            if path.is_dir() {
+                let _ = std::fs::remove_dir_all(path);
+            } else {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    // Queues the drop callbacks of values that are only kept alive by drop callbacks
+    // (like `weak(x, bind(f, x))`), returns how many there were
+    fn collect_cycles(&mut self) -> usize {
+        let mut marked = FxHashSet::default();
+        let frames = self.call_frames.iter()
+            .flat_map(|frame| frame.regs.iter().chain(frame.args.iter().flatten()));
+        let queued = self.queued.iter()
+            .flat_map(|(functi, args)| std::iter::once(functi).chain(args));
+        let roots = self.globals.iter().chain(&self.locals).chain(&self.stack)
+            .chain(&self.regs).chain(frames).chain(queued)
+            .chain(self.watchers.iter().map(|(_, functi, _)| functi))
+            .chain(&self.error_handler).chain(&self.debugger).chain(&self.exit_hooks)
+            .chain(self.modules.values()).chain(&self.program.consts);
+        for root in roots {
+            root.mark(&mut marked);
+        }
+        for task in &self.tasks {
+            Value::Task(task.clone()).mark(&mut marked);
+        }
+        // A callback is only reachable if the value it waits for is
+        let reachable = |weak: &WeakRef, marked: &FxHashSet<*const ()>| {
+            !weak.is_alive() || weak.ptr().is_none_or(|ptr| marked.contains(&ptr))
+        };
+        loop {
+            let before = marked.len();
+            for (weak, functi) in &self.drops {
+                if reachable(weak, &marked) {
+                    functi.mark(&mut marked);
+                }
+            }
+            if marked.len() == before {
+                break;
+            }
+        }
+        let mut collected = 0;
+        self.drops.retain(|(weak, functi)| {
+            if reachable(weak, &marked) {
+                return true;
+            }
+            self.queued.push((functi.clone(), vec![]));
+            collected += 1;
+            false
+        });
+        self.check_drops |= collected != 0;
+        return collected;
+    }
+
+    // Call the drop callbacks of dead weak handles, and queued callbacks
+    fn run_drops(&mut self) -> Result<(), String> {
+        let mut dead = vec![];
+        self.drops.retain(|(weak, functi)| {
+            if weak.is_alive() {
+                return true;
+            }
+            dead.push((functi.clone(), vec![]));
+            false
+        });
+        dead.append(&mut self.queued);
+        // The callbacks return to the next op, the last frame runs first
+        for (functi, args) in dead.into_iter().rev() {
+            let (name, arg_num) = match functi {
+                Value::Functi(name) => (name, 0),
+                Value::BoundFuncti(name, bound) => {
+                    self.stack.extend(bound.iter().cloned());
+                    (name, bound.len())
+                },
+                _ => continue,
+            };
+            let Ok(arg_num) = u8::try_from(arg_num + args.len()) else {
+                return Err("too many arguments".to_string());
+            };
+            self.stack.extend(args);
+            let (frames, at) = (self.call_frames.len(), self.at);
+            self.at = at.wrapping_sub(1);
+            self.call_name((*name).clone(), arg_num)?;
+            if self.call_frames.len() == frames {
+                // It's a functie, so it already ran
+                self.stack.pop();
+                self.at = at;
+            } else {
+                self.call_frames.last_mut().unwrap().discard = true;
+            }
+        }
+        self.jump = false;
+        return Ok(());
+    }
+
     // Call a function
-    pub fn call(&mut self, addr: usize) {
+    pub fn call(&mut self, addr: usize) -> Result<(), String> {
+        if self.args.max_call_depth != 0 && self.call_frames.len() >= self.args.max_call_depth {
+            // The most recent calls, they're usually the recursive ones
+            let calls: Vec<String> = self.call_frames.iter().rev().take(3).map(|frame| {
+                let (line, file, _) = self.program.get_info(frame.return_addr as u32 + 1);
+                format!("{}:{}", file, line)
+            }).collect();
+            return Err(format!(
+                "maximum recursion depth exceeded ({} calls), most recent calls: {}",
+                self.call_frames.len(), calls.join(", ")
+            ));
+        }
         // Store reteurn address and registers
         const NONE: Value = Value::None;
         let mut regs = [NONE; 16];
@@ -375,12 +1630,43 @@ impl Vm {
                 args: None,
                 return_addr: self.at,
                 local_size: self.locals.len(),
-                regs
+                regs, defers: vec![], defer_from: 0, discard: false,
+                stack_size: self.stack.len(),
             }
         );
         // Jump there
         self.at = addr;
         self.jump = true;
+        Ok(())
+    }
+
+    // Runs a call natively if it's hot enough, false if the interpreter has to
+    #[cfg(feature = "jit")]
+    fn jit_call(&mut self, addr: usize) -> bool {
+        // Other overflow modes and debugging need the interpreter
+        if self.args.is_debug || self.args.overflow != crate::backend::value::Overflow::Wrap {
+            return false;
+        }
+        let Some(argc) = self.jit.get(&self.program, addr, &self.args).map(|c| c.argc) else {
+            return false;
+        };
+        let Some(at) = self.stack.len().checked_sub(argc) else {
+            return false;
+        };
+        let mut args = Vec::with_capacity(argc);
+        for arg in &self.stack[at..] {
+            let Value::Int(i) = arg else {
+                return false;
+            };
+            args.push(*i);
+        }
+        let depth = self.call_frames.len();
+        let Some(ret) = self.jit.call(addr, &args, depth, self.args.max_call_depth) else {
+            return false;
+        };
+        self.stack.truncate(at);
+        self.stack.push(ret);
+        true
     }
 
     /*pub fn cur_op(&mut self) -> u8 {
@@ -488,34 +1774,397 @@ impl Vm {
     }
 }
 
+impl Drop for Vm {
+    fn drop(&mut self) {
+        self.remove_temps();
+    }
+}
+
 // Builtin Functions (prefixed with 'sk_')
 // Print
 fn sk_print(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     if vm.args.extension_va_print {
         // VA print extension
+        let mut line = String::new();
         for i in args {
-            print!("{} ", i.to_string()?);
+            line += &(i.to_string()? + " ");
         }
-        println!();
+        print_line(&line);
     } else if args.len() != 1 {
         // Invalid args
         vm.bad_args("print", args.len(), 1)?;
     } else {
         // Normal printing
-        println!("{}", args[0].to_string()?);
+        print_line(&args[0].to_string()?);
     }
     return Ok(Value::None);
 }
 
-// Input
-fn sk_input(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+// Stderr
+#[cfg(not(target_family = "wasm"))]
+fn sk_eprint(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     if args.len() != 1 {
         // Invalid args
-        vm.bad_args("input", args.len(), 1)?;
+        vm.bad_args("eprint", args.len(), 1)?;
     }
-    // Print the prompt
-    print!("{}", args[0].to_string()?);
-    let _ = std::io::stdout().flush();
+    eprint!("{}", args[0].to_string()?);
+    return Ok(Value::None);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_eprintln(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("eprintln", args.len(), 1)?;
+    }
+    eprintln!("{}", args[0].to_string()?);
+    return Ok(Value::None);
+}
+
+// Exit
+#[cfg(not(target_family = "wasm"))]
+fn sk_exit(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() > 1 {
+        // Invalid args
+        vm.bad_args("exit", args.len(), 1)?;
+    }
+    // The run loop exits once deferred code has run
+    vm.exit_code.get_or_insert(args.first().map(|c| c.to_int()).unwrap_or(0));
+    return Ok(Value::None);
+}
+
+fn sk_at_exit(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("at_exit", args.len(), 1)?;
+    }
+    let (Value::Functi(_) | Value::BoundFuncti(..)) = args[0] else {
+        return Err("at_exit() argument 1 must be a functi".to_string());
+    };
+    vm.exit_hooks.push(args[0].clone());
+    return Ok(Value::None);
+}
+
+fn sk_set_error_handler(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("set_error_handler", args.len(), 1)?;
+    }
+    vm.error_handler = match &args[0] {
+        Value::None => None,
+        handler @ (Value::Functi(_) | Value::BoundFuncti(..)) => Some(handler.clone()),
+        _ => return Err("set_error_handler() argument 1 must be a functi".to_string()),
+    };
+    return Ok(Value::None);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_breakpoint(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("breakpoint", args.len(), 0)?;
+    }
+    vm.breakpoint()?;
+    return Ok(Value::None);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_set_debugger(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("set_debugger", args.len(), 1)?;
+    }
+    vm.debugger = match &args[0] {
+        Value::None => None,
+        debugger @ (Value::Functi(_) | Value::BoundFuncti(..)) => Some(debugger.clone()),
+        _ => return Err("set_debugger() argument 1 must be a functi".to_string()),
+    };
+    return Ok(Value::None);
+}
+
+// Docs
+// Gets the name and docs of a functi
+fn get_doc(vm: &Vm, name: &str, functi: &Value) -> Result<(String, Option<String>), String> {
+    let (Value::Functi(f) | Value::BoundFuncti(f, _)) = functi else {
+        return Err(format!("{}() argument 1 must be a functi", name));
+    };
+    // Overloads can each have docs
+    let docs: Vec<&String> = vm.program.functis.iter()
+        .filter(|i| &i.0 == f.as_ref() && !i.3.is_empty()).map(|i| &i.3).collect();
+    if docs.is_empty() {
+        // Builtins are documented in the registry
+        let doc = get_builtin(&vm.args, f).map(|b| b.doc.to_string());
+        return Ok((f.to_string(), doc));
+    }
+    let docs = docs.into_iter().cloned().collect::<Vec<String>>().join("\n\n");
+    return Ok((f.to_string(), Some(docs)));
+}
+
+fn sk_help(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("help", args.len(), 1)?;
+    }
+    match get_doc(vm, "help", &args[0])? {
+        (_, Some(doc)) => println!("{}", doc),
+        (name, None) => println!("No documentation for {}", name),
+    }
+    return Ok(Value::None);
+}
+
+fn sk_doc(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("__doc", args.len(), 1)?;
+    }
+    return Ok(match get_doc(vm, "__doc", &args[0])?.1 {
+        Some(doc) => Value::Str(Rc::new(doc)),
+        None => Value::None,
+    });
+}
+
+// Eval
+fn sk_eval(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("eval", args.len(), 1)?;
+    }
+    let Value::Str(code) = &args[0] else {
+        return Err("eval() argument 1 must be a string".to_string());
+    };
+    let Some(start) = vm.compile_code(code, "<eval>", None)? else {
+        return Ok(Value::None);
+    };
+    return vm.run_code(start);
+}
+
+fn sk_compile(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("compile", args.len(), 1)?;
+    }
+    let Value::Str(code) = &args[0] else {
+        return Err("compile() argument 1 must be a string".to_string());
+    };
+    // It's only declared, so it isn't run
+    let name = format!(
+        "__compiled_{}", vm.ast.as_ref().map(|ast| ast.functis.len()).unwrap_or(0)
+    );
+    vm.compile_code(code, "<eval>", Some(&name))?;
+    return Ok(Value::Functi(Rc::new(name)));
+}
+
+// Reflection
+fn sk_globals(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("globals", args.len(), 0)?;
+    }
+    return Ok(Value::List(Rc::new(vm.global_vars())));
+}
+
+fn sk_functions(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("functions", args.len(), 0)?;
+    }
+    // Anonymous and async functis are hidden
+    let functis = vm.program.functis.iter()
+        .filter(|(name, ..)| !name.contains('$') && !name.starts_with("__"))
+        .map(|(name, _, arg_num, _)| (name.as_str(), *arg_num));
+    // Builtins with more than one arity are there once for each, like overloads
+    let builtins = get_builtins(&vm.args)
+        .filter(|b| !b.name.starts_with("__"))
+        .flat_map(|b| b.arities().into_iter().map(|arg_num| (b.name, arg_num)));
+    let functis = functis.chain(builtins)
+        .map(|(name, arg_num)| Value::Tuple(Rc::new(vec![
+            Value::Str(Rc::new(name.to_string())), Value::Int(arg_num)
+        ])))
+        .collect();
+    return Ok(Value::FastList(Rc::new(functis)));
+}
+
+// If the caller is in the main file, and not an import
+fn sk_is_main(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("is_main", args.len(), 0)?;
+    }
+    let (_, file, _) = vm.program.get_info(vm.at as u32);
+    return Ok(Value::Bool(vm.program.main_file() == Some(file.as_str())));
+}
+
+fn sk_arity(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("arity", args.len(), 1)?;
+    }
+    let (name, bound) = match &args[0] {
+        Value::Functi(name) => (name, 0),
+        Value::BoundFuncti(name, bound) => (name, bound.len() as i32),
+        _ => return Err("arity() argument 1 must be a functi".to_string()),
+    };
+    // Functis, then builtins
+    let mut arities: Vec<i32> = vm.program.functis.iter()
+        .filter(|f| &f.0 == name.as_ref()).map(|f| f.2).collect();
+    if arities.is_empty() {
+        if let Some(builtin) = get_builtin(&vm.args, name) {
+            arities = builtin.arities();
+        }
+    }
+    arities.dedup();
+    return match arities[..] {
+        [] => Err(format!("no function called \"{}\"", name)),
+        [-1] => Ok(Value::Int(-1)),
+        [arity] => Ok(Value::Int(arity - bound)),
+        _ => Err(format!(
+            "\"{}\" is overloaded, so it doesn't have one arity (use functions())",
+            name
+        )),
+    };
+}
+
+// Terminal
+fn sk_term_color(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("term_color", args.len(), 2)?;
+    }
+    // Names or a 256 color number
+    let code = match &args[1] {
+        Value::Int(n @ 0..=255) => format!("38;5;{}", n),
+        Value::Str(name) => {
+            let (bright, name) = match name.strip_prefix("bright_") {
+                Some(name) => (true, name),
+                None => (false, name.as_str()),
+            };
+            let Some(at) = [
+                "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"
+            ].iter().position(|c| *c == name) else {
+                return Err(format!("unknown color \"{}\"", name));
+            };
+            format!("{}", if bright { 90 } else { 30 } + at)
+        },
+        _ => return Err("term_color() argument 2 must be a color name or number".to_string()),
+    };
+    return Ok(Value::Str(Rc::new(
+        format!("\x1b[{}m{}\x1b[0m", code, args[0].to_string()?)
+    )));
+}
+
+// Runs stty on the terminal
+#[cfg(not(target_family = "wasm"))]
+fn stty(args: &[&str]) -> Option<String> {
+    let out = std::process::Command::new("stty").args(args)
+        .stdin(std::process::Stdio::inherit()).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    return Some(String::from_utf8_lossy(&out.stdout).trim().to_string());
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_term_size(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("term_size", args.len(), 0)?;
+    }
+    // (columns, rows), from stty, then the environment, then the usual default
+    let from_env = |name: &str, default: i32| std::env::var(name).ok()
+        .and_then(|v| v.parse::<i32>().ok()).unwrap_or(default);
+    let (cols, rows) = stty(&["size"]).and_then(|size| {
+        let (rows, cols) = size.split_once(' ')?;
+        Some((cols.parse::<i32>().ok()?, rows.parse::<i32>().ok()?))
+    }).filter(|(cols, _)| *cols > 0).unwrap_or_else(|| (from_env("COLUMNS", 80), from_env("LINES", 24)));
+    return Ok(Value::Tuple(Rc::new(vec![Value::Int(cols), Value::Int(rows)])));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_term_clear(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("term_clear", args.len(), 0)?;
+    }
+    print!("\x1b[2J\x1b[H");
+    let _ = io::stdout().flush();
+    return Ok(Value::None);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_cursor_move(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("cursor_move", args.len(), 2)?;
+    }
+    // Zero based, the terminal is one based
+    let (x, y) = (args[0].to_int().max(0), args[1].to_int().max(0));
+    print!("\x1b[{};{}H", y + 1, x + 1);
+    let _ = io::stdout().flush();
+    return Ok(Value::None);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_cursor_visible(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("cursor_visible", args.len(), 1)?;
+    }
+    print!("{}", if args[0].is_truthy() { "\x1b[?25h" } else { "\x1b[?25l" });
+    let _ = io::stdout().flush();
+    return Ok(Value::None);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_read_key(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("read_key", args.len(), 0)?;
+    }
+    // Raw mode, so keys come without waiting for enter
+    let Some(old) = stty(&["-g"]) else {
+        return Err("read_key() needs a terminal".to_string());
+    };
+    stty(&["raw", "-echo"]);
+    let mut stdin = io::stdin();
+    let mut buf = [0u8; 4];
+    let read = stdin.read(&mut buf[..1]).unwrap_or(0);
+    let key = if read == 0 {
+        Value::None
+    } else if buf[0] == 0x1b {
+        // Arrow keys are escape sequences, a lone escape times out
+        stty(&["min", "0", "time", "1"]);
+        let len = stdin.read(&mut buf[1..3]).unwrap_or(0);
+        Value::Str(Rc::new(match &buf[1..1 + len] {
+            b"[A" => "up",
+            b"[B" => "down",
+            b"[C" => "right",
+            b"[D" => "left",
+            _ => "escape",
+        }.to_string()))
+    } else {
+        // The rest of a multibyte character
+        let len = match buf[0] {
+            0xf0..=0xff => 4,
+            0xe0..=0xef => 3,
+            0xc0..=0xdf => 2,
+            _ => 1,
+        };
+        let _ = stdin.read_exact(&mut buf[1..len]);
+        Value::Str(Rc::new(String::from_utf8_lossy(&buf[..len]).to_string()))
+    };
+    stty(&[&old]);
+    return Ok(key);
+}
+
+// Input
+fn sk_input(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("input", args.len(), 1)?;
+    }
+    // Print the prompt
+    print!("{}", args[0].to_string()?);
+    let _ = std::io::stdout().flush();
     // Get input
     let mut buffer = String::new();
     return Ok(match io::stdin().read_line(&mut buffer) {
@@ -524,6 +2173,33 @@ fn sk_input(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     });
 }
 
+#[cfg(not(target_family = "wasm"))]
+fn sk_read_line(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("read_line", args.len(), 0)?;
+    }
+    // None at the end of the input
+    let mut buffer = String::new();
+    return Ok(match io::stdin().read_line(&mut buffer) {
+        Ok(0) | Err(_) => Value::None,
+        _ => Value::Str(Rc::new(buffer.trim_end_matches(['\n', '\r']).to_string()))
+    });
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_read_all_stdin(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("read_all_stdin", args.len(), 0)?;
+    }
+    let mut buffer = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut buffer) {
+        return Err(format!("failed to read stdin: {}", e));
+    }
+    return Ok(Value::Str(Rc::new(buffer)));
+}
+
 // Type
 fn sk_type(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     if args.len() != 1 {
@@ -533,12 +2209,73 @@ fn sk_type(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     return Ok(Value::Str(Rc::new(args[0].get_type())));
 }
 
+// Type checks, calls to these are compiled to IST
+pub const TYPE_CHECKS: [&str; 6] = ["is_num", "is_str", "is_list", "is_bool", "is_none", "is_functi"];
+
+pub fn is_type(val: &Value, ty: u8) -> bool {
+    match ty {
+        0 => matches!(val, Value::Int(_) | Value::Float(_)),
+        1 => matches!(val, Value::Str(_)),
+        2 => matches!(val, Value::List(_) | Value::FastList(_)),
+        3 => matches!(val, Value::Bool(_)),
+        4 => matches!(val, Value::None),
+        5 => matches!(val, Value::Functi(_) | Value::BoundFuncti(..)),
+        _ => panic!("{}", IMPOSSIBLE_STATE),
+    }
+}
+
+// For when they aren't called directly, like `filter(l, is_num)`
+fn type_check(vm: &mut Vm, args: &[Value], ty: u8) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args(TYPE_CHECKS[ty as usize], args.len(), 1)?;
+    }
+    return Ok(Value::Bool(is_type(&args[0], ty)));
+}
+
+fn sk_is_num(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return type_check(vm, &args, 0);
+}
+
+fn sk_is_str(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return type_check(vm, &args, 1);
+}
+
+fn sk_is_list(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return type_check(vm, &args, 2);
+}
+
+fn sk_is_bool(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return type_check(vm, &args, 3);
+}
+
+fn sk_is_none(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return type_check(vm, &args, 4);
+}
+
+fn sk_is_functi(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return type_check(vm, &args, 5);
+}
+
+// Args to the program, args() only gives these at the top level
+fn sk_program_args(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("program_args", args.len(), 0)?;
+    }
+    return Ok(Value::FastList(Rc::new(
+        vm.args.program_args.iter().map(|i| Value::Str(Rc::new(i.clone()))).collect()
+    )));
+}
+
 fn len_helper(iterable: &Value) -> Option<i32> {
     // Get the len
-    Some(if let Value::FastList(l) = iterable {
+    Some(if let Value::FastList(l) | Value::Tuple(l) = iterable {
         l.len()
     } else if let Value::List(l) = iterable {
         l.len()
+    } else if let Value::Set(s) = iterable {
+        s.len()
     } else if let Value::Str(s) = iterable {
         s.chars().count()
     } else {
@@ -590,21 +2327,66 @@ fn sk_len(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
 
 // Range
 fn sk_range(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 2 {
+    if args.len() != 2 && args.len() != 3 {
         // Invalid args
-        vm.bad_args("range", args.len(), 2)?;
+        vm.bad_args("range", args.len(), 3)?;
     }
-    // Get the values
-    let (mut min, max) = (args[0].to_int(), args[1].to_int());
-    // Find out which way it's going
-    let offset: i32 = if min > max { -1 } else { 1 };
+    let (min, max, step) = range_args("range", &args)?;
+    let size = range_len(min, max, step);
+    if size > RANGE_LIST_MAX {
+        // Too big to be worth a list, so it's lazy like a for-loop range
+        return sk_fastrange(vm, args);
+    }
+    check_size(vm, size, false)?;
     // Loop and get values
-    let mut ret = Vec::<Value>::new();
-    while min != max {
-        ret.push(Value::Int(min));
-        min += offset;
+    let ret = (0..size as i64).map(|at| Value::Int((min as i64 + at * step as i64) as i32)).collect();
+    return Ok(Value::FastList(Rc::new(ret)));
+}
+
+// Ranges with more numbers than this are lazy
+const RANGE_LIST_MAX: usize = 10000;
+
+// The start, end, and step of a range
+fn range_args(name: &str, args: &[Value]) -> Result<(i32, i32, i32), String> {
+    let (min, max) = (args[0].to_int(), args[1].to_int());
+    let step = match args.get(2) {
+        // Find out which way it's going
+        None => if min > max { -1 } else { 1 },
+        Some(Value::Int(0)) => return Err(format!("{}() step can't be 0", name)),
+        Some(Value::Int(step)) => *step,
+        Some(step) => return Err(format!("{}() step must be an int, not {}", name, step.get_type())),
+    };
+    return Ok((min, max, step));
+}
+
+// How many numbers are in a range, the end is included if the step lands on it
+fn range_len(min: i32, max: i32, step: i32) -> usize {
+    let steps = (max as i64 - min as i64) / step as i64;
+    if steps < 0 || (steps == 0 && min != max && (max > min) != (step > 0)) {
+        return 0;
+    }
+    return steps as usize + 1;
+}
+
+// Linspace
+fn sk_linspace(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 3 {
+        // Invalid args
+        vm.bad_args("linspace", args.len(), 3)?;
+    }
+    let (start, end) = (to_num("linspace", &args[0])?, to_num("linspace", &args[1])?);
+    let Value::Int(count) = args[2] else {
+        return Err("linspace() argument 3 must be an int".to_string());
+    };
+    if count < 0 {
+        return Err("linspace() can't make a negative number of values".to_string());
     }
-    ret.push(Value::Int(min));
+    check_size(vm, count as usize, false)?;
+    // Both ends are included, so the last one is exactly the end
+    let gap = if count > 1 { (end - start) / (count - 1) as f64 } else { 0.0 };
+    let ret = (0..count).map(|at| Value::Float(
+        if at == count - 1 && count > 1 { end } else { start + gap * at as f64 } as f32
+    )).collect();
     return Ok(Value::FastList(Rc::new(ret)));
 }
 
@@ -621,172 +2403,2427 @@ fn sk_rand(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     return Ok(Value::Int(rand::thread_rng().gen_range(min..max+1)));
 }
 
-// File IO
-fn sk_open(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 2 {
-        // Invalid args
-        vm.bad_args("open", args.len(), 2)?;
-    }
-    let Value::Str(ref file) = args[0] else {
-        return Err("invalid file name".to_string());
-    };
-    let file = file.to_string();
-    let Value::Str(ref mode) = args[1] else {
-        return Err("invalid file mode".to_string());
+// Math
+// Numbers (and bytes) as f64, which can hold every number exactly
+fn to_num(name: &str, val: &Value) -> Result<f64, String> {
+    return match val {
+        Value::Int(i) => Ok(*i as f64),
+        Value::Float(f) => Ok(*f as f64),
+        Value::Byte(b) => Ok(*b as f64),
+        _ => Err(format!("{}() only works on numbers, not {}", name, val.get_type())),
     };
-    // Open with mode
-    let (infofile, mode) = match mode.as_str() {
-        // Write
-        "w" | "wb" => {(
-            OpenOptions::new().write(true).create(true).truncate(false)
-                .open(file.clone()),
-            if **mode == "w" {2} else {-2}
-        )},
-        // Read
-        "r" | "rb" => {(
-            OpenOptions::new().read(true).open(file.clone()),
-            if **mode == "r" {1} else {-1}
-        )},
-        // Append
-        "a" => {(
-            OpenOptions::new().append(true).create(true).open(file.clone()),
-            0
-        )},
-        _ => return Err("invalid file mode".to_string()),
+}
+
+// The smallest or biggest of the args, or of a list if there is only one
+fn min_max(name: &str, args: &[Value], max: bool) -> Result<Value, String> {
+    let vals = match args {
+        [list] => list.values().ok_or_else(|| format!(
+            "{}() with one argument needs a list, not {}", name, list.get_type()
+        ))?,
+        _ => args.to_vec(),
     };
-    // Check
-    let Ok(fileinfo) = infofile else {
-        return Err("failed to open file".to_string());
+    let mut best: Option<(f64, &Value)> = None;
+    for val in &vals {
+        let num = to_num(name, val)?;
+        if num.is_nan() {
+            // Nothing compares to NaN, so it's the answer
+            return Ok(val.clone());
+        }
+        if best.is_none_or(|(b, _)| if max { num > b } else { num < b }) {
+            best = Some((num, val));
+        }
+    }
+    let Some((_, best)) = best else {
+        return Err(format!("{}() needs at least one number", name));
     };
-    // File info
-    let fi = Rc::new(RefCell::new(FileInfo{
-        name: file, mode,
-        closed: false,
-        file: Some(fileinfo)
-    }));
-    return Ok(Value::File(fi));
+    return Ok(best.clone());
 }
 
-fn sk_close(vm: &mut Vm, mut args: Vec<Value>) -> Result<Value, String> {
+fn sk_min(_vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return min_max("min", &args, false);
+}
+
+fn sk_max(_vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return min_max("max", &args, true);
+}
+
+// Numbers stay numbers unless there is a decimal
+fn sk_sum(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     if args.len() != 1 {
         // Invalid args
-        vm.bad_args("close", args.len(), 1)?;
+        vm.bad_args("sum", args.len(), 1)?;
     }
-    let Value::File(ref mut info) = args[0] else {
-        return Err(format!("cannot close {}", args[0].get_type()));
+    let Some(vals) = args[0].values() else {
+        return Err("sum() argument 1 must be a list".to_string());
+    };
+    let mut int: Option<i32> = Some(0);
+    let mut float = 0.0;
+    for val in &vals {
+        float += to_num("sum", val)?;
+        int = match val {
+            Value::Int(i) => int.and_then(|sum| sum.checked_add(*i)),
+            Value::Byte(b) => int.and_then(|sum| sum.checked_add(*b as i32)),
+            _ => None,
+        };
+    }
+    return Ok(match int {
+        Some(int) => Value::Int(int),
+        None if vals.iter().all(|v| !matches!(v, Value::Float(_))) => {
+            return Err("sum() overflowed".to_string());
+        },
+        None => Value::Float(float as f32),
+    });
+}
+
+fn sk_abs(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("abs", args.len(), 1)?;
+    }
+    return Ok(match args[0] {
+        Value::Int(i) => Value::Int(i.checked_abs().ok_or("abs() overflowed")?),
+        Value::Float(f) => Value::Float(f.abs()),
+        Value::Byte(b) => Value::Byte(b),
+        ref val => return Err(format!("abs() only works on numbers, not {}", val.get_type())),
+    });
+}
+
+// Most digits to_fixed shows, so a typo can't make a huge string
+const MAX_FIXED_DIGITS: i32 = 100;
+
+// Rounds to a number, or to a decimal with the number of digits
+fn round_with(
+    vm: &mut Vm, name: &str, args: &[Value], op: fn(f64) -> f64
+) -> Result<Value, String> {
+    if args.len() != 1 && args.len() != 2 {
+        // Invalid args
+        vm.bad_args(name, args.len(), 1)?;
+    }
+    let num = to_num(name, &args[0])?;
+    let Some(digits) = args.get(1) else {
+        let rounded = op(num);
+        if rounded.is_nan() || rounded < i32::MIN as f64 || rounded > i32::MAX as f64 {
+            return Err(format!("{}() result doesn't fit in a number", name));
+        }
+        return Ok(Value::Int(rounded as i32));
+    };
+    let Value::Int(digits) = digits else {
+        return Err(format!("{}() argument 2 must be a number", name));
+    };
+    // Past 60 digits every float is already rounded (or rounds to 0), and the scale would be inf
+    let scale = 10f64.powi((*digits).clamp(-60, 60));
+    return Ok(Value::Float((op(num * scale) / scale) as f32));
+}
+
+fn sk_round(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return round_with(vm, "round", &args, f64::round);
+}
+
+fn sk_floor(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return round_with(vm, "floor", &args, f64::floor);
+}
+
+fn sk_ceil(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return round_with(vm, "ceil", &args, f64::ceil);
+}
+
+fn sk_trunc(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return round_with(vm, "trunc", &args, f64::trunc);
+}
+
+// Digits after the point, the float's exact value is rounded (so 2.675 is "2.67")
+fn sk_to_fixed(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("to_fixed", args.len(), 2)?;
+    }
+    let num = to_num("to_fixed", &args[0])?;
+    let Value::Int(digits) = args[1] else {
+        return Err("to_fixed() argument 2 must be a number".to_string());
+    };
+    if !(0..=MAX_FIXED_DIGITS).contains(&digits) {
+        return Err(format!("to_fixed() can only show 0 to {} digits", MAX_FIXED_DIGITS));
+    }
+    if !num.is_finite() {
+        return Ok(Value::Str(Rc::new(format_float(num as f32))));
+    }
+    return Ok(Value::Str(Rc::new(format!("{:.*}", digits as usize, num))));
+}
+
+fn sk_is_nan(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("is_nan", args.len(), 1)?;
+    }
+    return Ok(Value::Bool(to_num("is_nan", &args[0])?.is_nan()));
+}
+
+// Not inf, -inf, or nan
+fn sk_is_finite(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("is_finite", args.len(), 1)?;
+    }
+    return Ok(Value::Bool(to_num("is_finite", &args[0])?.is_finite()));
+}
+
+// Secure random bytes from the OS, for tokens and salts
+fn sk_random_bytes(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("random_bytes", args.len(), 1)?;
+    }
+    let Value::Int(len @ 0..) = args[0] else {
+        return Err("random_bytes() argument 1 must be a positive number".to_string());
+    };
+    check_size(vm, len as usize, false)?;
+    let mut bytes = vec![0u8; len as usize];
+    rand::rngs::OsRng.try_fill_bytes(&mut bytes).map_err(|e| e.to_string())?;
+    return Ok(Value::FastList(Rc::new(bytes.into_iter().map(Value::Byte).collect())));
+}
+
+// Random (version 4) UUID, like "e0b7a1c2-5d3f-4a8b-9c6d-1e2f3a4b5c6d"
+fn sk_uuid4(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("uuid4", args.len(), 0)?;
+    }
+    let mut bytes = [0u8; 16];
+    rand::rngs::OsRng.try_fill_bytes(&mut bytes).map_err(|e| e.to_string())?;
+    // Version and variant bits
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    return Ok(Value::Str(Rc::new(format!(
+        "{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..]
+    ))));
+}
+
+// Changing lists in place
+// Runs f on the list in a var (from __burlap_reftype), it isn't copied if the var is the only copy
+fn change_list<T>(
+    vm: &mut Vm, name: &str, var: &Value,
+    f: impl FnOnce(&mut Vm, &mut Value) -> Result<T, String>
+) -> Result<T, String> {
+    let Value::RefType(offset, global) = *var else {
+        return Err(format!("{}() argument 1 must be a variable", name));
+    };
+    // The var is none while it's being changed
+    let mut list = if global {
+        vm.take_var(offset as u16, true)
+    } else {
+        std::mem::replace(&mut vm.locals[offset as usize], Value::None)
+    };
+    let ret = if let Value::List(_) | Value::FastList(_) = list {
+        f(vm, &mut list)
+    } else {
+        Err(format!("{}() argument 1 must be a list", name))
+    };
+    if global {
+        vm.set_var(offset as u16, list, true);
+    } else {
+        vm.locals[offset as usize] = list;
+    }
+    return ret;
+}
+
+// Gets an index for insert/remove_at, max is the largest one allowed
+fn list_index(name: &str, index: &Value, max: usize) -> Result<usize, String> {
+    let Value::Int(index) = index else {
+        return Err(format!("{}() argument 2 must be an int", name));
+    };
+    if *index < 0 || *index as usize > max {
+        return Err(format!("{}() index {} is out of range", name, index));
+    }
+    return Ok(*index as usize);
+}
+
+fn sk_push(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("push", args.len(), 2)?;
+    }
+    change_list(vm, "push", &args[0], |vm, list| {
+        check_size(vm, len_helper(list).unwrap_or(0) as usize + 1, false)?;
+        match list {
+            Value::FastList(l) => Rc::make_mut(l).push(args[1].clone()),
+            Value::List(l) => Rc::make_mut(l).push((Value::None, args[1].clone())),
+            _ => panic!("{}", IMPOSSIBLE_STATE),
+        }
+        Ok(Value::None)
+    })
+}
+
+fn sk_pop(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("pop", args.len(), 1)?;
+    }
+    // Empty lists give none
+    change_list(vm, "pop", &args[0], |_, list| Ok(match list {
+        Value::FastList(l) => Rc::make_mut(l).pop(),
+        Value::List(l) => Rc::make_mut(l).pop().map(|(_, v)| v),
+        _ => panic!("{}", IMPOSSIBLE_STATE),
+    }.unwrap_or(Value::None)))
+}
+
+fn sk_insert(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 3 {
+        // Invalid args
+        vm.bad_args("insert", args.len(), 3)?;
+    }
+    change_list(vm, "insert", &args[0], |vm, list| {
+        let size = len_helper(list).unwrap_or(0) as usize;
+        let at = list_index("insert", &args[1], size)?;
+        check_size(vm, size + 1, false)?;
+        match list {
+            Value::FastList(l) => Rc::make_mut(l).insert(at, args[2].clone()),
+            Value::List(l) => Rc::make_mut(l).insert(at, (Value::None, args[2].clone())),
+            _ => panic!("{}", IMPOSSIBLE_STATE),
+        }
+        Ok(Value::None)
+    })
+}
+
+fn sk_remove_at(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("remove_at", args.len(), 2)?;
+    }
+    change_list(vm, "remove_at", &args[0], |_, list| {
+        let size = len_helper(list).unwrap_or(0) as usize;
+        if size == 0 {
+            return Err("remove_at() list is empty".to_string());
+        }
+        let at = list_index("remove_at", &args[1], size - 1)?;
+        Ok(match list {
+            Value::FastList(l) => Rc::make_mut(l).remove(at),
+            Value::List(l) => Rc::make_mut(l).remove(at).1,
+            _ => panic!("{}", IMPOSSIBLE_STATE),
+        })
+    })
+}
+
+fn sk_extend(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("extend", args.len(), 2)?;
+    }
+    let Some(vals) = args[1].values() else {
+        return Err("extend() argument 2 must be a list".to_string());
+    };
+    change_list(vm, "extend", &args[0], |vm, list| {
+        check_size(vm, len_helper(list).unwrap_or(0) as usize + vals.len(), false)?;
+        match list {
+            Value::FastList(l) => Rc::make_mut(l).extend(vals),
+            Value::List(l) => Rc::make_mut(l).extend(vals.into_iter().map(|v| (Value::None, v))),
+            _ => panic!("{}", IMPOSSIBLE_STATE),
+        }
+        Ok(Value::None)
+    })
+}
+
+// Sort
+fn sk_sort(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("sort", args.len(), 1)?;
+    }
+    return Ok(match &args[0] {
+        Value::FastList(l) => {
+            let mut list = (**l).clone();
+            list.sort_by(|a, b| a.total_cmp(b));
+            Value::FastList(Rc::new(list))
+        },
+        Value::List(l) => {
+            // Keys stay with their values
+            let mut list = (**l).clone();
+            list.sort_by(|a, b| a.1.total_cmp(&b.1));
+            Value::List(Rc::new(list))
+        },
+        _ => return Err("sort() argument 1 must be a list".to_string()),
+    });
+}
+
+// Reverse
+fn sk_reverse(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("reverse", args.len(), 1)?;
+    }
+    return Ok(match &args[0] {
+        Value::FastList(l) => Value::FastList(Rc::new(l.iter().rev().cloned().collect())),
+        // Keys stay with their values
+        Value::List(l) => Value::List(Rc::new(l.iter().rev().cloned().collect())),
+        Value::Str(s) => Value::Str(Rc::new(s.chars().rev().collect())),
+        _ => return Err("reverse() argument 1 must be a list or string".to_string()),
+    });
+}
+
+// Index of
+fn sk_index_of(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("index_of", args.len(), 2)?;
+    }
+    // Keyed lists give the index, not the key
+    let index = match &args[0] {
+        Value::FastList(l) => l.iter().position(|v| args[1].eq(v)),
+        Value::List(l) => l.iter().position(|(_, v)| args[1].eq(v)),
+        Value::Str(s) => {
+            let Value::Str(s2) = &args[1] else {
+                return Err("index_of() argument 2 must be a string".to_string());
+            };
+            // In chars, not bytes
+            s.find(s2.as_str()).map(|at| s[..at].chars().count())
+        },
+        _ => return Err("index_of() argument 1 must be a list or string".to_string()),
+    };
+    return Ok(index.map_or(Value::None, |at| Value::Int(at as i32)));
+}
+
+// Contains, `in` as a functi
+fn sk_contains(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("contains", args.len(), 2)?;
+    }
+    let Some(contains) = args[0].contains(&args[1]) else {
+        return Err(format!(
+            "contains() can't look for {} in {}", args[1].get_type(), args[0].get_type()
+        ));
+    };
+    return Ok(Value::Bool(contains));
+}
+
+// Flatten
+fn flatten_into(list: &Value, depth: i32, out: &mut Vec<Value>) {
+    for val in list.values().unwrap() {
+        if depth > 0 && matches!(val, Value::FastList(_) | Value::List(_)) {
+            flatten_into(&val, depth - 1, out);
+        } else {
+            out.push(val);
+        }
+    }
+}
+
+fn sk_flatten(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.is_empty() || args.len() > 2 {
+        // Invalid args
+        vm.bad_args("flatten", args.len(), 2)?;
+    }
+    if !matches!(args[0], Value::FastList(_) | Value::List(_)) {
+        return Err("flatten() argument 1 must be a list".to_string());
+    }
+    let depth = match args.get(1) {
+        None => 1,
+        Some(Value::Int(depth)) if *depth >= 0 => *depth,
+        _ => return Err("flatten() argument 2 must be a positive int".to_string()),
+    };
+    // Keys are dropped
+    let mut ret = vec![];
+    flatten_into(&args[0], depth, &mut ret);
+    return Ok(Value::FastList(Rc::new(ret)));
+}
+
+// Unique
+// SetKey is only mutable through files and such, which hash by type
+#[allow(clippy::mutable_key_type)]
+fn sk_unique(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("unique", args.len(), 1)?;
+    }
+    // Like sets, 1 and 1.0 are different
+    let mut seen = FxHashSet::default();
+    return Ok(match &args[0] {
+        Value::FastList(l) => Value::FastList(Rc::new(
+            l.iter().filter(|v| seen.insert(SetKey((*v).clone()))).cloned().collect()
+        )),
+        // The first key is kept
+        Value::List(l) => Value::List(Rc::new(
+            l.iter().filter(|(_, v)| seen.insert(SetKey(v.clone()))).cloned().collect()
+        )),
+        _ => return Err("unique() argument 1 must be a list".to_string()),
+    });
+}
+
+// Chunk
+fn sk_chunk(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("chunk", args.len(), 2)?;
+    }
+    let Some(vals) = args[0].values() else {
+        return Err("chunk() argument 1 must be a list".to_string());
+    };
+    let Value::Int(size) = args[1] else {
+        return Err("chunk() argument 2 must be an int".to_string());
+    };
+    if size <= 0 {
+        return Err("chunk() size must be more than 0".to_string());
+    }
+    // The last one can be smaller
+    return Ok(Value::FastList(Rc::new(
+        vals.chunks(size as usize).map(|c| Value::FastList(Rc::new(c.to_vec()))).collect()
+    )));
+}
+
+// Items
+fn sk_items(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("items", args.len(), 1)?;
+    }
+    // Lists without a key use the index
+    let pair = |key: Value, val: &Value| Value::FastList(Rc::new(vec![key, val.clone()]));
+    return Ok(Value::FastList(Rc::new(match &args[0] {
+        Value::FastList(l) => l.iter().enumerate()
+            .map(|(at, val)| pair(Value::Int(at as i32), val)).collect(),
+        Value::List(l) => l.iter().enumerate()
+            .map(|(at, (key, val))| pair(
+                if *key == Value::None { Value::Int(at as i32) } else { key.clone() }, val
+            )).collect(),
+        _ => return Err("items() argument 1 must be a list".to_string()),
+    })));
+}
+
+// Serialization, bytes are a list of Byte like read() in "rb" mode
+// Gets the bytes from a list of bytes (or numbers 0-255)
+fn to_bytes(name: &str, val: &Value) -> Result<Vec<u8>, String> {
+    let Some(vals) = val.values() else {
+        return Err(format!("{}() argument 1 must be a list of bytes", name));
+    };
+    return vals.iter().map(|v| match v {
+        Value::Byte(b) => Ok(*b),
+        Value::Int(i @ 0..=255) => Ok(*i as u8),
+        _ => Err(format!("{}() argument 1 must be a list of bytes", name)),
+    }).collect();
+}
+
+// Characters
+fn sk_ord(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("ord", args.len(), 1)?;
+    }
+    let c = match &args[0] {
+        Value::Str(s) if s.chars().count() == 1 => s.chars().next().unwrap(),
+        _ => return Err("ord() argument 1 must be a single character".to_string()),
+    };
+    return Ok(Value::Int(c as i32));
+}
+
+fn sk_chr(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("chr", args.len(), 1)?;
+    }
+    let c = match args[0] {
+        Value::Int(num) => u32::try_from(num).ok().and_then(char::from_u32),
+        Value::Byte(byte) => Some(byte as char),
+        _ => None,
+    };
+    let Some(c) = c else {
+        return Err("chr() argument 1 must be a valid character code".to_string());
+    };
+    return Ok(Value::Str(Rc::new(c.to_string())));
+}
+
+// If every character in a (non-empty) string matches
+fn is_class(
+    vm: &mut Vm, name: &str, args: &[Value], class: fn(char) -> bool
+) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args(name, args.len(), 1)?;
+    }
+    let Value::Str(s) = &args[0] else {
+        return Err(format!("{}() argument 1 must be a string", name));
+    };
+    return Ok(Value::Bool(!s.is_empty() && s.chars().all(class)));
+}
+
+fn sk_is_digit(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return is_class(vm, "is_digit", &args, |c| c.is_ascii_digit());
+}
+
+fn sk_is_alpha(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return is_class(vm, "is_alpha", &args, char::is_alphabetic);
+}
+
+fn sk_is_space(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return is_class(vm, "is_space", &args, char::is_whitespace);
+}
+
+fn sk_is_upper(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return is_class(vm, "is_upper", &args, char::is_uppercase);
+}
+
+fn sk_is_lower(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return is_class(vm, "is_lower", &args, char::is_lowercase);
+}
+
+// Encodings
+// The encoding and if errors should be replaced instead of failing
+fn get_encoding(name: &str, args: &[Value]) -> Result<(String, bool), String> {
+    let Value::Str(enc) = &args[1] else {
+        return Err(format!("{}() argument 2 must be a string", name));
+    };
+    let enc = enc.to_lowercase().replace('_', "-");
+    let enc = match enc.as_str() {
+        "utf8" => "utf-8",
+        "utf16" | "utf-16le" => "utf-16",
+        "latin1" | "iso-8859-1" => "latin-1",
+        enc => enc,
+    }.to_string();
+    if !["utf-8", "utf-16", "utf-16be", "latin-1"].contains(&enc.as_str()) {
+        return Err(format!("unknown encoding: {}", enc));
+    }
+    let replace = match args.get(2) {
+        None => false,
+        Some(Value::Str(policy)) if **policy == "strict" => false,
+        Some(Value::Str(policy)) if **policy == "replace" => true,
+        _ => return Err(format!("{}() argument 3 must be \"strict\" or \"replace\"", name)),
+    };
+    return Ok((enc, replace));
+}
+
+// Turns a string into bytes, UTF-16 is little endian without a BOM
+fn sk_encode(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 && args.len() != 3 {
+        // Invalid args
+        vm.bad_args("encode", args.len(), 2)?;
+    }
+    let Value::Str(str) = &args[0] else {
+        return Err("encode() argument 1 must be a string".to_string());
+    };
+    let (enc, replace) = get_encoding("encode", &args)?;
+    let bytes: Vec<u8> = match enc.as_str() {
+        "utf-8" => str.as_bytes().to_vec(),
+        "utf-16" => str.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        "utf-16be" => str.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+        _ => str.chars().map(|c| match u8::try_from(c) {
+            Ok(b) => Ok(b),
+            Err(_) if replace => Ok(b'?'),
+            Err(_) => Err(format!("cannot encode '{}' as latin-1", c)),
+        }).collect::<Result<_, String>>()?,
+    };
+    return Ok(Value::FastList(Rc::new(bytes.into_iter().map(Value::Byte).collect())));
+}
+
+// Turns bytes into a string, invalid bytes are errors or U+FFFD
+fn sk_decode(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 && args.len() != 3 {
+        // Invalid args
+        vm.bad_args("decode", args.len(), 2)?;
+    }
+    let bytes = to_bytes("decode", &args[0])?;
+    let (enc, replace) = get_encoding("decode", &args)?;
+    let invalid = || format!("invalid {} data", enc);
+    let str = match enc.as_str() {
+        "utf-8" if replace => String::from_utf8_lossy(&bytes).to_string(),
+        "utf-8" => String::from_utf8(bytes).map_err(|_| invalid())?,
+        "utf-16" | "utf-16be" => {
+            if bytes.len() % 2 != 0 && !replace {
+                return Err(invalid());
+            }
+            let units = bytes.chunks(2).map(|c| match (c, enc == "utf-16") {
+                ([a, b], true) => u16::from_le_bytes([*a, *b]),
+                ([a, b], false) => u16::from_be_bytes([*a, *b]),
+                // Odd byte out
+                _ => 0xFFFD,
+            });
+            char::decode_utf16(units).map(|c| match c {
+                Ok(c) => Ok(c),
+                Err(_) if replace => Ok(char::REPLACEMENT_CHARACTER),
+                Err(_) => Err(invalid()),
+            }).collect::<Result<String, String>>()?
+        },
+        _ => bytes.into_iter().map(char::from).collect(),
+    };
+    return Ok(Value::Str(Rc::new(str)));
+}
+
+fn sk_serialize(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("serialize", args.len(), 1)?;
+    }
+    let mut bytes = b"SK\x01".to_vec();
+    args[0].serialize(&mut bytes)?;
+    return Ok(Value::FastList(Rc::new(bytes.into_iter().map(Value::Byte).collect())));
+}
+
+fn sk_deserialize(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("deserialize", args.len(), 1)?;
+    }
+    let bytes = to_bytes("deserialize", &args[0])?;
+    let Some(data) = bytes.strip_prefix(b"SK\x01") else {
+        return Err("not serialized data (or from a different version)".to_string());
+    };
+    let mut at = 0;
+    let ret = Value::deserialize(data, &mut at, 0)?;
+    if at != data.len() {
+        return Err("invalid serialized data".to_string());
+    }
+    return Ok(ret);
+}
+
+// Compression
+#[cfg(feature = "compression")]
+fn compress(
+    vm: &mut Vm, name: &str, args: &[Value], gzip: bool, decompress: bool
+) -> Result<Value, String> {
+    use flate2::Compression;
+    use flate2::read::{DeflateDecoder, DeflateEncoder, GzDecoder, GzEncoder};
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args(name, args.len(), 1)?;
+    }
+    let bytes = match &args[0] {
+        Value::Str(s) if !decompress => s.as_bytes().to_vec(),
+        val => to_bytes(name, val)?,
+    };
+    let bytes = bytes.as_slice();
+    let mut reader: Box<dyn Read> = match (gzip, decompress) {
+        (true, false) => Box::new(GzEncoder::new(bytes, Compression::default())),
+        (true, true) => Box::new(GzDecoder::new(bytes)),
+        (false, false) => Box::new(DeflateEncoder::new(bytes, Compression::default())),
+        (false, true) => Box::new(DeflateDecoder::new(bytes)),
+    };
+    let mut out = vec![];
+    reader.read_to_end(&mut out).map_err(|e| e.to_string())?;
+    return Ok(Value::FastList(Rc::new(out.into_iter().map(Value::Byte).collect())));
+}
+
+#[cfg(feature = "compression")]
+fn sk_gzip_compress(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return compress(vm, "gzip_compress", &args, true, false);
+}
+
+#[cfg(feature = "compression")]
+fn sk_gzip_decompress(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return compress(vm, "gzip_decompress", &args, true, true);
+}
+
+#[cfg(feature = "compression")]
+fn sk_deflate_compress(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return compress(vm, "deflate_compress", &args, false, false);
+}
+
+#[cfg(feature = "compression")]
+fn sk_deflate_decompress(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return compress(vm, "deflate_decompress", &args, false, true);
+}
+
+// Set
+fn sk_set(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("set", args.len(), 1)?;
+    }
+    if let Value::Set(_) = args[0] {
+        return Ok(args[0].clone());
+    }
+    let Some(vals) = args[0].values() else {
+        return Err("set() argument 1 must be a list".to_string());
+    };
+    return Ok(Value::Set(Rc::new(vals.into_iter().map(SetKey).collect())));
+}
+
+// Tuple
+fn sk_tuple(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("tuple", args.len(), 1)?;
+    }
+    let vals = if let Some(vals) = args[0].set_values() {
+        vals
+    } else if let Some(vals) = args[0].values() {
+        vals
+    } else {
+        return Err("tuple() argument 1 must be a list".to_string());
+    };
+    return Ok(Value::Tuple(Rc::new(vals)));
+}
+
+// Weak handles
+// gc runs by itself once there are this many drop callbacks, then at double what's left
+const GC_THRESHOLD: usize = 1000;
+
+fn sk_weak(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 && args.len() != 2 {
+        // Invalid args
+        vm.bad_args("weak", args.len(), 1)?;
+    }
+    let weak = WeakRef::new(&args[0]);
+    if let Some(functi) = args.get(1) {
+        // Called once the value is gone
+        let (Value::Functi(_) | Value::BoundFuncti(..)) = functi else {
+            return Err("weak() argument 2 must be a functi".to_string());
+        };
+        vm.drops.push((weak.clone(), functi.clone()));
+        if vm.drops.len() >= vm.gc_threshold {
+            vm.collect_cycles();
+            vm.gc_threshold = GC_THRESHOLD.max(vm.drops.len() * 2);
+        }
+    }
+    return Ok(Value::Weak(Rc::new(weak)));
+}
+
+fn sk_upgrade(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("upgrade", args.len(), 1)?;
+    }
+    let Value::Weak(ref weak) = args[0] else {
+        return Err("upgrade() argument 1 must be a weak handle".to_string());
+    };
+    return Ok(weak.upgrade().unwrap_or(Value::None));
+}
+
+// Results
+fn sk_ok(vm: &mut Vm, mut args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("ok", args.len(), 1)?;
+    }
+    return Ok(Value::Result(true, Rc::new(args.pop().unwrap())));
+}
+
+fn sk_err(vm: &mut Vm, mut args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("err", args.len(), 1)?;
+    }
+    return Ok(Value::Result(false, Rc::new(args.pop().unwrap())));
+}
+
+fn sk_is_err(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("is_err", args.len(), 1)?;
+    }
+    return Ok(Value::Bool(matches!(args[0], Value::Result(false, _))));
+}
+
+fn sk_unwrap(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("unwrap", args.len(), 1)?;
+    }
+    match args[0] {
+        Value::Result(true, ref val) => Ok((**val).clone()),
+        Value::Result(false, ref val) => Err(format!("unwrap() called on an err: {}", val.to_string()?)),
+        _ => Err("unwrap() argument 1 must be a result".to_string()),
+    }
+}
+
+fn sk_try_call(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 && args.len() != 2 {
+        // Invalid args
+        vm.bad_args("try_call", args.len(), 2)?;
+    }
+    let (name, mut call_args) = match &args[0] {
+        Value::Functi(name) => ((**name).clone(), vec![]),
+        Value::BoundFuncti(name, bound) => ((**name).clone(), (**bound).clone()),
+        _ => return Err("try_call() argument 1 must be a functi".to_string()),
+    };
+    if let Some(list) = args.get(1) {
+        let Some(vals) = list.values() else {
+            return Err("try_call() argument 2 must be a list".to_string());
+        };
+        call_args.extend(vals);
+    }
+    return Ok(match vm.call_nested(name, call_args) {
+        Ok(ret) => Value::Result(true, Rc::new(ret)),
+        Err(err) => Value::Result(false, Rc::new(Value::Str(Rc::new(err)))),
+    });
+}
+
+// Garbage collection
+// Lists are copy on write, so they can never contain themselves and
+// reference counting frees everything. This just runs drop callbacks early.
+fn sk_gc(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("gc", args.len(), 0)?;
+    }
+    let tracked = vm.drops.len();
+    let dead = vm.drops.iter().filter(|(weak, _)| !weak.is_alive()).count();
+    let cycles = vm.collect_cycles();
+    // The callbacks run after gc returns
+    vm.check_drops |= dead != 0;
+    let key = |k: &str| Value::Str(Rc::new(k.to_string()));
+    return Ok(Value::List(Rc::new(vec![
+        (key("tracked"), Value::Int(tracked as i32)),
+        (key("freed"), Value::Int((dead + cycles) as i32)),
+        (key("cycles"), Value::Int(cycles as i32)),
+        (key("threshold"), Value::Int(vm.gc_threshold as i32)),
+    ])));
+}
+
+// Bind
+fn sk_bind(vm: &mut Vm, mut args: Vec<Value>) -> Result<Value, String> {
+    if args.is_empty() {
+        // Invalid args
+        vm.bad_args("bind", args.len(), 1)?;
+    }
+    let bound: Vec<Value> = args.drain(1..).collect();
+    return Ok(match &args[0] {
+        Value::Functi(name) => Value::BoundFuncti(name.clone(), Rc::new(bound)),
+        // Binding again adds to the end
+        Value::BoundFuncti(name, old) => {
+            let mut all = (**old).clone();
+            all.extend(bound);
+            Value::BoundFuncti(name.clone(), Rc::new(all))
+        },
+        _ => return Err("bind() argument 1 must be a functi".to_string()),
+    });
+}
+
+// Threads
+#[cfg(not(target_family = "wasm"))]
+fn sk_spawn(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 && args.len() != 2 {
+        // Invalid args
+        vm.bad_args("spawn", args.len(), 2)?;
+    }
+    let (name, mut call_args) = match &args[0] {
+        Value::Functi(name) => ((**name).clone(), vec![]),
+        Value::BoundFuncti(name, bound) => ((**name).clone(), (**bound).clone()),
+        _ => return Err("spawn() argument 1 must be a functi".to_string()),
+    };
+    if let Some(list) = args.get(1) {
+        let Some(vals) = list.values() else {
+            return Err("spawn() argument 2 must be a list".to_string());
+        };
+        call_args.extend(vals);
+    }
+    // Everything is copied, so the threads don't share anything
+    let call_args: Vec<SendValue> = call_args.iter()
+        .map(|arg| arg.to_send()).collect::<Result<_, String>>()?;
+    // Globals that can't be copied (like files) are none in the thread
+    let globals: Vec<SendValue> = vm.globals.iter()
+        .map(|g| g.to_send().unwrap_or(SendValue::None)).collect();
+    let program = vm.program.to_send()?;
+    let mut thread_args = vm.args.clone();
+    thread_args.is_repl = false;
+    thread_args.print_result = false;
+    let handle = std::thread::spawn(move || {
+        let mut vm = Vm::new(thread_args, program.to_program());
+        vm.globals = globals.into_iter().map(|g| g.to_value()).collect();
+        let call_args = call_args.into_iter().map(|arg| arg.to_value()).collect();
+        vm.run_functi(name, call_args)?.to_send()
+    });
+    return Ok(Value::Thread(Rc::new(RefCell::new(ThreadInfo {
+        handle: Some(handle)
+    }))));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_join(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("join", args.len(), 1)?;
+    }
+    let Value::Thread(ref info) = args[0] else {
+        return Err("join() argument 1 must be a thread".to_string());
+    };
+    let Some(handle) = info.borrow_mut().handle.take() else {
+        return Err("thread was already joined".to_string());
+    };
+    return match handle.join() {
+        Ok(ret) => Ok(ret.map_err(|e| format!("thread failed: {}", e))?.to_value()),
+        Err(_) => Err("thread panicked".to_string()),
+    };
+}
+
+// Time
+// Milliseconds since the first VM started
+#[cfg(not(target_family = "wasm"))]
+fn clock_ms() -> f64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    return START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0;
+}
+
+#[cfg(target_family = "wasm")]
+fn clock_ms() -> f64 {
+    return crate::common::now();
+}
+
+// Finds a file to import, relative to the running file like import
+#[cfg(not(target_family = "wasm"))]
+fn module_path(vm: &Vm, file: &str) -> Result<(PathBuf, String), String> {
+    let mut path = vm.args.path.clone();
+    path.pop();
+    path.push(file);
+    path.set_extension("sk");
+    if !path.exists() {
+        path.set_extension("sack");
+    }
+    let Ok(src) = std::fs::read_to_string(&path) else {
+        return Err(format!("cannot import {}", file));
+    };
+    return Ok((path, src));
+}
+
+// Gets a file bundled with the program, or next to it if it isn't bundled
+#[cfg(not(target_family = "wasm"))]
+fn sk_resource(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("resource", args.len(), 1)?;
+    }
+    let Value::Str(name) = &args[0] else {
+        return Err("resource() argument 1 must be a string".to_string());
+    };
+    let data = if let Some(data) = vm.resources.get(name.as_str()) {
+        data.clone()
+    } else {
+        let Ok(data) = std::fs::read(vm.args.path.with_file_name(name.as_str())) else {
+            return Err(format!("no resource named {}", name));
+        };
+        data
+    };
+    return Ok(Value::Str(Rc::new(String::from_utf8_lossy(&data).to_string())));
+}
+
+// Imports at runtime, returns a list of the module's globals and functis
+#[cfg(not(target_family = "wasm"))]
+fn sk_import_module(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("import_module", args.len(), 1)?;
+    }
+    let Value::Str(file) = &args[0] else {
+        return Err("import_module() argument 1 must be a string".to_string());
+    };
+    let (path, src) = module_path(vm, file)?;
+    let name = path.to_string_lossy().to_string();
+    let key = std::fs::canonicalize(&path).map(|p| p.to_string_lossy().to_string())
+        .unwrap_or(name.clone());
+    if let Some(module) = vm.modules.get(&key) {
+        return Ok(module.clone());
+    }
+    if vm.ast.as_ref().is_some_and(|ast| ast.imported.contains(&key)) {
+        // It was already imported with import, so it already ran
+        let module = imported_module(vm, &key);
+        vm.modules.insert(key, module.clone());
+        return Ok(module);
+    }
+    // Compile and run it
+    let globals = vm.program.global_names.len();
+    let functis = vm.program.functis.len();
+    let exports = vm.ast.as_ref().map_or(0, |ast| ast.exports.len());
+    let old_path = std::mem::replace(&mut vm.args.path, path);
+    let start = vm.compile_code(&src, &name, None);
+    vm.args.path = old_path;
+    if let Some(start) = start? {
+        vm.run_code(start)?;
+    }
+    // Everything it declared, or only what it exported
+    let exports = vm.ast.as_ref().map(|ast| ast.exports[exports..].to_vec()).unwrap_or_default();
+    let hidden = |name: &String| name.is_empty() || name.contains('$') || name.starts_with("__")
+        || !(exports.is_empty() || exports.contains(name));
+    let mut module: Vec<(Value, Value)> = vec![];
+    for (at, name) in vm.program.global_names.iter().enumerate().skip(globals) {
+        if !hidden(name) {
+            let val = vm.globals.get(at).cloned().unwrap_or(Value::None);
+            module.push((Value::Str(Rc::new(name.clone())), val));
+        }
+    }
+    for (name, ..) in &vm.program.functis[functis..] {
+        let key = Value::Str(Rc::new(name.clone()));
+        if !hidden(name) && !module.iter().any(|(k, _)| *k == key) {
+            module.push((key, Value::Functi(Rc::new(name.clone()))));
+        }
+    }
+    let module = Value::List(Rc::new(module));
+    vm.modules.insert(key, module.clone());
+    return Ok(module);
+}
+
+// The globals and functis a file that was imported with import made (the ones it exported)
+#[cfg(not(target_family = "wasm"))]
+fn imported_module(vm: &Vm, key: &str) -> Value {
+    let Some(ast) = vm.ast.as_ref() else {
+        return Value::List(Rc::new(vec![]));
+    };
+    let is_file = |module: &String| std::fs::canonicalize(module)
+        .map(|p| p.to_string_lossy() == key).unwrap_or(module == key);
+    let hidden = |name: &String| name.contains('$') || name.starts_with("__");
+    let mut module: Vec<(Value, Value)> = vec![];
+    // Hidden globals were already taken out of cur_vars
+    for var in ast.cur_vars.iter().filter(|v| is_file(&v.module) && !hidden(&v.name)) {
+        let Some(at) = ast.get_var_offset(var.module.clone() + "::" + &var.real_name, None) else {
+            continue;
+        };
+        let val = vm.globals.get(at as usize).cloned().unwrap_or(Value::None);
+        module.push((Value::Str(Rc::new(var.name.clone())), val));
+    }
+    for functi in ast.functis.iter().filter(|f| is_file(&f.module) && !f.private && !hidden(&f.name)) {
+        let key = Value::Str(Rc::new(functi.name.clone()));
+        if !module.iter().any(|(k, _)| *k == key) {
+            module.push((key, Value::Functi(Rc::new(functi.name.clone()))));
+        }
+    }
+    return Value::List(Rc::new(module));
+}
+
+// Only the functis at the top level of lexed code
+#[cfg(not(target_family = "wasm"))]
+fn top_level_functis(tokens: Vec<Token>) -> Vec<Token> {
+    let mut ret = vec![];
+    let mut depth = 0;
+    let mut keep = false;
+    for token in tokens {
+        if depth == 0 && matches!(token.token, TokenType::Func(_) | TokenType::Async | TokenType::Eof) {
+            keep = true;
+        }
+        match token.token {
+            TokenType::Lbrace => depth += 1,
+            TokenType::Rbrace => depth -= 1,
+            _ => {},
+        }
+        let end = depth == 0 && token.token == TokenType::Rbrace;
+        if keep || matches!(token.token, TokenType::Comment(_)) {
+            ret.push(token);
+        }
+        if end {
+            keep = false;
+        }
+    }
+    return ret;
+}
+
+// Recompiles the functis in an imported file, calls to the old ones go to the new ones
+// The rest of the file doesn't run again
+#[cfg(not(target_family = "wasm"))]
+fn sk_reload(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("reload", args.len(), 1)?;
+    }
+    let Value::Str(file) = &args[0] else {
+        return Err("reload() argument 1 must be a string".to_string());
+    };
+    let (path, src) = module_path(vm, file)?;
+    let name = path.to_string_lossy().to_string();
+    // The functis that came from the file
+    let mut old = vec![];
+    for at in 0..vm.program.functis.len() {
+        let pos = vm.program.functis[at].1;
+        if vm.program.get_info(pos as u32).1 == name {
+            old.push((vm.program.functis[at].0.clone(), vm.program.functis[at].2));
+        }
+    }
+    if old.is_empty() {
+        return Err(format!("{} wasn't imported, or has no functis", file));
+    }
+    let tokens = top_level_functis(vm.lex_code(&src, &name)?);
+    // They're being replaced, so they can be defined again
+    let ast = vm.ast.clone();
+    if let Some(ast) = &mut vm.ast {
+        ast.functis.retain(|f| !old.contains(&(f.name.clone(), f.arg_num)));
+    }
+    let functis = vm.program.functis.len();
+    let old_path = std::mem::replace(&mut vm.args.path, path);
+    let ret = vm.compile_tokens(tokens, &name, None);
+    vm.args.path = old_path;
+    if let Err(e) = ret {
+        vm.ast = ast;
+        return Err(e);
+    }
+    let mut replaced = 0;
+    for functi in vm.program.functis.split_off(functis) {
+        let Some(entry) = vm.program.functis.iter_mut().find(|f| {
+            f.0 == functi.0 && f.2 == functi.2 && old.contains(&(f.0.clone(), f.2))
+        }) else {
+            vm.program.functis.push(functi);
+            continue;
+        };
+        // Static calls still go to the old code, so it jumps to the new code
+        vm.program.ops[entry.1] = ((Opcode::JMP as u32) << 24) + (functi.1 - entry.1) as u32;
+        (entry.1, entry.3) = (functi.1, functi.3);
+        replaced += 1;
+    }
+    #[cfg(feature = "jit")]
+    vm.jit.clear();
+    return Ok(Value::Int(replaced));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_sleep(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("sleep", args.len(), 1)?;
+    }
+    let secs = args[0].to_float();
+    if secs > 0.0 {
+        // Infinity (or anything too long to be a Duration) errors instead of panicking
+        let Ok(time) = Duration::try_from_secs_f32(secs) else {
+            return Err(format!("sleep() can't sleep for {} seconds", format_float(secs)));
+        };
+        std::thread::sleep(time);
+    }
+    return Ok(Value::None);
+}
+
+fn sk_now_ms(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("now_ms", args.len(), 0)?;
+    }
+    return Ok(Value::Int(clock_ms() as i32));
+}
+
+fn sk_stopwatch(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("stopwatch", args.len(), 0)?;
+    }
+    // The start time in seconds
+    return Ok(Value::Float((clock_ms() / 1000.0) as f32));
+}
+
+fn sk_elapsed(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("elapsed", args.len(), 1)?;
+    }
+    let Value::Float(start) = args[0] else {
+        return Err("elapsed() argument 1 must be a stopwatch".to_string());
+    };
+    return Ok(Value::Float((clock_ms() / 1000.0 - start as f64) as f32));
+}
+
+// Logging
+// Lines look like "2024-03-05 10:20:30 INFO file.sk:12: message"
+fn log(vm: &mut Vm, name: &str, args: &[Value], level: usize) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args(name, args.len(), 1)?;
+    }
+    if level < vm.args.log_level {
+        return Ok(Value::None);
+    }
+    let (line, file, _) = vm.program.get_info(vm.at as u32);
+    #[cfg(not(target_family = "wasm"))]
+    let time = Utc::now().format("%Y-%m-%d %H:%M:%S ").to_string();
+    #[cfg(target_family = "wasm")]
+    let time = "";
+    let msg = format!(
+        "{}{} {}:{}: {}\n", time, LOG_LEVELS[level].to_uppercase(), file, line,
+        args[0].to_string()?
+    );
+    if let Some(log_file) = &mut vm.log_file {
+        log_file.write_all(msg.as_bytes()).map_err(|e| e.to_string())?;
+    } else {
+        eprint!("{}", msg);
+    }
+    return Ok(Value::None);
+}
+
+fn sk_log_debug(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return log(vm, "log_debug", &args, 0);
+}
+
+fn sk_log_info(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return log(vm, "log_info", &args, 1);
+}
+
+fn sk_log_warn(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return log(vm, "log_warn", &args, 2);
+}
+
+fn sk_log_error(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return log(vm, "log_error", &args, 3);
+}
+
+// Logs below the level are ignored
+fn sk_set_log_level(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("set_log_level", args.len(), 1)?;
+    }
+    let level = match &args[0] {
+        Value::Str(name) => LOG_LEVELS.iter().position(|l| **l == **name),
+        _ => None,
+    };
+    let Some(level) = level else {
+        return Err(format!(
+            "set_log_level() argument 1 must be one of: {}", LOG_LEVELS.join(", ")
+        ));
+    };
+    vm.args.log_level = level;
+    return Ok(Value::None);
+}
+
+// Appends logs to a file, or stderr with none
+#[cfg(not(target_family = "wasm"))]
+fn sk_set_log_file(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("set_log_file", args.len(), 1)?;
+    }
+    vm.log_file = match &args[0] {
+        Value::None => None,
+        Value::Str(path) => Some(
+            OpenOptions::new().append(true).create(true).open(path.as_str())
+                .map_err(|e| e.to_string())?
+        ),
+        _ => return Err("set_log_file() argument 1 must be a string or none".to_string()),
+    };
+    return Ok(Value::None);
+}
+
+// Dates are [time: seconds since 1970, offset: seconds from UTC]
+fn from_date(date: DateTime<FixedOffset>) -> Result<Value, String> {
+    let Ok(time) = i32::try_from(date.timestamp()) else {
+        return Err("date is out of range".to_string());
+    };
+    let offset = date.offset().local_minus_utc();
+    let key = |k: &str| Value::Str(Rc::new(k.to_string()));
+    return Ok(Value::List(Rc::new(vec![
+        (key("time"), Value::Int(time)), (key("offset"), Value::Int(offset)),
+    ])));
+}
+
+// Numbers are UTC times
+fn to_date(name: &str, val: &Value) -> Result<DateTime<FixedOffset>, String> {
+    let err = || format!("{}() argument 1 must be a date or time", name);
+    let (time, offset) = match val {
+        Value::Int(time) => (*time, 0),
+        Value::List(_) => {
+            let (Some(Value::Int(time)), Some(Value::Int(offset))) = (
+                val.index(&Value::Str(Rc::new("time".to_string()))),
+                val.index(&Value::Str(Rc::new("offset".to_string())))
+            ) else {
+                return Err(err());
+            };
+            (time, offset)
+        },
+        _ => return Err(err()),
+    };
+    let offset = FixedOffset::east_opt(offset).ok_or_else(err)?;
+    let date = DateTime::from_timestamp(time as i64, 0).ok_or_else(err)?;
+    return Ok(date.with_timezone(&offset));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_utc_now(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("utc_now", args.len(), 0)?;
+    }
+    return from_date(Utc::now().fixed_offset());
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_local_now(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("local_now", args.len(), 0)?;
+    }
+    return from_date(Local::now().fixed_offset());
+}
+
+fn sk_strftime(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("strftime", args.len(), 2)?;
+    }
+    let date = to_date("strftime", &args[0])?;
+    let Value::Str(fmt) = &args[1] else {
+        return Err("strftime() argument 2 must be a string".to_string());
+    };
+    let Ok(items) = StrftimeItems::new(fmt).parse() else {
+        return Err(format!("invalid time format: {}", fmt));
+    };
+    return Ok(Value::Str(Rc::new(date.format_with_items(items.iter()).to_string())));
+}
+
+// Without an offset the time is UTC, and without a time it's midnight
+fn sk_strptime(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("strptime", args.len(), 2)?;
+    }
+    let (Value::Str(str), Value::Str(fmt)) = (&args[0], &args[1]) else {
+        return Err("strptime() arguments must be strings".to_string());
+    };
+    let date = DateTime::parse_from_str(str, fmt)
+        .or_else(|_| NaiveDateTime::parse_from_str(str, fmt).map(|d| d.and_utc().fixed_offset()))
+        .or_else(|_| NaiveDate::parse_from_str(str, fmt)
+            .map(|d| d.and_time(NaiveTime::MIN).and_utc().fixed_offset()))
+        .map_err(|e| format!("cannot parse time '{}': {}", str, e))?;
+    return from_date(date);
+}
+
+// Async
+// How much IO a task does at once, and how long the event loop waits for IO (at first, and at most)
+#[cfg(not(target_family = "wasm"))]
+const IO_CHUNK: usize = 64 * 1024;
+const IO_POLL: Duration = Duration::from_millis(1);
+#[cfg(not(target_family = "wasm"))]
+const IO_POLL_MAX: Duration = Duration::from_millis(50);
+
+// Does a bit of a task's IO, gives back the result once it's done
+// WouldBlock means it couldn't do anything yet
+#[cfg(not(target_family = "wasm"))]
+fn poll_io(op: &mut IoOp) -> io::Result<Option<Value>> {
+    let mut buf = vec![0u8; IO_CHUNK];
+    return match op {
+        IoOp::Read(file, data) => file.read(&mut buf).map(|got| {
+            data.extend_from_slice(&buf[..got]);
+            (got == 0).then(|| Value::Str(Rc::new(
+                String::from_utf8_lossy(&std::mem::take(data)).into_owned()
+            )))
+        }),
+        IoOp::Write(file, data) => file.write(&data[..data.len().min(IO_CHUNK)]).map(|wrote| {
+            data.drain(..wrote);
+            data.is_empty().then_some(Value::None)
+        }),
+        IoOp::Accept(socket) => {
+            let SocketInfo::Listener(listener) = &**socket else {
+                return Err(io::Error::other(IMPOSSIBLE_STATE));
+            };
+            listener.accept().and_then(|(stream, _)| {
+                stream.set_nonblocking(true)?;
+                Ok(Some(Value::Socket(Rc::new(SocketInfo::Stream(stream)))))
+            })
+        },
+        // Whatever has come in, none once the other end is closed
+        IoOp::Recv(socket) => {
+            let SocketInfo::Stream(stream) = &**socket else {
+                return Err(io::Error::other(IMPOSSIBLE_STATE));
+            };
+            // Reading and writing only need a shared reference to it
+            let mut stream = stream;
+            stream.read(&mut buf).map(|got| Some(if got == 0 {
+                Value::None
+            } else {
+                Value::Str(Rc::new(String::from_utf8_lossy(&buf[..got]).into_owned()))
+            }))
+        },
+        IoOp::Send(socket, data) => {
+            let SocketInfo::Stream(stream) = &**socket else {
+                return Err(io::Error::other(IMPOSSIBLE_STATE));
+            };
+            // Reading and writing only need a shared reference to it
+            let mut stream = stream;
+            stream.write(data).map(|wrote| {
+                data.drain(..wrote);
+                data.is_empty().then_some(Value::None)
+            })
+        },
+    };
+}
+
+// Calls an async functi, what __burlap_task(f$async, args) is made from
+#[cfg(not(target_family = "wasm"))]
+fn sk_task(vm: &mut Vm, mut args: Vec<Value>) -> Result<Value, String> {
+    if args.is_empty() {
+        // Invalid args
+        vm.bad_args("__burlap_task", args.len(), 1)?;
+    }
+    let call_args = args.split_off(1);
+    let (Value::Functi(_) | Value::BoundFuncti(..)) = args[0] else {
+        return Err("__burlap_task() argument 1 must be a functi".to_string());
+    };
+    return Ok(vm.new_task(TaskState::Start(args.remove(0), call_args)));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_sleep_async(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("sleep_async", args.len(), 1)?;
+    }
+    let ms = args[0].to_int().max(0) as u64;
+    return Ok(vm.new_task(TaskState::Sleep(Instant::now() + Duration::from_millis(ms))));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_await(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("__burlap_await", args.len(), 1)?;
+    }
+    let task = match &args[0] {
+        Value::Task(task) => task.clone(),
+        Value::Thread(_) => return sk_join(vm, args),
+        // Awaiting something that isn't a task just gives it back
+        _ => return Ok(args[0].clone()),
+    };
+    if let TaskState::Done(ret) = &task.borrow().state {
+        return ret.clone().map_err(|e| format!("async functi failed: {}", e));
+    }
+    // An async functi pauses, so the event loop can run others until the task is done
+    if vm.running.last().is_some_and(|(_, depth)| *depth == vm.nested) {
+        vm.awaiting = Some(task);
+        return Ok(Value::None);
+    }
+    // Anything else (like a callback in an async functi) runs the event loop until then
+    return vm.run_until(&task);
+}
+
+// Non-blocking IO
+#[cfg(not(target_family = "wasm"))]
+fn sk_read_async(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("read_async", args.len(), 1)?;
+    }
+    let Value::Str(path) = &args[0] else {
+        return Err("read_async() argument 1 must be a string".to_string());
+    };
+    let file = std::fs::File::open(&**path)
+        .map_err(|e| format!("failed to open '{}': {}", path, e))?;
+    return Ok(vm.new_task(TaskState::Io(IoOp::Read(file, vec![]))));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_write_async(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("write_async", args.len(), 2)?;
+    }
+    let (Value::Str(path), Value::Str(data)) = (&args[0], &args[1]) else {
+        return Err("write_async() arguments must be strings".to_string());
+    };
+    let file = std::fs::File::create(&**path)
+        .map_err(|e| format!("failed to open '{}': {}", path, e))?;
+    return Ok(vm.new_task(TaskState::Io(IoOp::Write(file, data.as_bytes().to_vec()))));
+}
+
+// Sockets
+#[cfg(not(target_family = "wasm"))]
+fn sk_listen(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("listen", args.len(), 1)?;
+    }
+    let Value::Str(addr) = &args[0] else {
+        return Err("listen() argument 1 must be a string".to_string());
+    };
+    let listener = std::net::TcpListener::bind(&**addr)
+        .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+        .map_err(|e| format!("failed to listen on '{}': {}", addr, e))?;
+    return Ok(Value::Socket(Rc::new(SocketInfo::Listener(listener))));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_connect(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("connect", args.len(), 1)?;
+    }
+    let Value::Str(addr) = &args[0] else {
+        return Err("connect() argument 1 must be a string".to_string());
+    };
+    // Connecting blocks, sending and receiving don't
+    let stream = std::net::TcpStream::connect(&**addr)
+        .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
+        .map_err(|e| format!("failed to connect to '{}': {}", addr, e))?;
+    return Ok(Value::Socket(Rc::new(SocketInfo::Stream(stream))));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_socket_addr(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("socket_addr", args.len(), 1)?;
+    }
+    let Value::Socket(socket) = &args[0] else {
+        return Err("socket_addr() argument 1 must be a socket".to_string());
+    };
+    let addr = match &**socket {
+        SocketInfo::Listener(listener) => listener.local_addr(),
+        SocketInfo::Stream(stream) => stream.local_addr(),
+    };
+    return Ok(Value::Str(Rc::new(addr.map_err(|e| e.to_string())?.to_string())));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_accept_async(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("accept_async", args.len(), 1)?;
+    }
+    let Value::Socket(socket) = &args[0] else {
+        return Err("accept_async() argument 1 must be a socket".to_string());
+    };
+    let SocketInfo::Listener(_) = **socket else {
+        return Err("accept_async() argument 1 must be a socket from listen".to_string());
+    };
+    return Ok(vm.new_task(TaskState::Io(IoOp::Accept(socket.clone()))));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_recv_async(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("recv_async", args.len(), 1)?;
+    }
+    let Value::Socket(socket) = &args[0] else {
+        return Err("recv_async() argument 1 must be a socket".to_string());
+    };
+    let SocketInfo::Stream(_) = **socket else {
+        return Err("recv_async() argument 1 must be a connected socket".to_string());
+    };
+    return Ok(vm.new_task(TaskState::Io(IoOp::Recv(socket.clone()))));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_send_async(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("send_async", args.len(), 2)?;
+    }
+    let (Value::Socket(socket), Value::Str(data)) = (&args[0], &args[1]) else {
+        return Err("send_async() arguments must be a socket and a string".to_string());
+    };
+    let SocketInfo::Stream(_) = **socket else {
+        return Err("send_async() argument 1 must be a connected socket".to_string());
+    };
+    return Ok(vm.new_task(TaskState::Io(IoOp::Send(socket.clone(), data.as_bytes().to_vec()))));
+}
+
+// Channels
+#[cfg(not(target_family = "wasm"))]
+fn sk_channel(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("channel", args.len(), 0)?;
+    }
+    let (sender, receiver) = std::sync::mpsc::channel();
+    return Ok(Value::Channel(Rc::new(ChannelInfo {
+        sender, receiver: Arc::new(Mutex::new(receiver))
+    })));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_send(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("send", args.len(), 2)?;
+    }
+    let Value::Channel(ref ch) = args[0] else {
+        return Err("send() argument 1 must be a channel".to_string());
+    };
+    // The channel holds a receiver, so this can't fail
+    ch.sender.send(args[1].to_send()?).unwrap();
+    return Ok(Value::None);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_recv(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("recv", args.len(), 1)?;
+    }
+    let Value::Channel(ref ch) = args[0] else {
+        return Err("recv() argument 1 must be a channel".to_string());
+    };
+    // Waits for a value, the channel holds a sender, so this can't fail
+    let val = ch.receiver.lock().unwrap().recv().unwrap();
+    return Ok(val.to_value());
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_try_recv(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("try_recv", args.len(), 1)?;
+    }
+    let Value::Channel(ref ch) = args[0] else {
+        return Err("try_recv() argument 1 must be a channel".to_string());
+    };
+    // None if nothing has been sent
+    let val = ch.receiver.lock().unwrap().try_recv();
+    return Ok(val.map(|v| v.to_value()).unwrap_or(Value::None));
+}
+
+// File IO
+fn sk_open(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("open", args.len(), 2)?;
+    }
+    let Value::Str(ref file) = args[0] else {
+        return Err("invalid file name".to_string());
+    };
+    let file = file.to_string();
+    let Value::Str(ref mode) = args[1] else {
+        return Err("invalid file mode".to_string());
+    };
+    // Open with mode
+    let (infofile, mode) = match mode.as_str() {
+        // Write
+        "w" | "wb" => {(
+            OpenOptions::new().write(true).create(true).truncate(false)
+                .open(file.clone()),
+            if **mode == "w" {2} else {-2}
+        )},
+        // Read
+        "r" | "rb" => {(
+            OpenOptions::new().read(true).open(file.clone()),
+            if **mode == "r" {1} else {-1}
+        )},
+        // Append
+        "a" => {(
+            OpenOptions::new().append(true).create(true).open(file.clone()),
+            0
+        )},
+        _ => return Err("invalid file mode".to_string()),
+    };
+    // Check
+    let Ok(fileinfo) = infofile else {
+        return Err("failed to open file".to_string());
+    };
+    // File info
+    let fi = Rc::new(RefCell::new(FileInfo{
+        name: file, mode,
+        closed: false,
+        file: Some(fileinfo)
+    }));
+    return Ok(Value::File(fi));
+}
+
+fn sk_close(vm: &mut Vm, mut args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("close", args.len(), 1)?;
+    }
+    let Value::File(ref mut info) = args[0] else {
+        return Err(format!("cannot close {}", args[0].get_type()));
+    };
+    info.borrow_mut().closed = true;
+    info.borrow_mut().file = None;
+    Ok(Value::None)
+}
+
+fn sk_flush(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("flush", args.len(), 1)?;
+    }
+    // Burlap doesn't buffer file io
+    Ok(Value::None)
+}
+
+fn sk_read(vm: &mut Vm, mut args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("read", args.len(), 1)?;
+    }
+    let Value::File(ref mut info) = args[0] else {
+        return Err(format!("cannot read from {}", args[0].get_type()));
+    };
+    if info.borrow().closed {
+        return Err("cannot read from closed file".to_string());
+    }
+    let mode = info.borrow().mode;
+    if mode.abs() != 1 {
+        return Err("can only read from 'r'/'rb'".to_string());
+    }
+    // Read the file
+    let mut ret: Vec<u8> = vec![];
+    if let Err(e) = info.borrow_mut().file.as_ref().unwrap()
+        .read_to_end(&mut ret) {
+        return Err(e.to_string());
+    }
+    if mode == 1 {
+        // Return a string
+        let Ok(string) = String::from_utf8(ret) else {
+            return Err("invalid string".to_string());
+        };
+        return Ok(Value::Str(Rc::new(string)));
+    }
+    return Ok(Value::FastList(Rc::new(ret.iter().map(|i| Value::Byte(*i)).collect())));
+}
+
+fn sk_seek(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("seek", args.len(), 2)?;
+    }
+    // Check and get file
+    let Value::File(ref mut info) = args[0].clone() else {
+        return Err(format!("cannot seek on {}", args[0].get_type()));
+    };
+    if info.borrow().closed {
+        return Err("cannot seek on closed file".to_string());
+    }
+    if info.borrow().mode.abs() != 2 {
+        return Err("can only seek on 'w'/'wb'".to_string());
+    }
+    // Get position
+    let pos = args[1].to_int();
+    if pos < 0 {
+        return Err("position cannot be negative".to_string());
+    }
+    let max = info.borrow_mut().file.as_ref().unwrap()
+        .seek(SeekFrom::End(0)).unwrap();
+    let pos: u64 = pos.try_into().unwrap();
+    if pos > max {
+        return Err("cannot seek to position larger than file".to_string());
+    }
+    // Seek
+    info.borrow_mut().file.as_ref().unwrap()
+        .seek(SeekFrom::Start(pos)).unwrap();
+    Ok(Value::None)
+}
+
+fn sk_write(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("write", args.len(), 2)?;
+    }
+    // Now check args
+    let Value::File(ref mut info) = args[0].clone() else {
+        return Err(format!("cannot write to {}", args[0].get_type()));
+    };
+    if info.borrow().closed {
+        return Err("cannot write to closed file".to_string());
+    }
+    let mode = info.borrow().mode;
+    if mode.abs() != 2 && mode != 0 {
+        return Err("can only write to 'w'/'wb'/'a'".to_string());
+    }
+    let Value::Str(ref str) = args[1] else {
+        return Err(format!("expected String got {}", args[1].get_type()));
+    };
+    if let Err(err) =
+        write!(info.borrow_mut().file.as_ref().unwrap(), "{}", str)
+    {
+        return Err(err.to_string());
+    }
+    return Ok(Value::None);
+}
+
+// Files and dirs
+#[cfg(not(target_family = "wasm"))]
+fn path_arg(name: &str, args: &[Value], at: usize) -> Result<String, String> {
+    let Value::Str(path) = &args[at] else {
+        return Err(format!("{}() argument {} must be a string", name, at + 1));
+    };
+    return Ok(path.to_string());
+}
+
+// Sorted names of the files in a dir
+#[cfg(not(target_family = "wasm"))]
+fn sk_list_dir(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("list_dir", args.len(), 1)?;
+    }
+    let path = path_arg("list_dir", &args, 0)?;
+    let mut names = std::fs::read_dir(path).map_err(|e| e.to_string())?
+        .map(|e| e.map(|e| e.file_name().to_string_lossy().to_string()))
+        .collect::<Result<Vec<String>, _>>().map_err(|e| e.to_string())?;
+    names.sort();
+    return Ok(Value::FastList(Rc::new(
+        names.into_iter().map(|n| Value::Str(Rc::new(n))).collect()
+    )));
+}
+
+// Also makes the parent dirs
+#[cfg(not(target_family = "wasm"))]
+fn sk_mkdir(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("mkdir", args.len(), 1)?;
+    }
+    let path = path_arg("mkdir", &args, 0)?;
+    std::fs::create_dir_all(path).map_err(|e| e.to_string())?;
+    return Ok(Value::None);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_remove_file(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("remove_file", args.len(), 1)?;
+    }
+    let path = path_arg("remove_file", &args, 0)?;
+    std::fs::remove_file(path).map_err(|e| e.to_string())?;
+    return Ok(Value::None);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_rename(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("rename", args.len(), 2)?;
+    }
+    let from = path_arg("rename", &args, 0)?;
+    let to = path_arg("rename", &args, 1)?;
+    std::fs::rename(from, to).map_err(|e| e.to_string())?;
+    return Ok(Value::None);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_file_size(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("file_size", args.len(), 1)?;
+    }
+    let path = path_arg("file_size", &args, 0)?;
+    let size = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+    // Numbers are 32 bit, so huge files use a decimal
+    return Ok(i32::try_from(size).map(Value::Int).unwrap_or(Value::Float(size as f32)));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_is_dir(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("is_dir", args.len(), 1)?;
+    }
+    let path = path_arg("is_dir", &args, 0)?;
+    return Ok(Value::Bool(std::path::Path::new(&path).is_dir()));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_is_file(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("is_file", args.len(), 1)?;
+    }
+    let path = path_arg("is_file", &args, 0)?;
+    return Ok(Value::Bool(std::path::Path::new(&path).is_file()));
+}
+
+// Last modified time, in seconds since 1970 (UTC)
+#[cfg(not(target_family = "wasm"))]
+fn sk_mtime(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("mtime", args.len(), 1)?;
+    }
+    let path = path_arg("mtime", &args, 0)?;
+    let time = std::fs::metadata(path).and_then(|m| m.modified())
+        .map_err(|e| e.to_string())?;
+    let secs = time.duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?.as_secs();
+    // Floats are too small for the time, so it sticks at the biggest number after 2038
+    return Ok(Value::Int(secs.min(i32::MAX as u64) as i32));
+}
+
+// Watching
+// Every file under a path
+#[cfg(not(target_family = "wasm"))]
+fn scan_path(path: &std::path::Path, files: &mut FileTimes) {
+    let Ok(meta) = std::fs::metadata(path) else {
+        return;
+    };
+    if meta.is_dir() {
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            scan_path(&entry.path(), files);
+        }
+    } else {
+        let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        files.insert(path.to_string_lossy().to_string(), (modified, meta.len()));
+    }
+}
+
+// Returns a watcher for poll_events
+#[cfg(not(target_family = "wasm"))]
+fn sk_watch(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("watch", args.len(), 2)?;
+    }
+    let path = path_arg("watch", &args, 0)?;
+    let (Value::Functi(_) | Value::BoundFuncti(..)) = args[1] else {
+        return Err("watch() argument 2 must be a functi".to_string());
+    };
+    if !std::path::Path::new(&path).exists() {
+        return Err(format!("no such file or directory: {}", path));
+    }
+    let mut files = FxHashMap::default();
+    scan_path(std::path::Path::new(&path), &mut files);
+    vm.watchers.push((path, args[1].clone(), files));
+    return Ok(Value::Int(vm.watchers.len() as i32 - 1));
+}
+
+// Calls the watcher's functi with each change since the last poll, and returns them
+#[cfg(not(target_family = "wasm"))]
+fn sk_poll_events(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("poll_events", args.len(), 1)?;
+    }
+    let Some((path, functi, old)) = (match args[0] {
+        Value::Int(id) => usize::try_from(id).ok().and_then(|id| vm.watchers.get_mut(id)),
+        _ => None,
+    }) else {
+        return Err("poll_events() argument 1 must be a watcher".to_string());
+    };
+    let mut files = FxHashMap::default();
+    scan_path(std::path::Path::new(path), &mut files);
+    let mut events = vec![];
+    for (file, info) in &files {
+        match old.get(file) {
+            None => events.push(("create", file.clone())),
+            Some(old_info) if old_info != info => events.push(("modify", file.clone())),
+            _ => {},
+        }
+    }
+    for file in old.keys().filter(|f| !files.contains_key(*f)) {
+        events.push(("delete", file.clone()));
+    }
+    events.sort_by(|a, b| a.1.cmp(&b.1));
+    *old = files;
+    let functi = functi.clone();
+    let mut ret = vec![];
+    for (kind, file) in events {
+        let event = vec![Value::Str(Rc::new(kind.to_string())), Value::Str(Rc::new(file))];
+        // The callbacks run after this returns
+        vm.queued.push((functi.clone(), event.clone()));
+        ret.push(Value::Tuple(Rc::new(event)));
+    }
+    vm.check_drops |= !vm.queued.is_empty();
+    return Ok(Value::FastList(Rc::new(ret)));
+}
+
+// Globbing
+// Matches a single path part against a pattern with *, ?, and [abc] (or [!abc])
+#[cfg(not(target_family = "wasm"))]
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|at| glob_match(&pattern[1..], &name[at..])),
+        Some('?') => !name.is_empty() && glob_match(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let Some(end) = pattern.iter().skip(2).position(|c| *c == ']') else {
+                // Not a set, just a [
+                return name.first() == Some(&'[') && glob_match(&pattern[1..], &name[1..]);
+            };
+            let Some(c) = name.first() else {
+                return false;
+            };
+            let set = &pattern[1..end + 2];
+            let (negate, set) = match set.first() {
+                Some('!' | '^') => (true, &set[1..]),
+                _ => (false, set),
+            };
+            // Ranges like a-z
+            let mut found = false;
+            let mut at = 0;
+            while at < set.len() {
+                if at + 2 < set.len() && set[at + 1] == '-' {
+                    found |= set[at] <= *c && *c <= set[at + 2];
+                    at += 3;
+                } else {
+                    found |= set[at] == *c;
+                    at += 1;
+                }
+            }
+            found != negate && glob_match(&pattern[end + 3..], &name[1..])
+        },
+        Some(p) => name.first() == Some(p) && glob_match(&pattern[1..], &name[1..]),
+    }
+}
+
+// Adds the paths under base matching the pattern parts
+#[cfg(not(target_family = "wasm"))]
+fn glob_walk(base: &str, parts: &[&str], out: &mut Vec<String>) {
+    let join = |name: &str| if base.is_empty() {
+        name.to_string()
+    } else if base.ends_with('/') {
+        base.to_string() + name
+    } else {
+        format!("{}/{}", base, name)
+    };
+    let Some(part) = parts.first() else {
+        out.push(base.to_string());
+        return;
+    };
+    let dir = if base.is_empty() { "." } else { base };
+    if !part.contains(['*', '?', '[']) {
+        // No need to read the dir
+        let path = join(part);
+        if std::path::Path::new(&path).exists() {
+            glob_walk(&path, &parts[1..], out);
+        }
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let pattern: Vec<char> = part.chars().collect();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // Hidden files have to be matched explicitly
+        if name.starts_with('.') && !part.starts_with('.') {
+            continue;
+        }
+        let path = join(&name);
+        if *part == "**" {
+            if entry.path().is_dir() {
+                glob_walk(&path, parts, out);
+            }
+        } else if glob_match(&pattern, &name.chars().collect::<Vec<char>>()) {
+            glob_walk(&path, &parts[1..], out);
+        }
+    }
+    if *part == "**" {
+        // ** can also match nothing
+        glob_walk(base, &parts[1..], out);
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_glob(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("glob", args.len(), 1)?;
+    }
+    let pattern = path_arg("glob", &args, 0)?;
+    let base = if pattern.starts_with('/') { "/" } else { "" };
+    let parts: Vec<&str> = pattern.split('/').filter(|p| !p.is_empty()).collect();
+    let mut paths = vec![];
+    glob_walk(base, &parts, &mut paths);
+    paths.sort();
+    paths.dedup();
+    return Ok(Value::FastList(Rc::new(
+        paths.into_iter().map(|p| Value::Str(Rc::new(p))).collect()
+    )));
+}
+
+// Temp files
+// Makes a new temp file or dir, which is removed when the VM is gone
+#[cfg(not(target_family = "wasm"))]
+fn make_temp(vm: &mut Vm, name: &str, args: &[Value], dir: bool) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args(name, args.len(), 0)?;
+    }
+    let mut tries = 0;
+    let path = loop {
+        let path = std::env::temp_dir().join(format!(
+            "burlap-{}-{:08x}", std::process::id(), rand::thread_rng().gen::<u32>()
+        ));
+        let made = if dir {
+            std::fs::create_dir(&path)
+        } else {
+            OpenOptions::new().write(true).create_new(true).open(&path).map(|_| ())
+        };
+        match made {
+            Ok(()) => break path,
+            // Name clash, try again
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && tries < 100 => tries += 1,
+            Err(e) => return Err(e.to_string()),
+        }
+    };
+    vm.temps.push(path.clone());
+    return Ok(Value::Str(Rc::new(path.to_string_lossy().to_string())));
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_temp_file(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return make_temp(vm, "temp_file", &args, false);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn sk_temp_dir(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return make_temp(vm, "temp_dir", &args, true);
+}
+
+// CSV
+#[cfg(not(target_family = "wasm"))]
+fn parse_csv(src: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                // "" is an escaped quote
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => quoted = false,
+                c => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            },
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote in CSV".to_string());
+    }
+    // The last line might not end with a newline
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    return Ok(rows);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn csv_field(val: &Value) -> Result<String, String> {
+    let str = val.to_string()?;
+    if str.contains([',', '"', '\n', '\r']) {
+        return Ok(format!("\"{}\"", str.replace('"', "\"\"")));
+    }
+    return Ok(str);
+}
+
+// Reads a file (or a string of CSV), with a header the rows are keyed
+#[cfg(not(target_family = "wasm"))]
+fn sk_csv_read(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("csv_read", args.len(), 2)?;
+    }
+    let Value::Str(src) = &args[0] else {
+        return Err("csv_read() argument 1 must be a string".to_string());
+    };
+    let is_file = !src.contains('\n') && std::path::Path::new(src.as_str()).is_file();
+    let src = if is_file {
+        std::fs::read_to_string(src.as_str()).map_err(|e| e.to_string())?
+    } else {
+        src.to_string()
+    };
+    let mut rows = parse_csv(&src)?.into_iter();
+    let str = |s: String| Value::Str(Rc::new(s));
+    if !args[1].is_truthy() {
+        return Ok(Value::FastList(Rc::new(rows.map(|row| Value::FastList(Rc::new(
+            row.into_iter().map(str).collect()
+        ))).collect())));
+    }
+    let header = rows.next().unwrap_or_default();
+    return Ok(Value::FastList(Rc::new(rows.map(|row| Value::List(Rc::new(
+        row.into_iter().enumerate().map(|(at, cell)| (
+            header.get(at).cloned().map(str).unwrap_or(Value::None), str(cell)
+        )).collect()
+    ))).collect())));
+}
+
+// Writes rows to a file, keyed rows also write a header
+#[cfg(not(target_family = "wasm"))]
+fn sk_csv_write(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 2 {
+        // Invalid args
+        vm.bad_args("csv_write", args.len(), 2)?;
+    }
+    let Value::Str(path) = &args[0] else {
+        return Err("csv_write() argument 1 must be a string".to_string());
+    };
+    let Some(rows) = args[1].values() else {
+        return Err("csv_write() argument 2 must be a list".to_string());
+    };
+    let line = |vals: Vec<Value>| -> Result<String, String> {
+        Ok(vals.iter().map(csv_field).collect::<Result<Vec<String>, String>>()?.join(",") + "\n")
+    };
+    let mut out = String::new();
+    if let Some(Value::List(first)) = rows.first() {
+        if first.iter().any(|(k, _)| *k != Value::None) {
+            out += &line(first.iter().map(|(k, _)| k.clone()).collect())?;
+        }
+    }
+    for row in rows {
+        let Some(vals) = row.values() else {
+            return Err(format!("csv_write() rows must be lists, not {}", row.get_type()));
+        };
+        out += &line(vals)?;
+    }
+    std::fs::write(path.as_str(), out).map_err(|e| e.to_string())?;
+    return Ok(Value::None);
+}
+
+// Casting
+// Int
+fn sk_int(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("int", args.len(), 1)?;
+    }
+    return Ok(Value::Int(args[0].to_int()));
+}
+
+// Strict casts, unlike int() and friends these error instead of making something up
+fn cast_err(name: &str, val: &Value, to: &str) -> String {
+    format!("{}() can't turn {} into {}", name, val.short_repr(), to)
+}
+
+fn sk_to_num(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("to_num", args.len(), 1)?;
+    }
+    return Ok(match &args[0] {
+        Value::Int(_) | Value::Float(_) => args[0].clone(),
+        Value::Byte(b) => Value::Int(*b as i32),
+        Value::Bool(b) => Value::Int(*b as i32),
+        // Ints stay ints, and "nan" or "inf" (or ones too big) aren't numbers, use float() for those
+        Value::Str(s) => if let Ok(num) = s.trim().parse::<i32>() {
+            Value::Int(num)
+        } else if let Some(num) = s.trim().parse::<f32>().ok().filter(|n| n.is_finite()) {
+            Value::Float(num)
+        } else {
+            return Err(cast_err("to_num", &args[0], "a number"));
+        },
+        _ => return Err(cast_err("to_num", &args[0], "a number")),
+    });
+}
+
+fn sk_to_str(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("to_str", args.len(), 1)?;
+    }
+    // The same as string(), which gives none on errors
+    if let Value::Byte(byte) = args[0] {
+        return Ok(Value::Str(Rc::new((byte as char).to_string())));
+    }
+    return match args[0].to_string() {
+        Ok(str) => Ok(Value::Str(Rc::new(str))),
+        Err(_) => Err(cast_err("to_str", &args[0], "a string")),
     };
-    info.borrow_mut().closed = true;
-    info.borrow_mut().file = None;
-    Ok(Value::None)
 }
 
-fn sk_flush(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+fn sk_to_bool(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     if args.len() != 1 {
         // Invalid args
-        vm.bad_args("flush", args.len(), 1)?;
+        vm.bad_args("to_bool", args.len(), 1)?;
     }
-    // Burlap doesn't buffer file io
-    Ok(Value::None)
+    return Ok(Value::Bool(match &args[0] {
+        Value::Bool(_) | Value::Int(_) | Value::Float(_) | Value::Byte(_) | Value::None =>
+            args[0].is_truthy(),
+        // Only what a bool turns into
+        Value::Str(s) if s.as_str() == "true" => true,
+        Value::Str(s) if s.as_str() == "false" => false,
+        _ => return Err(cast_err("to_bool", &args[0], "a bool")),
+    }));
 }
 
-fn sk_read(vm: &mut Vm, mut args: Vec<Value>) -> Result<Value, String> {
+fn sk_to_list(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     if args.len() != 1 {
         // Invalid args
-        vm.bad_args("read", args.len(), 1)?;
-    }
-    let Value::File(ref mut info) = args[0] else {
-        return Err(format!("cannot read from {}", args[0].get_type()));
-    };
-    if info.borrow().closed {
-        return Err("cannot read from closed file".to_string());
-    }
-    let mode = info.borrow().mode;
-    if mode.abs() != 1 {
-        return Err("can only read from 'r'/'rb'".to_string());
-    }
-    // Read the file
-    let mut ret: Vec<u8> = vec![];
-    if let Err(e) = info.borrow_mut().file.as_ref().unwrap()
-        .read_to_end(&mut ret) {
-        return Err(e.to_string());
+        vm.bad_args("to_list", args.len(), 1)?;
     }
-    if mode == 1 {
-        // Return a string
-        let Ok(string) = String::from_utf8(ret) else {
-            return Err("invalid string".to_string());
-        };
-        return Ok(Value::Str(Rc::new(string)));
-    }
-    return Ok(Value::FastList(Rc::new(ret.iter().map(|i| Value::Byte(*i)).collect())));
+    return Ok(match &args[0] {
+        Value::List(_) | Value::FastList(_) => args[0].clone(),
+        Value::Tuple(t) => Value::FastList(t.clone()),
+        Value::Set(_) => Value::FastList(Rc::new(args[0].set_values().unwrap())),
+        // Strings are split into chars
+        Value::Str(s) => Value::FastList(Rc::new(
+            s.chars().map(|c| Value::Str(Rc::new(c.to_string()))).collect()
+        )),
+        _ => return Err(cast_err("to_list", &args[0], "a list")),
+    });
 }
 
-fn sk_seek(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 2 {
+// Like int(), but errors on invalid numbers
+fn sk_parse_int(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 && args.len() != 2 {
         // Invalid args
-        vm.bad_args("seek", args.len(), 2)?;
+        vm.bad_args("parse_int", args.len(), 1)?;
     }
-    // Check and get file
-    let Value::File(ref mut info) = args[0].clone() else {
-        return Err(format!("cannot seek on {}", args[0].get_type()));
+    let Value::Str(str) = &args[0] else {
+        return Err("parse_int() argument 1 must be a string".to_string());
     };
-    if info.borrow().closed {
-        return Err("cannot seek on closed file".to_string());
-    }
-    if info.borrow().mode.abs() != 2 {
-        return Err("can only seek on 'w'/'wb'".to_string());
-    }
-    // Get position
-    let pos = args[1].to_int();
-    if pos < 0 {
-        return Err("position cannot be negative".to_string());
-    }
-    let max = info.borrow_mut().file.as_ref().unwrap()
-        .seek(SeekFrom::End(0)).unwrap();
-    let pos: u64 = pos.try_into().unwrap();
-    if pos > max {
-        return Err("cannot seek to position larger than file".to_string());
+    let radix = match args.get(1) {
+        None => 10,
+        Some(Value::Int(radix @ 2..=36)) => *radix as u32,
+        _ => return Err("parse_int() argument 2 must be a number from 2 to 36".to_string()),
+    };
+    let trimmed = str.trim();
+    let (neg, digits) = match trimmed.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    // Allow the prefix that matches the radix, like 0xff
+    let prefix = match radix { 16 => "0x", 8 => "0o", 2 => "0b", _ => "" };
+    let digits = if prefix.is_empty() {
+        digits
+    } else {
+        digits.strip_prefix(prefix)
+            .or_else(|| digits.strip_prefix(&prefix.to_uppercase()))
+            .unwrap_or(digits)
+    };
+    // from_str_radix allows signs, which were already handled
+    if digits.starts_with(['+', '-']) {
+        return Err(format!("invalid number: {}", str));
     }
-    // Seek
-    info.borrow_mut().file.as_ref().unwrap()
-        .seek(SeekFrom::Start(pos)).unwrap();
-    Ok(Value::None)
+    let num = i64::from_str_radix(digits, radix)
+        .map(|num| if neg { -num } else { num }).ok()
+        .and_then(|num| i32::try_from(num).ok());
+    return match num {
+        Some(num) => Ok(Value::Int(num)),
+        None => Err(format!("invalid number: {}", str)),
+    };
 }
 
-fn sk_write(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 2 {
+// Like float(), but errors on invalid numbers
+fn sk_parse_float(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
         // Invalid args
-        vm.bad_args("write", args.len(), 2)?;
+        vm.bad_args("parse_float", args.len(), 1)?;
     }
-    // Now check args
-    let Value::File(ref mut info) = args[0].clone() else {
-        return Err(format!("cannot write to {}", args[0].get_type()));
+    let Value::Str(str) = &args[0] else {
+        return Err("parse_float() argument 1 must be a string".to_string());
     };
-    if info.borrow().closed {
-        return Err("cannot write to closed file".to_string());
-    }
-    let mode = info.borrow().mode;
-    if mode.abs() != 2 && mode != 0 {
-        return Err("can only write to 'w'/'wb'/'a'".to_string());
-    }
-    let Value::Str(ref str) = args[1] else {
-        return Err(format!("expected String got {}", args[1].get_type()));
+    return match str.trim().parse::<f32>() {
+        Ok(num) => Ok(Value::Float(num)),
+        Err(_) => Err(format!("invalid number: {}", str)),
     };
-    if let Err(err) =
-        write!(info.borrow_mut().file.as_ref().unwrap(), "{}", str)
-    {
-        return Err(err.to_string());
-    }
-    return Ok(Value::None);
 }
 
-// Casting
-// Int
-fn sk_int(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+// Formats a number in a radix, without a prefix
+fn to_radix(vm: &mut Vm, name: &str, args: &[Value], radix: u32) -> Result<Value, String> {
     if args.len() != 1 {
         // Invalid args
-        vm.bad_args("int", args.len(), 1)?;
+        vm.bad_args(name, args.len(), 1)?;
     }
-    return Ok(Value::Int(args[0].to_int()));
+    let num = match args[0] {
+        Value::Int(num) => num as i64,
+        Value::Byte(num) => num as i64,
+        _ => return Err(format!("{}() argument 1 must be a number", name)),
+    };
+    let abs = num.unsigned_abs();
+    let digits = match radix {
+        16 => format!("{:x}", abs),
+        8 => format!("{:o}", abs),
+        _ => format!("{:b}", abs),
+    };
+    return Ok(Value::Str(Rc::new(if num < 0 { "-".to_string() + &digits } else { digits })));
+}
+
+fn sk_to_hex(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return to_radix(vm, "to_hex", &args, 16);
+}
+
+fn sk_to_bin(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return to_radix(vm, "to_bin", &args, 2);
+}
+
+fn sk_to_oct(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    return to_radix(vm, "to_oct", &args, 8);
 }
 
 // Float
@@ -870,6 +4907,22 @@ fn sk_typed_eq(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     return Ok(Value::Bool(args[0] == args[1]));
 }
 
+fn sk_stats(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        // Invalid args
+        vm.bad_args("__burlap_stats", args.len(), 0)?;
+    }
+    let stat = |k: &str, v: usize| (Value::Str(Rc::new(k.to_string())), Value::Int(v as i32));
+    return Ok(Value::List(Rc::new(vec![
+        // Variable and stack slots, even ones that are none
+        stat("slots", vm.globals.len() + vm.locals.len() + vm.stack.len()),
+        stat("consts", vm.program.consts.len()),
+        stat("globals", vm.globals.len()),
+        stat("call_depth", vm.call_frames.len()),
+        stat("instructions", vm.ops_run as usize),
+    ])));
+}
+
 fn sk_real_print(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     if args.len() != 1 {
         // Invalid args
@@ -893,7 +4946,11 @@ fn sk_set_var(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     let Value::RefType(offset, global) = args[0] else {
         return Err("__burlap_set_var requires a __burlap_reftype".to_string());
     };
-    (if global { &mut vm.globals } else { &mut vm.locals })[offset as usize] = args[1].clone();
+    if global {
+        vm.set_var(offset as u16, args[1].clone(), true);
+    } else {
+        vm.locals[offset as usize] = args[1].clone();
+    }
     return Ok(Value::None);
 }
 
@@ -904,7 +4961,10 @@ fn sk_load_var(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     let Value::RefType(offset, global) = args[0] else {
         return Err("__burlap_set_var requires a __burlap_reftype".to_string());
     };
-    return Ok((if global { &vm.globals } else { &vm.locals })[offset as usize].clone());
+    if global {
+        return Ok(vm.get_var(offset as u16, true));
+    }
+    return Ok(vm.locals[offset as usize].clone());
 }
 
 #[cfg(feature = "cffi")]
@@ -912,7 +4972,7 @@ fn sk_libload(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
     if args.len() != 1 {
         vm.bad_args("__burlap_load_library", args.len(), 1)?;
     }
-    let (_, file) = vm.program.get_info(vm.at as u32);
+    let (_, file, _) = vm.program.get_info(vm.at as u32);
     let mut path = PathBuf::from("./".to_owned() + &file);
     path.pop();
     path.push(args[0].to_string()?);
@@ -954,16 +5014,80 @@ fn sk_call_c(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
 }
 
 fn sk_fastrange(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 2 {
-        vm.bad_args("__burlap_range", args.len(), 2)?;
+    if args.len() != 2 && args.len() != 3 {
+        vm.bad_args("__burlap_range", args.len(), 3)?;
     }
-    let (at, max) = (args[0].to_int(), args[1].to_int());
     // For (0, 100) step is 1, for (100, 0) it's -1, etc..
-    let step = if max.gt(&at) {1} else {-1};
+    let (at, max, step) = range_args("range", &args)?;
+    if range_len(at, max, step) == 0 {
+        // A step of 0 is an empty range
+        return Ok(Value::RangeType(at, max, 0));
+    }
     return Ok(Value::RangeType(at, max, step));
 }
 
+// Feature detection
+fn sk_version(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if !args.is_empty() {
+        vm.bad_args("__burlap_version", args.len(), 0)?;
+    }
+    let part = |p: &str| Value::Int(p.parse().unwrap_or(0));
+    return Ok(Value::Tuple(Rc::new(vec![
+        part(env!("CARGO_PKG_VERSION_MAJOR")),
+        part(env!("CARGO_PKG_VERSION_MINOR")),
+        part(env!("CARGO_PKG_VERSION_PATCH")),
+    ])));
+}
+
+fn sk_has(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        vm.bad_args("__burlap_has", args.len(), 1)?;
+    }
+    let Value::Str(feature) = &args[0] else {
+        return Err("__burlap_has() argument 1 must be a string".to_string());
+    };
+    return Ok(Value::Bool(match feature.as_str() {
+        "cffi" => cfg!(feature = "cffi"),
+        "compression" => cfg!(feature = "compression"),
+        "repl" => cfg!(feature = "repl"),
+        "fancyrepl" => cfg!(feature = "fancyrepl"),
+        "jit" => cfg!(feature = "jit"),
+        "jupyter" => cfg!(feature = "jupyter"),
+        "hooks" => cfg!(feature = "hooks"),
+        "wasm" => cfg!(target_family = "wasm"),
+        // File IO, threads, and the terminal
+        "io" | "threads" => cfg!(not(target_family = "wasm")),
+        // Extensions
+        "auto-none" => vm.args.extension_auto_none,
+        "functies" => vm.args.extension_functies,
+        "va-print" => vm.args.extension_va_print,
+        "more-debug" => vm.args.extension_debugging_functies,
+        "total-order" => vm.args.extension_total_order,
+        _ => false,
+    }));
+}
+
 // Debugging functies
+fn sk_add_key(vm: &mut Vm, mut args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 3 {
+        // Invalid args
+        vm.bad_args("__burlap_add_key", args.len(), 3)?;
+    }
+    let val = args.pop().unwrap();
+    let key = args.pop().unwrap();
+    let Value::List(mut list) = args.pop().unwrap() else {
+        return Err("__burlap_add_key() argument 1 must be a keyed list".to_string());
+    };
+    // Unlike set_key, numbers are always keys
+    let list_mut = Rc::make_mut(&mut list);
+    if let Some(at) = list_mut.iter().position(|i| i.0.is_key(&key)) {
+        list_mut[at].1 = val;
+    } else {
+        list_mut.push((key, val));
+    }
+    return Ok(Value::List(list));
+}
+
 fn sk_debug_on(vm: &mut Vm, _args: Vec<Value>) -> Result<Value, String> {
     vm.args.is_debug = true;
     Ok(Value::None)
@@ -972,12 +5096,23 @@ fn sk_debug_off(vm: &mut Vm, _args: Vec<Value>) -> Result<Value, String> {
     vm.args.is_debug = false;
     Ok(Value::None)
 }
+// Direct calls are compiled away, this is for when it's used as a value
+fn sk_debug_blackbox(vm: &mut Vm, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() != 1 {
+        // Invalid args
+        vm.bad_args("__burlap_debug_blackbox", args.len(), 1)?;
+    }
+    Ok(args[0].clone())
+}
 
 // Sets a key in a list and returns it
-fn set_key(
+pub fn set_key(
     vlist: &mut Value, key: Value, val: Value
 ) -> Result<(), String> {
     // TODO: This code sucks
+    if let Value::Tuple(_) = vlist {
+        return Err("cannot assign to a tuple".to_string());
+    }
     let Value::List(ref mut list_rc) = vlist else {
         let Value::FastList(ref mut list_rc) = vlist else {
             return Err(format!(
@@ -1009,11 +5144,11 @@ fn set_key(
             }
         } else {
             // Convert to normal list
-            let mut slowlist = Vec::<(String, Value)>::with_capacity(
+            let mut slowlist = Vec::<(Value, Value)>::with_capacity(
                 list.len()
             );
             for i in list {
-                slowlist.push(("".to_string(), i.clone()));
+                slowlist.push((Value::None, i.clone()));
             }
             // Set
             *vlist = Value::List(Rc::new(slowlist));
@@ -1022,19 +5157,29 @@ fn set_key(
         return Ok(());
     };
     let list = Rc::make_mut(list_rc);
-    // Insert
-    if key.get_type() == "Number" {
-        let key = key.to_int();
-        if key < 0 || key as usize > list.len() {
-            return Err(
-                "cannot assign to out of bounds key".to_string()
-            );
+    // Existing keys
+    if let Some(at) = list.iter().position(|i| i.0.is_key(&key)) {
+        if val == Value::None {
+            // Delete key
+            list.remove(at);
+        } else {
+            // Modify key
+            list[at].1 = val;
         }
-        let key = key as usize;
+        return Ok(());
+    }
+    // Insert
+    let is_num = key.get_type() == "Number";
+    if is_num && key.to_int() < 0 {
+        return Err(
+            "cannot assign to out of bounds key".to_string()
+        );
+    } else if is_num && key.to_int() as usize <= list.len() {
+        let key = key.to_int() as usize;
         if key == list.len() {
             // Add new key
             if val != Value::None {
-                list.push(("".to_string(), val));
+                list.push((Value::None, val));
             }
         } else {
             if val == Value::None {
@@ -1045,31 +5190,75 @@ fn set_key(
                 list[key].1 = val;
             }
         }
-    } else {
-        // Add or create string key
-        let key = key.to_string()?;
-        let mut index = usize::MAX;
-        for (at, ref mut i) in list.iter_mut().enumerate() {
-            if i.0 == key {
-                if val == Value::None {
-                    index = at;
-                    break;
-                } else {
-                    i.1 = val;
-                    return Ok(());
-                }
-            }
-        }
-        // Modify
-        if val != Value::None {
-            list.push((key, val));
-        } else if index != usize::MAX {
-            list.remove(index);
-        }
+    } else if val != Value::None {
+        // Create key
+        list.push((key, val));
     }
     return Ok(());
 }
 
+// Errors on implicit conversions when in strict mode
+// Errors if a string or list would be over --max-size, before it's made
+fn check_size(vm: &Vm, size: usize, is_str: bool) -> Result<(), String> {
+    if vm.args.max_size == 0 || size <= vm.args.max_size {
+        return Ok(());
+    }
+    return Err(if is_str {
+        format!("String is too big ({} bytes, the max is {})", size, vm.args.max_size)
+    } else {
+        format!("List is too big ({} items, the max is {})", size, vm.args.max_size)
+    });
+}
+
+// The size of `lhs + rhs` or `lhs * rhs`, if it's a string or list
+fn op_size(op: char, lhs: &Value, rhs: &Value) -> Option<(usize, bool)> {
+    let size = |v: &Value| match v {
+        Value::Str(s) => s.len(),
+        Value::List(l) => l.len(),
+        Value::FastList(l) => l.len(),
+        _ => 1,
+    };
+    match (op, lhs, rhs) {
+        ('+', Value::List(_) | Value::FastList(_), _) => Some((size(lhs) + size(rhs), false)),
+        ('+', Value::Str(_), _) | ('+', _, Value::Str(_)) => Some((size(lhs) + size(rhs), true)),
+        ('*', Value::Str(_) | Value::List(_) | Value::FastList(_), Value::Int(n)) => Some((
+            size(lhs).saturating_mul((*n).max(0) as usize), matches!(lhs, Value::Str(_))
+        )),
+        _ => None,
+    }
+}
+
+fn check_op_size(vm: &Vm, op: char, lhs: &Value, rhs: &Value) -> Result<(), String> {
+    match op_size(op, lhs, rhs) {
+        Some((size, is_str)) => check_size(vm, size, is_str),
+        None => Ok(()),
+    }
+}
+
+fn check_strict(vm: &Vm, op: &str, lhs: &Value, rhs: &Value) -> Result<(), String> {
+    if !vm.args.strict || (op == "compare" && vm.args.extension_total_order) {
+        return Ok(());
+    }
+    let is_num = |v: &Value| matches!(v, Value::Int(_) | Value::Float(_) | Value::Byte(_));
+    let ok = match (op, lhs, rhs) {
+        // Lists can have anything added
+        ("add", Value::List(_) | Value::FastList(_), _) => true,
+        // Strings can only be added to strings
+        ("add", Value::Str(_), r) | ("add", r, Value::Str(_)) =>
+            matches!(r, Value::Str(_)),
+        // Only numbers can be compared
+        ("compare", l, r) => is_num(l) && is_num(r),
+        _ => true,
+    };
+    if ok {
+        return Ok(());
+    }
+    return Err(format!(
+        "Cannot {} {} and {} in strict mode: {} and {}", op,
+        lhs.get_type(), rhs.get_type(), lhs.short_repr(), rhs.short_repr()
+    ));
+}
+
 // The big switch, runs every instruction
 #[inline]
 fn exec_next(vm: &mut Vm) -> Result<(), String> {
@@ -1114,28 +5303,79 @@ fn exec_next(vm: &mut Vm) -> Result<(), String> {
             vm.set_reg(a, args);
         },
         Opcode::CALL => {
-            vm.call(shift3(a, b, c));
+            #[cfg(feature = "jit")]
+            if vm.jit_call(shift3(a, b, c)) {
+                return Ok(());
+            }
+            vm.call(shift3(a, b, c))?;
         },
         Opcode::VCALL => {
             let functi = vm.get_reg(a);
-            let Value::Functi(fn_name) = functi else {
-                return Err(format!("cannot call {}", functi.get_type()));
+            let (fn_name, arg_num) = match functi {
+                Value::Functi(fn_name) => (fn_name, b),
+                Value::BoundFuncti(fn_name, bound) => {
+                    // Put the bound args before the normal ones
+                    let at = vm.stack.len() - b as usize;
+                    vm.stack.splice(at..at, bound.iter().cloned());
+                    let Ok(arg_num) = u8::try_from(b as usize + bound.len()) else {
+                        return Err("too many arguments".to_string());
+                    };
+                    (fn_name, arg_num)
+                },
+                _ => return Err(format!("cannot call {}", functi.get_type())),
             };
-            vm.call_name((*fn_name).clone(), b)?;
+            vm.call_name((*fn_name).clone(), arg_num)?;
         }
         Opcode::RCALL => {
             // Jump
             vm.at = shift3(a, b, c);
         },
+        Opcode::RET if !vm.call_frames.last().unwrap().defers.is_empty() => {
+            // Run deferred code first, it comes back to here after
+            let frame = vm.call_frames.last_mut().unwrap();
+            frame.defer_from = vm.at;
+            vm.at = frame.defers.pop().unwrap();
+            vm.jump = true;
+        },
         Opcode::RET => {
             let frame = vm.call_frames.pop().unwrap();
             let pos = frame.return_addr;
-            vm.at = pos + 1;
+            vm.at = pos.wrapping_add(1);
             vm.jump = true;
             vm.regs = frame.regs;
             vm.pop_locals(frame.local_size);
+            if frame.discard {
+                vm.stack.pop();
+            }
+            vm.check_drops |= !vm.drops.is_empty();
         }
 
+        Opcode::DEFER => {
+            vm.call_frames.last_mut().unwrap().defers.push(shift3(a, b, c));
+        },
+        Opcode::RDEF => {
+            vm.at = vm.call_frames.last().unwrap().defer_from;
+            vm.jump = true;
+            vm.exit_in_defer = false;
+        },
+        Opcode::TRY => {
+            if let Value::Result(false, _) = vm.get_reg_ref(a) {
+                // Anything half built is thrown away, then the RET returns the err
+                let err = vm.get_reg_ref(a).clone();
+                let frame = vm.call_frames.last().unwrap();
+                vm.stack.truncate(frame.stack_size);
+                vm.stack.push(err);
+            } else {
+                let val = match vm.get_reg(a) {
+                    Value::Result(_, val) => (*val).clone(),
+                    val => val,
+                };
+                vm.set_reg(c, val);
+                vm.at += b as usize;
+                vm.jump = true;
+            }
+        },
+
         // Lists
         Opcode::LFL => {
             let mut size = shift2(b, c);
@@ -1152,21 +5392,15 @@ fn exec_next(vm: &mut Vm) -> Result<(), String> {
         },
         Opcode::LL => {
             let mut size = shift2(b, c);
-            let mut list = Vec::<(String, Value)>::with_capacity(size);
+            let mut list = Vec::<(Value, Value)>::with_capacity(size);
             // Get the keys and values and put them into the list
             while size > 0 {
                 size -= 1;
-                let Value::Str(key) = vm.stack.pop().unwrap() else {
-                    return Err("Non-string list index".to_string());
-                };
+                let key = vm.stack.pop().unwrap();
                 let val = vm.stack.pop().unwrap();
                 if let Value::None = val {
                     continue;
                 }
-                let key = (*key).clone();
-                /*if key == "" {
-                    key = (old_size - list.len() - 1).to_string();
-                }*/
                 // Store
                 list.push((key, val));
             }
@@ -1215,6 +5449,64 @@ fn exec_next(vm: &mut Vm) -> Result<(), String> {
             let val = vm.get_reg(c);
             // Set
             vm.set_var(shift2(a, b) as u16, val, t_op == Opcode::SV_G);
+            vm.check_drops |= !vm.drops.is_empty();
+        },
+        t_op @ (Opcode::AV_L | Opcode::AV_G) => {
+            let val = vm.get_reg(c);
+            let (offset, global) = (shift2(a, b) as u16, t_op == Opcode::AV_G);
+            let var = vm.get_var(offset, global);
+            check_strict(vm, "add", &var, &val)?;
+            check_op_size(vm, '+', &var, &val)?;
+            // Adding to the only copy doesn't need to reallocate everything
+            // Anything that can fail is done first, so an error leaves the var alone
+            let var = match var {
+                Value::Str(_) => {
+                    let add = val.to_string()?;
+                    drop(var);
+                    let Value::Str(mut s) = vm.take_var(offset, global) else {
+                        return Err(IMPOSSIBLE_STATE.to_string());
+                    };
+                    Rc::make_mut(&mut s).push_str(&add);
+                    Value::Str(s)
+                },
+                Value::FastList(_) => {
+                    drop(var);
+                    let Value::FastList(mut l) = vm.take_var(offset, global) else {
+                        return Err(IMPOSSIBLE_STATE.to_string());
+                    };
+                    let list = Rc::make_mut(&mut l);
+                    if let Some(mut vals) = val.values() {
+                        list.append(&mut vals);
+                    } else {
+                        list.push(val);
+                    }
+                    Value::FastList(l)
+                },
+                Value::Int(l) if matches!(val, Value::Int(_)) => {
+                    int_math('+', l, val.to_int(), vm.args.overflow)?
+                },
+                // A copy, the var is only changed if it works
+                var => (var + val)?,
+            };
+            vm.set_var(offset, var, global);
+            vm.check_drops |= !vm.drops.is_empty();
+        },
+        t_op @ (Opcode::INC_L | Opcode::INC_G | Opcode::DEC_L | Opcode::DEC_G) => {
+            let offset = shift2(a, b) as u16;
+            let global = matches!(t_op, Opcode::INC_G | Opcode::DEC_G);
+            let var = vm.get_var(offset, global);
+            let var = match (var, matches!(t_op, Opcode::INC_L | Opcode::INC_G)) {
+                (Value::Int(i), true) => int_math('+', i, 1, vm.args.overflow)?,
+                (Value::Int(i), false) => int_math('-', i, 1, vm.args.overflow)?,
+                (var, true) => {
+                    check_strict(vm, "add", &var, &Value::Int(1))?;
+                    (var + Value::Int(1))?
+                },
+                (var, false) => (var - Value::Int(1))?,
+            };
+            vm.set_var(offset, var.clone(), global);
+            vm.set_reg(c, var);
+            vm.check_drops |= !vm.drops.is_empty();
         },
         t_op @ (Opcode::PLC | Opcode::PGB) => {
             let (mut len, vec) = if t_op == Opcode::PLC {
@@ -1226,6 +5518,9 @@ fn exec_next(vm: &mut Vm) -> Result<(), String> {
                     vec.push(val);
                     c -= 1;
                 }
+                if let Some(frame) = vm.call_frames.last_mut() {
+                    frame.stack_size = vm.stack.len();
+                }
                 // Locals says how many more
                 (shift2(a, b), vec)
             } else {
@@ -1245,7 +5540,7 @@ fn exec_next(vm: &mut Vm) -> Result<(), String> {
         },
         Opcode::ALO => {
             // Get offset
-            let offset = shift2(a, b) + vm.locals.len() - 1;
+            let offset = vm.locals.len() - (shift2(a, b) + 1);
             // Set
             vm.set_reg(c, Value::RefType(offset as i32, false));
         },
@@ -1254,17 +5549,32 @@ fn exec_next(vm: &mut Vm) -> Result<(), String> {
         Opcode::ADD => {
             let rhs = vm.get_reg(b);
             let lhs = vm.get_reg(a);
-            vm.set_reg(c, (lhs + rhs)?);
+            check_strict(vm, "add", &lhs, &rhs)?;
+            check_op_size(vm, '+', &lhs, &rhs)?;
+            vm.set_reg(c, if let (Value::Int(l), Value::Int(r)) = (&lhs, &rhs) {
+                int_math('+', *l, *r, vm.args.overflow)?
+            } else {
+                (lhs + rhs)?
+            });
         },
         Opcode::SUB => {
             let rhs = vm.get_reg(b);
             let lhs = vm.get_reg(a);
-            vm.set_reg(c, (lhs - rhs)?);
+            vm.set_reg(c, if let (Value::Int(l), Value::Int(r)) = (&lhs, &rhs) {
+                int_math('-', *l, *r, vm.args.overflow)?
+            } else {
+                (lhs - rhs)?
+            });
         },
         Opcode::MUL => {
             let rhs = vm.get_reg(b);
             let lhs = vm.get_reg(a);
-            vm.set_reg(c, (lhs * rhs)?);
+            check_op_size(vm, '*', &lhs, &rhs)?;
+            vm.set_reg(c, if let (Value::Int(l), Value::Int(r)) = (&lhs, &rhs) {
+                int_math('*', *l, *r, vm.args.overflow)?
+            } else {
+                (lhs * rhs)?
+            });
         },
         Opcode::DIV => {
             let rhs = vm.get_reg(b);
@@ -1274,7 +5584,41 @@ fn exec_next(vm: &mut Vm) -> Result<(), String> {
         Opcode::MOD => {
             let rhs = vm.get_reg(b);
             let lhs = vm.get_reg(a);
-            vm.set_reg(c, (lhs % rhs)?);
+            vm.set_reg(c, if let (Value::Int(l), Value::Int(r)) = (&lhs, &rhs) {
+                int_math('%', *l, *r, vm.args.overflow)?
+            } else {
+                (lhs % rhs)?
+            });
+        },
+        Opcode::ADDI => {
+            let lhs = vm.get_reg(a);
+            let imm = b as i8 as i32;
+            vm.set_reg(c, if let Value::Int(i) = lhs {
+                int_math('+', i, imm, vm.args.overflow)?
+            } else {
+                check_strict(vm, "add", &lhs, &Value::Int(imm))?;
+                check_op_size(vm, '+', &lhs, &Value::Int(imm))?;
+                (lhs + Value::Int(imm))?
+            });
+        },
+        Opcode::SUBI => {
+            let lhs = vm.get_reg(a);
+            let imm = b as i8 as i32;
+            vm.set_reg(c, if let Value::Int(i) = lhs {
+                int_math('-', i, imm, vm.args.overflow)?
+            } else {
+                (lhs - Value::Int(imm))?
+            });
+        },
+        Opcode::BOR => {
+            let rhs = vm.get_reg(b);
+            let lhs = vm.get_reg(a);
+            vm.set_reg(c, (lhs | rhs)?);
+        },
+        Opcode::BAND => {
+            let rhs = vm.get_reg(b);
+            let lhs = vm.get_reg(a);
+            vm.set_reg(c, (lhs & rhs)?);
         },
         Opcode::IN => {
             let rhs = vm.get_reg(b);
@@ -1282,15 +5626,17 @@ fn exec_next(vm: &mut Vm) -> Result<(), String> {
             let contains = lhs.contains(&rhs);
             // Side effects
             let ltype = lhs.get_type();
+            let lrepr = lhs.short_repr();
             if a == 16 {
                 vm.stack.pop().unwrap();
             }
             if let Some(b) = contains {
                 vm.set_reg(c, Value::Bool(b));
             } else {
-                return Err(
-                    format!("Cannot use in on {:?} and {:?}", ltype, rhs.get_type())
-                );
+                return Err(format!(
+                    "Cannot use in on {} and {}: {} in {}",
+                    rhs.get_type(), ltype, rhs.short_repr(), lrepr
+                ));
             }
         },
         Opcode::EQ => {
@@ -1301,12 +5647,71 @@ fn exec_next(vm: &mut Vm) -> Result<(), String> {
         Opcode::LT => {
             let rhs = vm.get_reg(b);
             let lhs = vm.get_reg(a);
-            vm.set_reg(c, Value::Bool(lhs.to_float() < rhs.to_float()));
+            check_strict(vm, "compare", &lhs, &rhs)?;
+            vm.set_reg(c, Value::Bool(if vm.args.extension_total_order {
+                lhs.total_cmp(&rhs).is_lt()
+            } else {
+                lhs.to_float() < rhs.to_float()
+            }));
         },
         Opcode::GT => {
             let rhs = vm.get_reg(b);
             let lhs = vm.get_reg(a);
-            vm.set_reg(c, Value::Bool(lhs.to_float() > rhs.to_float()));
+            check_strict(vm, "compare", &lhs, &rhs)?;
+            vm.set_reg(c, Value::Bool(if vm.args.extension_total_order {
+                lhs.total_cmp(&rhs).is_gt()
+            } else {
+                lhs.to_float() > rhs.to_float()
+            }));
+        },
+        Opcode::GE => {
+            let rhs = vm.get_reg(b);
+            let lhs = vm.get_reg(a);
+            check_strict(vm, "compare", &lhs, &rhs)?;
+            vm.set_reg(c, Value::Bool(if vm.args.extension_total_order {
+                lhs.total_cmp(&rhs).is_ge()
+            } else {
+                lhs.to_float() >= rhs.to_float()
+            }));
+        },
+        Opcode::LE => {
+            let rhs = vm.get_reg(b);
+            let lhs = vm.get_reg(a);
+            check_strict(vm, "compare", &lhs, &rhs)?;
+            vm.set_reg(c, Value::Bool(if vm.args.extension_total_order {
+                lhs.total_cmp(&rhs).is_le()
+            } else {
+                lhs.to_float() <= rhs.to_float()
+            }));
+        },
+        Opcode::EQI => {
+            let lhs = vm.get_reg(a);
+            vm.set_reg(c, Value::Bool(lhs.eq(&Value::Int(b as i8 as i32))));
+        },
+        Opcode::LTI => {
+            let lhs = vm.get_reg(a);
+            let imm = Value::Int(b as i8 as i32);
+            check_strict(vm, "compare", &lhs, &imm)?;
+            vm.set_reg(c, Value::Bool(if vm.args.extension_total_order {
+                lhs.total_cmp(&imm).is_lt()
+            } else {
+                lhs.to_float() < (b as i8) as f32
+            }));
+        },
+        Opcode::GEI => {
+            let lhs = vm.get_reg(a);
+            let imm = Value::Int(b as i8 as i32);
+            check_strict(vm, "compare", &lhs, &imm)?;
+            // Not the same as !(lhs < imm), NaN is neither
+            vm.set_reg(c, Value::Bool(if vm.args.extension_total_order {
+                lhs.total_cmp(&imm).is_ge()
+            } else {
+                lhs.to_float() >= (b as i8) as f32
+            }));
+        },
+        Opcode::IST => {
+            let val = vm.get_reg(a);
+            vm.set_reg(c, Value::Bool(is_type(&val, b)));
         },
         Opcode::AND => {
             let rhs = vm.get_reg(b);
@@ -1363,29 +5768,145 @@ pub fn run(vm: &mut Vm) -> bool {
         println!("Consts: {:?}", vm.program.consts);
         println!("Ops: {:?}", vm.program.ops);
     }*/
+    let mut ok = run_loop(vm);
+    // Async functis that nothing waited for still run
+    #[cfg(not(target_family = "wasm"))]
+    if ok {
+        ok = vm.finish_tasks();
+    }
+    // The REPL runs them when it's closed
+    if !vm.args.is_repl {
+        vm.run_exit_hooks();
+    }
+    return ok;
+}
+
+fn run_loop(vm: &mut Vm) -> bool {
     loop {
+        // Exiting, run deferred code first
+        if vm.exit_code.is_some() && !vm.exit_in_defer {
+            if vm.call_frames.len() <= vm.nested_frames && vm.nested > 0 {
+                // The outer loop unwinds the rest
+                return true;
+            }
+            if let Some(frame) = vm.call_frames.last_mut() {
+                if let Some(defer) = frame.defers.pop() {
+                    frame.defer_from = vm.at;
+                    vm.at = defer;
+                    vm.exit_in_defer = true;
+                } else {
+                    let frame = vm.call_frames.pop().unwrap();
+                    vm.regs = frame.regs;
+                    vm.pop_locals(frame.local_size);
+                }
+                continue;
+            }
+            vm.finish_exit();
+            vm.at = vm.program.ops.len() - 1;
+            return true;
+        }
+        // Values might have been freed
+        if vm.check_drops {
+            vm.check_drops = false;
+            if let Err(s) = vm.run_drops() {
+                if vm.nested > 0 {
+                    vm.nested_err = Some(format!("in drop callback: {}", s));
+                } else {
+                    println!("Runtime Error in drop callback: {}", s);
+                }
+                return false;
+            }
+        }
         if vm.args.is_debug {
             // Print debugging info
-            let (line, filename) = vm.program.get_info(vm.at as u32);
+            let (line, filename, _) = vm.program.get_info(vm.at as u32);
             let op = dis_single(&vm.program, vm.at);
             println!("{filename}:{line}: {op}");
         }
+        #[cfg(feature = "hooks")]
+        if let Some(hook) = &mut vm.instruction_hook {
+            hook(vm.at, vm.program.ops[vm.at], &VmState {
+                program: &vm.program, stack: &vm.stack, regs: &vm.regs,
+                locals: &vm.locals, globals: &vm.globals, call_depth: vm.call_frames.len(),
+            });
+        }
         // Run
-        if let Err(s) = exec_next(vm) {
-            let (line, filename) = vm.program.get_info(vm.at as u32);
-            println!("Runtime Error at {}:{}: {}", filename, line, s);
+        vm.ops_run += 1;
+        let ret = if vm.args.fuel != 0 && vm.ops_run > vm.args.fuel {
+            Err("out of fuel".to_string())
+        } else {
+            exec_next(vm)
+        };
+        if let Err(s) = ret {
+            if vm.nested > 0 {
+                // run_nested gives it back
+                vm.nested_err = Some(s);
+                return false;
+            }
+            let mut handler_err = None;
+            if let Some(handler) = vm.error_handler.take() {
+                // The handler reports the error instead, if it fails both are reported
+                let handled = vm.handle_error(&handler, s.clone());
+                vm.error_handler = Some(handler);
+                if let Err(e) = handled {
+                    handler_err = Some(e);
+                } else {
+                    vm.at = vm.program.ops.len() - 1;
+                    return false;
+                }
+            }
+            let (line, filename, span) = vm.program.get_info(vm.at as u32);
+            let col = span.map(|(col, _)| col).unwrap_or(0);
+            let msg = if span.is_some() {
+                format!("Runtime Error at {}:{}:{}: {}", filename, line, col, s)
+            } else {
+                format!("Runtime Error at {}:{}: {}", filename, line, s)
+            };
+            if !capture_err(msg.clone()) {
+                if json_errors() {
+                    print_json_err(Some((&filename, line, col)), "runtime", &s, ErrType::Err);
+                } else {
+                    println!("{}", msg);
+                    runtime_snippet(&filename, line, span, vm.args.extension_color);
+                }
+            }
 
             // Backtrace
             if vm.args.backtrace {
                 for i in vm.call_frames.iter().rev() {
-                    let (line, filename) = vm.program.get_info(i.return_addr as u32 + 1);
-                    println!("Triggered by call at {}:{}", filename, line);
+                    let (line, filename, _) = vm.program.get_info(i.return_addr as u32 + 1);
+                    let msg = format!("Triggered by call at {}:{}", filename, line);
+                    if capture_err(msg.clone()) {
+                        continue;
+                    }
+                    if json_errors() {
+                        print_json_err(
+                            Some((&filename, line, 0)), "runtime",
+                            "triggered by call", ErrType::Hint
+                        );
+                    } else {
+                        println!("{}", msg);
+                    }
+                }
+            }
+            if let Some(e) = handler_err {
+                let msg = format!("Runtime Error in error handler: {}", e);
+                if !capture_err(msg.clone()) {
+                    if json_errors() {
+                        print_json_err(None, "runtime", &msg, ErrType::Err);
+                    } else {
+                        println!("{}", msg);
+                    }
                 }
             }
 
             vm.at = vm.program.ops.len() - 1;
             return false;
         }
+        if vm.awaiting.is_some() {
+            // An async functi is waiting, resume_task pauses it
+            return true;
+        }
         if vm.args.is_debug {
             println!("Stk: {:?}, regs: {:?}", vm.stack, vm.regs);
         }
@@ -1403,12 +5924,15 @@ pub fn run(vm: &mut Vm) -> bool {
                 // print the stack at the end
                 println!("FINAL: {:?}", vm.stack);
             }
-            if vm.args.is_repl && !vm.stack.is_empty() {
+            if (vm.args.is_repl || vm.args.print_result) && !vm.stack.is_empty() {
                 // Print the result
                 if vm.stack[0] != Value::None {
-                    print!("{}", vm.stack[0].to_string()
-                        .map(|x| x + "\n").unwrap_or("".to_string())
-                    );
+                    if vm.args.is_repl {
+                        println!("{}", vm.stack[0].pretty(vm.args.extension_color));
+                    } else if let Ok(s) = vm.stack[0].to_string() {
+                        // Plain for -e, so the output can be used by other programs
+                        println!("{}", s);
+                    }
                 }
             }
             break;
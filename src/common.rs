@@ -13,7 +13,9 @@ use cfg_mod::*;
 #[cfg(target_family = "wasm")]
 use crate::THE_SOURCE;
 
-use crate::Arguments;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::cell::RefCell;
+use std::sync::Mutex;
 
 // Stream
 #[derive(Debug, Clone)]
@@ -42,6 +44,9 @@ use wasm_bindgen::prelude::*;
 #[wasm_bindgen]
 extern "C" {
     pub fn println(s: &str);
+    // For timers
+    #[wasm_bindgen(js_namespace = performance)]
+    pub fn now() -> f64;
 }
 
 #[cfg(target_family = "wasm")]
@@ -51,17 +56,220 @@ macro_rules! println {
 
 // Errors
 pub enum ErrType{Err, Warn, Hint}
+
+impl ErrType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrType::Err => "error",
+            ErrType::Warn => "warning",
+            ErrType::Hint => "hint",
+        }
+    }
+}
+
+// If errors should be JSON (--error-format=json)
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_json_errors(json: bool) {
+    JSON_ERRORS.store(json, Ordering::Relaxed);
+}
+
+pub fn json_errors() -> bool {
+    JSON_ERRORS.load(Ordering::Relaxed)
+}
+
+fn json_str(str: &str) -> String {
+    let mut ret = "\"".to_string();
+    for c in str.chars() {
+        match c {
+            '"' => ret += "\\\"",
+            '\\' => ret += "\\\\",
+            '\n' => ret += "\\n",
+            '\t' => ret += "\\t",
+            '\r' => ret += "\\r",
+            c if c.is_control() => ret += &format!("\\u{:04x}", c as u32),
+            c => ret.push(c),
+        }
+    }
+    ret + "\""
+}
+
+// Prints an error as a line of JSON, pos is (file, line, column)
+pub fn print_json_err(
+    pos: Option<(&str, usize, usize)>, code: &str, msg: &str, errtype: ErrType
+) {
+    let severity = errtype.name();
+    let (file, line, column) = match pos {
+        Some((file, line, column)) =>
+            (json_str(file), line.to_string(), column.to_string()),
+        None => ("null".to_string(), "null".to_string(), "null".to_string()),
+    };
+    println!(
+        "{{\"file\": {}, \"line\": {}, \"column\": {}, \"code\": {}, \"message\": {}, \"severity\": \"{}\"}}",
+        file, line, column, json_str(code), json_str(msg), severity
+    );
+}
+
+// Gets each line of output as it's printed
+pub type OutputSink = Box<dyn FnMut(&str) + Send>;
+
+// Output and errors that are sent somewhere else instead of printed (for the remote REPL)
+#[derive(Default)]
+pub struct Captured {
+    pub out: String,
+    pub errors: Vec<String>,
+    // Output goes here instead of out if it's set
+    sink: Option<OutputSink>,
+}
+
+static CAPTURED: Mutex<Option<Captured>> = Mutex::new(None);
+
+// Starts capturing, or stops, returns what was captured before
+pub fn capture_output(on: bool) -> Option<Captured> {
+    std::mem::replace(&mut *CAPTURED.lock().unwrap(), on.then(Captured::default))
+}
+
+// Starts capturing, but output is streamed to sink
+#[cfg(feature = "jupyter")]
+pub fn stream_output(sink: OutputSink) -> Option<Captured> {
+    let captured = Captured { sink: Some(sink), ..Default::default() };
+    CAPTURED.lock().unwrap().replace(captured)
+}
+
+// Prints a line of program output
+pub fn print_line(line: &str) {
+    match CAPTURED.lock().unwrap().as_mut() {
+        Some(Captured { sink: Some(sink), .. }) => sink(line),
+        Some(captured) => {
+            captured.out.push_str(line);
+            captured.out.push('\n');
+        },
+        None => write_line(line),
+    }
+}
+
+#[cfg(target_family = "wasm")]
+fn write_line(line: &str) {
+    println!("{}", line);
+}
+
+// Writes to stdout, a closed pipe (like `| head`) means nothing else wants the output
+#[cfg(not(target_family = "wasm"))]
+fn write_line(line: &str) {
+    use std::io::{ErrorKind, Write};
+    if let Err(err) = writeln!(std::io::stdout().lock(), "{}", line) {
+        if err.kind() == ErrorKind::BrokenPipe {
+            std::process::exit(0);
+        }
+        panic!("failed printing to stdout: {}", err);
+    }
+}
+
+thread_local! {
+    // Errors from this thread only, so compile_str calls don't see each other's
+    static COLLECTED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+// Runs f and returns the errors it made on this thread instead of printing them
+pub fn collect_errors<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    let outer = COLLECTED.with(|c| c.replace(Some(vec![])));
+    let ret = f();
+    let errors = COLLECTED.with(|c| c.replace(outer)).unwrap_or_default();
+    return (ret, errors);
+}
+
+// Keeps an error if they're captured, false if it should be printed
+pub fn capture_err(msg: String) -> bool {
+    let msg = COLLECTED.with(|c| match c.borrow_mut().as_mut() {
+        Some(errors) => {
+            errors.push(msg);
+            None
+        },
+        None => Some(msg),
+    });
+    let Some(msg) = msg else {
+        return true;
+    };
+    let mut captured = CAPTURED.lock().unwrap();
+    let Some(captured) = captured.as_mut() else {
+        return false;
+    };
+    captured.errors.push(msg);
+    return true;
+}
+
+// If warnings should be errors (--deny-warnings)
+static DENY_WARNINGS: AtomicBool = AtomicBool::new(false);
+// Allowed warnings, a file of None means everywhere (--allow)
+static ALLOWED_WARNINGS: Mutex<Vec<(Option<String>, String)>> = Mutex::new(vec![]);
+// If a warning was shown, so the program isn't cached (the cache would hide it)
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_deny_warnings(deny: bool) {
+    DENY_WARNINGS.store(deny, Ordering::Relaxed);
+}
+
+pub fn allow_warning(file: Option<&str>, name: &str) {
+    let allow = (file.map(|f| f.to_string()), name.to_string());
+    let mut allowed = ALLOWED_WARNINGS.lock().unwrap();
+    if !allowed.contains(&allow) {
+        allowed.push(allow);
+    }
+}
+
+pub fn warned() -> bool {
+    WARNED.load(Ordering::Relaxed)
+}
+
+pub fn warning_allowed(file: &str, name: &str) -> bool {
+    ALLOWED_WARNINGS.lock().unwrap().iter().any(|(f, n)|
+        n == name && f.as_ref().is_none_or(|f| f == file)
+    )
+}
+
+// Prints a named warning, returns true if it was denied (and is now an error)
+pub fn warn(stream: &Stream, name: &str, msg: &str, color: bool) -> bool {
+    if warning_allowed(&stream.name, name) {
+        return false;
+    }
+    WARNED.store(true, Ordering::Relaxed);
+    let msg = format!("{} [{}]", msg, name);
+    if DENY_WARNINGS.load(Ordering::Relaxed) {
+        err(stream, &msg, ErrType::Err, color);
+        return true;
+    }
+    err(stream, &msg, ErrType::Warn, color);
+    return false;
+}
+
+// Log levels, in order, for log_X() and --log-level
+pub const LOG_LEVELS: [&str; 5] = ["debug", "info", "warn", "error", "off"];
+
+// Source of the last eval(), so errors can show it
+static EVAL_SOURCE: Mutex<String> = Mutex::new(String::new());
+
+pub fn set_eval_source(src: &str) {
+    *EVAL_SOURCE.lock().unwrap() = src.to_string();
+}
+
 // Prints and error and returns the color
 pub fn print_err(msg: &str, errtype: ErrType, color: bool) -> String {
-    // Get the name and color code from errtype
-    let (color_code, name) = match errtype {
+    if capture_err(format!("{}: {}", errtype.name(), msg)) {
+        return "".to_string();
+    }
+    if json_errors() {
+        print_json_err(None, "general", msg, errtype);
+        return "".to_string();
+    }
+    // Get the color code from errtype
+    let (color_code, name) = (match errtype {
         // Red
-        ErrType::Err => ("\x1b[1;31m", "error"),
+        ErrType::Err => "\x1b[1;31m",
         // Yellow
-        ErrType::Warn => ("\x1b[1;33m", "warning"),
+        ErrType::Warn => "\x1b[1;33m",
         // Cyan
-        ErrType::Hint => ("\x1b[1;36m", "hint"),
-    };
+        ErrType::Hint => "\x1b[1;36m",
+    }, errtype.name());
     if color {
         println!("{}{}:\x1b[0m {}", color_code, name, msg);
         return color_code.to_string();
@@ -85,10 +293,13 @@ fn get_line(stream: &Stream) -> String {
     // Special cases
     if stream.name == "<cli>" {
         let mut args = env::args();
-        args.position(|x| x == "-");
+        args.position(|x| x == "-" || x == "-e" || x == "-c");
         return args.next()
             .unwrap().lines().nth(stream.line - 1)
             .expect("failed to read file for errors").to_string()
+    } else if stream.name == "<eval>" {
+        return EVAL_SOURCE.lock().unwrap().lines().nth(stream.line - 1)
+            .unwrap_or("").to_string();
     } else if stream.name == "<stdin>" {
         #[cfg(not(target_family = "wasm"))]
         #[cfg(feature = "repl")]
@@ -113,15 +324,29 @@ fn get_line(stream: &Stream) -> String {
 }
 
 pub fn err(stream: &Stream, msg: &str, errtype: ErrType, color: bool) {
+    let pos = format!("{}:{}:{}", stream.name, stream.line, stream.at);
+    if capture_err(format!("{}: {}: {}", pos, errtype.name(), msg)) {
+        return;
+    }
+    if json_errors() {
+        let pos = (stream.name.as_str(), stream.line, stream.at);
+        print_json_err(Some(pos), "compile", msg, errtype);
+        return;
+    }
     let line = get_line(stream);
     // Print file name and line/char info ("test.sk:1:3: ")
     if color {
-        print!("\x1b[1m{}:{}:{}:\x1b[0m ", stream.name, stream.line, stream.at);
+        print!("\x1b[1m{}:\x1b[0m ", pos);
     } else {
-        print!("{}:{}:{}: ", stream.name, stream.line, stream.at);
+        print!("{}: ", pos);
     }
     // Print the type ("error:")
     let color_code = print_err(msg, errtype, color);
+    print_snippet(stream, &line, &color_code);
+}
+
+// Prints the line and an arrow under the stream
+fn print_snippet(stream: &Stream, line: &str, color_code: &str) {
     // Print the line ("    1 | print("Hello World!");")
     let prefix = format!("    {} | ", stream.line);
     println!("{}{}", prefix, line.replace('\t', "    "));
@@ -132,79 +357,49 @@ pub fn err(stream: &Stream, msg: &str, errtype: ErrType, color: bool) {
         "{}| {}",
         " ".repeat(prefix.len() - 2), " ".repeat(at)
     );
-    if color {
+    if !color_code.is_empty() {
         println!("{}{}\x1b[0m", color_code, "^".repeat(stream.size));
     } else {
         println!("{}", "^".repeat(stream.size));
     }
 }
 
-fn _get_builtins(args: &Arguments) -> Vec<(String, i32)> {
-    let mut ret: Vec<(String, i32)> = vec![
-        ("print", 1),
-        ("input", 1),
-        ("type", 1),
-        ("len", 1),
-        ("count", 1),
-        ("count", 2),
-        ("rand", 2),
-        ("range", 2),
-        ("args", 0),
-        ("int", 1),
-        ("float", 1),
-        ("string", 1),
-        ("byte", 1),
-        ("__burlap_range", 2),
-    ].iter().map(|(n, a)| (n.to_string(), *a)).collect();
-    // File IO
-    #[cfg(not(target_family = "wasm"))] {
-        let mut tmp = vec![
-            ("open", 2),
-            ("close", 1),
-            ("read", 1),
-            ("write", 2),
-            ("seek", 2),
-            ("flush", 1),
-        ].iter().map(|(n, a)| (n.to_string(), *a)).collect();
-        ret.append(&mut tmp);
-    }
-    // Extensions
-    if args.extension_functies {
-        let mut tmp = vec![
-            ("__burlap_typed_eq", 2),
-            ("__burlap_print", 1),
-            ("__burlap_throw", 1),
-            ("__burlap_reftype", 1),
-            ("__burlap_set_var", 2),
-            ("__burlap_load_var", 1),
-        ].iter().map(|(n, a)| (n.to_string(), *a)).collect();
-        ret.append(&mut tmp);
-        #[cfg(feature = "cffi")] {
-            tmp = vec![
-                ("__burlap_load_lib", 1),
-                ("__burlap_load_functi", 2),
-                ("__burlap_ffi_call", 3),
-                ("__burlap_ptr", 1),
-            ].iter().map(|(n, a)| (n.to_string(), *a)).collect();
-            ret.append(&mut tmp);
-        }
+#[cfg(target_family = "wasm")]
+fn try_get_line(stream: &Stream) -> Option<String> {
+    Some(get_line(stream))
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn try_get_line(stream: &Stream) -> Option<String> {
+    if stream.line == 0 {
+        return None;
     }
-    if args.extension_debugging_functies {
-        ret.push(("__burlap_debug_on".to_string(), 0));
-        ret.push(("__burlap_debug_off".to_string(), 0));
-        ret.push(("__burlap_debug_blackbox".to_string(), 1));
+    if stream.name == "<cli>" || stream.name == "<eval>" {
+        return Some(get_line(stream));
     }
-    return ret;
+    // The REPL only keeps the current line, which might not be the right one
+    fs::read_to_string(&stream.name).ok()?
+        .lines().nth(stream.line.checked_sub(1)?).map(|l| l.to_string())
 }
 
-pub fn get_builtins(args: &Arguments) -> &'static Vec<(String, i32)> {
-    unsafe {
-        static mut BUILTINS: Option<Vec<(String, i32)>> = None;
-        static mut EXTENDED: bool = false;
-        if BUILTINS.is_none() || EXTENDED != args.extension_functies {
-            EXTENDED = args.extension_functies;
-            BUILTINS = Some(_get_builtins(args));
-        }
-        return BUILTINS.as_mut().unwrap();
+// Shows the source line of a runtime error, span is (column, size)
+pub fn runtime_snippet(
+    name: &str, line: usize, span: Option<(usize, usize)>, color: bool
+) {
+    let mut stream = Stream{
+        name: name.to_string(), line, at: 0, rat: 0, size: 0
+    };
+    let Some(src) = try_get_line(&stream) else {
+        return;
+    };
+    if let Some((at, size)) = span.filter(|(at, size)| at + size <= src.len()) {
+        stream.at = at;
+        stream.size = size;
+    } else {
+        // Underline the whole line (without indentation)
+        let code = src.trim_start();
+        stream.at = src.len() - code.len();
+        stream.size = code.trim_end().chars().count();
     }
+    print_snippet(&stream, &src, if color { "\x1b[1;31m" } else { "" });
 }
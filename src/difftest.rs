@@ -0,0 +1,304 @@
+// `burlap difftest`, runs random programs on the VM and the tree walking evaluator
+// The evaluator is the reference, so anything printed differently is a compiler or VM bug
+use std::panic::{self, AssertUnwindSafe};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{to_ast, Arguments};
+use crate::common::{capture_output, print_err, ErrType};
+use crate::backend::tree::evaluator::Evaluator;
+use crate::backend::vm::compiler::{compile, Compiler};
+use crate::backend::vm::vm::{run, Vm};
+
+// Ops (or statements for the evaluator) a program can run, they should never need this many
+const FUEL: u64 = 1_000_000;
+
+// Makes random programs, every var is set before it's used and every loop ends
+struct Gen {
+    rng: StdRng,
+    // Vars that can be read, and if they can be changed (loop vars can't)
+    vars: Vec<(String, bool)>,
+    // Functis that can be called, with their number of args
+    functis: Vec<(String, usize)>,
+    // For unique names
+    count: usize,
+    in_functi: bool,
+    in_loop: bool,
+}
+
+impl Gen {
+    fn name(&mut self, prefix: &str) -> String {
+        self.count += 1;
+        format!("{}{}", prefix, self.count)
+    }
+
+    fn num(&mut self) -> String {
+        match self.rng.gen_range(0..10) {
+            0 => self.rng.gen_range(-100000..100000).to_string(),
+            1 => format!("{}.5", self.rng.gen_range(0..10)),
+            _ => self.rng.gen_range(0..10).to_string(),
+        }
+    }
+
+    fn atom(&mut self) -> String {
+        match self.rng.gen_range(0..10) {
+            0..=3 if !self.vars.is_empty() => {
+                let at = self.rng.gen_range(0..self.vars.len());
+                self.vars[at].0.clone()
+            },
+            4 => ["true", "false", "none", "\"s\""][self.rng.gen_range(0..4)].to_string(),
+            _ => self.num(),
+        }
+    }
+
+    fn expr(&mut self, depth: usize) -> String {
+        if depth == 0 || self.rng.gen_range(0..4) == 0 {
+            return self.atom();
+        }
+        match self.rng.gen_range(0..12) {
+            0 => format!("-({})", self.expr(depth - 1)),
+            1 => format!("!{}", self.expr(depth - 1)),
+            2 => format!("({} % {})", self.expr(depth - 1), self.rng.gen_range(1..10)),
+            3 => format!("({} / {})", self.expr(depth - 1), self.rng.gen_range(1..10)),
+            4 => {
+                let items: Vec<String> = (0..3).map(|_| self.expr(depth - 1)).collect();
+                format!("[{}][{}]", items.join(", "), self.rng.gen_range(0..3))
+            },
+            5 => {
+                let (l, a, b) = (self.expr(depth - 1), self.expr(depth - 1), self.expr(depth - 1));
+                format!("({} in [{}, {}])", l, a, b)
+            },
+            6 if !self.functis.is_empty() => {
+                let (name, argc) = self.functis[self.rng.gen_range(0..self.functis.len())].clone();
+                let args: Vec<String> = (0..argc).map(|_| self.expr(depth - 1)).collect();
+                format!("{}({})", name, args.join(", "))
+            },
+            _ => {
+                let ops = [
+                    "+", "-", "*", "==", "!=", "<", ">", "<=", ">=", "&&", "||", "^^",
+                ];
+                let op = ops[self.rng.gen_range(0..ops.len())];
+                format!("({} {} {})", self.expr(depth - 1), op, self.expr(depth - 1))
+            },
+        }
+    }
+
+    fn body(&mut self, depth: usize) -> String {
+        let vars = self.vars.len();
+        let mut ret = String::new();
+        for _ in 0..self.rng.gen_range(1..4) {
+            ret += &self.stmt(depth);
+        }
+        self.vars.truncate(vars);
+        return ret;
+    }
+
+    fn stmt(&mut self, depth: usize) -> String {
+        let assignable: Vec<String> = self.vars.iter()
+            .filter(|(_, can_set)| *can_set).map(|(name, _)| name.clone()).collect();
+        let choice = if depth == 0 { self.rng.gen_range(0..6) } else { self.rng.gen_range(0..11) };
+        match choice {
+            0 | 1 => format!("print({});\n", self.expr(3)),
+            2 | 3 if !assignable.is_empty() => {
+                let var = &assignable[self.rng.gen_range(0..assignable.len())];
+                let op = ["=", "+=", "-=", "*="][self.rng.gen_range(0..4)];
+                format!("{} {} {};\n", var, op, self.expr(2))
+            },
+            4 if !assignable.is_empty() => {
+                let var = &assignable[self.rng.gen_range(0..assignable.len())];
+                format!("{}{};\n", ["++", "--"][self.rng.gen_range(0..2)], var)
+            },
+            5 if self.in_loop => format!(
+                "if {} {{ {}; }}\n", self.expr(2), ["break", "continue"][self.rng.gen_range(0..2)]
+            ),
+            5 if self.in_functi => format!("if {} {{ return {}; }}\n", self.expr(2), self.expr(2)),
+            6 => {
+                let cond = self.expr(2);
+                let (body, else_body) = (self.body(depth - 1), self.body(depth - 1));
+                format!("if {} {{\n{}}} else {{\n{}}}\n", cond, body, else_body)
+            },
+            7 => {
+                // The counter is changed after the body, so continue can't be used
+                let i = self.name("i");
+                let max = self.rng.gen_range(0..5);
+                let in_loop = std::mem::replace(&mut self.in_loop, false);
+                self.vars.push((i.clone(), false));
+                let body = self.body(depth - 1);
+                self.vars.pop();
+                self.in_loop = in_loop;
+                format!("let {i} = 0;\nloop (while {i} < {max}) {{\n{body}{i} += 1;\n}}\n")
+            },
+            8 => {
+                let x = self.name("x");
+                let items: Vec<String> = (0..self.rng.gen_range(0..4)).map(|_| self.expr(2)).collect();
+                let in_loop = std::mem::replace(&mut self.in_loop, true);
+                self.vars.push((x.clone(), false));
+                let body = self.body(depth - 1);
+                self.vars.pop();
+                self.in_loop = in_loop;
+                format!("loop ({} in [{}]) {{\n{}}}\n", x, items.join(", "), body)
+            },
+            _ => {
+                let v = self.name("v");
+                let val = self.expr(3);
+                self.vars.push((v.clone(), true));
+                format!("let {} = {};\n", v, val)
+            },
+        }
+    }
+
+    fn program(&mut self) -> String {
+        let mut ret = String::new();
+        for _ in 0..self.rng.gen_range(1..4) {
+            let g = self.name("g");
+            ret += &format!("let {} = {};\n", g, self.expr(2));
+            self.vars.push((g, true));
+        }
+        let globals = self.vars.clone();
+        for _ in 0..self.rng.gen_range(0..4) {
+            let f = self.name("f");
+            let args: Vec<String> = (0..self.rng.gen_range(0..3)).map(|_| self.name("a")).collect();
+            self.vars.extend(args.iter().map(|a| (a.clone(), true)));
+            self.in_functi = true;
+            let body = self.body(2);
+            let ret_val = self.expr(2);
+            self.in_functi = false;
+            self.vars.truncate(globals.len());
+            ret += &format!("functi {}({}) {{\n{}return {};\n}}\n", f, args.join(", "), body, ret_val);
+            self.functis.push((f, args.len()));
+        }
+        for _ in 0..self.rng.gen_range(3..8) {
+            ret += &self.stmt(2);
+        }
+        // So changes to the globals are seen
+        for (g, _) in globals {
+            ret += &format!("print({});\n", g);
+        }
+        return ret;
+    }
+}
+
+fn get_args(args: &Arguments, source: &str) -> Arguments {
+    let mut args = args.clone();
+    args.source = source.to_string();
+    args.name = "<difftest>".to_string();
+    args.is_repl = false;
+    args.cache = false;
+    args.extension_color = false;
+    args.sandbox = true;
+    args.fuel = FUEL;
+    return args;
+}
+
+// What a program did
+struct Outcome {
+    out: String,
+    ok: bool,
+    // Only for the report, the evaluator's errors don't have to match
+    errors: Vec<String>,
+}
+
+impl Outcome {
+    fn new(out: String, ok: bool, errors: Vec<String>) -> Outcome {
+        Outcome { out, ok, errors }
+    }
+
+    fn same(&self, other: &Outcome) -> bool {
+        self.out == other.out && self.ok == other.ok
+    }
+
+    fn print(&self, name: &str) {
+        println!("--- {}{} ---\n{}", name, if self.ok { "" } else { " (error)" }, self.out);
+        for error in &self.errors {
+            println!("{}", error);
+        }
+    }
+}
+
+fn run_vm(args: &Arguments, source: &str, opt_level: u8) -> Option<Outcome> {
+    let mut args = get_args(args, source);
+    args.opt_level = opt_level;
+    capture_output(true);
+    let ok = to_ast(&mut args).and_then(|mut ast| {
+        let mut compiler = Compiler::new();
+        compiler.opt_level = args.opt_level;
+        if !compile(&mut ast, &Some(args.name.clone()), &mut compiler, false) {
+            return None;
+        }
+        let mut vm = Vm::new(args, compiler.program);
+        Some(run(&mut vm))
+    });
+    let captured = capture_output(false).unwrap_or_default();
+    return Some(Outcome::new(captured.out, ok?, captured.errors));
+}
+
+fn run_tree(args: &Arguments, source: &str) -> Option<Outcome> {
+    let mut args = get_args(args, source);
+    capture_output(true);
+    let ret = to_ast(&mut args).map(|ast| {
+        let mut eval = Evaluator::new(args, &ast);
+        eval.fuel = FUEL;
+        eval.run(&ast.nodes)
+    });
+    let mut captured = capture_output(false).unwrap_or_default();
+    let ok = ret?.map_err(|e| captured.errors.push(e)).is_ok();
+    return Some(Outcome::new(captured.out, ok, captured.errors));
+}
+
+pub fn difftest(cli_args: impl Iterator<Item = String>, args: &Arguments) -> bool {
+    let mut count: usize = 1000;
+    let mut seed: u64 = rand::thread_rng().gen();
+    let mut cli_args = cli_args;
+    while let Some(arg) = cli_args.next() {
+        if arg == "-n" || arg == "--seed" {
+            let Some(value) = cli_args.next().and_then(|v| v.parse::<u64>().ok()) else {
+                print_err(&format!("'{}' needs a number", arg), ErrType::Err, args.extension_color);
+                return false;
+            };
+            if arg == "-n" {
+                count = value as usize;
+            } else {
+                seed = value;
+            }
+        } else {
+            print_err(&format!("unknown argument: {}", arg), ErrType::Warn, args.extension_color);
+        }
+    }
+    let mut gen = Gen {
+        rng: StdRng::seed_from_u64(seed), vars: vec![], functis: vec![],
+        count: 0, in_functi: false, in_loop: false,
+    };
+    for at in 0..count {
+        (gen.vars, gen.functis, gen.count) = (vec![], vec![], 0);
+        let source = gen.program();
+        let Some(expected) = run_tree(args, &source) else {
+            print_err("a generated program has errors:", ErrType::Err, args.extension_color);
+            println!("{}", source);
+            return false;
+        };
+        // Every opt level should do the same thing
+        for opt_level in 0..=3 {
+            let got = panic::catch_unwind(AssertUnwindSafe(|| run_vm(args, &source, opt_level)));
+            if got.as_ref().is_ok_and(|got| got.as_ref().is_some_and(|got| got.same(&expected))) {
+                continue;
+            }
+            // It might have panicked while capturing
+            capture_output(false);
+            print_err(
+                &format!("program {} (seed {}, -O{}) is different:", at, seed, opt_level),
+                ErrType::Err, args.extension_color
+            );
+            println!("{}", source);
+            expected.print("expected");
+            match got {
+                Ok(Some(got)) => got.print("got"),
+                Ok(None) => println!("--- it didn't compile ---"),
+                Err(_) => println!("--- it panicked ---"),
+            }
+            return false;
+        }
+    }
+    println!("{} programs (seed {}), no differences", count, seed);
+    return true;
+}
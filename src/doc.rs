@@ -0,0 +1,175 @@
+use crate::common::{print_err, ErrType};
+use crate::parser::DocItem;
+use crate::{to_ast, Arguments};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Finds all the Sack files in a path
+pub fn find_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        let mut entries: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+        entries.sort();
+        for entry in entries {
+            find_files(&entry, files);
+        }
+    } else if path.extension().is_some_and(|e| e == "sk" || e == "sack") {
+        files.push(path.to_path_buf());
+    }
+}
+
+fn escape_html(str: &str) -> String {
+    str.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn to_markdown(name: &str, docs: &[DocItem]) -> String {
+    let mut ret = format!("# {}\n", name);
+    for item in docs {
+        ret += &format!("\n## `{}`\n", item.signature);
+        if !item.doc.is_empty() {
+            ret += &format!("\n{}\n", item.doc);
+        }
+    }
+    ret
+}
+
+fn to_html(name: &str, docs: &[DocItem]) -> String {
+    let mut ret = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n",
+        escape_html(name)
+    );
+    for item in docs {
+        ret += &format!(
+            "<h2 id=\"{}\"><code>{}</code></h2>\n",
+            escape_html(&item.name), escape_html(&item.signature)
+        );
+        // Blank lines split paragraphs
+        for para in item.doc.split("\n\n").filter(|p| !p.trim().is_empty()) {
+            ret += &format!("<p>{}</p>\n", escape_html(para.trim()));
+        }
+    }
+    ret + "</body>\n</html>\n"
+}
+
+// `burlap doc <files or dirs> [-o out] [--html]`, returns true on success
+pub fn doc(cli_args: impl Iterator<Item = String>, args: &Arguments) -> bool {
+    let mut out = PathBuf::from("docs");
+    let mut html = false;
+    let mut paths: Vec<PathBuf> = vec![];
+    let mut cli_args = cli_args;
+    while let Some(arg) = cli_args.next() {
+        if arg == "-o" || arg == "--output" {
+            let Some(dir) = cli_args.next() else {
+                print_err("'-o' is missing output directory", ErrType::Err, args.extension_color);
+                return false;
+            };
+            out = PathBuf::from(dir);
+        } else if arg == "--html" {
+            html = true;
+        } else if arg == "--markdown" {
+            html = false;
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+    // Find files
+    let mut files = vec![];
+    for path in &paths {
+        if !path.exists() {
+            print_err(
+                &format!("no such file or directory: {}", path.display()),
+                ErrType::Err, args.extension_color
+            );
+            return false;
+        }
+        // Files are named relative to the dir they were found in
+        let mut found = vec![];
+        find_files(path, &mut found);
+        for file in found {
+            let rel = if path.is_dir() {
+                file.strip_prefix(path).unwrap().to_path_buf()
+            } else {
+                PathBuf::from(file.file_name().unwrap())
+            };
+            files.push((file, rel));
+        }
+    }
+    if let Err(err) = fs::create_dir_all(&out) {
+        print_err(
+            &format!("failed to create {}: {}", out.display(), err),
+            ErrType::Err, args.extension_color
+        );
+        return false;
+    }
+    let mut ok = true;
+    let mut index = vec![];
+    for (file, rel) in files {
+        let Ok(src) = fs::read_to_string(&file) else {
+            print_err(
+                &format!("failed to open file: {}", file.display()),
+                ErrType::Err, args.extension_color
+            );
+            ok = false;
+            continue;
+        };
+        // Parse
+        let mut file_args = args.clone();
+        file_args.source = src;
+        file_args.name = file.to_string_lossy().to_string();
+        file_args.path = file.clone();
+        file_args.is_repl = false;
+        let Some(ast) = to_ast(&mut file_args) else {
+            ok = false;
+            continue;
+        };
+        // Only document the file itself, not its imports
+        let docs: Vec<DocItem> = ast.docs.into_iter()
+            .filter(|i| i.file == file_args.name).collect();
+        // Pages are named after the path, so files in different dirs don't clash
+        let title = rel.to_string_lossy().to_string();
+        let name = rel.with_extension("").to_string_lossy().replace(['/', '\\'], "_");
+        let (text, ext) = if html {
+            (to_html(&title, &docs), "html")
+        } else {
+            (to_markdown(&title, &docs), "md")
+        };
+        let page = format!("{}.{}", name, ext);
+        if let Err(err) = fs::write(out.join(&page), text) {
+            print_err(
+                &format!("failed to write {}: {}", page, err),
+                ErrType::Err, args.extension_color
+            );
+            ok = false;
+            continue;
+        }
+        index.push((title, page));
+    }
+    // Index of all the pages
+    let (text, ext) = if html {
+        let mut ret = "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index</title></head>\n<body>\n<h1>Index</h1>\n<ul>\n".to_string();
+        for (name, page) in &index {
+            ret += &format!("<li><a href=\"{}\">{}</a></li>\n", page, escape_html(name));
+        }
+        (ret + "</ul>\n</body>\n</html>\n", "html")
+    } else {
+        let mut ret = "# Index\n\n".to_string();
+        for (name, page) in &index {
+            ret += &format!("- [{}]({})\n", name, page);
+        }
+        (ret, "md")
+    };
+    if let Err(err) = fs::write(out.join(format!("index.{}", ext)), text) {
+        print_err(
+            &format!("failed to write index: {}", err), ErrType::Err,
+            args.extension_color
+        );
+        return false;
+    }
+    ok
+}
@@ -5,6 +5,9 @@ use crate::parser::ASTNode::*;
 
 use std::rc::Rc;
 
+// The longest string a repeat is folded into
+const MAX_FOLDED_STR: usize = 4096;
+
 pub fn is_const_expr(node: &ASTNode) -> bool {
     // Very basic literals
     if let StringExpr(_) | NumberExpr(_) | DecimalExpr(_) | BoolExpr(_) | ByteExpr(_) | NoneExpr = node {
@@ -68,6 +71,33 @@ pub fn fold_binop(lhs: &ASTNode, op: TokenType, rhs: &ASTNode) -> Option<ASTNode
     // To values
     let lv = to_value(lhs)?;
     let rv = to_value(rhs)?;
+    // Leave implicit conversions to the VM, so strict mode can catch them
+    let is_str = |v: &Value| matches!(v, Value::Str(_));
+    let is_num = |v: &Value| matches!(v, Value::Int(_) | Value::Float(_) | Value::Byte(_));
+    match op {
+        TokenType::Plus if is_str(&lv) != is_str(&rv) => return None,
+        TokenType::Lt | TokenType::Gt | TokenType::LtEquals | TokenType::GtEquals
+            if !is_num(&lv) || !is_num(&rv) => return None,
+        _ => {},
+    }
+    // So do big string repeats, so --max-size can catch them (and the consts stay small)
+    if let (TokenType::Times, Value::Str(s), Value::Int(n)) = (&op, &lv, &rv) {
+        if s.len().saturating_mul((*n).max(0) as usize) > MAX_FOLDED_STR {
+            return None;
+        }
+    }
+    // Overflows depend on the overflow mode, so leave them to the VM too
+    if let (Value::Int(l), Value::Int(r)) = (&lv, &rv) {
+        let overflows = match op {
+            TokenType::Plus => l.checked_add(*r).is_none(),
+            TokenType::Minus => l.checked_sub(*r).is_none(),
+            TokenType::Times => l.checked_mul(*r).is_none(),
+            _ => false,
+        };
+        if overflows {
+            return None;
+        }
+    }
     Some(match op {
         // binop_math1
         TokenType::Plus => {
@@ -109,10 +139,10 @@ pub fn fold_binop(lhs: &ASTNode, op: TokenType, rhs: &ASTNode) -> Option<ASTNode
             ASTNode::BoolExpr(lv.to_float() > rv.to_float())
         },
         TokenType::LtEquals => {
-            ASTNode::BoolExpr(!(lv.to_float() > rv.to_float()))
+            ASTNode::BoolExpr(lv.to_float() <= rv.to_float())
         },
         TokenType::GtEquals => {
-            ASTNode::BoolExpr(!(lv.to_float() < rv.to_float()))
+            ASTNode::BoolExpr(lv.to_float() >= rv.to_float())
         },
         // binop_logic
         TokenType::And => {
@@ -140,7 +170,7 @@ pub fn fold_binop(lhs: &ASTNode, op: TokenType, rhs: &ASTNode) -> Option<ASTNode
 
 pub fn fold_node(node: &ASTNode) -> Option<ASTNode> {
     Some(match node {
-        BinopExpr(lhs, op, rhs) => {
+        BinopExpr(lhs, op, rhs, _) => {
             let new_lhs = fold_node(lhs)?;
             let new_rhs = fold_node(rhs)?;
             return fold_binop(&fold_node(&new_lhs)?, op.clone(), &fold_node(&new_rhs)?);
@@ -0,0 +1,76 @@
+// Entry points for fuzzing (see fuzz/), none of them should ever panic
+// Programs run without system builtins and with limited fuel, and nothing is printed
+use crate::{to_ast, Arguments};
+use crate::common::capture_output;
+use crate::lexer::lex as lex_code;
+use crate::backend::vm::compiler::{compile as compile_ast, Compiler};
+use crate::backend::vm::vm::{run as run_vm, Vm};
+
+// Ops a program can run before it's stopped
+const FUEL: u64 = 100_000;
+
+fn get_args(data: &[u8]) -> Option<Arguments> {
+    let mut args = Arguments::new();
+    args.source = std::str::from_utf8(data).ok()?.to_string();
+    args.name = "<fuzz>".to_string();
+    args.is_repl = false;
+    args.cache = false;
+    args.extension_color = false;
+    args.sandbox = true;
+    args.fuel = FUEL;
+    // Small enough that the frames don't take much memory
+    args.max_call_depth = 200;
+    // And strings and lists
+    args.max_size = 1_000_000;
+    return Some(args);
+}
+
+// Errors are captured so they aren't printed, the output is thrown away
+fn quietly<T>(f: impl FnOnce() -> T) -> T {
+    capture_output(true);
+    let ret = f();
+    capture_output(false);
+    return ret;
+}
+
+pub fn lex(data: &[u8]) {
+    let Some(args) = get_args(data) else {
+        return;
+    };
+    quietly(|| lex_code(&args.source, args.name.clone(), true, false));
+}
+
+pub fn parse(data: &[u8]) {
+    let Some(mut args) = get_args(data) else {
+        return;
+    };
+    quietly(|| to_ast(&mut args));
+}
+
+pub fn compile(data: &[u8]) {
+    let Some(mut args) = get_args(data) else {
+        return;
+    };
+    quietly(|| {
+        let mut ast = to_ast(&mut args)?;
+        let mut compiler = Compiler::new();
+        compile_ast(&mut ast, &Some(args.name.clone()), &mut compiler, false).then_some(())
+    });
+}
+
+pub fn run(data: &[u8]) {
+    let Some(mut args) = get_args(data) else {
+        return;
+    };
+    quietly(|| {
+        let mut ast = to_ast(&mut args)?;
+        let mut compiler = Compiler::new();
+        if !compile_ast(&mut ast, &Some(args.name.clone()), &mut compiler, false) {
+            return None;
+        }
+        let mut vm = Vm::new(args, compiler.program);
+        run_vm(&mut vm);
+        vm.remove_temps();
+        Some(())
+    });
+}
@@ -0,0 +1,351 @@
+// `burlap kernel --connection-file <file>`, a Jupyter kernel
+// `burlap kernel --install` tells Jupyter about it
+use std::fs;
+use std::env;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value as Json};
+use sha2::Sha256;
+
+use crate::Arguments;
+use crate::common::{capture_output, print_err, stream_output, ErrType};
+use crate::lexer::lex;
+use crate::parser::needs_more_input;
+use crate::remote::{new_session, run_in_session};
+use crate::zmtp::{self, write_message, Message, Publisher};
+use crate::backend::value::Value;
+use crate::backend::vm::vm::Vm;
+
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    hex.chunks(2).map(|pair| {
+        u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()
+    }).collect()
+}
+
+// Makes and checks messages, it's cloned into the print callback
+#[derive(Clone)]
+struct Signer {
+    key: Vec<u8>,
+    session: String,
+}
+
+impl Signer {
+    fn mac(&self, parts: &[Vec<u8>]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).unwrap();
+        for part in parts {
+            mac.update(part);
+        }
+        mac
+    }
+
+    fn encode(&self, ids: &[Vec<u8>], msg_type: &str, parent: &Json, content: Json) -> Message {
+        let header = json!({
+            "msg_id": format!("{:032x}", rand::random::<u128>()),
+            "session": self.session,
+            "username": "burlap",
+            "date": Utc::now().to_rfc3339(),
+            "msg_type": msg_type,
+            "version": "5.3",
+        });
+        let parts = vec![
+            header.to_string().into_bytes(), parent.to_string().into_bytes(),
+            b"{}".to_vec(), content.to_string().into_bytes(),
+        ];
+        let signature = if self.key.is_empty() {
+            vec![]
+        } else {
+            to_hex(&self.mac(&parts).finalize().into_bytes()).into_bytes()
+        };
+        let mut frames = ids.to_vec();
+        frames.push(DELIMITER.to_vec());
+        frames.push(signature);
+        frames.extend(parts);
+        frames
+    }
+
+    // Gets (identities, header, content), None if it's invalid or badly signed
+    fn decode(&self, mut frames: Message) -> Option<(Message, Json, Json)> {
+        let at = frames.iter().position(|frame| frame == DELIMITER)?;
+        let mut rest = frames.split_off(at + 1);
+        if rest.len() < 5 {
+            return None;
+        }
+        let parts = rest.drain(1..5).collect::<Vec<_>>();
+        if !self.key.is_empty() {
+            self.mac(&parts).verify_slice(&from_hex(&rest[0])?).ok()?;
+        }
+        frames.pop();
+        let header = serde_json::from_slice(&parts[0]).ok()?;
+        let content = serde_json::from_slice(&parts[3]).ok()?;
+        Some((frames, header, content))
+    }
+}
+
+// Lists are shown as tables
+fn to_html(val: &Value) -> Option<String> {
+    let rows: Vec<(String, String)> = if let Value::List(list) = val {
+        list.iter().map(|(k, v)| (k.pretty(false), v.pretty(false))).collect()
+    } else {
+        val.values()?.iter().enumerate().map(|(i, v)| (i.to_string(), v.pretty(false))).collect()
+    };
+    let escape = |s: &str| s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let mut html = "<table>".to_string();
+    for (key, val) in rows {
+        html += &format!("<tr><th>{}</th><td>{}</td></tr>", escape(&key), escape(&val));
+    }
+    Some(html + "</table>")
+}
+
+struct Kernel {
+    signer: Signer,
+    iopub: Publisher,
+    vm: Vm,
+    execution_count: u32,
+}
+
+impl Kernel {
+    fn publish(&self, msg_type: &str, parent: &Json, content: Json) {
+        self.iopub.send(&self.signer.encode(&[], msg_type, parent, content));
+    }
+
+    fn execute(&mut self, parent: &Json, content: &Json) -> Json {
+        let code = content["code"].as_str().unwrap_or("");
+        let silent = content["silent"].as_bool().unwrap_or(false);
+        if !silent {
+            self.execution_count += 1;
+        }
+        let count = self.execution_count;
+        self.publish("execute_input", parent, json!({"code": code, "execution_count": count}));
+        // Prints are sent as they happen
+        let (signer, iopub, print_parent) = (self.signer.clone(), self.iopub.clone(), parent.clone());
+        stream_output(Box::new(move |line| {
+            let content = json!({"name": "stdout", "text": line.to_string() + "\n"});
+            iopub.send(&signer.encode(&[], "stream", &print_parent, content));
+        }));
+        let ret = run_in_session(&mut self.vm, code);
+        // exit() only ends the cell
+        self.vm.take_exit_code();
+        let mut errors = capture_output(false).map(|c| c.errors).unwrap_or_default();
+        let ret = match ret {
+            Ok(ret) => ret,
+            Err(e) => {
+                // Printed errors say more
+                if errors.is_empty() {
+                    errors.push(e);
+                }
+                let ename = if errors[0].starts_with("Runtime Error") {
+                    "RuntimeError"
+                } else {
+                    "CompileError"
+                };
+                let error = json!({"ename": ename, "evalue": errors[0], "traceback": errors});
+                self.publish("error", parent, error.clone());
+                let mut reply = json!({"status": "error", "execution_count": count});
+                reply.as_object_mut().unwrap().extend(error.as_object().unwrap().clone());
+                return reply;
+            },
+        };
+        // Warnings
+        if !errors.is_empty() {
+            let text = errors.join("\n") + "\n";
+            self.publish("stream", parent, json!({"name": "stderr", "text": text}));
+        }
+        if ret != Value::None && !silent {
+            let mut data = json!({"text/plain": ret.pretty(false)});
+            if let Some(html) = to_html(&ret) {
+                data["text/html"] = json!(html);
+            }
+            self.publish("execute_result", parent, json!({
+                "execution_count": count, "data": data, "metadata": {},
+            }));
+        }
+        json!({
+            "status": "ok", "execution_count": count, "user_expressions": {}, "payload": [],
+        })
+    }
+
+    fn complete(&self, content: &Json) -> Json {
+        let code: Vec<char> = content["code"].as_str().unwrap_or("").chars().collect();
+        let end = (content["cursor_pos"].as_u64().unwrap_or(0) as usize).min(code.len());
+        let mut start = end;
+        while start > 0 && (code[start - 1].is_alphanumeric() || code[start - 1] == '_') {
+            start -= 1;
+        }
+        let prefix: String = code[start..end].iter().collect();
+        let mut symbols = self.vm.get_symbols(true);
+        if let Some(ast) = &self.vm.ast {
+            symbols.extend(ast.all_vars.iter().map(|var| var.name.clone()));
+        }
+        let mut matches: Vec<String> = symbols.into_iter()
+            .filter(|s| s.starts_with(&prefix) && !s.contains('$')).collect();
+        matches.sort();
+        matches.dedup();
+        json!({
+            "status": "ok", "matches": matches, "cursor_start": start, "cursor_end": end,
+            "metadata": {},
+        })
+    }
+
+    fn kernel_info(&self) -> Json {
+        json!({
+            "status": "ok",
+            "protocol_version": "5.3",
+            "implementation": "burlap",
+            "implementation_version": env!("CARGO_PKG_VERSION"),
+            "language_info": {
+                "name": "sack",
+                "version": env!("CARGO_PKG_VERSION"),
+                "mimetype": "text/x-sack",
+                "file_extension": ".sk",
+            },
+            "banner": format!("Burlap v{}", env!("CARGO_PKG_VERSION")),
+            "help_links": [],
+        })
+    }
+
+    // Replies to a request, false if the kernel should stop
+    fn handle(&mut self, stream: &mut TcpStream, frames: Message) -> bool {
+        let Some((ids, header, content)) = self.signer.decode(frames) else {
+            print_err("got an invalid message", ErrType::Warn, false);
+            return true;
+        };
+        let msg_type = header["msg_type"].as_str().unwrap_or("").to_string();
+        self.publish("status", &header, json!({"execution_state": "busy"}));
+        let reply = match msg_type.as_str() {
+            "kernel_info_request" => Some(self.kernel_info()),
+            "execute_request" => Some(self.execute(&header, &content)),
+            "complete_request" => Some(self.complete(&content)),
+            "is_complete_request" => {
+                let code = content["code"].as_str().unwrap_or("");
+                let tokens = lex(code, "<cell>".to_string(), false, false).unwrap_or(vec![]);
+                Some(if needs_more_input(&tokens) {
+                    json!({"status": "incomplete", "indent": "    "})
+                } else {
+                    json!({"status": "complete"})
+                })
+            },
+            "inspect_request" => Some(json!({
+                "status": "ok", "found": false, "data": {}, "metadata": {},
+            })),
+            "history_request" => Some(json!({"status": "ok", "history": []})),
+            "comm_info_request" => Some(json!({"status": "ok", "comms": {}})),
+            "shutdown_request" => Some(json!({
+                "status": "ok", "restart": content["restart"].as_bool().unwrap_or(false),
+            })),
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            let reply_type = msg_type.replace("_request", "_reply");
+            let _ = write_message(stream, &self.signer.encode(&ids, &reply_type, &header, reply));
+        }
+        self.publish("status", &header, json!({"execution_state": "idle"}));
+        msg_type != "shutdown_request"
+    }
+}
+
+// Writes kernel.json to where Jupyter looks for kernels
+fn install() -> Result<PathBuf, String> {
+    let dir = if let Ok(dir) = env::var("JUPYTER_DATA_DIR") {
+        PathBuf::from(dir)
+    } else if cfg!(windows) {
+        PathBuf::from(env::var("APPDATA").map_err(|_| "APPDATA isn't set")?).join("jupyter")
+    } else {
+        let home = PathBuf::from(env::var("HOME").map_err(|_| "HOME isn't set")?);
+        if cfg!(target_os = "macos") {
+            home.join("Library/Jupyter")
+        } else {
+            home.join(".local/share/jupyter")
+        }
+    }.join("kernels/burlap");
+    let exe = env::current_exe().map_err(|e| format!("failed to find burlap: {}", e))?;
+    let spec = json!({
+        "argv": [exe, "kernel", "--connection-file", "{connection_file}"],
+        "display_name": "Sack (Burlap)",
+        "language": "sack",
+    });
+    fs::create_dir_all(&dir).map_err(|e| format!("failed to make {}: {}", dir.display(), e))?;
+    let file = dir.join("kernel.json");
+    fs::write(&file, serde_json::to_string_pretty(&spec).unwrap())
+        .map_err(|e| format!("failed to write {}: {}", file.display(), e))?;
+    Ok(file)
+}
+
+fn run_kernel(connection_file: &str, args: &Arguments) -> Result<(), String> {
+    let info: Json = serde_json::from_str(
+        &fs::read_to_string(connection_file)
+            .map_err(|e| format!("failed to open {}: {}", connection_file, e))?
+    ).map_err(|e| format!("invalid connection file: {}", e))?;
+    let key = info["key"].as_str().unwrap_or("").as_bytes().to_vec();
+    let scheme = info["signature_scheme"].as_str().unwrap_or("hmac-sha256");
+    if !key.is_empty() && scheme != "hmac-sha256" {
+        return Err(format!("unsupported signature scheme: {}", scheme));
+    }
+    if info["transport"].as_str().unwrap_or("tcp") != "tcp" {
+        return Err("only the tcp transport is supported".to_string());
+    }
+    let ip = info["ip"].as_str().unwrap_or("127.0.0.1");
+    let addr = |port: &str| format!("{}:{}", ip, info[port].as_u64().unwrap_or(0));
+    let bind_err = |e: std::io::Error| format!("failed to listen: {}", e);
+    // Shell and control are handled the same way
+    let (send, requests) = channel();
+    zmtp::router(&addr("shell_port"), send.clone()).map_err(bind_err)?;
+    zmtp::router(&addr("control_port"), send).map_err(bind_err)?;
+    // input() doesn't ask the frontend, so nothing is read from stdin
+    zmtp::router(&addr("stdin_port"), channel().0).map_err(bind_err)?;
+    zmtp::echo(&addr("hb_port")).map_err(bind_err)?;
+    let iopub = Publisher::bind(&addr("iopub_port")).map_err(bind_err)?;
+    let vm = new_session(args, "<cell>").ok_or("failed to start")?;
+    let signer = Signer {
+        key, session: format!("{:032x}", rand::random::<u128>()),
+    };
+    let mut kernel = Kernel { signer, iopub, vm, execution_count: 0 };
+    for (mut stream, frames) in requests {
+        if !kernel.handle(&mut stream, frames) {
+            break;
+        }
+    }
+    kernel.vm.run_exit_hooks();
+    Ok(())
+}
+
+pub fn kernel(cli_args: impl Iterator<Item = String>, args: &Arguments) -> bool {
+    let mut connection_file = None;
+    let mut cli_args = cli_args;
+    while let Some(arg) = cli_args.next() {
+        if arg == "--install" {
+            return match install() {
+                Ok(file) => {
+                    println!("Installed the kernel to {}", file.display());
+                    true
+                },
+                Err(e) => {
+                    print_err(&e, ErrType::Err, args.extension_color);
+                    false
+                },
+            };
+        } else if arg == "--connection-file" || arg == "-f" {
+            connection_file = cli_args.next();
+        } else {
+            print_err(&format!("unknown argument: {}", arg), ErrType::Warn, args.extension_color);
+        }
+    }
+    let Some(connection_file) = connection_file else {
+        print_err("'kernel' is missing '--connection-file <file>'", ErrType::Err, args.extension_color);
+        return false;
+    };
+    if let Err(e) = run_kernel(&connection_file, args) {
+        print_err(&e, ErrType::Err, args.extension_color);
+        return false;
+    }
+    true
+}
@@ -1,6 +1,15 @@
 use logos::Logos;
 
-use crate::common::{err, ErrType, Stream};
+use crate::common::{allow_warning, err, ErrType, Stream};
+
+// Number literals can have _ between digits, hex and octal are 32 bit patterns
+fn parse_int(slice: &str, radix: u32) -> Option<i32> {
+    let digits = slice.replace('_', "");
+    if radix == 10 {
+        return digits.parse().ok();
+    }
+    return u32::from_str_radix(&digits[2..], radix).ok().map(|num| num as i32);
+}
 
 #[derive(Logos, Debug, PartialEq, Clone)]
 #[logos(subpattern numbers = r"[0-9]((_?[0-9])*)?")]
@@ -9,8 +18,10 @@ pub enum TokenType {
     // Special tokens
     Invalid,
     Eof,
-    #[regex(r"(#[^\n]*)|([ \t\f\r]+)")]
+    #[regex(r"[ \t\f\r]+")]
     Skipped,
+    #[regex(r"#[^\n]*", |lex| lex.slice()[1..].to_string())]
+    Comment(String),
     #[token("\n")]
     Newline,
     // Literals/data types
@@ -23,9 +34,13 @@ pub enum TokenType {
         "\"[^\"]*\"", |lex| lex.slice()[1..lex.slice().len()-1].to_string()
     )]
     Str(String),
-    #[regex(r"(?&numbers)", |lex| lex.slice().parse().ok())]
+    #[regex(r"(?&numbers)", |lex| parse_int(lex.slice(), 10))]
+    #[regex(r"0[xX][0-9a-fA-F]((_?[0-9a-fA-F])*)?", |lex| parse_int(lex.slice(), 16))]
+    #[regex(r"0o[0-7]((_?[0-7])*)?", |lex| parse_int(lex.slice(), 8))]
     Int(i32),
-    #[regex(r"(?&numbers)\.(?&numbers)", |lex| lex.slice().parse().ok())]
+    #[regex(r"(?&numbers)\.(?&numbers)", |lex| lex.slice().replace('_', "").parse().ok())]
+    #[token("inf", |_| f32::INFINITY)]
+    #[token("nan", |_| f32::NAN)]
     Float(f32),
     #[regex("(true)|(false)", |lex| lex.slice() == "true")]
     Bool(bool),
@@ -66,9 +81,15 @@ pub enum TokenType {
     PlusPlus,
     #[token("--")]
     MinusMinus,
+    #[token("->")]
+    Arrow,
+    #[token("?")]
+    Question,
     // Keywords
     #[regex("func(ti)?", |lex| lex.slice() == "functi")]
     Func(bool),
+    #[token("fn")]
+    Lambda,
     #[token("let")]
     Let,
     #[token("return")]
@@ -89,6 +110,14 @@ pub enum TokenType {
     Break,
     #[token("import")]
     Import,
+    #[token("export")]
+    Export,
+    #[token("defer")]
+    Defer,
+    #[token("async")]
+    Async,
+    #[token("await")]
+    Await,
     // Basic operators
     #[token("+")]
     Plus,
@@ -100,6 +129,11 @@ pub enum TokenType {
     Div,
     #[token("%")]
     Modulo,
+    // Bitwise and set operators
+    #[token("|")]
+    BitOr,
+    #[token("&")]
+    BitAnd,
     // Basic Operators =
     #[token("+=")]
     PlusEquals,
@@ -118,6 +152,8 @@ pub enum TokenType {
     Or,
     #[token("^^")]
     Xor,
+    #[token("|>")]
+    Pipe,
     #[token("!")]
     Not,
     // Comparison operators
@@ -149,10 +185,27 @@ impl std::fmt::Debug for Token {
     }
 }
 
+// Handles `# burlap: allow(name, ...)` comments
+fn allow_comment(comment: &str, file: &str) {
+    let Some(names) = comment.trim().strip_prefix("burlap:")
+        .and_then(|c| c.trim().strip_prefix("allow("))
+        .and_then(|c| c.trim_end().strip_suffix(')'))
+    else {
+        return;
+    };
+    for name in names.split(',') {
+        allow_warning(Some(file), name.trim());
+    }
+}
+
 pub fn lex(
     src: &str, name: String, print_err: bool, color: bool
 ) -> Option<Vec<Token>> {
     let mut lex = TokenType::lexer(src);
+    // Skip the shebang so scripts can be run directly
+    if src.starts_with("#!") {
+        lex.bump(src.find('\n').unwrap_or(src.len()));
+    }
     let mut ret: Vec<Token> = vec![];
     // Lines
     let lines = src.lines().collect::<Vec<&str>>();
@@ -192,8 +245,15 @@ pub fn lex(
             if let TokenType::Str(ref str) = token {
                 // Lines can have multiple lines in them
                 let newlines = str.matches("\n").count();
-                stream.line += newlines;
-                lastat = lex.span().start + newlines;
+                if newlines != 0 {
+                    stream.line += newlines;
+                    lastat = lex.span().start + lex.slice().rfind('\n').unwrap() + 1;
+                }
+            }
+            if let TokenType::Comment(ref comment) = token {
+                if print_err {
+                    allow_comment(comment, &stream.name);
+                }
             }
             if let TokenType::Newline | TokenType::Skipped = token {
                 if print_err {
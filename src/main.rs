@@ -4,6 +4,8 @@ pub mod backend;
 pub mod lexer;
 pub mod parser;
 pub mod folding;
+#[cfg(not(target_family = "wasm"))]
+pub mod fuzz;
 
 #[macro_use]
 extern crate impl_ops;
@@ -14,10 +16,16 @@ use std::path::PathBuf;
 #[path = ""]
 mod cfg_mod {
     pub mod common;
-    #[cfg(feature = "cffi")]
-    pub mod cffi;
     #[cfg(feature = "repl")]
     pub mod repl;
+    pub mod doc;
+    pub mod remote;
+    pub mod difftest;
+    pub mod snapshot;
+    #[cfg(feature = "jupyter")]
+    pub mod kernel;
+    #[cfg(feature = "jupyter")]
+    pub mod zmtp;
 
     pub use std::fs;
     pub use std::env;
@@ -25,10 +33,25 @@ mod cfg_mod {
 
     #[cfg(feature = "repl")]
     pub use crate::repl::repl;
-    pub use crate::common::{print_err, ErrType};
+    pub use crate::doc::doc;
+    pub use crate::remote::remote_repl;
+    pub use crate::difftest::difftest;
+    pub use crate::snapshot::{bytecode_test, output_test};
+    #[cfg(feature = "jupyter")]
+    pub use crate::kernel::kernel;
+    pub use crate::common::{
+        allow_warning, collect_errors, print_err, set_deny_warnings, set_json_errors, warned,
+        ErrType, LOG_LEVELS
+    };
 }
 #[cfg(not(target_family = "wasm"))]
 use crate::backend::vm::dis::dis;
+#[cfg(not(target_family = "wasm"))]
+use crate::backend::vm::cache;
+#[cfg(not(target_family = "wasm"))]
+use crate::backend::vm::bundle::{self, bundle};
+#[cfg(not(target_family = "wasm"))]
+use crate::backend::c::compile_cmd;
 
 #[cfg(target_family = "wasm")]
 #[path = ""]
@@ -44,6 +67,9 @@ use crate::lexer::lex;
 use crate::parser::{parse, AST};
 use crate::backend::vm::compiler::{compile, Compiler};
 use crate::backend::vm::vm::{run, Vm};
+use crate::backend::value::Overflow;
+
+pub use crate::backend::vm::compiler::Program;
 
 #[derive(Clone, Default)]
 pub struct Arguments {
@@ -52,14 +78,33 @@ pub struct Arguments {
     path: PathBuf,
     is_debug: bool,
     is_repl: bool,
+    // Print the value of the last expression (for -e)
+    print_result: bool,
     backtrace: bool,
     dis: bool,
+    strict: bool,
+    overflow: Overflow,
+    // Index into LOG_LEVELS, lower logs are ignored
+    log_level: usize,
+    // Use the bytecode cache
+    cache: bool,
+    // How much the compiler optimizes (0 is none)
+    opt_level: u8,
+    // Max number of nested calls (0 is unlimited)
+    max_call_depth: usize,
+    // Max number of ops to run (0 is unlimited)
+    fuel: u64,
+    // Max bytes in a string or items in a list (0 is unlimited)
+    max_size: usize,
+    // Leave out builtins that touch the system or wait for input (for fuzzing)
+    sandbox: bool,
     // Extensions
     extension_color: bool,
     extension_auto_none: bool,
     extension_functies: bool,
     extension_va_print: bool,
     extension_debugging_functies: bool,
+    extension_total_order: bool,
     // Arguments to the program itself
     program_args: Vec<String>
 }
@@ -67,8 +112,10 @@ pub struct Arguments {
 impl Arguments {
     pub fn new() -> Arguments {
         Arguments {
-            source: "".to_string(), is_debug: false, dis: false,
-            is_repl: true, name: "<stdin>".to_string(),
+            source: "".to_string(), is_debug: false, dis: false, strict: false,
+            overflow: Overflow::Wrap, log_level: 1, cache: true, opt_level: 1,
+            max_call_depth: 10000, fuel: 0, max_size: 100_000_000, sandbox: false,
+            is_repl: true, print_result: false, name: "<stdin>".to_string(),
             backtrace: false, program_args: vec![],
             path: PathBuf::from("."),
             // Extensions
@@ -77,8 +124,50 @@ impl Arguments {
             extension_functies: false,
             extension_va_print: false,
             extension_debugging_functies: false,
+            extension_total_order: false,
         }
     }
+
+    // For compile_str, errors name the file and imports are next to it
+    pub fn with_name(mut self, name: &str) -> Arguments {
+        self.name = name.to_string();
+        self.path = PathBuf::from(name);
+        self.is_repl = false;
+        self
+    }
+
+    pub fn with_opt_level(mut self, opt_level: u8) -> Arguments {
+        self.opt_level = opt_level;
+        self
+    }
+
+    // Turns on an extension by its `--use-` name, false if there isn't one
+    pub fn use_extension(&mut self, extension: &str) -> bool {
+        match extension {
+            "all" => {
+                self.extension_color = true;
+                self.extension_auto_none = true;
+                self.extension_functies = true;
+                self.extension_va_print = true;
+                self.extension_debugging_functies = true;
+                self.extension_total_order = true;
+            },
+            "color" => self.extension_color = true,
+            "auto-none" => self.extension_auto_none = true,
+            "burlap-extensions" => self.extension_functies = true,
+            "va-print" => self.extension_va_print = true,
+            "more-debug" => self.extension_debugging_functies = true,
+            "total-order" => self.extension_total_order = true,
+            _ => return false,
+        }
+        return true;
+    }
+}
+
+// Everything compile_str printed, like "file.sk:1:4: error: msg"
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+    pub messages: Vec<String>,
 }
 
 pub fn to_ast(args: &mut Arguments) -> Option<AST> {
@@ -92,6 +181,24 @@ pub fn to_ast(args: &mut Arguments) -> Option<AST> {
     return parse(AST::new(), tokens, args);
 }
 
+// Compiles code without running it, for tools that use burlap as a library
+#[cfg(not(target_family = "wasm"))]
+pub fn compile_str(source: &str, options: &Arguments) -> Result<Program, Diagnostics> {
+    let mut args = options.clone();
+    args.source = source.to_string();
+    args.is_repl = false;
+    // Errors are given back instead of printed, only this call's are kept
+    let (program, messages) = collect_errors(|| to_ast(&mut args).and_then(|mut ast| {
+        let mut compiler = Compiler::new();
+        compiler.opt_level = args.opt_level;
+        compiler.program.path = args.path.clone();
+        compiler.program.path.pop();
+        compile(&mut ast, &Some(args.name.clone()), &mut compiler, false)
+            .then_some(compiler.program)
+    }));
+    return program.ok_or(Diagnostics { messages });
+}
+
 #[cfg(not(target_family = "wasm"))]
 fn get_args() -> Result<Arguments, bool> {
     let mut args = Arguments::new();
@@ -101,7 +208,46 @@ fn get_args() -> Result<Arguments, bool> {
     // Skip first arg
     cli_args.next();
     while let Some(arg) = cli_args.next() {
-        if !arg.starts_with('-') && file.is_empty() {
+        if arg == "run" && file.is_empty() {
+            // `burlap run file.sk` is the same as `burlap file.sk`
+            continue;
+        } else if arg == "doc" && file.is_empty() {
+            // Documentation generator
+            return Err(doc(cli_args, &args));
+        } else if arg == "repl" && file.is_empty() {
+            // `burlap repl` is the normal REPL, with args it's the remote one
+            let rest: Vec<String> = cli_args.by_ref().collect();
+            if rest.is_empty() {
+                break;
+            }
+            return Err(remote_repl(rest.into_iter(), &args));
+        } else if arg == "kernel" && file.is_empty() {
+            // Jupyter
+            #[cfg(feature = "jupyter")]
+            {
+                return Err(kernel(cli_args, &args));
+            }
+            #[cfg(not(feature = "jupyter"))]
+            {
+                println!("You don't have the Jupyter kernel enabled!");
+                return Err(false);
+            }
+        } else if arg == "difftest" && file.is_empty() {
+            // Checking the VM against the tree walking evaluator
+            return Err(difftest(cli_args, &args));
+        } else if arg == "bytecode-test" && file.is_empty() {
+            // Checking the compiler output against snapshots
+            return Err(bytecode_test(cli_args, &args));
+        } else if arg == "output-test" && file.is_empty() {
+            // Checking what programs print against snapshots
+            return Err(output_test(cli_args, &args));
+        } else if arg == "bundle" && file.is_empty() {
+            // Standalone executables
+            return Err(bundle(cli_args, &args));
+        } else if arg == "compile" && file.is_empty() {
+            // Other backends
+            return Err(compile_cmd(cli_args, &args));
+        } else if !arg.starts_with('-') && file.is_empty() {
             // Files
             file = arg.to_string();
             args.name = arg;
@@ -109,25 +255,7 @@ fn get_args() -> Result<Arguments, bool> {
             break;
         } else if let Some(extension) = arg.strip_prefix("--use-") {
             // Extensions
-            let extension = extension.to_string();
-            if extension == "all" {
-                // Set all extensions
-                args.extension_color = true;
-                args.extension_auto_none = true;
-                args.extension_functies = true;
-                args.extension_va_print = true;
-                args.extension_debugging_functies = true;
-            } else if extension == "color" {
-                args.extension_color = true;
-            } else if extension == "auto-none" {
-                args.extension_auto_none = true;
-            } else if extension == "burlap-extensions" {
-                args.extension_functies = true;
-            } else if extension == "va-print" {
-                args.extension_va_print = true;
-            } else if extension == "more-debug" {
-                args.extension_debugging_functies = true;
-            } else {
+            if !args.use_extension(extension) {
                 print_err(
                     format!("unknown extension: {}", arg).as_str(),
                     ErrType::Warn,
@@ -144,6 +272,99 @@ fn get_args() -> Result<Arguments, bool> {
         } else if arg == "-a" || arg == "--disassemble" {
             // Disassemble
             args.dis = true;
+        } else if arg == "-s" || arg == "--strict" {
+            // Strict mode
+            args.strict = true;
+        } else if let Some(mode) = arg.strip_prefix("--overflow=") {
+            // Integer overflow mode
+            args.overflow = match mode {
+                "wrap" => Overflow::Wrap,
+                "saturate" => Overflow::Saturate,
+                "error" => Overflow::Error,
+                _ => {
+                    print_err(
+                        format!("unknown overflow mode: {}", mode).as_str(),
+                        ErrType::Warn,
+                        args.extension_color
+                    );
+                    continue;
+                }
+            };
+        } else if let Some(level) = arg.strip_prefix("--log-level=") {
+            // Minimum log level
+            let Some(level) = LOG_LEVELS.iter().position(|l| *l == level) else {
+                print_err(
+                    format!("unknown log level: {}", level).as_str(),
+                    ErrType::Warn,
+                    args.extension_color
+                );
+                continue;
+            };
+            args.log_level = level;
+        } else if let Some(level) = arg.strip_prefix("--opt-level=") {
+            // Optimization level
+            let Ok(level) = level.parse::<u8>() else {
+                print_err(
+                    format!("unknown opt level: {}", level).as_str(),
+                    ErrType::Warn,
+                    args.extension_color
+                );
+                continue;
+            };
+            args.opt_level = level;
+        } else if let Some(depth) = arg.strip_prefix("--max-call-depth=") {
+            // Recursion limit
+            let Ok(depth) = depth.parse::<usize>() else {
+                print_err(
+                    format!("invalid max call depth: {}", depth).as_str(),
+                    ErrType::Warn,
+                    args.extension_color
+                );
+                continue;
+            };
+            args.max_call_depth = depth;
+        } else if let Some(size) = arg.strip_prefix("--max-size=") {
+            // Size limit for strings and lists
+            let Ok(size) = size.parse::<usize>() else {
+                print_err(
+                    format!("invalid max size: {}", size).as_str(),
+                    ErrType::Warn,
+                    args.extension_color
+                );
+                continue;
+            };
+            args.max_size = size;
+        } else if let Some(format) = arg.strip_prefix("--error-format=") {
+            // Error format
+            match format {
+                "human" => set_json_errors(false),
+                "json" => set_json_errors(true),
+                _ => {
+                    print_err(
+                        format!("unknown error format: {}", format).as_str(),
+                        ErrType::Warn,
+                        args.extension_color
+                    );
+                }
+            }
+        } else if arg == "--no-cache" {
+            // Always compile
+            args.cache = false;
+        } else if arg == "--deny-warnings" {
+            // Warnings as errors
+            set_deny_warnings(true);
+        } else if arg == "--allow" || arg.starts_with("--allow=") {
+            // Silence a warning
+            let Some(name) = arg.strip_prefix("--allow=").map(|n| n.to_string())
+                .or_else(|| cli_args.next())
+            else {
+                print_err(
+                    "'--allow' is missing warning name", ErrType::Err,
+                    args.extension_color
+                );
+                return Err(false);
+            };
+            allow_warning(None, &name);
         } else if arg == "-b" || arg == "--backtrace" {
             // Backtrace
             args.backtrace = true;
@@ -152,7 +373,15 @@ fn get_args() -> Result<Arguments, bool> {
             println!("Burlap v{}", env!("CARGO_PKG_VERSION"));
             println!();
             println!("Usage:");
-            println!("burlap <args> <file> <args for file>");
+            println!("burlap <args> [run] <file> [--] <args for file>");
+            println!("burlap <args> doc <files or dirs> [-o <dir>] [--html]");
+            println!("burlap <args> bundle <file> [-o <out>] [-r <resource>]...");
+            println!("burlap <args> compile --emit=c <file> [-o <out.c>]");
+            println!("burlap <args> repl [--listen <addr> [--token <token>]]");
+            println!("burlap <args> kernel --connection-file <file> | --install");
+            println!("burlap <args> difftest [-n <count>] [--seed <seed>]");
+            println!("burlap <args> bytecode-test [--update-snapshots] [<dir>]");
+            println!("burlap <args> output-test [--update-snapshots] [<dir>]");
             println!();
             println!("Args:");
             println!("    -h --help         prints this help");
@@ -160,9 +389,20 @@ fn get_args() -> Result<Arguments, bool> {
             println!("    --use-X           enables X feature");
             println!("    --use-all         enables all features");
             println!("    - [command]       runs [command]");
+            println!("    -e -c [command]   runs [command] and prints the result");
             println!("    -d --debug        runs in debug mode");
             println!("    -b --backtrace    prints backtrace on runtime errors");
+            println!("    -s --strict       errors on implicit type conversions");
+            println!("    --overflow=X      wrap, saturate, or error on integer overflow");
             println!("    -a --disassemble  prints disassembly instead of running");
+            println!("    --error-format=X  human or json (one error per line)");
+            println!("    --log-level=X     debug, info, warn, error, or off");
+            println!("    --opt-level=N     0 disables optimizations, 2 inlines small functis, 3 more (default 1)");
+            println!("    --max-call-depth=N errors after N nested calls (0 for no limit)");
+            println!("    --max-size=N      errors on strings and lists over N bytes/items (0 for no limit)");
+            println!("    --deny-warnings   treats warnings as errors");
+            println!("    --allow X         silences the X warning");
+            println!("    --no-cache        doesn't use or write .burlap-cache");
             println!();
             println!(
                 "Thank you for using Burlap! {}{}",
@@ -170,18 +410,23 @@ fn get_args() -> Result<Arguments, bool> {
                 env!("CARGO_PKG_REPOSITORY")
             );
             return Err(true);
-        } else if arg == "-" && file.is_empty() {
+        } else if (arg == "-" || arg == "-e" || arg == "-c") && file.is_empty() {
             // Read source from command line
             args.is_repl = false;
+            args.print_result = arg != "-";
             args.name = "<cli>".to_string();
             let Some(src) = cli_args.next() else {
                 print_err(
-                    "'-' is missing option source", ErrType::Err,
+                    &format!("'{}' is missing option source", arg), ErrType::Err,
                     args.extension_color
                 );
                 return Err(false);
             };
             args.source = src.to_string();
+            if args.print_result {
+                // Like the REPL, the last semicolon is optional
+                args.source += ";";
+            }
         } else if arg == "--" {
             break;
         } else {
@@ -194,6 +439,11 @@ fn get_args() -> Result<Arguments, bool> {
     }
     // Get the args to the program
     args.program_args.push(args.name.clone());
+    let mut cli_args = cli_args.peekable();
+    if !args.is_repl && cli_args.peek().is_some_and(|arg| arg == "--") {
+        // Skip the `--` in `burlap file.sk -- args`
+        cli_args.next();
+    }
     for arg in cli_args {
         args.program_args.push(arg.to_string());
     }
@@ -221,6 +471,15 @@ fn get_args() -> Result<Arguments, bool> {
 #[cfg(not(target_family = "wasm"))]
 #[allow(dead_code)]
 fn main() {
+    // Bundled executables only run their program
+    if let Some((args, program, resources)) = bundle::load() {
+        let mut vm = Vm::new(args, program);
+        vm.resources = resources.into_iter().collect();
+        let ok = run(&mut vm);
+        let code = vm.take_exit_code();
+        drop(vm);
+        exit(code.unwrap_or(if ok { 0 } else { 2 }));
+    }
     // Parse args
     let mut args = match get_args() {
         Ok(x) => x,
@@ -240,25 +499,51 @@ fn main() {
         }
     } else {
         args.path = PathBuf::from(args.name.clone());
-        // Execute file
-        let Some(mut ast) = to_ast(&mut args) else {
-            exit(1);
+        // Files (not -e) can use the cache
+        let use_cache = args.cache && args.name != "<cli>";
+        let cached = if use_cache { cache::load(&args) } else { None };
+        let (program, ast) = if let Some(program) = cached {
+            // The names are only needed by eval, so it parses args.source then
+            (program, None)
+        } else {
+            let source = args.source.clone();
+            // Execute file
+            let Some(mut ast) = to_ast(&mut args) else {
+                exit(1);
+            };
+            let mut compiler = Compiler::new();
+            compiler.opt_level = args.opt_level;
+            // Fix import path
+            compiler.program.path = args.path.clone();
+            compiler.program.path.pop();
+            if !compile(
+                &mut ast, &Some(args.name.clone()), &mut compiler, args.print_result
+            ) {
+                exit(1);
+            }
+            // Warnings (and --deny-warnings) need the file to be parsed every time
+            if use_cache && !warned() {
+                cache::store(&args, &source, &compiler.program);
+            }
+            ast.nodes = vec![];
+            (compiler.program, Some(ast))
         };
-        let mut compiler = Compiler::new();
-        // Fix import path
-        compiler.program.path = args.path.clone();
-        compiler.program.path.pop();
-        if !compile(&mut ast, &Some(args.name.clone()), &mut compiler, false) {
-            exit(1);
-        }
         if args.dis {
             // Disassemble
-            dis(&compiler.program, 0);
+            dis(&program, 0);
             exit(0);
         }
         // Run
-        let mut vm = Vm::new(args.clone(), compiler.program);
-        if !run(&mut vm) {
+        let mut vm = Vm::new(args.clone(), program);
+        vm.ast = ast;
+        let ok = run(&mut vm);
+        let code = vm.take_exit_code();
+        // Clean up before exiting
+        drop(vm);
+        if let Some(code) = code {
+            exit(code);
+        }
+        if !ok {
             exit(2);
         }
     }
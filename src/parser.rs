@@ -1,9 +1,11 @@
-use std::fs::read_to_string;
-use std::mem::swap;
+use std::fs::{canonicalize, read_to_string};
+use std::mem::{swap, take};
+use std::path::Path;
 
-use crate::{Arguments, to_ast};
-use crate::common::{err, ErrType, IMPOSSIBLE_STATE, get_builtins};
-use crate::lexer::{Token, TokenType};
+use crate::Arguments;
+use crate::common::{err, warn, ErrType, IMPOSSIBLE_STATE};
+use crate::backend::vm::vm::get_builtins;
+use crate::lexer::{lex, Token, TokenType};
 use crate::folding::fold_expr;
 use TokenType::*;
 
@@ -24,16 +26,20 @@ pub enum ASTNode {
     ByteExpr(u8),
     // Var, (x)
     VarExpr(String),
-    // Call, (print, [Number(7)])
-    CallExpr(Box<ASTNode>, Vec<ASTNode>),
-    // Index, (mylist, Number(7))
-    IndexExpr(Box<ASTNode>, Box<ASTNode>),
+    // Call, (print, [Number(7)], span)
+    CallExpr(Box<ASTNode>, Vec<ASTNode>, Span),
+    // Index, (mylist, Number(7), span)
+    IndexExpr(Box<ASTNode>, Box<ASTNode>, Span),
     // Unary, (Minus, Number(1))
     UnaryExpr(TokenType, Box<ASTNode>),
-    // Binop, (Number(2), "+", Number(2))
-    BinopExpr(Box<ASTNode>, TokenType, Box<ASTNode>),
-    // List (keys["1", "e"], values[node, node], fast = false)
-    ListExpr(Vec<String>, Vec<ASTNode>, bool),
+    // Binop, (Number(2), "+", Number(2), span)
+    BinopExpr(Box<ASTNode>, TokenType, Box<ASTNode>, Span),
+    // List (keys[None, String("e")], values[node, node], fast = false)
+    ListExpr(Vec<ASTNode>, Vec<ASTNode>, bool),
+    // Statements then a value, ([Let(x, 1)], Var(x)), used for desugaring
+    BlockExpr(Vec<StmtNode>, Box<ASTNode>),
+    // The ? operator, unwraps an ok or returns the err, (Call(Var(parse), [Var(s)]))
+    TryExpr(Box<ASTNode>),
 
     // Statements
     // Body, ([Call(Var(print), [String("Hello World")])])
@@ -46,6 +52,8 @@ pub enum ASTNode {
     LetStmt(Vec<String>, Vec<ASTNode>),
     // Return, ("Return Val")
     ReturnStmt(Box<ASTNode>),
+    // Defer, (Call(Var(close), [Var(f)]))
+    DeferStmt(Box<StmtNode>),
     // Infinite loop
     LoopStmt(Box<StmtNode>),
     // Iter loop, (i, range(0, 100), Body(...), already_defined)
@@ -56,8 +64,8 @@ pub enum ASTNode {
     BreakStmt,
     // Continue
     ContinueStmt,
-    // ImportStmt, used for the file table as the parser handles imports
-    ImportStmt,
+    // ImportStmt, (filename), used for the file table as the parser handles imports
+    ImportStmt(String),
     // EndImportStmt, (filename), used for marking the end of the import
     EndImportStmt(String),
 
@@ -66,16 +74,55 @@ pub enum ASTNode {
     Nop,
 }
 
+impl ASTNode {
+    pub fn is_expr(&self) -> bool {
+        matches!(self,
+            ASTNode::StringExpr(_) | ASTNode::NumberExpr(_) | ASTNode::DecimalExpr(_)
+            | ASTNode::BoolExpr(_) | ASTNode::NoneExpr | ASTNode::ByteExpr(_)
+            | ASTNode::VarExpr(_) | ASTNode::CallExpr(..) | ASTNode::IndexExpr(..)
+            | ASTNode::UnaryExpr(..) | ASTNode::BinopExpr(..) | ASTNode::ListExpr(..)
+            | ASTNode::BlockExpr(..) | ASTNode::TryExpr(..)
+        )
+    }
+}
+
+// Where an expression is, for runtime errors (line 0 means unknown)
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub size: usize,
+}
+
+impl Span {
+    // From the start of one token to the end of another
+    fn between(start: &Token, end: &Token) -> Span {
+        let (start, end) = (&start.stream, &end.stream);
+        let size = if start.line == end.line && end.at >= start.at {
+            end.at + end.size - start.at
+        } else {
+            start.size
+        };
+        Span { line: start.line, col: start.at, size }
+    }
+}
+
 #[derive(PartialEq, Clone)]
 pub struct Variable {
-    // Number of times it's been used
     pub name: String,
+    // Name used for offsets, differs from name when shadowing
+    pub real_name: String,
+    // Number of times it's been used
     pub count: u32,
+    // Token it was declared at (for warnings)
+    pub decl: usize,
+    // File it was declared in, each file has its own globals
+    pub module: String,
 }
 
 impl std::fmt::Debug for Variable {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Var({}, count: {})", self.name, self.count)
+        write!(f, "Var({}, count: {})", self.real_name, self.count)
     }
 }
 
@@ -129,7 +176,23 @@ pub struct FunctiData {
     pub name: String,
     pub arg_num: i32,
     pub locals: Vec<Variable>,
+    // Where each local is kept, locals in blocks that don't overlap share slots
+    pub slots: Vec<u16>,
     pub count: i32,
+    // The `###` comment above it
+    pub doc: String,
+    // File it was declared in
+    pub module: String,
+    // Not exported, so only its own file can use it
+    pub private: bool,
+}
+
+impl FunctiData {
+    // The number of slots needed by locals that aren't args
+    pub fn frame_size(&self) -> usize {
+        let slots = self.slots.iter().max().map_or(self.locals.len(), |s| *s as usize + 1);
+        slots - self.arg_num as usize
+    }
 }
 
 impl std::fmt::Debug for FunctiData {
@@ -140,6 +203,17 @@ impl std::fmt::Debug for FunctiData {
 
 pub type VecFunctis = Vec<FunctiData>;
 
+// A top level functi or global, with its `###` doc comment
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct DocItem {
+    pub name: String,
+    // Like `functi add(a, b)` or `let x`
+    pub signature: String,
+    pub doc: String,
+    pub file: String,
+    pub line: usize,
+}
+
 // The AST itself
 #[derive(Debug, PartialEq, Clone, Default)]
 #[allow(clippy::upper_case_acronyms)]
@@ -148,6 +222,13 @@ pub struct AST {
     pub nodes: Vec<StmtNode>,
     pub cur_vars: Vec<Variable>,
     pub all_vars: Vec<Variable>,
+    pub docs: Vec<DocItem>,
+    // Files that were imported (and the main file), so they only run once
+    pub imported: Vec<String>,
+    // Imported files by the name used in `name::var` (name, file)
+    pub modules: Vec<(String, String)>,
+    // Names from `export`, files that export anything hide everything else
+    pub exports: Vec<String>,
 }
 
 impl AST {
@@ -156,7 +237,11 @@ impl AST {
             functis: vec![],
             cur_vars: vec![],
             all_vars: vec![],
-            nodes: vec![]
+            nodes: vec![],
+            docs: vec![],
+            imported: vec![],
+            modules: vec![],
+            exports: vec![],
         }
     }
 
@@ -165,14 +250,15 @@ impl AST {
     }
 
     pub fn get_var_offset(&self, name: String, functi: Option<&FunctiData>) -> Option<u16> {
-        let name = name.split("::").nth(1).unwrap_or(&name);
+        let (module, name) = name.split_once("::").unwrap_or(("", &name));
         if let Some(functi) = functi {
             // Local level, find fn first
-            &functi.locals
-        } else {
-            // Global level
-            &self.all_vars
-        }.iter().position(|i| i.name == name).and_then(|i| u16::try_from(i).ok())
+            let at = functi.locals.iter().position(|i| i.real_name == name)?;
+            return functi.slots.get(at).copied().or_else(|| u16::try_from(at).ok());
+        }
+        // Global level, the parser already found which file's it is
+        self.all_vars.iter().position(|i| i.real_name == name && i.module == module)
+            .and_then(|i| u16::try_from(i).ok())
     }
 
     pub fn add_var(&mut self, var: Variable) {
@@ -189,9 +275,21 @@ struct Parser {
     in_func: bool,
     in_loop: bool,
     has_err: bool,
+    // Where the variables of the current block start in cur_vars
+    scope_start: usize,
+    // Where the variables of the current function start in cur_vars
+    func_start: usize,
+    // Disables the colon operator (for dict comprehension keys)
+    no_colon: bool,
     name: String,
     ast: AST,
-    functi_locals: Vec<Variable>
+    functi_locals: Vec<Variable>,
+    // Where each of functi_locals was in cur_vars, relative to func_start
+    functi_slots: Vec<usize>,
+    // If the current functi uses defer
+    has_defer: bool,
+    // `###` comments (line, text)
+    doc_comments: Vec<(usize, String)>,
 }
 
 impl Parser {
@@ -200,9 +298,11 @@ impl Parser {
         self.tokens[self.at].token.clone()
     }
 
-    // Advance and return token
+    // Advance and return token, it stays on the EOF at the end
     fn next(&mut self) -> TokenType {
-        self.at += 1;
+        if self.at + 1 < self.tokens.len() {
+            self.at += 1;
+        }
         self.current()
     }
 }
@@ -224,6 +324,20 @@ macro_rules! error {
         );
     )
 }
+// Named warning macro, errors with --deny-warnings
+macro_rules! warning {
+    ($parser:expr, $name:expr, $msg:expr) => (
+        warning!($parser, $parser.at, $name, $msg)
+    );
+    ($parser:expr, $at:expr, $name:expr, $msg:expr) => (
+        if warn(
+            &$parser.tokens[$at].stream, $name, $msg,
+            $parser.args.extension_color
+        ) {
+            $parser.has_err = true;
+        }
+    );
+}
 // Eat the token or error
 macro_rules! eat {
     ($parser:expr, $tok:pat, $msg:expr) => (
@@ -254,9 +368,9 @@ enum SymLookupRes {
 fn get_sym(parser: &mut Parser, name: &str, arg_num: i32) -> SymLookupRes {
     // -1 arg_num means name is a variable
     // Check variables
-    if let Some(v) = parser.ast.cur_vars.iter_mut().find(|i| i.name == name) {
+    if let Some(at) = find_var(parser, name) {
         // Used again
-        v.count += 1;
+        parser.ast.cur_vars[at].count += 1;
         return SymLookupRes::TakenByVar;
     }
     // Check functions
@@ -266,8 +380,8 @@ fn get_sym(parser: &mut Parser, name: &str, arg_num: i32) -> SymLookupRes {
         return SymLookupRes::TakenByFuncti;
     }
     // Check builtins
-    if get_builtins(&parser.args).iter()
-        .any(|(n, a)| n == name && (arg_num == -1 || arg_num == *a))
+    if get_builtins(&parser.args)
+        .any(|b| b.name == name && (arg_num == -1 || b.max_args == -1 || b.takes(arg_num)))
     {
         return SymLookupRes::TakenByBuiltin;
     }
@@ -296,12 +410,15 @@ fn _check_unique(parser: &mut Parser, name: &str, arg_num: i32) -> Result<bool,
         );},
         SymLookupRes::Free => if arg_num != -1 {
             parser.ast.functis.push(FunctiData {
-                name: name.to_string(), arg_num, count: 0, locals: vec![]
+                name: name.to_string(), arg_num, count: 0, locals: vec![],
+                slots: vec![], doc: String::new(), module: parser.name.clone(),
+                private: false
             });
         } else {
             return Err(Variable {
                 name: name.to_string(),
-                count: 0
+                real_name: name.to_string(),
+                count: 0, decl: parser.at, module: parser.name.clone()
             });
         }
     };
@@ -311,6 +428,33 @@ fn _check_unique(parser: &mut Parser, name: &str, arg_num: i32) -> Result<bool,
 fn check_unique(parser: &mut Parser, name: &str, arg_num: i32) -> Result<bool, Variable>{
     let ret = _check_unique(parser, name, arg_num);
     if let Ok(false) = ret {
+        let name = name.split("::").nth(1).unwrap_or(name);
+        let module = find_var(parser, name)
+            .map(|at| parser.ast.cur_vars[at].module.clone()).unwrap_or_default();
+        let top_level = !parser.in_func && parser.func_start == 0 && parser.scope_start == 0;
+        if module != parser.name && top_level {
+            // It's a global from an imported file, this file gets its own
+            warning!(
+                parser, "collision",
+                format!("\"{}\" is also a global in {}", name, module).as_str()
+            );
+            return Err(Variable {
+                name: name.to_string(), real_name: name.to_string(),
+                count: 0, decl: parser.at, module: parser.name.clone()
+            });
+        }
+        if !parser.ast.cur_vars.iter().skip(parser.scope_start).any(|i| i.name == name) {
+            // It's from an outer block, so it can be shadowed
+            warning!(
+                parser, "shadow",
+                format!("\"{}\" shadows a variable from an outer scope", name).as_str()
+            );
+            return Err(Variable {
+                name: name.to_string(),
+                real_name: format!("{}${}", name, parser.ast.all_vars.len()),
+                count: 0, decl: parser.at, module: parser.name.clone()
+            });
+        }
         error!(
             parser,
             format!("the name \"{}\" is already taken by a variable", name).as_str()
@@ -319,27 +463,103 @@ fn check_unique(parser: &mut Parser, name: &str, arg_num: i32) -> Result<bool, V
     ret
 }
 
+// Where the visible variable called name is in cur_vars, the innermost one from this file
+// first, then the first one imported
+fn find_var(parser: &Parser, name: &str) -> Option<usize> {
+    let vars = &parser.ast.cur_vars;
+    vars.iter().rposition(|i| i.name == name && i.module == parser.name)
+        .or_else(|| vars.iter().position(|i| i.name == name))
+}
+
+// Gets the full name (with the file that declared it) of the visible variable called name
+fn resolve_name(parser: &Parser, name: &str) -> String {
+    match find_var(parser, name) {
+        Some(at) => {
+            let var = &parser.ast.cur_vars[at];
+            var.module.clone() + "::" + &var.real_name
+        },
+        Option::None => parser.name.clone() + "::" + name,
+    }
+}
+
+// Edit distance (swapping two letters counts as one edit), for suggestions
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            rows[i][j] = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                rows[i][j] = rows[i][j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+// Finds the closest visible name to a misspelled one
+fn suggest_name(parser: &Parser, name: &str) -> Option<String> {
+    let builtins = get_builtins(&parser.args);
+    let names = parser.ast.cur_vars.iter().map(|v| v.name.as_str())
+        .chain(parser.ast.functis.iter().map(|f| f.name.as_str()))
+        .chain(builtins.map(|b| b.name))
+        .filter(|n| !n.starts_with("__") && !n.contains('$'));
+    // Allow about one typo every three letters
+    let max = 1.max(name.chars().count() / 3);
+    names.map(|n| (edit_distance(name, n), n))
+        .filter(|(dist, _)| *dist <= max)
+        .min_by_key(|(dist, _)| *dist)
+        .map(|(_, n)| n.to_string())
+}
+
 fn check_name(parser: &mut Parser, name: &str) -> bool {
     let name = name.split("::").nth(1).unwrap_or(name);
     match get_sym(parser, name, -1) {
         SymLookupRes::TakenByVar => true,
-        SymLookupRes::TakenByFuncti => false,
-        SymLookupRes::TakenByBuiltin => false,
-        SymLookupRes::Free => {error!(
-            parser,
-            format!("\"{}\" is not defined", name).as_str()
-        );
-        true
+        SymLookupRes::TakenByFuncti | SymLookupRes::TakenByBuiltin => false,
+        SymLookupRes::Free => {
+            // Private functis were renamed by hide_private
+            if let Some(module) = hidden_from(parser, name) {
+                error!(parser, format!("\"{}\" isn't exported from {}", name, module).as_str());
+                return true;
+            }
+            error!(
+                parser,
+                format!("\"{}\" is not defined", name).as_str()
+            );
+            if let Some(close) = suggest_name(parser, name) {
+                error!(
+                    parser,
+                    format!("did you mean \"{}\"?", close).as_str(),
+                    ErrType::Hint
+                );
+            }
+            true
         }
     }
 }
 
+// If the functi is from a file that didn't export it
+fn is_hidden(parser: &Parser, functi: &FunctiData) -> bool {
+    functi.private && functi.module != parser.name
+}
+
 fn check_call(parser: &mut Parser, name: &str, arg_num: i32) {
     let name = name.split("::").nth(1).unwrap_or(name);
     let mut wrong_args = false;
     // Functions
     for i in &parser.ast.functis {
-        if i.name == name {
+        if i.name == name && !is_hidden(parser, i) {
             if i.arg_num == arg_num {
                 // A correct call was found
                 return;
@@ -348,9 +568,9 @@ fn check_call(parser: &mut Parser, name: &str, arg_num: i32) {
         }
     }
     // Builtins
-    for (n, a) in get_builtins(&parser.args) {
-        if n == name {
-            if *a == arg_num {
+    for builtin in get_builtins(&parser.args) {
+        if builtin.name == name {
+            if builtin.takes(arg_num) {
                 // A correct call was found
                 return;
             }
@@ -373,8 +593,36 @@ fn check_call(parser: &mut Parser, name: &str, arg_num: i32) {
 }
 
 // Expressions
+// Call args, starts after the '(' and stops on the ')'
+fn parse_call_args(parser: &mut Parser) -> Option<Vec<ASTNode>> {
+    let mut args: Vec<ASTNode> = vec![];
+    loop {
+        if let Rparan = parser.current() {
+            break;
+        }
+        args.push(parse_expr(parser)?.node);
+        if let Rparan = parser.current() {
+            break;
+        }
+        eat!(parser, Comma, "expected ')' or ',' in argument list")?;
+    }
+    return Some(args);
+}
+
 // Calls or indexes
 fn parse_callindex_from(parser: &mut Parser, mut ret: ExprNode) -> Option<ExprNode> {
+    // Parse `?`
+    if parser.current() == Question {
+        if !parser.in_func {
+            error!(parser, "'?' outside of functions");
+            parser.next();
+            return Option::None;
+        }
+        parser.next();
+        ret.node = ASTNode::TryExpr(Box::new(ret.node));
+        ret.lvalue = false;
+        return parse_callindex_from(parser, ret);
+    }
     // Parse call or index
     let is_call = parser.current() == Lparan;
     let is_colon = parser.current() == Colon;
@@ -382,40 +630,39 @@ fn parse_callindex_from(parser: &mut Parser, mut ret: ExprNode) -> Option<ExprNo
         // Not a call or index
         return Some(ret);
     };
+    if is_colon && parser.no_colon {
+        // The colon operator is disabled
+        return Some(ret);
+    }
+    // The callee/list ends just before the '('/'['
+    let start = parser.at - 1;
     // Eat '('/'['
     parser.next();
     // Get args/index
     if is_call {
-        let mut args: Vec<ASTNode> = vec![];
-        loop {
-            if let Rparan = parser.current() {
-                break;
-            }
-            args.push(parse_expr(parser)?.node);
-            if let Rparan = parser.current() {
-                break;
-            }
-            eat!(parser, Comma, "expected ')' or ',' in argument list")?;
-        }
+        let args = parse_call_args(parser)?;
         if let ASTNode::VarExpr(ref name) = ret.node {
             check_call(parser, name, args.len().try_into().unwrap());
         }
+        let span = Span::between(&parser.tokens[start], &parser.tokens[parser.at]);
         parser.next();
-        ret.node = ASTNode::CallExpr(Box::new(ret.node), args);
+        ret.node = ASTNode::CallExpr(Box::new(ret.node), args, span);
         ret.lvalue = false;
     } else if is_colon {
         let Identifier(ident) = parser.current() else {
             error!(parser, "The colon operator requires a identifier!");
             return Option::None;
         };
+        let span = Span::between(&parser.tokens[start], &parser.tokens[parser.at]);
         parser.next();
         ret.node = ASTNode::IndexExpr(
-            Box::new(ret.node), Box::new(ASTNode::StringExpr(ident))
+            Box::new(ret.node), Box::new(ASTNode::StringExpr(ident)), span
         );
     } else {
         let expr = parse_expr(parser)?.node;
+        let span = Span::between(&parser.tokens[start], &parser.tokens[parser.at]);
         eat!(parser, Rbracket, "expected ']' at end of index")?;
-        ret.node = ASTNode::IndexExpr(Box::new(ret.node), Box::new(expr));
+        ret.node = ASTNode::IndexExpr(Box::new(ret.node), Box::new(expr), span);
     }
     // Functions and indexes can return functions and lists, so loop
     return parse_callindex_from(parser, ret);
@@ -435,11 +682,10 @@ fn parse_unary(parser: &mut Parser) -> Option<ExprNode> {
         ASTNode::UnaryExpr(op, Box::new(parse_unary(parser)?.node))
     } else if vec![PlusPlus, MinusMinus].contains(&parser.current()) {
         let op = parser.current();
-        if let Identifier(mut v) = parser.next() {
+        if let Identifier(v) = parser.next() {
             check_name(parser, &v);
             parser.next();
-            v = parser.name.clone() + "::" + &v;
-            ASTNode::UnaryExpr(op, Box::new(ASTNode::VarExpr(v)))
+            ASTNode::UnaryExpr(op, Box::new(ASTNode::VarExpr(resolve_name(parser, &v))))
         } else {
             error!(parser, "++/-- require identifiers");
             return Option::None;
@@ -464,12 +710,13 @@ fn parse_binop_helper(
         // Left arg
         // Op in the middle
         let op = parser.current();
+        let span = Span::between(&parser.tokens[parser.at], &parser.tokens[parser.at]);
         parser.next();
         // Right arg
         let right = callback(parser)?.node;
         // Make binop
         expr = ExprNode {
-            node: ASTNode::BinopExpr(Box::new(expr.node), op, Box::new(right)),
+            node: ASTNode::BinopExpr(Box::new(expr.node), op, Box::new(right), span),
             lvalue: false
         };
         if !can_repeat {
@@ -486,9 +733,9 @@ fn parse_binop_math2(parser: &mut Parser) -> Option<ExprNode> {
     ], &parse_unary, true)
 }
 fn parse_binop_math1(parser: &mut Parser) -> Option<ExprNode> {
-    // Math 1 binops, + and -
+    // Math 1 binops, +, -, |, and &
     parse_binop_helper(parser, vec![
-        Plus, Minus,
+        Plus, Minus, BitOr, BitAnd
     ], &parse_binop_math2, true)
 }
 fn parse_binop_cmp(parser: &mut Parser) -> Option<ExprNode> {
@@ -504,9 +751,36 @@ fn parse_binop_logic(parser: &mut Parser) -> Option<ExprNode> {
     ], &parse_binop_cmp, true)
 }
 
+fn parse_pipe(parser: &mut Parser) -> Option<ExprNode> {
+    // Pipelines, `x |> f |> g(2)` is `g(f(x), 2)`
+    let mut ret = parse_binop_logic(parser)?;
+    while parser.current() == Pipe {
+        let span = Span::between(&parser.tokens[parser.at], &parser.tokens[parser.at]);
+        parser.next();
+        let callee = parse_base_expr(parser)?.node;
+        // The piped value is the first arg
+        let mut args = vec![ret.node];
+        let has_args = parser.current() == Lparan;
+        if has_args {
+            parser.next();
+            args.append(&mut parse_call_args(parser)?);
+        }
+        if let ASTNode::VarExpr(ref name) = callee {
+            check_call(parser, name, args.len().try_into().unwrap());
+        }
+        if has_args {
+            parser.next();
+        }
+        ret = ExprNode {
+            node: ASTNode::CallExpr(Box::new(callee), args, span), lvalue: false
+        };
+    }
+    return Some(ret);
+}
+
 // Simply a wrapper to the highest expression parser and fold
 fn parse_expr(parser: &mut Parser) -> Option<ExprNode> {
-    let ret = parse_binop_logic(parser)?;
+    let ret = parse_pipe(parser)?;
     Some(if let Some(folded) = fold_expr(&ret) {
         //println!("Folded {:?} into {:?}!", ret, folded);
         folded
@@ -528,10 +802,11 @@ fn parse_binop_set(parser: &mut Parser) -> Option<ASTNode> {
     // Check that the value is an lvalue
     if ret.lvalue {
         let op = parser.current();
+        let span = Span::between(&parser.tokens[parser.at], &parser.tokens[parser.at]);
         parser.next();
         Some(ASTNode::BinopExpr(
             Box::new(ret.node), op,
-            Box::new(parse_expr(parser)?.node)
+            Box::new(parse_expr(parser)?.node), span
         ))
     } else {
         error!(parser, "expected lvalue on left hand side of setter");
@@ -541,68 +816,73 @@ fn parse_binop_set(parser: &mut Parser) -> Option<ASTNode> {
 }
 
 // Lists
-fn parse_list_item(parser: &mut Parser) -> (String, Option<ASTNode>) {
+fn parse_list_item(parser: &mut Parser) -> (ASTNode, Option<ASTNode>) {
     // Parses a single item in a list
-    let mut name: String;
-    // Get the key name
-    if let Identifier(n) = parser.current() {
+    let mut key = ASTNode::NoneExpr;
+    // Get the key name (`file::name` isn't one)
+    if let (Identifier(n), false) = (parser.current(), is_qualified(parser)) {
         // Use identifier name
-        name = n;
         parser.next();
         if let Colon = parser.current() {
+            key = ASTNode::StringExpr(n);
             parser.next();
         } else if let Comma | Rbracket = parser.current() {
             // Named indexes don't need values
-            let var_name = parser.name.clone() + "::" + &name;
-            check_name(parser, &var_name);
-            return (name, Some(ASTNode::VarExpr(var_name)));
+            check_name(parser, &n);
+            let var = ASTNode::VarExpr(resolve_name(parser, &n));
+            return (ASTNode::StringExpr(n), Some(var));
         } else {
             // It's not a named index (`[myvar + 1]`)
-            name = "".to_string();
             parser.at -= 1;
         }
     } else if let Func(_) = parser.current() {
         // Method
         let Some((method, method_name)) = parse_functi(parser, true) else {
-            return ("".to_string(), Option::None);
+            return (key, Option::None);
         };
-        return (method_name.to_string(), Some(method.clone()));
-    } else {
+        return (ASTNode::StringExpr(method_name.to_string()), Some(method.clone()));
+    } else if parser.current() == Colon {
         // Use number index
-        if parser.current() == Colon {
+        parser.next();
+    } else if let Some(Colon) = parser.tokens.get(parser.at + 1).map(|t| t.token.clone()) {
+        // Literal keys (`[1: "one"]`)
+        if let Str(_) | Int(_) | Float(_) | Bool(_) | Byte(_) = parser.current() {
+            let Some(lit) = parse_base_expr(parser) else {
+                return (key, Option::None);
+            };
+            key = lit.node;
             parser.next();
         }
-        name = "".to_string();
     }
     // Parse value
     let val = parse_expr(parser).map(|x| x.node);
-    return (name, val);
+    return (key, val);
 }
 fn parse_list(parser: &mut Parser) -> Option<ASTNode> {
     // Parses a list
     eat!(parser, Lbracket, "expecting [")?;
     // Parse elements
-    let mut names: Vec<String> = vec![];
+    let mut keys: Vec<ASTNode> = vec![];
     let mut vals: Vec<ASTNode> = vec![];
     let mut fastlist = true;
     while parser.current() != Rbracket {
-        let (name, val) = parse_list_item(parser);
+        let (key, val) = parse_list_item(parser);
         // Invalid element
         if val.is_none() {
             // Parse until the end of the list so there aren't trailing errors
-            while parser.next() != Semicolon {}
+            while !matches!(parser.next(), Semicolon | Eof) {}
             return Option::None;
         }
         // Valid element
-        names.push(name.clone());
         if fastlist {
-            fastlist = name == "";
+            fastlist = key == ASTNode::NoneExpr;
         }
+        keys.push(key);
         vals.push(val?);
         // Eat comma
         if parser.current() == Comma {
             if parser.next() == Rbracket {
-                error!(parser, "trailing comma", ErrType::Warn);
+                warning!(parser, "trailing-comma", "trailing comma");
             }
         } else if parser.current() == Rbracket {
         } else {
@@ -610,12 +890,216 @@ fn parse_list(parser: &mut Parser) -> Option<ASTNode> {
             if parser.current() == Colon {
                 error!(parser, "identifiers are used as keys", ErrType::Hint);
             }
-            while parser.next() != Semicolon {}
+            while !matches!(parser.next(), Semicolon | Eof) {}
             return Option::None;
         }
     }
     eat!(parser, Rbracket, "expecting ]")?;
-    return Some(ASTNode::ListExpr(names, vals, fastlist));
+    return Some(ASTNode::ListExpr(keys, vals, fastlist));
+}
+
+// Dict comprehensions (`{k: v for [k, v] in pairs}`)
+fn parse_dict_comp(parser: &mut Parser) -> Option<ASTNode> {
+    // Eat '{'
+    parser.next();
+    let start = parser.at;
+    // Find the 'for', the vars need to be defined before the key and value
+    let mut depth = 0;
+    loop {
+        match parser.current() {
+            Lparan | Lbracket | Lbrace => depth += 1,
+            Rparan | Rbracket => depth -= 1,
+            Rbrace if depth == 0 => {
+                error!(parser, "expected 'for' in dict comprehension");
+                return Option::None;
+            },
+            Rbrace => depth -= 1,
+            Identifier(ref i) if depth == 0 && i == "for" => break,
+            Eof => {
+                error!(parser, "reached EOF too soon!");
+                return Option::None;
+            },
+            _ => {},
+        }
+        parser.next();
+    }
+    let for_pos = parser.at;
+    parser.next();
+    // Loop vars, either `x` or `[k, v]`
+    let mut names: Vec<String> = vec![];
+    let unpack = parser.current() == Lbracket;
+    if unpack {
+        parser.next();
+    }
+    loop {
+        let Identifier(name) = parser.current() else {
+            error!(parser, "expected variable name");
+            return Option::None;
+        };
+        names.push(name);
+        parser.next();
+        if !unpack || parser.current() != Comma {
+            break;
+        }
+        parser.next();
+    }
+    if unpack {
+        eat!(parser, Rbracket, "expected ']' at end of variable names")?;
+    }
+    eat!(parser, In, "missing 'in' keyword in dict comprehension")?;
+    let iter = parse_expr(parser)?.node;
+    let end = parser.at;
+    // Define the vars
+    let old_len = parser.ast.cur_vars.len();
+    let mut vars: Vec<String> = vec![];
+    for name in names {
+        if let Err(var) = _check_unique(parser, &name, -1) {
+            parser.ast.add_var(var);
+        }
+        vars.push(resolve_name(parser, &name));
+    }
+    // Hidden result and item vars
+    let mut hidden = |name: String| {
+        let real_name = format!("{}${}", name, parser.ast.all_vars.len());
+        parser.ast.add_var(Variable {
+            name: real_name.clone(), real_name: real_name.clone(), count: 0,
+            decl: parser.at, module: parser.name.clone()
+        });
+        parser.name.clone() + "::" + &real_name
+    };
+    let res = hidden("__dict_comp".to_string());
+    let item = if unpack { hidden("__dict_item".to_string()) } else { vars[0].clone() };
+    // Key and value
+    parser.at = start;
+    let old_no_colon = parser.no_colon;
+    parser.no_colon = true;
+    let key = parse_expr(parser);
+    parser.no_colon = old_no_colon;
+    let key = key?.node;
+    eat!(parser, Colon, "expected ':' after dict comprehension key")?;
+    let val = parse_expr(parser)?.node;
+    if parser.at != for_pos {
+        error!(parser, "expected 'for' after dict comprehension value");
+        return Option::None;
+    }
+    parser.at = end;
+    eat!(parser, Rbrace, "expected '}' at end of dict comprehension")?;
+    end_scope(parser, old_len);
+    // Desugar
+    let line = parser.tokens[start].stream.line;
+    let mut body: Vec<StmtNode> = vec![];
+    if unpack {
+        let indexes = (0..vars.len()).map(|i| ASTNode::IndexExpr(
+            Box::new(ASTNode::VarExpr(item.clone())),
+            Box::new(ASTNode::NumberExpr(i as i32)), Span::default()
+        )).collect();
+        body.push(StmtNode { line, node: ASTNode::LetStmt(vars, indexes) });
+    }
+    body.push(StmtNode { line, node: ASTNode::BinopExpr(
+        Box::new(ASTNode::VarExpr(res.clone())), Equals,
+        Box::new(ASTNode::CallExpr(
+            Box::new(ASTNode::VarExpr("::__burlap_add_key".to_string())),
+            vec![ASTNode::VarExpr(res.clone()), key, val], Span::default()
+        )), Span::default()
+    )});
+    return Some(ASTNode::BlockExpr(vec![
+        StmtNode { line, node: ASTNode::LetStmt(
+            vec![res.clone()], vec![ASTNode::ListExpr(vec![], vec![], false)]
+        )},
+        StmtNode { line, node: ASTNode::IterLoopStmt(
+            item, Box::new(iter),
+            Box::new(StmtNode { line, node: ASTNode::BodyStmt(body) }), true
+        )},
+    ], Box::new(ASTNode::VarExpr(res))));
+}
+
+// Tuples are lists that get converted
+fn tuple_expr(vals: Vec<ASTNode>) -> ASTNode {
+    let keys = vec![ASTNode::NoneExpr; vals.len()];
+    return ASTNode::CallExpr(
+        Box::new(ASTNode::VarExpr("::__burlap_tuple".to_string())),
+        vec![ASTNode::ListExpr(keys, vals, true)], Span::default()
+    );
+}
+
+// Sets (`{1, 2, 3}`) and dict comprehensions
+fn parse_brace(parser: &mut Parser) -> Option<ASTNode> {
+    // It's a dict comprehension if there is a 'for'
+    let mut at = parser.at + 1;
+    let mut depth = 0;
+    loop {
+        match parser.tokens.get(at).map(|t| t.token.clone()) {
+            Some(Lparan | Lbracket | Lbrace) => depth += 1,
+            Some(Rparan | Rbracket) => depth -= 1,
+            Some(Rbrace) if depth == 0 => break,
+            Some(Rbrace) => depth -= 1,
+            Some(Identifier(ref i)) if depth == 0 && i == "for" => {
+                return parse_dict_comp(parser);
+            },
+            Some(Eof) | Option::None => break,
+            _ => {},
+        }
+        at += 1;
+    }
+    // Set literal
+    parser.next();
+    let mut vals: Vec<ASTNode> = vec![];
+    while parser.current() != Rbrace {
+        vals.push(parse_expr(parser)?.node);
+        // Eat comma
+        if parser.current() == Comma {
+            if parser.next() == Rbrace {
+                warning!(parser, "trailing-comma", "trailing comma");
+            }
+        } else if parser.current() != Rbrace {
+            error!(parser, "expected comma or '}'");
+            return Option::None;
+        }
+    }
+    eat!(parser, Rbrace, "expecting }")?;
+    let keys = vec![ASTNode::NoneExpr; vals.len()];
+    return Some(ASTNode::CallExpr(
+        Box::new(ASTNode::VarExpr("::__burlap_set".to_string())),
+        vec![ASTNode::ListExpr(keys, vals, true)], Span::default()
+    ));
+}
+
+// If the name is followed by `::`
+fn is_qualified(parser: &Parser) -> bool {
+    let token = |at: usize| parser.tokens.get(parser.at + at).map(|t| &t.token);
+    token(1) == Some(&Colon) && token(2) == Some(&Colon)
+}
+
+// `file::name`, for a global or functi from an imported file
+fn parse_qualified(parser: &mut Parser, short: &str) -> Option<(ASTNode, bool)> {
+    let Some((_, file)) = parser.ast.modules.iter().find(|(s, _)| s == short).cloned() else {
+        error!(parser, format!("no imported file is called \"{}\"", short).as_str());
+        return Option::None;
+    };
+    parser.next();
+    parser.next();
+    let Identifier(name) = parser.next() else {
+        error!(parser, "expected a name after '::'");
+        return Option::None;
+    };
+    let ret = if let Some(var) = parser.ast.cur_vars.iter_mut()
+        .find(|v| v.module == file && v.name == name)
+    {
+        var.count += 1;
+        (ASTNode::VarExpr(file + "::" + &var.real_name), true)
+    } else if parser.ast.functis.iter().any(|f| f.module == file && f.name == name && !f.private) {
+        (ASTNode::VarExpr(file + "::" + &name), false)
+    } else if parser.ast.all_vars.iter().any(|v| v.module == file && v.name == name)
+        || hidden_from(parser, &name).is_some_and(|module| module == file)
+    {
+        error!(parser, format!("\"{}\" isn't exported from {}", name, file).as_str());
+        return Option::None;
+    } else {
+        error!(parser, format!("\"{}\" is not defined in {}", name, file).as_str());
+        return Option::None;
+    };
+    parser.next();
+    return Some(ret);
 }
 
 // Normal expressions
@@ -623,12 +1107,15 @@ fn parse_base_expr(parser: &mut Parser) -> Option<ExprNode> {
     let mut lvalue = false;
     let node = match parser.current() {
         // Inbuilt type
+        Identifier(v) if is_qualified(parser) => {
+            let (node, is_var) = parse_qualified(parser, &v)?;
+            lvalue = is_var;
+            node
+        },
         Identifier(v) => {
             lvalue = check_name(parser, &v);
             parser.next();
-            ASTNode::VarExpr(
-                parser.name.clone() + "::" + &v
-            )
+            ASTNode::VarExpr(resolve_name(parser, &v))
         },
         Str(s)        => { parser.next(); ASTNode::StringExpr(s)  },
         Int(i)        => { parser.next(); ASTNode::NumberExpr(i)  },
@@ -638,18 +1125,49 @@ fn parse_base_expr(parser: &mut Parser) -> Option<ExprNode> {
         Byte(b)       => { parser.next(); ASTNode::ByteExpr(b)    },
         // Lists
         Lbracket => parse_list(parser)?,
+        // Lambdas
+        Lambda => parse_lambda(parser)?,
+        // Await (`await task`)
+        Await => {
+            let span = Span::between(&parser.tokens[parser.at], &parser.tokens[parser.at]);
+            parser.next();
+            let task = parse_unary(parser)?.node;
+            ASTNode::CallExpr(
+                Box::new(ASTNode::VarExpr("::__burlap_await".to_string())),
+                vec![task], span
+            )
+        },
+        // Sets and dict comprehensions
+        Lbrace => parse_brace(parser)?,
         // Nested expressions
         Lparan => {
             parser.next();
+            // Empty tuple
+            if parser.current() == Rparan {
+                parser.next();
+                return Some(ExprNode { node: tuple_expr(vec![]), lvalue: false });
+            }
             let ret = parse_expr(parser)?;
-            eat!(parser, Rparan, "expecting )")?;
-            return Some(ret);
+            if parser.current() != Comma {
+                eat!(parser, Rparan, "expecting )")?;
+                return Some(ret);
+            }
+            // Tuples (`(1, "a")` or `(1,)`)
+            let mut vals = vec![ret.node];
+            while parser.current() == Comma {
+                if parser.next() == Rparan {
+                    break;
+                }
+                vals.push(parse_expr(parser)?.node);
+            }
+            eat!(parser, Rparan, "expected comma or ')'")?;
+            tuple_expr(vals)
         },
         // Operators in the wrong spot
         EqualsEquals | NotEquals | Lt | Gt | LtEquals | GtEquals
         | PlusEquals | MinusEquals | TimesEquals | DivEquals
         | Plus | Minus | Times | Div | Modulo | And | Or | Xor
-        | PlusPlus | MinusMinus | Not | ModEquals => {
+        | BitOr | BitAnd | PlusPlus | MinusMinus | Not | ModEquals => {
             error!(
                 parser,
                 "invalid use of operator, did you forgot something before/after?"
@@ -684,6 +1202,7 @@ fn parse_statement(parser: &mut Parser) -> Option<StmtNode> {
         },
         // Functions
         Func(_) => parse_functi(parser, false).map(|i| i.0),
+        Async => parse_async(parser),
         // Var def
         Let => parse_let(parser),
         // Loop
@@ -720,6 +1239,7 @@ fn parse_statement(parser: &mut Parser) -> Option<StmtNode> {
         },
         // Return
         Return => parse_return(parser),
+        Defer => parse_defer(parser),
         // If
         If => parse_if(parser),
         Else => {
@@ -727,6 +1247,7 @@ fn parse_statement(parser: &mut Parser) -> Option<StmtNode> {
             parser.next();
             Option::None
         },
+        Export => parse_export(parser),
         // Imports
         Import => {
             error!(parser, "imports must be at highest level");
@@ -759,6 +1280,49 @@ fn parse_statement(parser: &mut Parser) -> Option<StmtNode> {
     });
 }
 
+// Ends a scope, its variables become locals of the current functi
+fn end_scope(parser: &mut Parser, start: usize) {
+    let vars = parser.ast.cur_vars.split_off(start);
+    parser.functi_slots.extend((0..vars.len()).map(|i| start + i - parser.func_start));
+    parser.functi_locals.extend(vars);
+}
+
+// Picks a slot for each local, positions are where they were in cur_vars
+// Blocks that don't overlap put their variables in the same places, so they can share
+fn local_slots(locals: &[Variable], positions: &[usize], arg_num: usize, share: bool) -> Vec<u16> {
+    let mut slots: Vec<usize> = (0..locals.len()).collect();
+    let others = locals.len() - arg_num;
+    if share {
+        // Sibling blocks can reuse a name, and lookups only find the first one
+        let reused = |i: usize| locals.iter()
+            .filter(|v| v.real_name == locals[i].real_name).count() > 1;
+        let mut size = 0;
+        for i in 0..others {
+            if !reused(i) {
+                slots[i] = positions[i] - arg_num;
+                size = size.max(slots[i] + 1);
+            }
+        }
+        for i in 0..others {
+            if !reused(i) {
+                continue;
+            }
+            let first = locals.iter().position(|v| v.real_name == locals[i].real_name).unwrap();
+            if first == i {
+                slots[i] = size;
+                size += 1;
+            } else {
+                slots[i] = slots[first];
+            }
+        }
+        // Args go after everything else
+        for (j, slot) in slots[others..].iter_mut().enumerate() {
+            *slot = size + j;
+        }
+    }
+    slots.into_iter().map(|s| s as u16).collect()
+}
+
 // Bodies
 fn parse_body(parser: &mut Parser) -> Option<ASTNode> {
     // Start
@@ -767,6 +1331,8 @@ fn parse_body(parser: &mut Parser) -> Option<ASTNode> {
     let mut body: Vec<StmtNode> = vec![];
     let mut err = false;
     let old_len = parser.ast.cur_vars.len();
+    let old_scope_start = parser.scope_start;
+    parser.scope_start = old_len;
     loop {
         // Exit loop on } or EOF
         if let Rbrace | Eof = parser.current() {
@@ -785,7 +1351,17 @@ fn parse_body(parser: &mut Parser) -> Option<ASTNode> {
         }
     }
     // End
-    parser.functi_locals.append(&mut parser.ast.cur_vars.split_off(old_len));
+    let unused: Vec<(String, usize)> = parser.ast.cur_vars[old_len..].iter()
+        .filter(|v| v.count == 0 && !v.name.starts_with('_') && !v.name.contains('$'))
+        .map(|v| (v.name.clone(), v.decl)).collect();
+    for (name, decl) in unused {
+        warning!(
+            parser, decl, "unused",
+            format!("\"{}\" is never used", name).as_str()
+        );
+    }
+    end_scope(parser, old_len);
+    parser.scope_start = old_scope_start;
     eat!(parser, Rbrace, "expected } to end body, not EOF")?;
     if err {
         return Option::None;
@@ -827,15 +1403,14 @@ fn parse_if(parser: &mut Parser) -> Option<ASTNode> {
 // Loops
 fn parse_loop_iter(parser: &mut Parser) -> Option<ASTNode> {
     // Name
-    let name: String;
-    if let Identifier(n) = parser.current() {
-        name = parser.name.clone() + "::" + &n;
-    } else {
+    let Identifier(name) = parser.current() else {
         error!(parser, "expected variable name");
         return Option::None;
-    }
+    };
     let old_len = parser.ast.cur_vars.len();
     let uniq_var = _check_unique(parser, &name, -1);
+    // Loop variables reuse existing variables instead of shadowing them
+    let name = resolve_name(parser, &name);
     parser.next();
     // Obligatory 'in'
     eat!(parser, In, "missing 'in' keyword in loop")?;
@@ -847,7 +1422,7 @@ fn parse_loop_iter(parser: &mut Parser) -> Option<ASTNode> {
     }
     let already_defined = !uniq_var.unwrap_or(true);
     // Range optimization
-    if let ASTNode::CallExpr(expr, args) = iter.clone() {
+    if let ASTNode::CallExpr(expr, args, span) = iter.clone() {
         let name = if let ASTNode::VarExpr(n) = *expr {
             n.clone().split("::").nth(1).unwrap_or(n.as_str()).to_string()
         } else {
@@ -855,17 +1430,17 @@ fn parse_loop_iter(parser: &mut Parser) -> Option<ASTNode> {
         };
         if name == *"range" {
             // Arg check
-            if args.len() != 2 {
+            if args.len() != 2 && args.len() != 3 {
                 error!(
                     parser,
-                    format!("range takes 2 args, not {}", args.len()).as_str()
+                    format!("range takes 2 or 3 args, not {}", args.len()).as_str()
                 );
                 return Option::None;
             }
             // Use the faster range
             iter = ASTNode::CallExpr(
                 Box::new(ASTNode::VarExpr("::__burlap_range".to_string())),
-                args
+                args, span
             );
         }
     }
@@ -873,7 +1448,7 @@ fn parse_loop_iter(parser: &mut Parser) -> Option<ASTNode> {
     eat!(parser, Rparan, "missing ')' in loop")?;
     // Body
     let body = into_stmt(parse_body, parser)?;
-    parser.functi_locals.append(&mut parser.ast.cur_vars.split_off(old_len));
+    end_scope(parser, old_len);
     // Return
     Some(ASTNode::IterLoopStmt(name, Box::new(iter), Box::new(body), already_defined))
 }
@@ -917,6 +1492,11 @@ fn parse_loop(parser: &mut Parser) -> Option<ASTNode> {
 }
 
 // Imports
+// What a file is known as for imports, so different paths to it are the same
+fn file_key(path: &Path) -> String {
+    canonicalize(path).unwrap_or(path.to_path_buf()).to_string_lossy().to_string()
+}
+
 fn parse_import(parser: &mut Parser) -> Option<(String, Vec<StmtNode>)> {
     // Eat import
     parser.next();
@@ -955,20 +1535,173 @@ fn parse_import(parser: &mut Parser) -> Option<(String, Vec<StmtNode>)> {
         }
     }
 
+    // Files only run once, even if they are imported again (or import each other)
+    let name = parser.args.name.clone();
+    let key = file_key(path);
+    // So its globals can be used as `file::name`
+    let short = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    if !parser.ast.modules.iter().any(|(_, m)| *m == name) {
+        parser.ast.modules.push((short, name.clone()));
+    }
+    if parser.ast.imported.contains(&key) {
+        parser.args.name = old_name;
+        parser.args.path = old_path;
+        eat_semicolon!(parser)?;
+        return Some((name, vec![]));
+    }
+    parser.ast.imported.push(key);
+
     // Return
-    let mut new_ast = to_ast(&mut parser.args)?;
+    let tokens = lex(
+        &parser.args.source, parser.args.name.clone(), true,
+        parser.args.extension_color,
+    )?;
+    parser.args.source = "".to_string();
+    let mut new_ast = AST::new();
+    new_ast.imported = take(&mut parser.ast.imported);
+    let mut new_ast = parse(new_ast, tokens, &parser.args)?;
+    parser.ast.imported = take(&mut new_ast.imported);
+    parser.args.name = old_name;
+    parser.args.path = old_path;
+    if !new_ast.exports.is_empty() {
+        hide_private(parser, &mut new_ast, &name);
+    }
+    report_collisions(parser, &new_ast);
+    for module in take(&mut new_ast.modules) {
+        if !parser.ast.modules.contains(&module) {
+            parser.ast.modules.push(module);
+        }
+    }
     parser.ast.functis.append(&mut new_ast.functis);
     parser.ast.cur_vars.append(&mut new_ast.cur_vars);
     parser.ast.all_vars.append(&mut new_ast.all_vars);
-    let name = parser.args.name.clone();
-    parser.args.name = old_name;
-    parser.args.path = old_path;
+    parser.ast.docs.append(&mut new_ast.docs);
 
     // Semicolon
     eat_semicolon!(parser)?;
     return Some((name, new_ast.nodes));
 }
 
+// Renames a functi, and the uses of it in the file it's from
+fn rename_functi(node: &mut ASTNode, file: &str, from: &str, to: &str) {
+    let sub = |node: &mut ASTNode| rename_functi(node, file, from, to);
+    match node {
+        ASTNode::VarExpr(name) if name.split_once("::") == Some((file, from)) => {
+            *name = format!("{}::{}", file, to);
+        },
+        ASTNode::CallExpr(functi, args, _) => {
+            sub(functi);
+            args.iter_mut().for_each(sub);
+        },
+        ASTNode::IndexExpr(lhs, rhs, _) | ASTNode::BinopExpr(lhs, _, rhs, _) => {
+            sub(lhs);
+            sub(rhs);
+        },
+        ASTNode::UnaryExpr(_, val) | ASTNode::TryExpr(val) | ASTNode::ReturnStmt(val) => sub(val),
+        ASTNode::ListExpr(keys, vals, _) => keys.iter_mut().chain(vals).for_each(sub),
+        ASTNode::LetStmt(_, vals) => vals.iter_mut().for_each(sub),
+        ASTNode::BlockExpr(body, val) => {
+            body.iter_mut().for_each(|stmt| sub(&mut stmt.node));
+            sub(val);
+        },
+        ASTNode::BodyStmt(body) => body.iter_mut().for_each(|stmt| sub(&mut stmt.node)),
+        ASTNode::FunctiStmt(functi) => {
+            if functi.name == from {
+                functi.name = to.to_string();
+            }
+            sub(&mut functi.body.node);
+        },
+        ASTNode::IfStmt(cond, body, else_body) => {
+            sub(cond);
+            sub(&mut body.node);
+            sub(&mut else_body.node);
+        },
+        ASTNode::IterLoopStmt(_, iter, body, _) | ASTNode::WhileStmt(iter, body) => {
+            sub(iter);
+            sub(&mut body.node);
+        },
+        ASTNode::DeferStmt(body) | ASTNode::LoopStmt(body) => sub(&mut body.node),
+        _ => {},
+    }
+}
+
+// Hides what an imported file didn't export, and drops private functis it didn't use
+fn hide_private(parser: &Parser, new_ast: &mut AST, file: &str) {
+    let exports = take(&mut new_ast.exports);
+    new_ast.cur_vars.retain(|v| v.module != file || exports.contains(&v.name));
+    for functi in &mut new_ast.functis {
+        if functi.module == file && !exports.contains(&functi.name) {
+            functi.private = true;
+        }
+    }
+    if parser.args.opt_level > 0 {
+        // Nothing else can call them, so they can go if the file didn't
+        // Hidden functis (lambdas and async bodies) are used without being looked up
+        let used = |name: &String| name.contains('$') || new_ast.functis.iter()
+            .any(|f| f.name == *name && (!f.private || f.count > 0));
+        let unused: Vec<String> = new_ast.functis.iter()
+            .filter(|f| f.private && !used(&f.name)).map(|f| f.name.clone()).collect();
+        new_ast.nodes.retain(|n|
+            !matches!(&n.node, ASTNode::FunctiStmt(f) if unused.contains(&f.name))
+        );
+        new_ast.functis.retain(|f| !unused.contains(&f.name));
+    }
+    // The rest get their own names, so the file importing it can use the same ones
+    let stem = Path::new(file).file_stem().unwrap_or_default().to_string_lossy().to_string();
+    for functi in &mut new_ast.functis {
+        if functi.private && functi.module == file && !functi.name.contains('$') {
+            let to = format!("{}${}", functi.name, stem);
+            for node in &mut new_ast.nodes {
+                rename_functi(&mut node.node, file, &functi.name, &to);
+            }
+            functi.name = to;
+        }
+    }
+}
+
+// The file a functi called name is from, if it didn't export it
+fn hidden_from(parser: &Parser, name: &str) -> Option<String> {
+    parser.ast.functis.iter()
+        .find(|f| is_hidden(parser, f) && f.name.split('$').next() == Some(name))
+        .map(|f| f.module.clone())
+}
+
+// Globals of an imported file with the same name as ones that already exist
+fn report_collisions(parser: &mut Parser, new_ast: &AST) {
+    for var in &new_ast.cur_vars {
+        if var.name.contains('$') || var.name.starts_with("__") {
+            continue;
+        }
+        let Some(old) = parser.ast.cur_vars.iter().find(|v| v.name == var.name) else {
+            continue;
+        };
+        if old.module != var.module {
+            let short = Path::new(&var.module).file_stem().unwrap_or_default().to_string_lossy();
+            warning!(
+                parser, "collision",
+                format!(
+                    "\"{}\" from {} is also a global in {}, use {}::{} for it",
+                    var.name, var.module, old.module, short, var.name
+                ).as_str()
+            );
+        }
+    }
+    // Functis don't have their own globals, so they can't share a name
+    for functi in &new_ast.functis {
+        if let Some(old) = parser.ast.functis.iter()
+            .find(|f| f.name == functi.name && f.arg_num == functi.arg_num && f.module != functi.module)
+        {
+            error!(
+                parser,
+                format!(
+                    "\"{}\" in {} is already defined in {} with the same number of args",
+                    functi.name, functi.module, old.module
+                ).as_str()
+            );
+        }
+    }
+}
+
 // Variable definition
 fn parse_let(parser: &mut Parser) -> Option<ASTNode> {
     let mut names = vec![];
@@ -977,27 +1710,33 @@ fn parse_let(parser: &mut Parser) -> Option<ASTNode> {
         if let Semicolon = parser.current() {
             break;
         }
-        let (name, value) = _parse_let(parser)?;
-        names.push(name);
-        values.push(value);
+        for (name, value) in _parse_let(parser)? {
+            names.push(name);
+            values.push(value);
+        }
     }
     // Semicolon
     eat_semicolon!(parser)?;
     return Some(ASTNode::LetStmt(names, values));
 }
 
-fn _parse_let(parser: &mut Parser) -> Option<(String, ASTNode)> {
+fn _parse_let(parser: &mut Parser) -> Option<Vec<(String, ASTNode)>> {
     // Eat let or comma
     parser.next();
+    if parser.current() == Lparan {
+        return parse_let_unpack(parser);
+    }
     // Get var name
-    let name: String;
-    if let Identifier(n) = parser.current() {
-        name = parser.name.clone() + "::" + &n;
-    } else {
+    let Identifier(name) = parser.current() else {
         error!(parser, "expected variable name");
         return Option::None;
-    }
+    };
     let uniq_var = check_unique(parser, &name, -1);
+    let name = if let Err(ref var) = uniq_var {
+        parser.name.clone() + "::" + &var.real_name
+    } else {
+        parser.name.clone() + "::" + &name
+    };
     parser.next();
     // Let without value (non-standard)
     if let Semicolon = parser.current() {
@@ -1006,7 +1745,7 @@ fn _parse_let(parser: &mut Parser) -> Option<(String, ASTNode)> {
             parser.ast.add_var(var.clone());
         }
         if parser.args.extension_auto_none {
-            return Some((name, ASTNode::NoneExpr));
+            return Some(vec![(name, ASTNode::NoneExpr)]);
         } else {
             error!(parser, "let must have value");
             error!(
@@ -1027,10 +1766,64 @@ fn _parse_let(parser: &mut Parser) -> Option<(String, ASTNode)> {
         parser.ast.add_var(var.clone());
     }
     // Return
-    return Some((name, value));
+    return Some(vec![(name, value)]);
+}
+
+fn parse_let_unpack(parser: &mut Parser) -> Option<Vec<(String, ASTNode)>> {
+    // Unpacking (`let (a, b) = pair;`)
+    parser.next();
+    let mut names: Vec<String> = vec![];
+    loop {
+        let Identifier(name) = parser.current() else {
+            error!(parser, "expected variable name");
+            return Option::None;
+        };
+        names.push(name);
+        if parser.next() != Comma {
+            break;
+        }
+        parser.next();
+    }
+    eat!(parser, Rparan, "expected ')' at end of variable names")?;
+    eat!(parser, Equals, "expected '=' in variable declaration")?;
+    let value = parse_expr(parser)?.node;
+    // The value is stored in a hidden var, then indexed
+    let real_name = format!("__unpack${}", parser.ast.all_vars.len());
+    parser.ast.add_var(Variable {
+        name: real_name.clone(), real_name: real_name.clone(), count: 0,
+        decl: parser.at, module: parser.name.clone()
+    });
+    let hidden = parser.name.clone() + "::" + &real_name;
+    let mut ret = vec![(hidden.clone(), value)];
+    for (at, name) in names.iter().enumerate() {
+        if let Err(var) = check_unique(parser, name, -1) {
+            parser.ast.add_var(var);
+        }
+        ret.push((resolve_name(parser, name), ASTNode::IndexExpr(
+            Box::new(ASTNode::VarExpr(hidden.clone())),
+            Box::new(ASTNode::NumberExpr(at as i32)), Span::default()
+        )));
+    }
+    return Some(ret);
 }
 
 // Returning
+fn parse_defer(parser: &mut Parser) -> Option<ASTNode> {
+    // Deferred code runs after its block ends, so the functi's locals can't share slots
+    parser.has_defer = true;
+    // Function check
+    if !parser.in_func {
+        error!(parser, "defer outside of functions");
+        parser.next();
+        return Option::None;
+    }
+    // Eat defer
+    parser.next();
+    let line = parser.tokens[parser.at].stream.line;
+    let node = parse_binop_set(parser)?;
+    eat_semicolon!(parser)?;
+    return Some(ASTNode::DeferStmt(Box::new(StmtNode { node, line })));
+}
 fn parse_return(parser: &mut Parser) -> Option<ASTNode> {
     // Function check
     if !parser.in_func {
@@ -1065,24 +1858,127 @@ fn parse_return(parser: &mut Parser) -> Option<ASTNode> {
 
 // Functions
 fn parse_functi(parser: &mut Parser, anon: bool) -> Option<(ASTNode, String)> {
-    // Disallow named functions in functions
-    if parser.in_func && !anon {
+    // Eat functi
+    parser.next();
+    // Name
+    let Identifier(name) = parser.current() else {
+        error!(parser, "expected function name");
+        return Option::None;
+    };
+    parser.next();
+    return parse_functi_scoped(parser, anon, name, false);
+}
+
+// Exports (`export functi f() { ... }` or `export let x = 1;`)
+fn parse_export(parser: &mut Parser) -> Option<ASTNode> {
+    if parser.in_func || parser.scope_start != 0 {
+        error!(parser, "exports must be at highest level");
         parser.next();
-        error!(parser, "cannot create function in function");
         return Option::None;
     }
-    // Eat functi
+    let (Func(_) | Async | Let) = parser.next() else {
+        error!(parser, "expected 'functi', 'async', or 'let' after 'export'");
+        return Option::None;
+    };
+    let (vars, functis) = (parser.ast.cur_vars.len(), parser.ast.functis.len());
+    let ret = parse_statement(parser)?.node;
+    // Everything it declared
+    let names: Vec<String> = parser.ast.cur_vars[vars..].iter().map(|v| v.name.clone())
+        .chain(parser.ast.functis[functis..].iter().map(|f| f.name.clone())).collect();
+    parser.ast.exports.extend(names);
+    return Some(ret);
+}
+
+// Async functis (`async functi f(a) { ... }`)
+fn parse_async(parser: &mut Parser) -> Option<ASTNode> {
+    // Eat async
     parser.next();
-    // Name
-    let mut name: String;
-    if let Identifier(n) = parser.current() {
-        name = n;
-    } else {
+    let Func(_) = parser.current() else {
+        error!(parser, "expected 'functi' after 'async'");
+        return Option::None;
+    };
+    parser.next();
+    let Identifier(name) = parser.current() else {
         error!(parser, "expected function name");
         return Option::None;
+    };
+    let line = parser.tokens[parser.at].stream.line;
+    parser.next();
+    // The wrapper is declared before the body, so the functi can call itself
+    let arg_num = parser.tokens[parser.at..].iter().take_while(|t| t.token != Rparan)
+        .filter(|t| matches!(t.token, Identifier(_))).count() as i32;
+    let unique = check_unique(parser, &name, arg_num).is_ok();
+    // The real functi
+    let real_name = format!("{}$async", name);
+    let (functi, _) = parse_functi_scoped(parser, false, real_name.clone(), false)?;
+    // And a wrapper that gives back a task for it (`return __burlap_task(f$async, args)`)
+    if unique {
+        let data = parser.ast.functis.iter_mut()
+            .find(|f| f.name == name && f.arg_num == arg_num).unwrap();
+        data.locals = (0..arg_num).map(|i| Variable {
+            name: format!("__async_arg${}", i),
+            real_name: format!("__async_arg${}", i), count: 1, decl: 0,
+            module: parser.name.clone()
+        }).collect();
     }
+    let args = (0..arg_num).map(|i| ASTNode::VarExpr(
+        format!("{}::__async_arg${}", parser.name, i)
+    )).collect::<Vec<ASTNode>>();
+    let call = ASTNode::CallExpr(
+        Box::new(ASTNode::VarExpr("::__burlap_task".to_string())),
+        std::iter::once(ASTNode::VarExpr(format!("::{}", real_name))).chain(args).collect(),
+        Span::default()
+    );
+    let wrapper = ASTNode::FunctiStmt(FunctiNode {
+        name,
+        body: Box::new(StmtNode { line, node: ASTNode::BodyStmt(vec![
+            StmtNode { line, node: ASTNode::ReturnStmt(Box::new(call)) }
+        ])}),
+    });
+    return Some(ASTNode::BodyStmt(vec![
+        StmtNode { line, node: functi },
+        StmtNode { line, node: wrapper },
+    ]));
+}
+
+// Lambdas (`fn(a, b) -> a + b`)
+fn parse_lambda(parser: &mut Parser) -> Option<ASTNode> {
+    // Eat fn
     parser.next();
-    parser.functi_locals = vec![];
+    parse_functi_scoped(parser, true, "lambda".to_string(), true).map(|i| i.0)
+}
+
+fn parse_functi_scoped(
+    parser: &mut Parser, anon: bool, name: String, lambda: bool
+) -> Option<(ASTNode, String)> {
+    // Hide the outer function's variables, there are no captures
+    let hide_from = if parser.in_func {
+        parser.func_start
+    } else {
+        parser.ast.cur_vars.len()
+    };
+    let mut hidden = parser.ast.cur_vars.split_off(hide_from);
+    let old_func_start = parser.func_start;
+    let old_scope_start = parser.scope_start;
+    let old_locals = std::mem::take(&mut parser.functi_locals);
+    let old_slots = std::mem::take(&mut parser.functi_slots);
+    let old_defer = std::mem::replace(&mut parser.has_defer, false);
+    parser.func_start = hide_from;
+    parser.scope_start = 0;
+    let ret = _parse_functi(parser, anon, name, lambda);
+    // Restore the outer function
+    parser.ast.cur_vars.append(&mut hidden);
+    parser.func_start = old_func_start;
+    parser.scope_start = old_scope_start;
+    parser.functi_locals = old_locals;
+    parser.functi_slots = old_slots;
+    parser.has_defer = old_defer;
+    return ret;
+}
+
+fn _parse_functi(
+    parser: &mut Parser, anon: bool, mut name: String, lambda: bool
+) -> Option<(ASTNode, String)> {
     // Args
     eat!(parser, Lparan, "expected '(' at start of argument list")?;
     let mut arg_names: Vec<String> = vec![];
@@ -1093,9 +1989,11 @@ fn parse_functi(parser: &mut Parser, anon: bool) -> Option<(ASTNode, String)> {
         // Arg name
         if let Identifier(n) = parser.current() {
             if let Err(var) = check_unique(parser, &n, -1) {
-                parser.ast.add_var(var.clone());
+                arg_names.push(parser.name.clone() + "::" + &var.real_name);
+                parser.ast.add_var(var);
+            } else {
+                arg_names.push(parser.name.clone() + "::" + &n);
             }
-            arg_names.push(parser.name.clone() + "::" + &n);
             parser.next();
         } else {
             error!(parser, "expected argument name");
@@ -1117,12 +2015,18 @@ fn parse_functi(parser: &mut Parser, anon: bool) -> Option<(ASTNode, String)> {
     if anon {
         name = format!("__anon_functi${}", parser.ast.functis.len());
     }
+    let fn_index = parser.ast.functis.len();
     if let Err(var) = check_unique(parser, &name, arg_names.len().try_into().unwrap()) {
         parser.ast.add_var(var.clone());
     }
-    let fn_index = parser.ast.functis.len() - 1;
+    if parser.ast.functis.len() == fn_index {
+        // The name was taken, so it was never added
+        return Option::None;
+    }
     // Body
-    if let Lbrace = parser.current() {} else {
+    if lambda {
+        eat!(parser, Arrow, "expected '->' after lambda arguments")?;
+    } else if let Lbrace = parser.current() {} else {
         error!(parser, "expected '{' to start function body");
         err(
             &parser.tokens[parser.at].stream,
@@ -1132,14 +2036,33 @@ fn parse_functi(parser: &mut Parser, anon: bool) -> Option<(ASTNode, String)> {
         return Option::None;
     }
     let old_in = parser.in_func;
+    let old_in_loop = parser.in_loop;
     parser.in_func = true;
-    let body = into_stmt(parse_body, parser);
+    parser.in_loop = false;
+    let body = if lambda {
+        // The body is just a returned expression
+        let line = parser.tokens[parser.at].stream.line;
+        parse_expr(parser).map(|ret| StmtNode { line, node: ASTNode::BodyStmt(vec![
+            StmtNode { line, node: ASTNode::ReturnStmt(Box::new(ret.node)) }
+        ])})
+    } else {
+        into_stmt(parse_body, parser)
+    };
     parser.in_func = old_in;
-    parser.functi_locals.append(&mut parser.ast.cur_vars.split_off(parser.ast.cur_vars.len() - arg_names.len()));
+    parser.in_loop = old_in_loop;
+    // Args that had errors weren't added
+    let args_start = parser.ast.cur_vars.len().saturating_sub(arg_names.len());
+    end_scope(parser, args_start.max(parser.func_start));
     swap(
         &mut parser.ast.functis[fn_index].locals,
         &mut parser.functi_locals
     );
+    let positions = std::mem::take(&mut parser.functi_slots);
+    // With errors it won't be compiled, and the args might not all be there
+    if !parser.has_err {
+        let functi = &mut parser.ast.functis[fn_index];
+        functi.slots = local_slots(&functi.locals, &positions, arg_names.len(), !parser.has_defer);
+    }
     // Return
     return Some((ASTNode::FunctiStmt(FunctiNode {
         name,
@@ -1147,26 +2070,125 @@ fn parse_functi(parser: &mut Parser, anon: bool) -> Option<(ASTNode, String)> {
     }), old_name));
 }
 
+// Gets the `###` comment right above a line
+fn get_doc(parser: &Parser, line: usize) -> String {
+    let mut lines = vec![];
+    let mut line = line;
+    while let Some((_, doc)) = parser.doc_comments.iter().find(|(l, _)| *l + 1 == line) {
+        lines.push(doc.as_str());
+        line -= 1;
+    }
+    lines.reverse();
+    lines.join("\n")
+}
+
+// Records docs for a top level statement
+fn add_docs(parser: &mut Parser, start: usize, node: &ASTNode) {
+    let line = parser.tokens[start].stream.line;
+    let doc = get_doc(parser, line);
+    let file = parser.args.name.clone();
+    let mut at = start;
+    if parser.tokens[at].token == Async {
+        at += 1;
+    }
+    if let Func(_) = parser.tokens[at].token {
+        // Signature from the tokens (`functi name(args)`)
+        let Identifier(name) = parser.tokens[at + 1].token.clone() else {
+            return;
+        };
+        let args: Vec<String> = parser.tokens[at + 2..].iter()
+            .take_while(|t| t.token != Rparan)
+            .filter_map(|t| if let Identifier(a) = &t.token {
+                Some(a.clone())
+            } else {
+                Option::None
+            }).collect();
+        let prefix = if at == start { "" } else { "async " };
+        let signature = format!("{}functi {}({})", prefix, name, args.join(", "));
+        // Kept for help() at runtime
+        if let Some(data) = parser.ast.functis.iter_mut()
+            .find(|f| f.name == name && f.arg_num == args.len() as i32) {
+            data.doc = doc.clone();
+        }
+        parser.ast.docs.push(DocItem { name, signature, doc, file, line });
+    } else if let ASTNode::LetStmt(names, _) = node {
+        for name in names {
+            let name = name.split("::").nth(1).unwrap_or(name);
+            if name.contains('$') {
+                continue;
+            }
+            parser.ast.docs.push(DocItem {
+                name: name.to_string(), signature: format!("let {}", name),
+                doc: doc.clone(), file: file.clone(), line
+            });
+        }
+    }
+}
+
 // Main parsing
+// Checks if (non-error printing) lexed input is unfinished, for REPL line continuation
+pub fn needs_more_input(tokens: &[Token]) -> bool {
+    let mut depth = 0;
+    let mut last = &Eof;
+    for token in tokens {
+        match &token.token {
+            // Unterminated string
+            Invalid if token.str.starts_with('"') || token.str.starts_with('\'') => {
+                return true;
+            },
+            Lparan | Lbracket | Lbrace => depth += 1,
+            Rparan | Rbracket | Rbrace => depth -= 1,
+            Skipped | Comment(_) | Newline | Eof => continue,
+            _ => {},
+        }
+        last = &token.token;
+    }
+    if depth > 0 {
+        return true;
+    }
+    // Trailing operators need a right hand side
+    return matches!(last,
+        Plus | Minus | Times | Div | Modulo | BitOr | BitAnd | And | Or | Xor
+        | Not | EqualsEquals | NotEquals | Lt | Gt | LtEquals | GtEquals
+        | Equals | PlusEquals | MinusEquals | TimesEquals | DivEquals
+        | ModEquals | Comma | Colon | Arrow | Pipe | In | Else
+    );
+}
+
 pub fn parse(ast: AST, tokens: Vec<Token>, args: &Arguments) -> Option<AST> {
     if tokens.is_empty() {
         return Some(ast);
     }
+    // Comments are kept by the lexer for tools, the parser only needs docs
+    let doc_comments = tokens.iter().filter_map(|t| match &t.token {
+        Comment(c) if c.starts_with("##") => Some((
+            t.stream.line, c[2..].strip_prefix(' ').unwrap_or(&c[2..]).to_string()
+        )),
+        _ => Option::None,
+    }).collect();
+    let tokens = tokens.into_iter()
+        .filter(|t| !matches!(t.token, Comment(_))).collect();
     // Set up
     let mut parser = Parser {
         tokens, args: args.clone(),
         at: 0, has_err: false, ast,
-        in_loop: false, in_func: false,
+        in_loop: false, in_func: false, scope_start: 0, func_start: 0,
+        no_colon: false,
         name: args.name.clone(),
-        functi_locals: vec![],
+        functi_locals: vec![], functi_slots: vec![], has_defer: false, doc_comments,
     };
+    // The main file can't be imported either
+    if parser.ast.imported.is_empty() {
+        parser.ast.imported.push(file_key(&args.path));
+    }
     // Parse
     while parser.current() != Eof {
         // Import must be highest scope
         if parser.current() == Import {
             let line = parser.tokens[parser.at].stream.line;
             if let Some((path, mut imported_ast)) = parse_import(&mut parser) {
-                parser.ast.nodes.push(StmtNode{node: ASTNode::ImportStmt, line});
+                let file = parser.args.name.clone();
+                parser.ast.nodes.push(StmtNode{node: ASTNode::ImportStmt(file), line});
                 parser.ast.nodes.append(&mut imported_ast);
                 parser.ast.nodes.push(StmtNode{node: ASTNode::EndImportStmt(path), line});
             } else {
@@ -1174,7 +2196,9 @@ pub fn parse(ast: AST, tokens: Vec<Token>, args: &Arguments) -> Option<AST> {
             };
             continue;
         }
+        let start = parser.at;
         if let Some(stmt) = parse_statement(&mut parser) {
+            add_docs(&mut parser, start, &stmt.node);
             parser.ast.nodes.push(stmt);
             continue;
         };
@@ -0,0 +1,284 @@
+// `burlap repl --listen <addr>`, a REPL that editors and other programs send code to
+// Each line is run in the same session, and the reply is:
+//     out <line>      for every line that was printed
+//     error <msg>     for every error
+//     ok <result>     or `fail` if there was an error
+//     exit <code>     instead, if it called exit() (the session keeps going)
+// With `--token <token>` the first line has to be `auth <token>`, which gets `ok` back
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::{to_ast, Arguments};
+use crate::common::{capture_output, print_err, ErrType};
+use crate::lexer::lex;
+use crate::parser::needs_more_input;
+use crate::backend::value::Value;
+use crate::backend::vm::compiler::{compile, Compiler};
+use crate::backend::vm::vm::Vm;
+
+// A VM that code can be added to, for the remote REPL and the Jupyter kernel
+pub fn new_session(args: &Arguments, name: &str) -> Option<Vm> {
+    let mut args = args.clone();
+    args.name = name.to_string();
+    args.extension_color = false;
+    // Compiled code is added to the end of the program, so it can't be empty
+    args.source = "none;".to_string();
+    let mut ast = to_ast(&mut args)?;
+    let mut compiler = Compiler::new();
+    if !compile(&mut ast, &Some(args.name.clone()), &mut compiler, false) {
+        return None;
+    }
+    ast.nodes = vec![];
+    let mut vm = Vm::new(args, compiler.program);
+    vm.ast = Some(ast);
+    return Some(vm);
+}
+
+// Runs code in a session, and gets the result
+pub fn run_in_session(vm: &mut Vm, code: &str) -> Result<Value, String> {
+    let name = vm.args.name.clone();
+    match vm.compile_code(code, &name, None)? {
+        // Errors aren't printed by nested runs, so say what kind it is
+        Some(start) => vm.run_code(start).map_err(|e| format!("Runtime Error: {}", e)),
+        None => Ok(Value::None),
+    }
+}
+
+// Runs a request, and makes the reply
+fn eval(vm: &mut Vm, code: &str) -> String {
+    capture_output(true);
+    let ret = run_in_session(vm, code);
+    let captured = capture_output(false).unwrap_or_default();
+    let mut reply = String::new();
+    for line in captured.out.lines() {
+        reply += &format!("out {}\n", line);
+    }
+    let mut errors = captured.errors;
+    // exit() only ends this request
+    if let Some(code) = vm.take_exit_code() {
+        for error in errors {
+            reply += &format!("error {}\n", error);
+        }
+        reply += &format!("exit {}\n", code);
+        return reply;
+    }
+    match ret {
+        Ok(ret) => {
+            for error in errors {
+                reply += &format!("error {}\n", error);
+            }
+            if ret == Value::None {
+                reply += "ok\n";
+            } else {
+                reply += &format!("ok {}\n", ret.pretty(false).replace('\n', " "));
+            }
+        },
+        Err(e) => {
+            // Printed errors say more
+            if errors.is_empty() {
+                errors.push(e);
+            }
+            for error in errors {
+                reply += &format!("error {}\n", error);
+            }
+            reply += "fail\n";
+        },
+    }
+    return reply;
+}
+
+// How long to wait between checking the clients
+const POLL_DELAY: Duration = Duration::from_millis(5);
+
+// The longest line that's read, anyone can send one (even before the token)
+const MAX_LINE: usize = 16 * 1024 * 1024;
+
+// Someone connected to the REPL
+struct Client {
+    stream: TcpStream,
+    // What was read that isn't a full line yet
+    pending: Vec<u8>,
+    // Replies that haven't been sent yet
+    outgoing: Vec<u8>,
+    // Unfinished code, it continues on the next line like the REPL
+    code: String,
+    authed: bool,
+    // It won't send anything else, it's dropped once the replies are sent
+    closed: bool,
+}
+
+impl Client {
+    fn new(stream: TcpStream, authed: bool) -> io::Result<Client> {
+        // Reads and writes don't wait, so one client can't block the others
+        stream.set_nonblocking(true)?;
+        Ok(Client {
+            stream, pending: vec![], outgoing: vec![], code: String::new(), authed, closed: false
+        })
+    }
+
+    // The lines that came in since last time
+    fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    // Half closed, what it already sent still gets run
+                    self.closed = true;
+                    break;
+                },
+                Ok(size) => self.pending.extend_from_slice(&buf[..size]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            if self.pending.len() > MAX_LINE {
+                if !self.pending.contains(&b'\n') {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData, format!("line is too long (over {} bytes)", MAX_LINE)
+                    ));
+                }
+                // Run what's there before reading more
+                break;
+            }
+        }
+        let mut lines = vec![];
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            lines.push(String::from_utf8_lossy(&line).to_string());
+        }
+        Ok(lines)
+    }
+
+    fn reply(&mut self, reply: &str) {
+        self.outgoing.extend_from_slice(reply.as_bytes());
+    }
+
+    // Sends as much of the replies as it will take
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(size) => {
+                    self.outgoing.drain(..size);
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs what a client sent, false if it should be disconnected
+fn handle(vm: &mut Vm, client: &mut Client, token: &Option<String>) -> io::Result<bool> {
+    // Nothing else runs for it until it has read the last reply
+    client.flush()?;
+    if !client.outgoing.is_empty() {
+        return Ok(true);
+    }
+    if client.closed {
+        return Ok(false);
+    }
+    for line in client.read_lines()? {
+        if !client.authed {
+            if line.trim_end().strip_prefix("auth ") != token.as_deref() {
+                client.reply("error bad token\n");
+                client.closed = true;
+                break;
+            }
+            client.authed = true;
+            client.reply("ok\n");
+            continue;
+        }
+        client.code += &line;
+        if needs_more_input(&lex(&client.code, "<remote>".to_string(), false, false).unwrap_or(vec![])) {
+            continue;
+        }
+        let code = std::mem::take(&mut client.code);
+        if code.trim().is_empty() {
+            continue;
+        }
+        client.reply(&eval(vm, &code));
+    }
+    client.flush()?;
+    Ok(!client.closed || !client.outgoing.is_empty())
+}
+
+pub fn remote_repl(cli_args: impl Iterator<Item = String>, args: &Arguments) -> bool {
+    let mut addr = None;
+    let mut token = None;
+    let mut cli_args = cli_args;
+    while let Some(arg) = cli_args.next() {
+        if arg == "--listen" || arg == "--token" {
+            let Some(value) = cli_args.next() else {
+                print_err(&format!("'{}' is missing a value", arg), ErrType::Err, args.extension_color);
+                return false;
+            };
+            if arg == "--listen" {
+                addr = Some(value);
+            } else {
+                token = Some(value);
+            }
+        } else {
+            print_err(&format!("unknown argument: {}", arg), ErrType::Warn, args.extension_color);
+        }
+    }
+    let Some(addr) = addr else {
+        print_err("'repl' is missing '--listen <addr>'", ErrType::Err, args.extension_color);
+        return false;
+    };
+    let listener = match TcpListener::bind(&addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            print_err(&format!("failed to listen on {}: {}", addr, e), ErrType::Err, args.extension_color);
+            return false;
+        }
+    };
+    if token.is_none() && !listener.local_addr().is_ok_and(|a| a.ip().is_loopback()) {
+        print_err(
+            "anyone who can connect can run code, use `--token`", ErrType::Warn,
+            args.extension_color
+        );
+    }
+    // The real address, for port 0
+    let addr = listener.local_addr().map(|a| a.to_string()).unwrap_or(addr);
+    println!("Burlap v{} listening on {}", env!("CARGO_PKG_VERSION"), addr);
+    // One session, shared by everything that connects
+    let Some(mut vm) = new_session(args, "<remote>") else {
+        return false;
+    };
+    // Clients take turns, each request runs on its own
+    if let Err(e) = listener.set_nonblocking(true) {
+        print_err(&format!("failed to listen on {}: {}", addr, e), ErrType::Err, args.extension_color);
+        return false;
+    }
+    let mut clients: Vec<Client> = vec![];
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => match Client::new(stream, token.is_none()) {
+                Ok(client) => clients.push(client),
+                Err(e) => {
+                    print_err(&format!("connection failed: {}", e), ErrType::Warn, false);
+                },
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            Err(e) => {
+                print_err(&format!("failed to accept: {}", e), ErrType::Err, false);
+                break;
+            },
+        }
+        clients.retain_mut(|client| match handle(&mut vm, client, &token) {
+            Ok(open) => open,
+            Err(e) => {
+                print_err(&format!("connection failed: {}", e), ErrType::Warn, false);
+                false
+            },
+        });
+        sleep(POLL_DELAY);
+    }
+    vm.run_exit_hooks();
+    return true;
+}
@@ -1,14 +1,13 @@
 use crate::backend::vm::vm::{run, Vm};
+use crate::lexer::lex;
 #[cfg(feature = "fancyrepl")]
-use crate::lexer::{lex, TokenType};
-use crate::parser::{parse, AST};
-use crate::backend::vm::compiler::{compile, Compiler, Program};
+use crate::lexer::TokenType;
+use crate::parser::{needs_more_input, parse, Variable, AST};
+use crate::backend::vm::compiler::{IncrementalCompiler, Program};
 use crate::common::{print_err, ErrType};
-use crate::backend::vm::dis::dis;
+use crate::backend::vm::dis::{dis, dis_functi};
 use crate::Arguments;
 
-#[cfg(feature = "fancyrepl")]
-use rustyline::validate::MatchingBracketValidator;
 #[cfg(feature = "fancyrepl")]
 use rustyline::{Helper, Hinter, Validator};
 use rustyline::error::ReadlineError;
@@ -17,12 +16,12 @@ use rustyline::Editor;
 #[cfg(not(feature = "fancyrepl"))]
 use rustyline::DefaultEditor;
 use home::home_dir;
+use std::fs::{read_to_string, write};
+use std::time::Instant;
 
 #[cfg(feature = "fancyrepl")]
 #[derive(Helper, Hinter, Validator)]
 struct FancyRepl {
-    #[rustyline(Validator)]
-    brackets: MatchingBracketValidator,
     color: bool,
     name: String,
     symbols: Vec<String>
@@ -49,17 +48,18 @@ impl rustyline::highlight::Highlighter for FancyRepl {
             // Get the color of the token
             let color = match token.token {
                 // Misc keywords
-                TokenType::Let | TokenType::Func(..)
-                | TokenType::Import | TokenType::Bool(..)
+                TokenType::Let | TokenType::Func(..) | TokenType::Lambda
+                | TokenType::Import | TokenType::Export | TokenType::Bool(..)
                 | TokenType::None => Some("\x1b[32m"),
                 // Control flow
                 TokenType::If | TokenType::Else | TokenType::In
                 | TokenType::Str(..) | TokenType::While
                 | TokenType::Loop | TokenType::Continue
                 | TokenType::Break => Some("\x1b[1;33m"),
-                TokenType::Return => Some("\x1b[35m"),
+                TokenType::Return | TokenType::Defer | TokenType::Question
+                | TokenType::Async | TokenType::Await => Some("\x1b[35m"),
                 // Comments
-                TokenType::Skipped => Some("\x1b[1;36m"),
+                TokenType::Comment(_) => Some("\x1b[1;36m"),
                 // Operators
                 TokenType::EqualsEquals | TokenType::NotEquals
                 | TokenType::Lt | TokenType::Gt | TokenType::LtEquals
@@ -70,7 +70,9 @@ impl rustyline::highlight::Highlighter for FancyRepl {
                 | TokenType::PlusPlus | TokenType::MinusMinus
                 | TokenType::Not | TokenType::Equals | TokenType::And
                 | TokenType::Or | TokenType::Xor | TokenType::ModEquals
-                | TokenType::Colon => Some("\x1b[31m"),
+                | TokenType::Colon | TokenType::Arrow
+                | TokenType::Pipe | TokenType::BitOr
+                | TokenType::BitAnd => Some("\x1b[31m"),
                 // Numbers
                 TokenType::Int(_) | TokenType::Float(_) => Some("\x1b[1;35m"),
                 // Bytes
@@ -84,7 +86,8 @@ impl rustyline::highlight::Highlighter for FancyRepl {
                 // Lexer errors
                 TokenType::Invalid => Some("\x1b[41;30m"),
                 // Non-highlighted
-                TokenType::Eof | TokenType::Newline | TokenType::Lparan
+                TokenType::Eof | TokenType::Newline | TokenType::Skipped
+                | TokenType::Lparan
                 | TokenType::Rparan | TokenType::Lbracket
                 | TokenType::Lbrace | TokenType::Rbrace
                 | TokenType::Rbracket | TokenType::Semicolon
@@ -184,13 +187,23 @@ pub fn get_repl_line() -> &'static mut String {
     }
 }
 
+const REPL_HELP: &str = "\
+:help          Show this message
+:type <expr>   Print the type of an expression
+:dis [functi]  Disassemble a functi (or everything)
+:time <expr>   Run an expression and print how long it took
+:vars          List globals and their values
+:reset         Forget all variables and functis
+:save <file>   Save the code run so far to a file
+:load <file>   Run a file in the REPL";
+
 pub fn repl(args: &mut Arguments) {
     // Print welcome msg
     println!("Burlap v{}", env!("CARGO_PKG_VERSION"));
 
     // Set everything up
     let mut ast = AST::new();
-    let mut compiler = Compiler::new();
+    let mut compiler = IncrementalCompiler::new(Program::new(), args.opt_level);
     let mut vm = Vm::new(args.clone(), Program::new());
     #[cfg(feature = "fancyrepl")]
     let mut rl = {
@@ -198,7 +211,6 @@ pub fn repl(args: &mut Arguments) {
         let mut rl = Editor::new().unwrap();
         // Helpers
         rl.set_helper(Some(FancyRepl{
-            brackets: MatchingBracketValidator::new(),
             name: args.name.clone(),
             color: args.extension_color,
             symbols: vm.get_symbols(true)
@@ -226,6 +238,11 @@ pub fn repl(args: &mut Arguments) {
         );
     };
 
+    // Successfully run input (for :save)
+    let mut session: Vec<String> = vec![];
+    // The code from exit()
+    let mut exited = None;
+
     // REPL loop
     loop {
         // Get input
@@ -235,13 +252,135 @@ pub fn repl(args: &mut Arguments) {
             break;
         }
         // Input
-        if let Ok(line) = readline {
+        if let Ok(mut line) = readline {
+            // Keep reading while the input is unfinished
+            while needs_more_input(
+                &lex(&line, args.name.clone(), false, false).unwrap_or(vec![])
+            ) {
+                match rl.readline(".. ") {
+                    Ok(more) => line = line + "\n" + &more,
+                    // C-c/C-d drops the unfinished input
+                    Err(_) => {
+                        line = "".to_string();
+                        break;
+                    }
+                }
+            }
             // Add to history
             if !hist_file.is_empty() {
                rl.add_history_entry(line.clone())
                     .expect("failed to add line to history");
             }
-            args.source = line + ";";
+            // REPL commands
+            let mut timed = false;
+            let mut record = true;
+            if let Some(cmd) = line.trim().strip_prefix(':') {
+                let (cmd, rest) = cmd.split_once(' ').unwrap_or((cmd, ""));
+                let rest = rest.trim().to_string();
+                match cmd {
+                    "help" => {
+                        println!("{}", REPL_HELP);
+                        continue;
+                    },
+                    "type" => {
+                        record = false;
+                        line = format!("type({})", rest);
+                    },
+                    "time" => {
+                        timed = true;
+                        line = rest;
+                    },
+                    "dis" => {
+                        if rest.is_empty() {
+                            dis(compiler.program(), 0);
+                        } else if !dis_functi(compiler.program(), &rest) {
+                            print_err(
+                                &format!("no functi named `{}`", rest),
+                                ErrType::Err, args.extension_color
+                            );
+                        }
+                        continue;
+                    },
+                    "vars" => {
+                        // Newest first, so shadowed globals are skipped
+                        let mut seen: Vec<&String> = vec![];
+                        let mut vars: Vec<String> = vec![];
+                        for var in ast.all_vars.iter().rev() {
+                            if var.name.contains('$') || seen.contains(&&var.name) {
+                                continue;
+                            }
+                            // Functi locals are also in all_vars, skip them
+                            let count = |vars: &Vec<Variable>| vars.iter()
+                                .filter(|v| v.real_name == var.real_name).count();
+                            let locals: usize = ast.functis.iter()
+                                .map(|f| count(&f.locals)).sum();
+                            if count(&ast.all_vars) <= locals {
+                                continue;
+                            }
+                            seen.push(&var.name);
+                            let name = format!("{}::{}", var.module, var.real_name);
+                            let val = ast.get_var_offset(name, None)
+                                .and_then(|i| vm.get_global(i as usize));
+                            if let Some(val) = val {
+                                vars.push(format!(
+                                    "{} = {}", var.name,
+                                    val.to_string().unwrap_or_default()
+                                ));
+                            }
+                        }
+                        for var in vars.iter().rev() {
+                            println!("{}", var);
+                        }
+                        continue;
+                    },
+                    "save" => {
+                        if rest.is_empty() {
+                            print_err(
+                                "expected a file name", ErrType::Err,
+                                args.extension_color
+                            );
+                        } else if let Err(e) = write(&rest, session.join("\n") + "\n") {
+                            print_err(
+                                &format!("failed to save `{}`: {}", rest, e),
+                                ErrType::Err, args.extension_color
+                            );
+                        }
+                        continue;
+                    },
+                    "load" => match read_to_string(&rest) {
+                        Ok(src) => line = src,
+                        Err(e) => {
+                            print_err(
+                                &format!("failed to load `{}`: {}", rest, e),
+                                ErrType::Err, args.extension_color
+                            );
+                            continue;
+                        }
+                    },
+                    "reset" => {
+                        ast = AST::new();
+                        compiler = IncrementalCompiler::new(Program::new(), args.opt_level);
+                        vm = Vm::new(args.clone(), Program::new());
+                        #[cfg(feature = "fancyrepl")]
+                        {
+                            rl.helper_mut().unwrap().symbols = vm.get_symbols(true);
+                        }
+                        continue;
+                    },
+                    _ => {
+                        print_err(
+                            &format!("unknown REPL command `:{}`", cmd),
+                            ErrType::Err, args.extension_color
+                        );
+                        print_err(
+                            "use `:help` to list commands",
+                            ErrType::Hint, args.extension_color
+                        );
+                        continue;
+                    }
+                }
+            }
+            args.source = line.clone() + ";";
             *get_repl_line() = args.source.clone();
             // Lex
             let Some(tokens) = lex(
@@ -253,42 +392,64 @@ pub fn repl(args: &mut Arguments) {
             args.source = "".to_string();
             // Get AST
             ast.nodes = vec![];
-            let Some(new_ast) = parse(ast.clone(), tokens, args) else {
+            let Some(mut new_ast) = parse(ast.clone(), tokens, args) else {
                 continue;
             };
-            ast = new_ast;
             if args.is_debug {
                 // Debug print ast
-                println!("Ast: {:?}", ast);
+                println!("Ast: {:?}", new_ast);
             }
-            // Compile
-            if !compile(&mut ast, &Some(args.name.clone()), &mut compiler, true) {
+            // Compile, only the new code is compiled
+            let Some(start) = compiler.compile(&mut new_ast, &Some(args.name.clone()), true) else {
                 continue;
-            }
+            };
+            ast = new_ast;
             // Reset file name (imports mess it up during compiling)
             args.name = "<stdin>".to_string();
             // Run
-            if compiler.program.ops.len() == vm.at + 1 {
+            if compiler.program().ops.len() == start {
                 continue;
             }
-            vm.program = compiler.program;
-            if vm.at != 0 {
-                vm.at += 1;
-            }
+            vm.program = compiler.take_program();
+            vm.at = start;
             if args.dis {
-                dis(&vm.program, vm.at);
+                dis(&vm.program, start);
                 vm.at = vm.program.ops.len() - 1;
             } else {
-                run(&mut vm);
+                let start = Instant::now();
+                // eval() can add names
+                vm.ast = Some(std::mem::take(&mut ast));
+                let ok = run(&mut vm);
+                ast = vm.ast.take().unwrap_or_default();
+                if ok && record {
+                    // Keep it for :save
+                    let line = line.trim_end();
+                    session.push(if line.ends_with([';', '}']) {
+                        line.to_string()
+                    } else {
+                        line.to_string() + ";"
+                    });
+                }
+                if timed {
+                    println!(
+                        "took {:.3}ms", start.elapsed().as_secs_f64() * 1000.0
+                    );
+                }
             }
             // Update symbols
             #[cfg(feature = "fancyrepl")]
             {
                 rl.helper_mut().unwrap().symbols = vm.get_symbols(true);
             }
-            compiler.program = vm.program;
+            compiler.give_program(std::mem::replace(&mut vm.program, Program::new()));
+            if let Some(code) = vm.take_exit_code() {
+                exited = Some(code);
+                break;
+            }
         }
     }
+    vm.program = compiler.take_program();
+    vm.run_exit_hooks();
     // Save history
     if !hist_file.is_empty() && rl.save_history(&hist_file).is_err() {
         print_err(
@@ -296,4 +457,7 @@ pub fn repl(args: &mut Arguments) {
             args.extension_color
         );
     }
+    if let Some(code) = exited {
+        std::process::exit(code);
+    }
 }
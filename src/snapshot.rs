@@ -0,0 +1,153 @@
+// `burlap bytecode-test`, compiles the files in tests/bytecode and compares the disassembly
+// Every `name.sk` has a `name.dis` next to it, `--update-snapshots` writes them
+// `burlap output-test` does the same for tests/output, but runs them and keeps what they print
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::{compile_str, Arguments};
+use crate::common::{print_err, ErrType};
+use crate::doc::find_files;
+use crate::backend::vm::dis::dis_to_string;
+
+// How many changed lines are shown for each file
+const MAX_SHOWN: usize = 10;
+
+// The disassembly, or the errors
+fn disassemble(args: &Arguments, file: &Path) -> Result<String, Vec<String>> {
+    let Ok(src) = fs::read_to_string(file) else {
+        return Err(vec![format!("failed to open file: {}", file.display())]);
+    };
+    let args = args.clone().with_name(&file.to_string_lossy());
+    compile_str(&src, &args)
+        .map(|program| dis_to_string(&program, 0))
+        .map_err(|diagnostics| diagnostics.messages)
+}
+
+// Prints the lines that are different
+fn show_diff(expected: &str, got: &str) {
+    let (expected, got): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), got.lines().collect());
+    let mut shown = 0;
+    for at in 0..expected.len().max(got.len()) {
+        let (old, new) = (expected.get(at), got.get(at));
+        if old == new {
+            continue;
+        }
+        if shown == MAX_SHOWN {
+            println!("    ...");
+            return;
+        }
+        shown += 1;
+        if let Some(old) = old {
+            println!("    - {}", old);
+        }
+        if let Some(new) = new {
+            println!("    + {}", new);
+        }
+    }
+}
+
+// Runs a file with this burlap, the output is stdout, then stderr and the exit code
+// Stdin is `name.in` and `name.args` has more args (one per line) if they're there
+// Colors are taken out so the snapshots are readable
+fn run_output(file: &Path) -> Result<String, Vec<String>> {
+    let Ok(exe) = std::env::current_exe() else {
+        return Err(vec!["failed to find the burlap executable".to_string()]);
+    };
+    let stdin = match fs::File::open(file.with_extension("in")) {
+        Ok(input) => Stdio::from(input),
+        Err(_) => Stdio::null(),
+    };
+    let args = fs::read_to_string(file.with_extension("args")).unwrap_or_default();
+    let out = Command::new(exe).arg("--no-cache").args(args.lines()).arg(file).stdin(stdin).output()
+        .map_err(|err| vec![format!("failed to run {}: {}", file.display(), err)])?;
+    let mut ret = String::from_utf8_lossy(&out.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    if !stderr.is_empty() {
+        ret += "--- stderr ---\n";
+        ret += &stderr;
+    }
+    ret += &format!("--- exit: {} ---\n", out.status.code().unwrap_or(-1));
+    // Strip "\x1b[...m"
+    let mut plain = String::new();
+    let mut chars = ret.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| *c == 'm');
+        } else {
+            plain.push(c);
+        }
+    }
+    return Ok(plain);
+}
+
+// Checks (or updates) the `ext` snapshot of every file in the dir
+fn check_snapshots(
+    cli_args: impl Iterator<Item = String>, args: &Arguments, default_dir: &str, ext: &str,
+    get: impl Fn(&Path) -> Result<String, Vec<String>>
+) -> bool {
+    let mut dir = PathBuf::from(default_dir);
+    let mut update = false;
+    for arg in cli_args {
+        if arg == "--update-snapshots" {
+            update = true;
+        } else {
+            dir = PathBuf::from(arg);
+        }
+    }
+    if !dir.is_dir() {
+        print_err(&format!("no such directory: {}", dir.display()), ErrType::Err, args.extension_color);
+        return false;
+    }
+    let mut files = vec![];
+    find_files(&dir, &mut files);
+    let mut passed = 0;
+    for file in &files {
+        let got = match get(file) {
+            Ok(got) => got,
+            Err(errors) => {
+                println!("FAIL: {} doesn't compile", file.display());
+                for error in errors {
+                    println!("    {}", error);
+                }
+                continue;
+            },
+        };
+        let snapshot = file.with_extension(ext);
+        if update {
+            if let Err(err) = fs::write(&snapshot, &got) {
+                print_err(
+                    &format!("failed to write {}: {}", snapshot.display(), err),
+                    ErrType::Err, args.extension_color
+                );
+                continue;
+            }
+            passed += 1;
+            continue;
+        }
+        let Ok(expected) = fs::read_to_string(&snapshot) else {
+            println!("FAIL: {} has no snapshot, run with --update-snapshots", file.display());
+            continue;
+        };
+        if expected == got {
+            passed += 1;
+        } else {
+            println!("FAIL: {} changed", file.display());
+            show_diff(&expected, &got);
+        }
+    }
+    if update {
+        println!("Updated {} out of {} snapshots", passed, files.len());
+    } else {
+        println!("SUMMARY: {} out of {} snapshots matched", passed, files.len());
+    }
+    return passed == files.len();
+}
+
+pub fn bytecode_test(cli_args: impl Iterator<Item = String>, args: &Arguments) -> bool {
+    check_snapshots(cli_args, args, "tests/bytecode", "dis", |file| disassemble(args, file))
+}
+
+pub fn output_test(cli_args: impl Iterator<Item = String>, args: &Arguments) -> bool {
+    check_snapshots(cli_args, args, "tests/output", "out", run_output)
+}
@@ -0,0 +1,180 @@
+// Just enough ZeroMQ (ZMTP 3.0) for the Jupyter kernel
+// Only TCP and the NULL mechanism, and every socket listens
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// The frames of a message
+pub type Message = Vec<Vec<u8>>;
+
+// Frame flags
+const MORE: u8 = 1;
+const LONG: u8 = 2;
+const COMMAND: u8 = 4;
+
+// The biggest frame (and message) that's read, the length comes from the peer
+const MAX_FRAME: u64 = 64 * 1024 * 1024;
+// The most frames in a message, Jupyter only sends a few
+const MAX_FRAMES: usize = 1024;
+
+fn write_frame(out: &mut Vec<u8>, body: &[u8], flags: u8) {
+    if body.len() > 255 {
+        out.push(flags | LONG);
+        out.extend((body.len() as u64).to_be_bytes());
+    } else {
+        out.push(flags);
+        out.push(body.len() as u8);
+    }
+    out.extend(body);
+}
+
+// Reads (flags, body)
+fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0u8; 1];
+    stream.read_exact(&mut flags)?;
+    let len = if flags[0] & LONG != 0 {
+        let mut len = [0u8; 8];
+        stream.read_exact(&mut len)?;
+        let len = u64::from_be_bytes(len);
+        if len > MAX_FRAME {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData, format!("frame is too big ({} bytes)", len)
+            ));
+        }
+        len as usize
+    } else {
+        let mut len = [0u8; 1];
+        stream.read_exact(&mut len)?;
+        len[0] as usize
+    };
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+    Ok((flags[0], body))
+}
+
+pub fn read_message(stream: &mut TcpStream) -> io::Result<Message> {
+    let mut frames = vec![];
+    let mut size = 0;
+    loop {
+        let (flags, body) = read_frame(stream)?;
+        // Commands (like PING) aren't needed
+        if flags & COMMAND != 0 {
+            continue;
+        }
+        size += body.len() as u64;
+        if frames.len() == MAX_FRAMES || size > MAX_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "message is too big"));
+        }
+        frames.push(body);
+        if flags & MORE == 0 {
+            return Ok(frames);
+        }
+    }
+}
+
+// Written all at once, so messages from different threads don't mix
+pub fn write_message(stream: &mut TcpStream, frames: &[Vec<u8>]) -> io::Result<()> {
+    let mut out = vec![];
+    for (at, frame) in frames.iter().enumerate() {
+        write_frame(&mut out, frame, if at + 1 < frames.len() { MORE } else { 0 });
+    }
+    stream.write_all(&out)
+}
+
+// Swaps greetings and READY commands
+fn handshake(stream: &mut TcpStream, socket_type: &str) -> io::Result<()> {
+    // Signature, version 3.0, NULL, not a server, then padding
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xFF;
+    greeting[9] = 0x7F;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    stream.write_all(&greeting)?;
+    let mut peer = [0u8; 64];
+    stream.read_exact(&mut peer)?;
+    if peer[0] != 0xFF || peer[9] != 0x7F || peer[10] < 3 || &peer[12..17] != b"NULL\0" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported ZeroMQ peer"));
+    }
+    let mut ready = vec![5];
+    ready.extend(b"READY");
+    ready.push(11);
+    ready.extend(b"Socket-Type");
+    ready.extend((socket_type.len() as u32).to_be_bytes());
+    ready.extend(socket_type.as_bytes());
+    let mut out = vec![];
+    write_frame(&mut out, &ready, COMMAND);
+    stream.write_all(&out)?;
+    // Nothing in the peer's READY is needed
+    read_frame(stream)?;
+    Ok(())
+}
+
+// Runs on_peer in a new thread for every peer
+fn listen(
+    addr: &str, socket_type: &'static str,
+    on_peer: impl Fn(TcpStream) + Send + Clone + 'static
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let on_peer = on_peer.clone();
+            thread::spawn(move || {
+                if handshake(&mut stream, socket_type).is_ok() {
+                    on_peer(stream);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+// A ROUTER, messages from every peer are sent to `to` with a stream for the reply
+pub fn router(addr: &str, to: Sender<(TcpStream, Message)>) -> io::Result<()> {
+    listen(addr, "ROUTER", move |mut stream| {
+        while let Ok(frames) = read_message(&mut stream) {
+            let Ok(reply) = stream.try_clone() else {
+                return;
+            };
+            if to.send((reply, frames)).is_err() {
+                return;
+            }
+        }
+    })
+}
+
+// A REP that sends everything back, for heartbeats
+pub fn echo(addr: &str) -> io::Result<()> {
+    listen(addr, "REP", |mut stream| {
+        while let Ok(frames) = read_message(&mut stream) {
+            if write_message(&mut stream, &frames).is_err() {
+                return;
+            }
+        }
+    })
+}
+
+// A PUB, subscriptions are ignored as Jupyter subscribes to everything
+#[derive(Clone)]
+pub struct Publisher(Arc<Mutex<Vec<TcpStream>>>);
+
+impl Publisher {
+    pub fn bind(addr: &str) -> io::Result<Publisher> {
+        let peers = Arc::new(Mutex::new(vec![]));
+        let publisher = Publisher(peers.clone());
+        listen(addr, "PUB", move |mut stream| {
+            if let Ok(peer) = stream.try_clone() {
+                peers.lock().unwrap().push(peer);
+            }
+            // Read until the peer is gone
+            while read_message(&mut stream).is_ok() {}
+        })?;
+        Ok(publisher)
+    }
+
+    pub fn send(&self, frames: &[Vec<u8>]) {
+        // Peers that are gone are dropped
+        self.0.lock().unwrap().retain_mut(|peer| write_message(peer, frames).is_ok());
+    }
+}
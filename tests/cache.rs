@@ -0,0 +1,54 @@
+// Tests for the bytecode cache, these run a file more than once so they aren't in test.sk
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+// A file in its own temp dir (with the cache next to it), removed when dropped
+struct TempFile {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn new(name: &str, code: &str) -> TempFile {
+        let dir = std::env::temp_dir().join(format!("burlap-cache-test-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.sk");
+        fs::write(&path, code).unwrap();
+        TempFile { dir, path }
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_burlap"))
+            .args(args).arg("--no-color").arg(&self.path)
+            .output().expect("failed to run burlap")
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn warnings_every_run() {
+    let file = TempFile::new("warnings", "let x = 1;\nif true {\n    let x = 2;\n}\nprint(x);\n");
+    for _ in 0..2 {
+        let out = file.run(&[]);
+        assert!(out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("[shadow]"));
+    }
+    // Not a cache hit, so it's denied
+    assert!(!file.run(&["--deny-warnings"]).status.success());
+}
+
+#[test]
+fn cache_is_used() {
+    let file = TempFile::new("used", "print(\"cached\");\n");
+    for _ in 0..2 {
+        let out = file.run(&[]);
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "cached\n");
+    }
+    assert!(file.dir.join(".burlap-cache").is_dir());
+}
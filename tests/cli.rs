@@ -0,0 +1,99 @@
+// Tests for running burlap from a shell
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn burlap() -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_burlap"));
+    cmd.current_dir(env!("CARGO_MANIFEST_DIR")).arg("--no-color");
+    cmd
+}
+
+// A fresh directory for files a test makes
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("burlap-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// What was printed, and the exit code
+fn printed(out: &Output) -> (String, String, Option<i32>) {
+    let text = |out: &[u8]| String::from_utf8_lossy(out).to_string();
+    (text(&out.stdout), text(&out.stderr), out.status.code())
+}
+
+#[test]
+fn closed_pipe_exits_quietly() {
+    // Like `yes | head -1`, it would print forever
+    let mut child = burlap().args(["-", "loop (while true) { print(\"y\"); }"])
+        .stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().expect("failed to run burlap");
+    let mut first = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut first).unwrap();
+    assert_eq!(first, "y\n");
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success(), "exited with {}", out.status);
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "");
+}
+
+#[test]
+fn c_backend_matches_the_vm() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        eprintln!("skipping, there's no cc");
+        return;
+    }
+    let dir = temp_dir("c-backend");
+    let mut compiled = vec![];
+    let mut files: Vec<PathBuf> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests"))
+        .unwrap().map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sk")).collect();
+    files.sort();
+    for file in files {
+        let name = file.file_stem().unwrap().to_string_lossy().to_string();
+        let file = format!("tests/{}.sk", name);
+        let c = dir.join(format!("{}.c", name));
+        // Most tests use things the C backend can't do
+        let emit = burlap().args(["compile", "--emit=c", &file, "-o"]).arg(&c).output().unwrap();
+        if !emit.status.success() {
+            continue;
+        }
+        let bin = dir.join(&name);
+        let cc = Command::new("cc").arg("-O2").arg(&c).arg("-o").arg(&bin).arg("-lm")
+            .output().unwrap();
+        let (_, errors, _) = printed(&cc);
+        assert!(cc.status.success(), "{} didn't build: {}", name, errors);
+        let native = Command::new(&bin).current_dir(env!("CARGO_MANIFEST_DIR")).output().unwrap();
+        let vm = burlap().arg(&file).output().unwrap();
+        assert_eq!(printed(&native), printed(&vm), "{} printed something else", name);
+        compiled.push(name);
+    }
+    for name in ["99-bottles-of-beer", "fizzbuzz", "hello-world", "scopes"] {
+        assert!(compiled.iter().any(|c| c == name), "{} wasn't compiled", name);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn bundles_run_like_the_file() {
+    let dir = temp_dir("bundle");
+    fs::write(
+        dir.join("app.sk"),
+        "print(program_args());\nprint(resource(\"data.txt\"));\n\
+        functi square(n) { return n * n; }\nprint(square(12));\n"
+    ).unwrap();
+    fs::write(dir.join("data.txt"), "some data").unwrap();
+    let bundled = burlap().current_dir(&dir)
+        .args(["bundle", "app.sk", "-o", "app", "-r", "data.txt"]).output().unwrap();
+    assert!(bundled.status.success(), "{}", String::from_utf8_lossy(&bundled.stderr));
+    let vm = burlap().current_dir(&dir).args(["app.sk", "a", "b"]).output().unwrap();
+    assert_eq!(printed(&vm).0, "[app.sk, a, b]\nsome data\n144\n");
+    // It still works without the files it was made from
+    fs::remove_file(dir.join("app.sk")).unwrap();
+    fs::remove_file(dir.join("data.txt")).unwrap();
+    fs::rename(dir.join("app"), dir.join("moved")).unwrap();
+    let app = Command::new(dir.join("moved")).current_dir(&dir).args(["a", "b"]).output().unwrap();
+    assert_eq!(printed(&app), printed(&vm));
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,55 @@
+// Tests for the JIT, hot functis have to act the same as they do in the interpreter
+#![cfg(feature = "jit")]
+use std::process::{Command, Output};
+
+// More calls than it takes to compile a functi
+const WARM: usize = 1100;
+
+// Runs a script after calling `warm` enough times to compile the functis
+fn run(functis: &str, warm: &str, checks: &str, times: usize, args: &[&str]) -> Output {
+    let script = format!("{}\nloop (i in range(0, {})) {{ {} }}\n{}", functis, times, warm, checks);
+    Command::new(env!("CARGO_BIN_EXE_burlap"))
+        .arg("--no-color").args(args).args(["-", &script])
+        .output().expect("failed to run burlap")
+}
+
+// Checks that hot functis print what the interpreter does (errors too), and gives what they printed
+fn same(functis: &str, warm: &str, checks: &str, args: &[&str]) -> String {
+    let hot = run(functis, warm, checks, WARM, args);
+    // The JIT is off when overflows are errors
+    let cold = run(functis, warm, checks, 0, &[args, &["--overflow=error"]].concat());
+    let text = |out: &[u8]| String::from_utf8_lossy(out).to_string();
+    assert_eq!(text(&hot.stdout), text(&cold.stdout));
+    assert_eq!(text(&hot.stderr), text(&cold.stderr));
+    assert_eq!(hot.status.success(), cold.status.success());
+    text(&hot.stdout)
+}
+
+#[test]
+fn modulo() {
+    let functis = "functi modBy(mn, md) { return mn % md; }";
+    let checks = "print(modBy(-2147483647 - 1, -1)); print(modBy(-7, 3));";
+    assert_eq!(same(functis, "modBy(7, 3);", checks, &[]), "0\n-1\n");
+    let out = same(functis, "modBy(7, 3);", "print(modBy(7, 2)); print(modBy(5, 0));", &[]);
+    assert!(out.starts_with("1\nRuntime Error at <cli>:1:33: Cannot modulo 5 by zero"), "{}", out);
+}
+
+#[test]
+fn other_args() {
+    let functis = "functi add(a, b) { return a + b; }";
+    let checks = "print(add(1.5, 2)); print(add(\"a\", \"b\")); print(add(2, 3));";
+    assert_eq!(same(functis, "add(1, 2);", checks, &[]), "3.5\nab\n5\n");
+    // Native math wraps too
+    let out = run(functis, "add(1, 2);", "print(add(2147483647, 1));", WARM, &[]);
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "-2147483648\n");
+}
+
+#[test]
+fn call_depth() {
+    let functis = "functi r(n) { if n == 0 { return 0; } return 1 + r(n - 1); }";
+    // Far deeper than native code goes
+    assert_eq!(same(functis, "r(5);", "print(r(300000));", &["--max-call-depth=0"]), "300000\n");
+    let out = same(functis, "r(5);", "print(r(9000)); print(r(20000));", &[]);
+    let error = "9000\nRuntime Error at <cli>:1:49: maximum recursion depth exceeded (10000 calls)";
+    assert!(out.starts_with(error), "{}", out);
+}
@@ -0,0 +1,257 @@
+// Tests for `burlap kernel`, these talk ZeroMQ to a real kernel like Jupyter would
+#![cfg(feature = "jupyter")]
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value as Json};
+use sha2::Sha256;
+
+const KEY: &[u8] = b"test-key";
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+// For message ids
+static SENT: AtomicUsize = AtomicUsize::new(0);
+
+// A kernel on free ports, it's killed when dropped
+struct Kernel {
+    child: Child,
+    file: PathBuf,
+    ports: Json,
+}
+
+impl Kernel {
+    fn start() -> Kernel {
+        // Ports that were free a moment ago
+        let free = || TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let ports = json!({
+            "shell_port": free(), "control_port": free(), "stdin_port": free(),
+            "hb_port": free(), "iopub_port": free(),
+        });
+        let mut info = ports.clone();
+        info["ip"] = json!("127.0.0.1");
+        info["transport"] = json!("tcp");
+        info["key"] = json!(String::from_utf8_lossy(KEY));
+        info["signature_scheme"] = json!("hmac-sha256");
+        let file = std::env::temp_dir().join(format!(
+            "burlap-kernel-test-{}-{}.json", std::process::id(), ports["shell_port"]
+        ));
+        fs::write(&file, info.to_string()).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_burlap"))
+            .arg("kernel").arg("--connection-file").arg(&file)
+            .stdout(Stdio::null()).stderr(Stdio::null())
+            .spawn().expect("failed to start the kernel");
+        Kernel { child, file, ports }
+    }
+
+    // Connects and does the ZMTP handshake, the kernel might still be starting
+    fn connect(&self, port: &str, socket_type: &str) -> TcpStream {
+        let addr = format!("127.0.0.1:{}", self.ports[port]);
+        let start = Instant::now();
+        let mut stream = loop {
+            match TcpStream::connect(&addr) {
+                Ok(stream) => break stream,
+                Err(_) if start.elapsed() < Duration::from_secs(10) => sleep(Duration::from_millis(20)),
+                Err(e) => panic!("failed to connect to {}: {}", port, e),
+            }
+        };
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        handshake(&mut stream, socket_type);
+        stream
+    }
+}
+
+impl Drop for Kernel {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_file(&self.file);
+    }
+}
+
+fn handshake(stream: &mut TcpStream, socket_type: &str) {
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xFF;
+    greeting[9] = 0x7F;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    stream.write_all(&greeting).unwrap();
+    let mut peer = [0u8; 64];
+    stream.read_exact(&mut peer).unwrap();
+    assert_eq!(&peer[12..16], b"NULL");
+    let mut ready = b"\x05READY\x0bSocket-Type".to_vec();
+    ready.extend((socket_type.len() as u32).to_be_bytes());
+    ready.extend(socket_type.as_bytes());
+    write_frame(stream, &ready, 4);
+    read_frame(stream).unwrap();
+}
+
+fn write_frame(stream: &mut TcpStream, body: &[u8], flags: u8) {
+    let mut out = vec![flags | 2];
+    out.extend((body.len() as u64).to_be_bytes());
+    out.extend(body);
+    stream.write_all(&out).unwrap();
+}
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0u8; 1];
+    stream.read_exact(&mut flags)?;
+    let len = if flags[0] & 2 != 0 {
+        let mut len = [0u8; 8];
+        stream.read_exact(&mut len)?;
+        u64::from_be_bytes(len) as usize
+    } else {
+        let mut len = [0u8; 1];
+        stream.read_exact(&mut len)?;
+        len[0] as usize
+    };
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+    Ok((flags[0], body))
+}
+
+fn sign(parts: &[Vec<u8>]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(KEY).unwrap();
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn send(stream: &mut TcpStream, msg_type: &str, content: Json, signature: Option<&str>) -> Json {
+    let header = json!({
+        "msg_id": format!("test-{}", SENT.fetch_add(1, Ordering::Relaxed)),
+        "session": "test", "username": "test",
+        "msg_type": msg_type, "version": "5.3",
+    });
+    let parts: Vec<Vec<u8>> = [header.clone(), json!({}), json!({}), content].iter()
+        .map(|part| part.to_string().into_bytes()).collect();
+    let signature = signature.map_or_else(|| sign(&parts), |s| s.to_string());
+    let mut frames = vec![DELIMITER.to_vec(), signature.into_bytes()];
+    frames.extend(parts);
+    for (at, frame) in frames.iter().enumerate() {
+        write_frame(stream, frame, if at + 1 < frames.len() { 1 } else { 0 });
+    }
+    header
+}
+
+// Gets (header, parent header, content), and checks the signature
+fn recv(stream: &mut TcpStream) -> (Json, Json, Json) {
+    let mut frames = vec![];
+    loop {
+        let (flags, body) = read_frame(stream).expect("no message from the kernel");
+        if flags & 4 != 0 {
+            continue;
+        }
+        frames.push(body);
+        if flags & 1 == 0 {
+            break;
+        }
+    }
+    let at = frames.iter().position(|frame| frame == DELIMITER).unwrap();
+    let parts = frames[at + 2..at + 6].to_vec();
+    assert_eq!(frames[at + 1], sign(&parts).into_bytes(), "badly signed reply");
+    let json = |at: usize| serde_json::from_slice::<Json>(&parts[at]).unwrap();
+    (json(0), json(1), json(3))
+}
+
+// The iopub messages for a request, up to it going idle
+fn published(iopub: &mut TcpStream, request: &Json) -> Vec<(String, Json)> {
+    let mut messages = vec![];
+    loop {
+        let (header, parent, content) = recv(iopub);
+        if parent["msg_id"] != request["msg_id"] {
+            continue;
+        }
+        let msg_type = header["msg_type"].as_str().unwrap().to_string();
+        if msg_type == "status" && content["execution_state"] == "idle" {
+            return messages;
+        }
+        messages.push((msg_type, content));
+    }
+}
+
+#[test]
+fn requests_and_iopub() {
+    let kernel = Kernel::start();
+    let mut shell = kernel.connect("shell_port", "DEALER");
+    let mut iopub = kernel.connect("iopub_port", "SUB");
+    // Like Jupyter, ask for the kernel info until iopub has caught up
+    iopub.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    let info = loop {
+        let request = send(&mut shell, "kernel_info_request", json!({}), None);
+        let (header, parent, content) = recv(&mut shell);
+        assert_eq!(header["msg_type"], "kernel_info_reply");
+        assert_eq!(parent["msg_id"], request["msg_id"]);
+        let mut status = [0u8; 1];
+        if iopub.peek(&mut status).is_ok() {
+            published(&mut iopub, &request);
+            break content;
+        }
+    };
+    assert_eq!(info["implementation"], "burlap");
+    assert_eq!(info["language_info"]["file_extension"], ".sk");
+    iopub.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+    let request = send(&mut shell, "execute_request", json!({"code": "print(\"hi\");\n1 + 2"}), None);
+    let (header, _, reply) = recv(&mut shell);
+    assert_eq!(header["msg_type"], "execute_reply");
+    assert_eq!(reply["status"], "ok");
+    assert_eq!(reply["execution_count"], 1);
+    let messages = published(&mut iopub, &request);
+    let types: Vec<&str> = messages.iter().map(|(t, _)| t.as_str()).collect();
+    assert_eq!(types, ["status", "execute_input", "stream", "execute_result"]);
+    assert_eq!(messages[2].1["text"], "hi\n");
+    assert_eq!(messages[3].1["data"]["text/plain"], "3");
+
+    let request = send(&mut shell, "execute_request", json!({"code": "none + 1;"}), None);
+    let (_, _, reply) = recv(&mut shell);
+    assert_eq!(reply["status"], "error");
+    assert_eq!(reply["ename"], "RuntimeError");
+    assert!(published(&mut iopub, &request).iter().any(|(t, _)| t == "error"));
+}
+
+#[test]
+fn bad_signatures_are_ignored() {
+    let kernel = Kernel::start();
+    let mut shell = kernel.connect("shell_port", "DEALER");
+    send(&mut shell, "execute_request", json!({"code": "let forged = 1;"}), Some("00"));
+    let request = send(&mut shell, "execute_request", json!({"code": "forged"}), None);
+    let (_, parent, reply) = recv(&mut shell);
+    // Only the signed one got a reply, and the forged one didn't run
+    assert_eq!(parent["msg_id"], request["msg_id"]);
+    assert_eq!(reply["status"], "error");
+    assert!(reply["evalue"].as_str().unwrap().contains("\"forged\" is not defined"));
+}
+
+#[test]
+fn endless_messages_are_cut_off() {
+    let kernel = Kernel::start();
+    let mut shell = kernel.connect("shell_port", "DEALER");
+    // Frames that all say more are coming, the kernel gives up on it
+    let mut closed = false;
+    for _ in 0..100_000 {
+        if shell.write_all(&[1, 0]).is_err() {
+            closed = true;
+            break;
+        }
+    }
+    if !closed {
+        let mut rest = [0u8; 1];
+        closed = match shell.read(&mut rest) {
+            Ok(got) => got == 0,
+            Err(e) => !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut),
+        };
+    }
+    assert!(closed);
+    // Other peers still work
+    let mut shell = kernel.connect("shell_port", "DEALER");
+    send(&mut shell, "kernel_info_request", json!({}), None);
+    assert_eq!(recv(&mut shell).0["msg_type"], "kernel_info_reply");
+}
@@ -0,0 +1,25 @@
+// Tests for using burlap as a library
+use burlap::{compile_str, Arguments};
+use std::thread;
+
+#[test]
+fn diagnostics_are_per_call() {
+    // Each thread should only get back its own errors
+    let threads: Vec<_> = (0..8).map(|i| thread::spawn(move || {
+        let name = format!("file{}.sk", i);
+        let args = Arguments::new().with_name(&name);
+        for _ in 0..50 {
+            let Err(diagnostics) = compile_str("let x = ;", &args) else {
+                panic!("bad code compiled");
+            };
+            assert!(!diagnostics.messages.is_empty());
+            for message in &diagnostics.messages {
+                assert!(message.contains(&name), "{} got {}", name, message);
+            }
+            assert!(compile_str("let x = 1;", &args).is_ok());
+        }
+    })).collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+// Tests for the `burlap repl --listen` protocol, these need a real server so they aren't in test.sk
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::process::{Child, Command, Stdio};
+
+// A server on a free port, it's killed when dropped
+struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Server {
+    fn start(args: &[&str]) -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_burlap"))
+            .args(["repl", "--listen", "127.0.0.1:0"]).args(args)
+            .stdout(Stdio::piped()).stderr(Stdio::null())
+            .spawn().expect("failed to start the server");
+        // "Burlap vX listening on <addr>"
+        let mut line = String::new();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        stdout.read_line(&mut line).unwrap();
+        let addr = line.trim().rsplit(' ').next().unwrap().to_string();
+        // Warnings go to stdout too, it would die if the pipe was closed
+        std::thread::spawn(move || std::io::copy(&mut stdout, &mut std::io::sink()));
+        Server { child, addr }
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(&self.addr).expect("failed to connect");
+        Client { reader: BufReader::new(stream.try_clone().unwrap()), stream }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    // Sends code and gets every line of the reply
+    fn send(&mut self, code: &str) -> Vec<String> {
+        self.stream.write_all(code.as_bytes()).unwrap();
+        let mut reply = vec![];
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).unwrap() == 0 {
+                return reply;
+            }
+            let line = line.trim_end().to_string();
+            let done = line == "ok" || line.starts_with("ok ") || line == "fail"
+                || line.starts_with("exit ") || line == "error bad token";
+            reply.push(line);
+            if done {
+                return reply;
+            }
+        }
+    }
+}
+
+#[test]
+fn results_and_errors() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    assert_eq!(client.send("print(\"hi\");\n"), ["out hi", "ok"]);
+    assert_eq!(client.send("1 + 2\n"), ["ok 3"]);
+    assert_eq!(
+        client.send("none + 1;\n"),
+        ["error Runtime Error: Cannot add None and Number: none + 1", "fail"]
+    );
+    assert_eq!(client.send("let x = ;\n")[1], "fail");
+}
+
+#[test]
+fn unfinished_code() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    assert_eq!(client.send("functi five() {\nreturn 5;\n}\n"), ["ok"]);
+    assert_eq!(client.send("five()\n"), ["ok 5"]);
+}
+
+#[test]
+fn shared_session() {
+    let server = Server::start(&[]);
+    assert_eq!(server.connect().send("let shared = 7;\n"), ["ok"]);
+    assert_eq!(server.connect().send("shared\n"), ["ok 7"]);
+}
+
+#[test]
+fn exit_ends_the_request() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    assert_eq!(client.send("let kept = 1;\n"), ["ok"]);
+    assert_eq!(
+        client.send("functi leave() { defer print(\"deferred\"); exit(3); }\n"), ["ok"]
+    );
+    assert_eq!(client.send("leave();\n"), ["out deferred", "exit 3"]);
+    // The server and the session are still there
+    assert_eq!(client.send("kept\n"), ["ok 1"]);
+    assert_eq!(server.connect().send("kept + 1\n"), ["ok 2"]);
+}
+
+#[test]
+fn idle_clients_dont_block() {
+    let server = Server::start(&[]);
+    let mut idle = server.connect();
+    // Half of a request
+    idle.stream.write_all(b"functi unfinished() {\n").unwrap();
+    assert_eq!(server.connect().send("1\n"), ["ok 1"]);
+    assert_eq!(idle.send("return 2; }\n"), ["ok"]);
+    assert_eq!(idle.send("unfinished()\n"), ["ok 2"]);
+}
+
+#[test]
+fn half_closed_clients_get_replies() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    // Like `printf 'print(1);\n' | nc -N host port`
+    client.stream.write_all(b"print(1);\n").unwrap();
+    client.stream.shutdown(Shutdown::Write).unwrap();
+    let mut reply = String::new();
+    client.reader.read_to_string(&mut reply).unwrap();
+    assert_eq!(reply, "out 1\nok\n");
+}
+
+#[test]
+fn slow_readers_dont_block() {
+    let server = Server::start(&[]);
+    let mut slow = server.connect();
+    // A lot more output than the socket can hold, and it never reads it
+    slow.stream.write_all(b"loop (i in range(1, 200000)) { print(\"x\" * 100); }\n").unwrap();
+    assert_eq!(server.connect().send("1\n"), ["ok 1"]);
+}
+
+#[test]
+fn long_lines_are_cut_off() {
+    let server = Server::start(&["--token", "secret"]);
+    let mut client = server.connect();
+    // No newline, and no token, the server gives up on it instead of keeping it all
+    let junk = vec![b'a'; 1024 * 1024];
+    for _ in 0..32 {
+        if client.stream.write_all(&junk).is_err() {
+            break;
+        }
+    }
+    let mut rest = vec![];
+    let _ = client.reader.read_to_end(&mut rest);
+    assert!(rest.is_empty());
+    assert_eq!(server.connect().send("auth secret\n"), ["ok"]);
+}
+
+#[test]
+fn token() {
+    let server = Server::start(&["--token", "secret"]);
+    assert_eq!(server.connect().send("auth wrong\n"), ["error bad token"]);
+    let mut client = server.connect();
+    assert_eq!(client.send("auth secret\n"), ["ok"]);
+    assert_eq!(client.send("2 * 2\n"), ["ok 4"]);
+}
@@ -0,0 +1,38 @@
+// Tests for the REPL, it reads from stdin (without prompts) when it isn't a terminal
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Runs the REPL on some input, and gets what it printed without colors
+fn repl(args: &[&str], input: &str) -> Vec<String> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_burlap"))
+        .args(args).arg("--no-color")
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null())
+        .spawn().expect("failed to start the REPL");
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let out = String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap();
+    let mut plain = String::new();
+    let mut in_escape = false;
+    for c in out.chars() {
+        match c {
+            '\x1b' => in_escape = true,
+            'm' if in_escape => in_escape = false,
+            _ if !in_escape => plain.push(c),
+            _ => {},
+        }
+    }
+    plain.lines().map(|l| l.to_string()).collect()
+}
+
+#[test]
+fn failed_lines_are_rolled_back() {
+    // The reftype can't be compiled, so none of the line happens
+    let out = repl(&["--use-burlap-extensions"], concat!(
+        "let rb = 1; __burlap_reftype(5); functi f() {}\n",
+        "let rb = 2;\n",
+        "functi f() { return rb; }\n",
+        "f() + 1\n",
+    ));
+    assert_eq!(out[1..], [
+        "Compiler Error (internal): __burlap_reftype requires a variable", "3"
+    ]);
+}